@@ -0,0 +1,157 @@
+//! 盤面操作 (駒のマスク、連結成分、消去、合法手) の 1 回あたりの所要時間を計測する。
+
+mod common;
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use samegame_sfc_small::*;
+
+fn bench_board(c: &mut Criterion) {
+    let cases = common::corpus_playouts();
+
+    let mut group = c.benchmark_group("board");
+    group.throughput(Throughput::Elements(cases.len() as u64));
+
+    group.bench_function("piece_mask", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, _)| {
+                    Piece::all()
+                        .map(|piece| black_box(pos.board()).piece_mask(piece).square_count())
+                        .sum::<u32>()
+                })
+                .sum::<u32>()
+        })
+    });
+    group.bench_function("flood_fill", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, action)| {
+                    let mb = black_box(pos.board()).piece_mask(action.piece());
+                    mb.flood_fill(action.least_square()).square_count()
+                })
+                .sum::<u32>()
+        })
+    });
+    group.bench_function("piece_components", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, _)| black_box(pos.board()).piece_components().count())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("erase", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, action)| black_box(pos.board()).erase(action.mask()).width_remain())
+                .sum::<u32>()
+        })
+    });
+    group.bench_function("has_action/bitwise", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .filter(|(pos, _)| black_box(pos.board()).has_action())
+                .count()
+        })
+    });
+    group.bench_function("has_action/components", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .filter(|(pos, _)| {
+                    black_box(pos.board())
+                        .piece_components()
+                        .any(|(_, mb)| !mb.is_single())
+                })
+                .count()
+        })
+    });
+    group.bench_function("canonical", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, _)| black_box(pos.board()).canonical().width_remain())
+                .sum::<u32>()
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_position(c: &mut Criterion) {
+    let cases = common::corpus_playouts();
+
+    let mut group = c.benchmark_group("position");
+    group.throughput(Throughput::Elements(cases.len() as u64));
+
+    group.bench_function("key", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .fold(0, |acc, (pos, _)| acc ^ black_box(pos).key())
+        })
+    });
+    group.bench_function("canonical_key", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .fold(0, |acc, (pos, _)| acc ^ black_box(pos).canonical_key())
+        })
+    });
+    group.bench_function("do_action", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, action)| black_box(pos).do_action(action).board().width_remain())
+                .sum::<u32>()
+        })
+    });
+    group.bench_function("actions", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, _)| black_box(pos).actions().count())
+                .sum::<usize>()
+        })
+    });
+    // 着手後の局面の合法手の一覧を、着手前の局面の一覧から差分更新する場合と、盤面から求め直す場合の比較。
+    // (コーパスの局面は合法手の一覧を求め済み)
+    // 後者はハッシュ値の更新を含まないので、後者に有利な比較となっている。
+    group.bench_function("do_action+actions/incremental", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, action)| black_box(pos).do_action(action).actions().count())
+                .sum::<usize>()
+        })
+    });
+    group.bench_function("do_action+actions/full", |b| {
+        b.iter(|| {
+            cases
+                .iter()
+                .map(|(pos, action)| {
+                    black_box(pos)
+                        .board()
+                        .erase(action.mask())
+                        .piece_components()
+                        .filter(|(_, mb)| !mb.is_single())
+                        .map(|(piece, mb)| Action::new(piece, mb))
+                        .collect::<Vec<_>>()
+                        .len()
+                })
+                .sum::<usize>()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_board, bench_position);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! ベンチマーク用の固定の盤面コーパス。
+//!
+//! 盤面はゲーム内乱数の状態 `0..BOARD_COUNT` (counter=0x00, inc_timing=39) から生成するので、
+//! 計測間で常に同一となる (ベースラインとの比較が意味を持つ)。
+
+// ベンチマークごとに使う関数が異なる。
+#![allow(dead_code)]
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+use samegame_sfc_small::*;
+
+/// コーパスに用いる乱数状態の数 (再生成判定に引っ掛かるものは除かれる)。
+pub const BOARD_COUNT: u16 = 64;
+
+const COUNTER: u8 = 0x00;
+const INC_TIMING: usize = 39;
+
+/// プレイアウトの着手選択に用いる乱数のシード。
+const PLAYOUT_SEED: u64 = 1;
+
+/// ゲーム内の初期盤面たちを返す。
+pub fn corpus_boards() -> Vec<Board> {
+    (0..BOARD_COUNT)
+        .filter_map(|state| GameRng::new(state).gen_board(COUNTER, INC_TIMING))
+        .collect()
+}
+
+/// 初期盤面たちからランダムな着手を終局まで進め、途中の (着手前の局面, 着手) の組を全て返す。
+pub fn corpus_playouts() -> Vec<(Position, Action)> {
+    let mut rng = StdRng::seed_from_u64(PLAYOUT_SEED);
+    let mut cases = Vec::new();
+
+    for board in corpus_boards() {
+        let mut pos = Position::new(board);
+        loop {
+            let actions: Vec<_> = pos.actions().collect();
+            if actions.is_empty() {
+                break;
+            }
+            let action = actions[rng.gen_range(0..actions.len())].clone();
+            let pos_child = pos.do_action(&action);
+            cases.push((pos, action));
+            pos = pos_child;
+        }
+    }
+
+    cases
+}
+
+/// 初期盤面たちからランダムな着手を `move_count` 手進めた盤面たちを返す。
+///
+/// 途中で終局した盤面は除かれる。初期盤面のままではソルバーの計測に時間が掛かりすぎるため用いる。
+pub fn corpus_midgame(move_count: usize) -> Vec<Board> {
+    let mut rng = StdRng::seed_from_u64(PLAYOUT_SEED);
+
+    corpus_boards()
+        .into_iter()
+        .filter_map(|board| {
+            let mut pos = Position::new(board);
+            for _ in 0..move_count {
+                let actions: Vec<_> = pos.actions().collect();
+                if actions.is_empty() {
+                    return None;
+                }
+                pos = pos.do_action(&actions[rng.gen_range(0..actions.len())]);
+            }
+            Some(pos.board().clone())
+        })
+        .collect()
+}
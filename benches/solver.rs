@@ -0,0 +1,140 @@
+//! 各ソルバーの探索速度 (ノード数/秒) と、並列探索のスレッド数や DP テーブルの設定による探索速度の違いを計測する。
+//!
+//! スループットは事前に 1 回解いた際の合計探索ノード数から求める
+//! (並列ソルバー以外は探索ノード数が決定的なので、ノード数/秒 となる)。
+
+mod common;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use samegame_sfc_small::*;
+
+/// ソルバーの計測に用いる局面の、初期盤面からの手数。
+const MIDGAME_MOVE_COUNT: usize = 3;
+
+const TABLE_BYTES: usize = 64 << 20;
+
+/// 盤面たちを最大スコアの初期値 0 から解き、合計探索ノード数と最後の DP テーブルの統計を返す。
+fn solve_all(solver: &mut dyn Solve, boards: &[Board]) -> (u64, Option<TableStats>) {
+    let mut node_count = 0;
+    let mut table_stats = None;
+    for board in boards {
+        solver.set_best_score(0);
+        if let Some(res) = solver.solve(board.clone()) {
+            node_count += res.node_count();
+            table_stats = Some(res.table_stats().clone());
+        }
+    }
+    (node_count, table_stats)
+}
+
+fn bench_solvers(c: &mut Criterion) {
+    let boards = common::corpus_midgame(MIDGAME_MOVE_COUNT);
+
+    let mut group = c.benchmark_group("solver");
+    group.sample_size(10);
+
+    for kind in [
+        SolverKind::Exact,
+        SolverKind::Bound,
+        SolverKind::Many,
+        SolverKind::Parallel,
+        SolverKind::Beam,
+        SolverKind::Mcts,
+        SolverKind::Aspiration,
+    ] {
+        let config = SolveConfig {
+            thread_count: 2,
+            table_bytes: TABLE_BYTES,
+            mcts: MctsConfig {
+                playout_count: 1000,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut solver = kind.new_solver(&config);
+
+        let (node_count, _) = solve_all(solver.as_mut(), &boards);
+        group.throughput(Throughput::Elements(node_count));
+        group.bench_function(BenchmarkId::from_parameter(kind), |b| {
+            b.iter(|| solve_all(solver.as_mut(), &boards))
+        });
+    }
+
+    group.finish();
+}
+
+/// スレッド数を変えて `ParallelSolver` を計測する。
+///
+/// スループットは 1 スレッドの場合の探索ノード数から求めるので、スレッド数による実時間の短縮を比較できる。
+/// 各スレッドが重複して探索したノードも含めた合計探索ノード数も表示する。
+fn bench_parallel(c: &mut Criterion) {
+    let boards = common::corpus_midgame(MIDGAME_MOVE_COUNT);
+
+    let mut group = c.benchmark_group("parallel");
+    group.sample_size(10);
+
+    let mut node_count_single = None;
+    for thread_count in [1, 2, 4, 8] {
+        let config = SolveConfig {
+            thread_count,
+            table_bytes: TABLE_BYTES,
+            ..Default::default()
+        };
+        let mut solver = SolverKind::Parallel.new_solver(&config);
+
+        let (node_count, _) = solve_all(solver.as_mut(), &boards);
+        println!("parallel/{thread_count}: nodes={node_count}");
+        let node_count_single = *node_count_single.get_or_insert(node_count);
+
+        group.throughput(Throughput::Elements(node_count_single));
+        group.bench_function(BenchmarkId::from_parameter(thread_count), |b| {
+            b.iter(|| solve_all(solver.as_mut(), &boards))
+        });
+    }
+
+    group.finish();
+}
+
+/// DP テーブルの容量とハッシュ衝突の検出方法を変えて `ExactSolver` を計測する。
+///
+/// 容量が小さいとエントリの置き換えが起こり、探索ノード数が増える。
+fn bench_table(c: &mut Criterion) {
+    let boards = common::corpus_midgame(MIDGAME_MOVE_COUNT);
+
+    let mut group = c.benchmark_group("table");
+    group.sample_size(10);
+
+    for collision_check in [
+        CollisionCheck::None,
+        CollisionCheck::Key128,
+        CollisionCheck::Board,
+    ] {
+        for table_bytes in [1 << 18, 1 << 20, TABLE_BYTES] {
+            let config = SolveConfig {
+                table_bytes,
+                collision_check,
+                ..Default::default()
+            };
+            let mut solver = SolverKind::Exact.new_solver(&config);
+
+            let (node_count, table_stats) = solve_all(solver.as_mut(), &boards);
+            let id = format!("{collision_check}/{}KiB", table_bytes >> 10);
+            if let Some(stats) = table_stats {
+                println!(
+                    "table/{id}: nodes={node_count} capacity={} load_factor={:.3}",
+                    stats.capacity(),
+                    stats.load_factor()
+                );
+            }
+
+            group.throughput(Throughput::Elements(node_count));
+            group.bench_function(id, |b| b.iter(|| solve_all(solver.as_mut(), &boards)));
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_solvers, bench_parallel, bench_table);
+criterion_main!(benches);
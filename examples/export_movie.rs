@@ -0,0 +1,47 @@
+use clap::Parser;
+
+use samegame_sfc_small::*;
+
+/// 手順を BizHawk の `Input Log.txt` 形式の入力ムービーに変換し、標準出力に書き出す。
+#[derive(Debug, Parser)]
+struct Cli {
+    /// 最初の入力を受け付ける時点でのカーソル位置 (列,行)。
+    #[arg(long, default_value = "1,1")]
+    cursor_start: Square,
+
+    /// 最初の入力の前に置く空入力のフレーム数。
+    #[arg(long, default_value_t = 0)]
+    lead_frames: usize,
+
+    /// 1 回のボタン入力で押し続けるフレーム数。
+    #[arg(long, default_value_t = 1)]
+    press_frames: usize,
+
+    /// D-pad を離してから次の入力までのフレーム数。
+    #[arg(long, default_value_t = 1)]
+    move_wait_frames: usize,
+
+    /// A を離してから次の入力を受け付けるまでのフレーム数。
+    #[arg(long, default_value_t = 30)]
+    erase_wait_frames: usize,
+
+    /// 手順 (例: "4,6 1,2 1,2")。
+    solution: ActionHistory,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let config = MovieConfig {
+        cursor_start: cli.cursor_start,
+        lead_frames: cli.lead_frames,
+        press_frames: cli.press_frames,
+        move_wait_frames: cli.move_wait_frames,
+        erase_wait_frames: cli.erase_wait_frames,
+    };
+    let inputs = solution_to_inputs(&cli.solution, &config);
+
+    write_bizhawk_input_log(std::io::stdout().lock(), &inputs)?;
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use clap::Parser;
+
+use samegame_sfc_small::*;
+
+/// 盤面 (一部未知でもよい) を生成するゲーム内乱数のパラメータを全て求める。
+///
+/// 盤面ファイルの形式は問題ファイルと同様だが、未知のマスは `?` で表す。
+#[derive(Debug, Parser)]
+struct Cli {
+    path_pattern: PathBuf,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let pattern = std::fs::read_to_string(&cli.path_pattern)
+        .with_context(|| format!("盤面ファイル {} を読めない", cli.path_pattern.display()))?;
+    let pattern: BoardPattern = pattern
+        .parse()
+        .with_context(|| format!("盤面ファイル {} のパースに失敗", cli.path_pattern.display()))?;
+
+    let params = find_rng_params(&pattern);
+    for &(state, counter, inc_timing) in &params {
+        println!("0x{state:04X}\t0x{counter:02X}\t{inc_timing}");
+    }
+    eprintln!("found: {}", params.len());
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::{bail, Context as _};
+use clap::Parser;
+
+use samegame_sfc_small::*;
+
+/// 手順のカーソル移動を最適化する。
+///
+/// 手順を複数与えた場合、スコアが最大のもののうち入力フレーム数が最小のものを選ぶ。
+/// 結果は `スコア\t入力フレーム数\t手順` の形式で標準出力に書き出す。
+#[derive(Debug, Parser)]
+struct Cli {
+    /// 最初の入力を受け付ける時点でのカーソル位置 (列,行)。
+    #[arg(long, default_value = "1,1")]
+    cursor_start: Square,
+
+    /// 最初の入力の前に置く空入力のフレーム数。
+    #[arg(long, default_value_t = 0)]
+    lead_frames: usize,
+
+    /// 1 回のボタン入力で押し続けるフレーム数。
+    #[arg(long, default_value_t = 1)]
+    press_frames: usize,
+
+    /// D-pad を離してから次の入力までのフレーム数。
+    #[arg(long, default_value_t = 1)]
+    move_wait_frames: usize,
+
+    /// A を離してから次の入力を受け付けるまでのフレーム数。
+    #[arg(long, default_value_t = 30)]
+    erase_wait_frames: usize,
+
+    #[arg(value_parser = parse_int::parse::<u16>)]
+    state: u16,
+
+    #[arg(value_parser = parse_int::parse::<u8>)]
+    counter: u8,
+
+    #[arg(value_parser = parse_int::parse::<usize>)]
+    inc_timing: usize,
+
+    /// 手順 (例: "4,6 1,2 1,2")。
+    #[arg(required = true)]
+    solutions: Vec<ActionHistory>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let Some(board) = GameRng::new(cli.state).gen_board(cli.counter, cli.inc_timing) else {
+        bail!("再生成判定に引っ掛かる盤面");
+    };
+
+    let config = MovieConfig {
+        cursor_start: cli.cursor_start,
+        lead_frames: cli.lead_frames,
+        press_frames: cli.press_frames,
+        move_wait_frames: cli.move_wait_frames,
+        erase_wait_frames: cli.erase_wait_frames,
+    };
+
+    let (solution, score, frame_count) = select_fastest_solution(&board, cli.solutions, &config)
+        .context("不正な手順")?
+        .unwrap();
+
+    println!("{score}\t{frame_count}\t{solution}");
+
+    Ok(())
+}
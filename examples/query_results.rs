@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use clap::{Parser, Subcommand};
+
+use samegame_sfc_small::*;
+
+/// `solve_many --results-db` で記録した探索結果を検索する。
+///
+/// 各結果を `スコア\t乱数状態\tカウンタ\tinc_timing\t手順` の形式で出力する (`verify` の入力にできる)。
+#[derive(Debug, Parser)]
+struct Cli {
+    /// 探索結果のデータベースファイル。
+    path_db: PathBuf,
+
+    /// 各行の末尾に探索ノード数を付加する。
+    #[arg(long)]
+    node_count: bool,
+
+    #[command(subcommand)]
+    query: Query,
+}
+
+#[derive(Debug, Subcommand)]
+enum Query {
+    /// スコアの高い順に出力する。
+    Top {
+        #[arg(long, default_value_t = 20)]
+        count: usize,
+    },
+    /// パーフェクトクリアできる盤面を全て出力する。
+    Perfect,
+    /// 指定した乱数の結果を出力する。
+    Seed {
+        #[arg(value_parser = parse_int::parse::<u16>)]
+        state: u16,
+        #[arg(value_parser = parse_int::parse::<u8>)]
+        counter: u8,
+        #[arg(value_parser = parse_int::parse::<usize>)]
+        inc_timing: usize,
+    },
+    /// 問題ファイルと同一の盤面となる乱数の結果を全て出力する。
+    Board { path_problem: PathBuf },
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let db = ResultsDb::read(&cli.path_db)?;
+
+    let records: Vec<&ResultRecord> = match cli.query {
+        Query::Top { count } => db.top(count),
+        Query::Perfect => db.perfect_clears(),
+        Query::Seed {
+            state,
+            counter,
+            inc_timing,
+        } => db.get(state, counter, inc_timing).into_iter().collect(),
+        Query::Board { path_problem } => {
+            let board: Board = std::fs::read_to_string(&path_problem)
+                .with_context(|| format!("問題ファイル {} を読めない", path_problem.display()))?
+                .parse()
+                .with_context(|| {
+                    format!("問題ファイル {} のパースに失敗", path_problem.display())
+                })?;
+            db.find_board(&board)
+        }
+    };
+
+    if records.is_empty() {
+        eprintln!("NOT FOUND");
+        return Ok(());
+    }
+
+    for record in records {
+        let mut line = format!(
+            "{}\t0x{:04X}\t0x{:02X}\t{}\t{}",
+            record.score(),
+            record.rng_state(),
+            record.rng_counter(),
+            record.rng_inc_timing(),
+            record.solution()
+        );
+        if cli.node_count {
+            line.push_str(&format!("\t{}", record.node_count()));
+        }
+        println!("{line}");
+    }
+
+    Ok(())
+}
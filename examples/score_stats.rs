@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+
+use samegame_sfc_small::*;
+
+/// ゲーム内で出現しうる盤面集合に対するスコア分布の統計をとる。
+///
+/// 全体、inc_timing ごと、counter ごとの要約統計量 (分位点、パーフェクトクリア率など) と度数分布を出力する。
+#[derive(Debug, Parser)]
+struct Cli {
+    /// 各盤面の値 (exact, approx, simple, separation)。
+    /// exact は最大スコア、approx は近似ソルバーで求めたスコア、それ以外は探索を行わないスコア上界。
+    #[arg(long, default_value_t = StatsMetric::Bound(ScoreBound::Separation))]
+    metric: StatsMetric,
+
+    /// 盤面集合を探索する代わりに、`solve_many --results-db` で記録した探索結果から統計をとる。
+    #[arg(long, conflicts_with = "metric")]
+    results_db: Option<PathBuf>,
+
+    /// 出力形式 (text, summary-csv, histogram-csv, json)。
+    #[arg(long, default_value_t = StatsFormat::Text)]
+    format: StatsFormat,
+
+    /// 度数分布の区間の幅。
+    #[arg(long, default_value_t = 20, value_parser = clap::value_parser!(Score).range(1..))]
+    bin_width: Score,
+
+    #[arg(long, default_value_t = 0, value_parser = parse_int::parse::<u16>)]
+    state_min: u16,
+
+    #[arg(long, default_value_t = u16::MAX, value_parser = parse_int::parse::<u16>)]
+    state_max: u16,
+
+    #[arg(long, default_value_t = 39, value_parser = parse_int::parse::<usize>)]
+    inc_timing_min: usize,
+
+    #[arg(long, default_value_t = 40, value_parser = parse_int::parse::<usize>)]
+    inc_timing_max: usize,
+
+    #[arg(long, default_value_t = 0, value_parser = parse_int::parse::<u8>)]
+    counter_min: u8,
+
+    #[arg(long, default_value_t = u8::MAX, value_parser = parse_int::parse::<u8>)]
+    counter_max: u8,
+
+    /// metric が exact, approx の場合に使用するソルバー。
+    /// exact には exact, bound, many, parallel, aspiration、approx には beam, mcts を指定する。
+    #[arg(long, default_value_t = SolverKind::Many)]
+    solver: SolverKind,
+
+    /// DP テーブルに用いるメモリ量の上限 (MiB)。各スレッドに等分される。
+    #[arg(long, default_value_t = 1024)]
+    table_mib: usize,
+
+    /// metric が exact, approx の場合にソルバーの枝刈りに用いるスコア上界 (simple, separation)。
+    #[arg(long, default_value_t = ScoreBound::Separation)]
+    score_bound: ScoreBound,
+
+    /// ワーカースレッド数。
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let stats = match &cli.results_db {
+        Some(path) => ScoreStats::from_results_db(&ResultsDb::read(path)?),
+        None => {
+            let config = SolveConfig {
+                table_bytes: cli.table_mib << 20,
+                score_bound: cli.score_bound,
+                ..Default::default()
+            };
+            collect_score_stats(
+                cli.metric,
+                cli.solver,
+                &config,
+                cli.state_min..=cli.state_max,
+                cli.counter_min..=cli.counter_max,
+                cli.inc_timing_min..=cli.inc_timing_max,
+                cli.threads,
+            )?
+        }
+    };
+
+    stats.write(std::io::stdout().lock(), cli.format, cli.bin_width)?;
+
+    Ok(())
+}
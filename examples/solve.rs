@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Context as _;
 use clap::Parser;
@@ -8,6 +9,75 @@ use samegame_sfc_small::*;
 /// SFC『鮫亀』さめがめ「かんたん」モードの問題に対する最大スコア手順を求める。
 #[derive(Debug, Parser)]
 struct Cli {
+    /// 使用するソルバー (exact, bound, many, parallel, beam, mcts, aspiration)。
+    #[arg(long, default_value_t = SolverKind::Exact)]
+    solver: SolverKind,
+
+    /// 探索に用いるスレッド数 (parallel ソルバーのみ)。
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+
+    /// DP テーブルに用いるメモリ量の上限 (MiB)。
+    #[arg(long, default_value_t = 8192)]
+    table_mib: usize,
+
+    /// DP テーブル満杯時の動作 (fail, replace)。
+    #[arg(long, default_value_t = TableFullPolicy::Replace)]
+    table_full: TableFullPolicy,
+
+    /// ハッシュ衝突の検出方法 (none, key128, board)。
+    #[arg(long, default_value_t = CollisionCheck::None)]
+    collision_check: CollisionCheck,
+
+    /// 枝刈りに用いるスコア上界 (simple, separation。bound, many, aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = ScoreBound::Simple)]
+    score_bound: ScoreBound,
+
+    /// 可換な着手の順序を入れ替えただけの手順も重複して探索する (beam, mcts 以外のソルバーのみ。k-best 探索では無視される)。
+    #[arg(long)]
+    no_partial_order_reduction: bool,
+
+    /// 目標スコアの下げ方 (正整数で下げ幅を指定、または bisect。aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = AspirationSchedule::default())]
+    aspiration: AspirationSchedule,
+
+    /// ビーム幅 (beam ソルバーのみ)。上限を指定した場合は初期値となり、上限まで倍々に広げる。
+    #[arg(long, default_value_t = 1000)]
+    beam_width: usize,
+
+    /// ビームサーチの評価関数 (score, upper-bound, components)。
+    #[arg(long, default_value_t = BeamEval::UpperBound)]
+    beam_eval: BeamEval,
+
+    /// 1 つの盤面の探索にかける時間の上限 (秒, beam, mcts ソルバーのみ)。
+    #[arg(long)]
+    time_limit: Option<f64>,
+
+    /// 1 つの盤面の探索で展開するノード数の上限 (beam ソルバーのみ)。
+    #[arg(long)]
+    node_limit: Option<u64>,
+
+    /// 乱数のシード (mcts ソルバーのみ)。
+    #[arg(long, default_value_t = 0)]
+    mcts_seed: u64,
+
+    /// 1 つの盤面に対して行うプレイアウトの回数 (mcts ソルバーのみ)。
+    #[arg(long, default_value_t = 10000)]
+    mcts_playouts: u64,
+
+    /// プレイアウトの方策 (random, tabu-color)。
+    #[arg(long, default_value_t = PlayoutPolicy::TabuColor)]
+    mcts_policy: PlayoutPolicy,
+
+    /// 全ての最適解を出力する (独立な着手の順序のみが異なる手順は同一視する)。
+    /// `--solver` の指定は無視し、全探索 + メモ化を用いる。
+    #[arg(long)]
+    all: bool,
+
+    /// 相異なる上位 k 個のスコアとそれぞれの手順を出力する。`--solver` の指定は無視する (DP テーブルの設定には従う)。
+    #[arg(long, conflicts_with = "all")]
+    k_best: Option<usize>,
+
     path_problem: PathBuf,
 }
 
@@ -20,7 +90,55 @@ fn main() -> anyhow::Result<()> {
         .parse()
         .with_context(|| format!("問題ファイル {} のパースに失敗", cli.path_problem.display()))?;
 
-    let (score, solution) = solve_problem(board);
+    let config = SolveConfig {
+        thread_count: cli.threads,
+        table_bytes: cli.table_mib << 20,
+        table_full_policy: cli.table_full,
+        collision_check: cli.collision_check,
+        score_bound: cli.score_bound,
+        partial_order_reduction: !cli.no_partial_order_reduction,
+        aspiration: cli.aspiration,
+        beam: BeamConfig {
+            width: cli.beam_width,
+            eval: cli.beam_eval,
+            time_limit: cli.time_limit.map(Duration::from_secs_f64),
+            node_limit: cli.node_limit,
+        },
+        mcts: MctsConfig {
+            seed: cli.mcts_seed,
+            playout_count: cli.mcts_playouts,
+            time_limit: cli.time_limit.map(Duration::from_secs_f64),
+            policy: cli.mcts_policy,
+        },
+        ..Default::default()
+    };
+
+    if cli.all {
+        let dag = solve_problem_all(board, &config);
+        eprintln!("solution count: {}", dag.solution_count());
+        for solution in dag.solutions() {
+            println!("{}\t{solution}", dag.score());
+        }
+        return Ok(());
+    }
+
+    if let Some(k) = cli.k_best {
+        for (score, solution) in solve_problem_k_best_with(board, k, &config) {
+            println!("{score}\t{solution}");
+        }
+        return Ok(());
+    }
+
+    cli.solver.ensure_supported(&config)?;
+    let mut solver = cli.solver.new_solver(&config);
+    let (score, solution) = solver.solve(board).map_or_else(
+        || (0, ActionHistory::new()),
+        |res| {
+            eprintln!("node count: {}", res.node_count());
+            eprintln!("collision count: {}", res.table_stats().collision_count());
+            (res.score(), res.into_solution())
+        },
+    );
 
     println!("{score}\t{solution}");
 
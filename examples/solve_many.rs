@@ -1,9 +1,16 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::Parser;
 
 use samegame_sfc_small::*;
 
 #[derive(Debug, Parser)]
 struct Cli {
+    /// 使用するソルバー (exact, bound, many, parallel, beam, mcts, aspiration)。
+    #[arg(long, default_value_t = SolverKind::Many)]
+    solver: SolverKind,
+
     #[arg(long, default_value_t = 39, value_parser = parse_int::parse::<usize>)]
     inc_timing_min: usize,
 
@@ -18,6 +25,74 @@ struct Cli {
 
     #[arg(long, default_value_t = 0, value_parser = parse_int::parse::<Score>)]
     best_score_ini: Score,
+
+    /// DP テーブルに用いるメモリ量の上限 (MiB)。全ワーカースレッドの合計で、各スレッドに等分される。
+    #[arg(long, default_value_t = 8192)]
+    table_mib: usize,
+
+    /// DP テーブル満杯時の動作 (fail, replace)。
+    #[arg(long, default_value_t = TableFullPolicy::Replace)]
+    table_full: TableFullPolicy,
+
+    /// ハッシュ衝突の検出方法 (none, key128, board)。
+    #[arg(long, default_value_t = CollisionCheck::None)]
+    collision_check: CollisionCheck,
+
+    /// 枝刈りに用いるスコア上界 (simple, separation。bound, many, aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = ScoreBound::Simple)]
+    score_bound: ScoreBound,
+
+    /// 可換な着手の順序を入れ替えただけの手順も重複して探索する (beam, mcts 以外のソルバーのみ)。
+    #[arg(long)]
+    no_partial_order_reduction: bool,
+
+    /// 目標スコアの下げ方 (正整数で下げ幅を指定、または bisect。aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = AspirationSchedule::default())]
+    aspiration: AspirationSchedule,
+
+    /// ビーム幅 (beam ソルバーのみ)。上限を指定した場合は初期値となり、上限まで倍々に広げる。
+    #[arg(long, default_value_t = 1000)]
+    beam_width: usize,
+
+    /// ビームサーチの評価関数 (score, upper-bound, components)。
+    #[arg(long, default_value_t = BeamEval::UpperBound)]
+    beam_eval: BeamEval,
+
+    /// 1 つの盤面の探索にかける時間の上限 (秒, beam, mcts ソルバーのみ)。
+    #[arg(long)]
+    time_limit: Option<f64>,
+
+    /// 1 つの盤面の探索で展開するノード数の上限 (beam ソルバーのみ)。
+    #[arg(long)]
+    node_limit: Option<u64>,
+
+    /// 乱数のシード (mcts ソルバーのみ)。
+    #[arg(long, default_value_t = 0)]
+    mcts_seed: u64,
+
+    /// 1 つの盤面に対して行うプレイアウトの回数 (mcts ソルバーのみ)。
+    #[arg(long, default_value_t = 10000)]
+    mcts_playouts: u64,
+
+    /// プレイアウトの方策 (random, tabu-color)。
+    #[arg(long, default_value_t = PlayoutPolicy::TabuColor)]
+    mcts_policy: PlayoutPolicy,
+
+    /// ワーカースレッド数。スレッドを増やすと、1 スレッドあたりの DP テーブルの容量は減る。
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+
+    /// チェックポイントファイル。存在すればそこから再開し、探索中も定期的に保存する。
+    #[arg(long)]
+    checkpoint: Option<PathBuf>,
+
+    /// チェックポイントの保存間隔 (秒)。
+    #[arg(long, default_value_t = 600.0)]
+    checkpoint_interval: f64,
+
+    /// 全ての盤面の探索結果を記録するデータベースファイル。存在すれば追記する。
+    #[arg(long)]
+    results_db: Option<PathBuf>,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -27,7 +102,48 @@ fn main() -> anyhow::Result<()> {
     let counters = cli.counter_min..=cli.counter_max;
     let inc_timings = cli.inc_timing_min..=cli.inc_timing_max;
 
-    let ans = solve_problems_many(states, counters, inc_timings, cli.best_score_ini);
+    let config = SolveConfig {
+        best_score_ini: cli.best_score_ini,
+        table_bytes: cli.table_mib << 20,
+        table_full_policy: cli.table_full,
+        collision_check: cli.collision_check,
+        score_bound: cli.score_bound,
+        partial_order_reduction: !cli.no_partial_order_reduction,
+        aspiration: cli.aspiration,
+        beam: BeamConfig {
+            width: cli.beam_width,
+            eval: cli.beam_eval,
+            time_limit: cli.time_limit.map(Duration::from_secs_f64),
+            node_limit: cli.node_limit,
+        },
+        mcts: MctsConfig {
+            seed: cli.mcts_seed,
+            playout_count: cli.mcts_playouts,
+            time_limit: cli.time_limit.map(Duration::from_secs_f64),
+            policy: cli.mcts_policy,
+        },
+        ..Default::default()
+    };
+    let options = SweepOptions {
+        checkpoint: cli.checkpoint.map(|path| CheckpointConfig {
+            path,
+            interval: Duration::from_secs_f64(cli.checkpoint_interval),
+        }),
+        results_db: cli.results_db,
+    };
+    let ans = solve_problems_many_with_options(
+        cli.solver,
+        &config,
+        states,
+        counters,
+        inc_timings,
+        cli.threads,
+        &options,
+    )?;
+    let Some(ans) = ans else {
+        eprintln!("NO SOLUTION");
+        return Ok(());
+    };
 
     println!(
         "{}\t0x{:04X}\t0x{:02X}\t{}\t{}",
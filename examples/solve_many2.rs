@@ -25,6 +25,34 @@ struct Cli {
 
     #[arg(long, default_value_t = 0)]
     best_score_ini: Score,
+
+    /// DP テーブルに用いるメモリ量の上限 (MiB)。
+    #[arg(long, default_value_t = 8192)]
+    table_mib: usize,
+
+    /// DP テーブル満杯時の動作 (fail, replace)。
+    #[arg(long, default_value_t = TableFullPolicy::Replace)]
+    table_full: TableFullPolicy,
+
+    /// ハッシュ衝突の検出方法 (none, key128, board)。
+    #[arg(long, default_value_t = CollisionCheck::None)]
+    collision_check: CollisionCheck,
+
+    /// 枝刈りに用いるスコア上界 (simple, separation。bound, many, aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = ScoreBound::Simple)]
+    score_bound: ScoreBound,
+
+    /// 可換な着手の順序を入れ替えただけの手順も重複して探索する (beam, mcts 以外のソルバーのみ)。
+    #[arg(long)]
+    no_partial_order_reduction: bool,
+
+    /// 目標スコアの下げ方 (正整数で下げ幅を指定、または bisect。aspiration ソルバーのみ)。
+    #[arg(long, default_value_t = AspirationSchedule::default())]
+    aspiration: AspirationSchedule,
+
+    /// 使用するソルバー (exact, bound, many, parallel, beam, mcts, aspiration)。
+    #[arg(long, default_value_t = SolverKind::Bound)]
+    solver: SolverKind,
 }
 
 fn make_range<T: PartialOrd>(min: T, max: T) -> std::ops::RangeInclusive<T> {
@@ -39,7 +67,18 @@ fn main() -> anyhow::Result<()> {
     let counters = make_range(cli.counter_min, cli.counter_max);
     let inc_timings = make_range(cli.inc_timing_min, cli.inc_timing_max);
 
-    let mut solver = Solver::new(cli.best_score_ini);
+    let config = SolveConfig {
+        best_score_ini: cli.best_score_ini,
+        table_bytes: cli.table_mib << 20,
+        table_full_policy: cli.table_full,
+        collision_check: cli.collision_check,
+        score_bound: cli.score_bound,
+        partial_order_reduction: !cli.no_partial_order_reduction,
+        aspiration: cli.aspiration,
+        ..Default::default()
+    };
+    cli.solver.ensure_supported(&config)?;
+    let mut solver = cli.solver.new_solver(&config);
     let mut best_ans: Option<Answer> = None;
 
     for (state, counter, inc_timing) in itertools::iproduct!(states, counters, inc_timings) {
@@ -50,7 +89,9 @@ fn main() -> anyhow::Result<()> {
 
         eprintln!("Search: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing}");
 
-        if let Some((score, solution)) = solver.solve(board) {
+        if let Some(res) = solver.solve(board) {
+            let score = res.score();
+            let solution = res.into_solution();
             eprintln!("Found {score}: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing} solution={solution}");
             best_ans.replace(Answer {
                 state,
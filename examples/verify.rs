@@ -0,0 +1,75 @@
+use std::io::BufRead as _;
+use std::path::PathBuf;
+
+use anyhow::{bail, ensure, Context as _};
+use clap::Parser;
+
+use samegame_sfc_small::*;
+
+/// `solve_many`, `solve_many2` が出力した解を盤面上で再生し、スコアが正しいか検証する。
+///
+/// 入力の各行は `スコア\t乱数状態\tカウンタ\tinc_timing\t手順` の形式。
+#[derive(Debug, Parser)]
+struct Cli {
+    /// 入力ファイル (省略時は標準入力)。
+    path_input: Option<PathBuf>,
+}
+
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let input: Box<dyn std::io::BufRead> = match &cli.path_input {
+        Some(path) => Box::new(std::io::BufReader::new(
+            std::fs::File::open(path)
+                .with_context(|| format!("入力ファイル {} を開けない", path.display()))?,
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    };
+
+    let mut ng_count = 0;
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let lineno = i + 1;
+        match verify_line(&line).with_context(|| format!("{lineno} 行目"))? {
+            Ok(score) => println!("OK\t{score}\t{line}"),
+            Err(msg) => {
+                println!("NG\t{msg}\t{line}");
+                ng_count += 1;
+            }
+        }
+    }
+
+    ensure!(ng_count == 0, "{ng_count} 個の解が検証に失敗した");
+
+    Ok(())
+}
+
+/// 1 行を検証する。
+///
+/// 行の形式が不正なら外側の `Err`、検証に失敗したら内側の `Err` を返す。
+fn verify_line(line: &str) -> anyhow::Result<Result<Score, String>> {
+    let fields: Vec<_> = line.split('\t').collect();
+    ensure!(fields.len() == 5, "フィールド数が 5 でない: '{line}'");
+
+    let score: Score = parse_int::parse(fields[0]).context("スコアのパースに失敗")?;
+    let state: u16 = parse_int::parse(fields[1]).context("乱数状態のパースに失敗")?;
+    let counter: u8 = parse_int::parse(fields[2]).context("カウンタのパースに失敗")?;
+    let inc_timing: usize = parse_int::parse(fields[3]).context("inc_timing のパースに失敗")?;
+    let solution: ActionHistory = fields[4].parse().context("手順のパースに失敗")?;
+
+    let Some(board) = GameRng::new(state).gen_board(counter, inc_timing) else {
+        bail!("再生成判定に引っ掛かる盤面: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing}");
+    };
+
+    let res = match verify(board, &solution) {
+        Ok(score_actual) if score_actual == score => Ok(score),
+        Ok(score_actual) => Err(format!("スコアが一致しない (実際は {score_actual})")),
+        Err(e) => Err(e.to_string()),
+    };
+
+    Ok(res)
+}
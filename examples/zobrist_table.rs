@@ -1,6 +1,8 @@
 //! zobrist hash 用テーブルを生成する (`zobrist.rs` 内で `include!` する)。
 //!
 //! `once_cell` などを使うと若干オーバーヘッドが生じるので...。
+//!
+//! テーブルは盤面サイズ・駒種数の上限に合わせて作る。
 
 use std::fs::File;
 use std::io::{BufWriter, Write as _};
@@ -21,22 +23,42 @@ fn main() -> anyhow::Result<()> {
 }
 
 fn make_table_board(path: impl AsRef<Path>, rng: &mut impl Rng) -> anyhow::Result<()> {
+    // 既存の 8x6 盤面 (5 駒種) のキーを従来と同じ順序 (駒種、列、行の順) で先に生成し、
+    // 残りのマスのキーはその後に生成する。これにより 8x6 盤面のハッシュ値は従来から変わらない。
+    const EASY: (usize, usize, usize) = (5, 8, 6);
+    let is_easy =
+        |piece: usize, col: usize, row: usize| piece < EASY.0 && col < EASY.1 && row < EASY.2;
+
+    let mut table = [[[0; ROW_NUM_MAX]; COL_NUM_MAX]; PIECE_NUM_MAX];
+    let slots_easy = itertools::iproduct!(0..EASY.0, 0..EASY.1, 0..EASY.2);
+    let slots_rest = itertools::iproduct!(0..PIECE_NUM_MAX, 0..COL_NUM_MAX, 0..ROW_NUM_MAX)
+        .filter(|&(piece, col, row)| !is_easy(piece, col, row));
+    for (piece, col, row) in slots_easy.chain(slots_rest) {
+        let key: Key = rng.gen();
+        table[piece][col][row] = key;
+    }
+
     let mut wtr = create_file(path)?;
 
-    write!(wtr, "PieceArray::new([")?;
+    write!(wtr, "[")?;
+
+    for table_piece in &table {
+        write!(wtr, "[")?;
+
+        for table_col in table_piece {
+            write!(wtr, "[")?;
 
-    for _ in 0..Piece::NUM {
-        write!(wtr, "SquareArray::new([")?;
+            for key in table_col {
+                write!(wtr, "0x{key:016X},")?;
+            }
 
-        for _ in 0..Square::NUM {
-            let key: Key = rng.gen();
-            write!(wtr, "0x{key:016X},")?;
+            write!(wtr, "],")?;
         }
 
-        write!(wtr, "]),")?;
+        write!(wtr, "],")?;
     }
 
-    write!(wtr, "])")?;
+    write!(wtr, "]")?;
 
     Ok(())
 }
@@ -6,20 +6,20 @@ use arrayvec::ArrayVec;
 use crate::board::{Board, MaskBoard};
 use crate::hint::*;
 use crate::piece::Piece;
-use crate::square::{Col, Row, Square};
+use crate::square::{Square, SQUARE_NUM_MAX};
 
 /// 着手。
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Action {
-    piece: Piece,
-    mb: MaskBoard,
+pub struct Action<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    piece: Piece<P>,
+    mb: MaskBoard<W, H>,
 }
 
-impl Action {
+impl<const W: usize, const H: usize, const P: usize> Action<W, H, P> {
     /// 駒種と盤面マスクを指定して着手を作る。
     ///
     /// `mb` は 2 つ以上のマスを含んでいなければならない。
-    pub fn new(piece: Piece, mb: MaskBoard) -> Self {
+    pub fn new(piece: Piece<P>, mb: MaskBoard<W, H>) -> Self {
         unsafe { assert_unchecked!(mb.square_count() >= 2) }
 
         Self { piece, mb }
@@ -28,7 +28,7 @@ impl Action {
     /// 盤面とマスを指定して着手を作る。
     ///
     /// `board` のマス `sq` には駒があり、かつ同種の駒が繋がっていなければならない。
-    pub fn from_board_square(board: &Board, sq: Square) -> Self {
+    pub fn from_board_square(board: &Board<W, H, P>, sq: Square<W, H>) -> Self {
         let piece = board.get(sq).expect("盤面のマス {sq} に駒がない");
 
         let mb = board.piece_mask(piece).flood_fill(sq);
@@ -41,12 +41,12 @@ impl Action {
     }
 
     /// 駒種を返す。
-    pub fn piece(&self) -> Piece {
+    pub fn piece(&self) -> Piece<P> {
         self.piece
     }
 
     /// 盤面マスクを返す。
-    pub fn mask(&self) -> &MaskBoard {
+    pub fn mask(&self) -> &MaskBoard<W, H> {
         &self.mb
     }
 
@@ -56,24 +56,42 @@ impl Action {
     }
 
     /// この着手により消える駒を含む最小のマスを返す。
-    pub fn least_square(&self) -> Square {
+    pub fn least_square(&self) -> Square<W, H> {
         unsafe { self.mb.least_square_unchecked() }
     }
+
+    /// 盤面 `board` において、この着手と `other` が可換であることを盤面マスクのみから判定する。
+    ///
+    /// 両者の列が重複も隣接もせず、いずれも列を消滅させないならば、どちらを先に行っても
+    /// 互いの駒群は変わらず、同じ盤面に至る。これは十分条件であり、可換な組を全て検出するわけではない。
+    pub fn commutes_with(&self, other: &Self, board: &Board<W, H, P>) -> bool {
+        col_masks_apart(self.mb.col_mask(), other.mb.col_mask())
+            && !board.erase_empties_col(&self.mb)
+            && !board.erase_empties_col(&other.mb)
+    }
+}
+
+/// 2 つの列集合 (bit i が列 i+1 に対応) が重複も隣接もしないかどうかを返す。
+pub(crate) fn col_masks_apart(lhs: u32, rhs: u32) -> bool {
+    lhs & (rhs | (rhs << 1) | (rhs >> 1)) == 0
 }
 
-const HISTORY_CAP: usize = Col::NUM * Row::NUM / 2;
+/// 着手履歴の容量。1 手で 2 個以上の駒が消えるので、どの盤面サイズでもこれで足りる。
+const HISTORY_CAP: usize = SQUARE_NUM_MAX / 2;
 
 /// 着手履歴。
 #[repr(transparent)]
-#[derive(Clone, Debug, Default, Eq, PartialEq)]
-pub struct ActionHistory(ArrayVec<Square, HISTORY_CAP>);
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ActionHistory<const W: usize = 8, const H: usize = 6>(
+    ArrayVec<Square<W, H>, HISTORY_CAP>,
+);
 
-impl ActionHistory {
+impl<const W: usize, const H: usize> ActionHistory<W, H> {
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn as_slice(&self) -> &[Square] {
+    pub fn as_slice(&self) -> &[Square<W, H>] {
         self.0.as_slice()
     }
 
@@ -85,14 +103,14 @@ impl ActionHistory {
         self.0.is_empty()
     }
 
-    pub fn push(&mut self, sq: Square) {
+    pub fn push(&mut self, sq: Square<W, H>) {
         self.0.push(sq);
     }
 
     /// # Safety
     ///
     /// 容量オーバーしてはならない。
-    pub unsafe fn push_unchecked(&mut self, sq: Square) {
+    pub unsafe fn push_unchecked(&mut self, sq: Square<W, H>) {
         self.0.push_unchecked(sq);
     }
 
@@ -112,45 +130,46 @@ impl ActionHistory {
     }
 }
 
-impl std::iter::FromIterator<Square> for ActionHistory {
-    fn from_iter<I: IntoIterator<Item = Square>>(sqs: I) -> Self {
+impl<const W: usize, const H: usize> std::iter::FromIterator<Square<W, H>> for ActionHistory<W, H> {
+    fn from_iter<I: IntoIterator<Item = Square<W, H>>>(sqs: I) -> Self {
         Self(ArrayVec::from_iter(sqs))
     }
 }
 
-impl std::iter::IntoIterator for ActionHistory {
-    type Item = Square;
-    type IntoIter = <ArrayVec<Square, HISTORY_CAP> as IntoIterator>::IntoIter;
+impl<const W: usize, const H: usize> std::iter::IntoIterator for ActionHistory<W, H> {
+    type Item = Square<W, H>;
+    type IntoIter = <ArrayVec<Square<W, H>, HISTORY_CAP> as IntoIterator>::IntoIter;
 
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
     }
 }
 
-impl<'a> std::iter::IntoIterator for &'a ActionHistory {
-    type Item = &'a Square;
-    type IntoIter = std::slice::Iter<'a, Square>;
+impl<'a, const W: usize, const H: usize> std::iter::IntoIterator for &'a ActionHistory<W, H> {
+    type Item = &'a Square<W, H>;
+    type IntoIter = std::slice::Iter<'a, Square<W, H>>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.as_slice().iter()
     }
 }
 
-impl std::str::FromStr for ActionHistory {
+impl<const W: usize, const H: usize> std::str::FromStr for ActionHistory<W, H> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let cap = W * H / 2;
         let tokens: Vec<_> = s.split_ascii_whitespace().collect();
         ensure!(
-            tokens.len() <= HISTORY_CAP,
-            "着手履歴は {HISTORY_CAP} 手以下でなければならない"
+            tokens.len() <= cap,
+            "着手履歴は {cap} 手以下でなければならない"
         );
 
         tokens.into_iter().map(str::parse).collect()
     }
 }
 
-impl std::fmt::Display for ActionHistory {
+impl<const W: usize, const H: usize> std::fmt::Display for ActionHistory<W, H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         for (i, &sq) in self.iter().enumerate() {
             if i != 0 {
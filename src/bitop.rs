@@ -1,6 +1,6 @@
 //! 各種ビット演算。
 
-use std::arch::x86_64::_pext_u32;
+use std::sync::atomic::{AtomicU8, Ordering};
 
 /// 最下位ビットを分離する。たとえば `0b110100` に対しては `0b000100` を返す。
 /// 引数が 0 の場合、0 を返す。
@@ -13,9 +13,205 @@ pub fn u32_one_indexs(x: u32) -> U32OneIndexs {
     U32OneIndexs(x)
 }
 
-/// PEXT 命令。
+/// PEXT 命令相当の演算。実装は実行時に `PextImpl::current()` で選択される。
+///
+/// `mask` を下位から 3bit ずつ区切ったとき、各グループ (bit30-31 は 2bit のグループとみなす) は
+/// 全て 0 または全て 1 でなければならない (`PextImpl::Column` はこれを前提とする)。
 pub fn u32_pext(x: u32, mask: u32) -> u32 {
-    unsafe { _pext_u32(x, mask) }
+    debug_assert!(
+        is_cells3_mask(mask),
+        "u32_pext: mask が 3bit 単位になっていない: 0b{mask:032b}"
+    );
+
+    match PextImpl::current() {
+        #[cfg(target_arch = "x86_64")]
+        PextImpl::Hardware => unsafe { u32_pext_hardware(x, mask) },
+        #[cfg(not(target_arch = "x86_64"))]
+        PextImpl::Hardware => unreachable!(),
+        PextImpl::Column => u32_pext_cells3(x, mask),
+        PextImpl::Software => u32_pext_software(x, mask),
+    }
+}
+
+/// PEXT 命令。
+///
+/// # Safety
+///
+/// CPU が BMI2 命令に対応していなければならない。
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "bmi2")]
+unsafe fn u32_pext_hardware(x: u32, mask: u32) -> u32 {
+    std::arch::x86_64::_pext_u32(x, mask)
+}
+
+/// PEXT のソフトウェア実装 (任意のマスクに対応)。
+pub(crate) fn u32_pext_software(x: u32, mask: u32) -> u32 {
+    let mut res = 0;
+    let mut bit_out = 1;
+    let mut mask = mask;
+    while mask != 0 {
+        let bit_in = u32_blsi(mask);
+        if (x & bit_in) != 0 {
+            res |= bit_out;
+        }
+        mask ^= bit_in;
+        bit_out <<= 1;
+    }
+
+    res
+}
+
+/// 3bit 単位のマスクに対する PEXT。シフトとマスクのみで列の詰め直しを行う。
+fn u32_pext_cells3(x: u32, mask: u32) -> u32 {
+    // 消すグループの最下位ビットを上から順に処理し、それより上の部分を 3bit ずつ下にずらす。
+    // 上から処理するので、未処理のグループの位置はずれない。
+    // (最上位の 2bit グループを消す場合、それより上には何もないのでずらしても問題ない)
+    let mut res = x & mask;
+    let mut holes = !mask & CELLS3_LSB;
+    while holes != 0 {
+        let i = 31 - holes.leading_zeros();
+        holes &= !(1 << i);
+
+        let low = (1 << i) - 1;
+        res = (res & low) | ((res >> 3) & !low);
+    }
+
+    res
+}
+
+/// `mask` の各 3bit グループの最下位ビット。
+const CELLS3_LSB: u32 = 0b01_001_001_001_001_001_001_001_001_001_001;
+
+/// `mask` の各 3bit グループ (bit30-31 は 2bit) が全て 0 または全て 1 かどうかを返す。
+fn is_cells3_mask(mask: u32) -> bool {
+    (mask & CELLS3_LSB).wrapping_mul(0b111) == mask
+}
+
+/// `u32_pext()` の実装方式。
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PextImpl {
+    /// BMI2 の PEXT 命令。
+    Hardware = 1,
+    /// シフトとマスクによる列の詰め直し。
+    Column = 2,
+    /// 汎用のソフトウェア PEXT。
+    Software = 3,
+}
+
+/// 選択中の `PextImpl`。0 は未選択を表す。
+static PEXT_IMPL: AtomicU8 = AtomicU8::new(0);
+
+#[cfg(test)]
+thread_local! {
+    /// テスト中、このスレッドでのみ用いる `PextImpl` (`PextImpl::with()` を参照)。
+    static PEXT_IMPL_OVERRIDE: std::cell::Cell<Option<PextImpl>> = const { std::cell::Cell::new(None) };
+}
+
+impl PextImpl {
+    /// 全ての実装方式を列挙する。
+    pub fn all() -> impl ExactSizeIterator<Item = Self> + Clone {
+        [Self::Hardware, Self::Column, Self::Software].into_iter()
+    }
+
+    /// 現在の CPU で使用可能かどうかを返す。
+    pub fn is_supported(self) -> bool {
+        match self {
+            Self::Hardware => has_bmi2(),
+            Self::Column | Self::Software => true,
+        }
+    }
+
+    /// 現在の CPU に最適な実装方式を返す。
+    ///
+    /// BMI2 に対応していても PEXT がマイクロコード実装で遅い CPU (Zen3 より前の AMD) では
+    /// `Column` を選ぶ。
+    pub fn detect() -> Self {
+        if has_bmi2() && !pext_is_slow() {
+            Self::Hardware
+        } else {
+            Self::Column
+        }
+    }
+
+    /// 現在選択されている実装方式を返す。初回は `detect()` の結果を選択する。
+    pub fn current() -> Self {
+        #[cfg(test)]
+        if let Some(imp) = PEXT_IMPL_OVERRIDE.get() {
+            return imp;
+        }
+
+        match PEXT_IMPL.load(Ordering::Relaxed) {
+            1 => Self::Hardware,
+            2 => Self::Column,
+            3 => Self::Software,
+            _ => {
+                let imp = Self::detect();
+                PEXT_IMPL.store(imp as u8, Ordering::Relaxed);
+                imp
+            }
+        }
+    }
+
+    /// 現在のスレッドでのみこの実装方式を選択して `f` を実行する (テスト用)。
+    ///
+    /// 他のスレッドで並行して動くテストの選択には影響しない。
+    /// 現在の CPU で使用できない場合は panic する。
+    #[cfg(test)]
+    pub(crate) fn with<R>(self, f: impl FnOnce() -> R) -> R {
+        assert!(
+            self.is_supported(),
+            "PEXT の実装方式 {self:?} はこの CPU では使用できない"
+        );
+
+        let prev = PEXT_IMPL_OVERRIDE.replace(Some(self));
+        let res = f();
+        PEXT_IMPL_OVERRIDE.set(prev);
+
+        res
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn has_bmi2() -> bool {
+    std::arch::is_x86_feature_detected!("bmi2")
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn has_bmi2() -> bool {
+    false
+}
+
+/// PEXT 命令がマイクロコード実装かどうかを返す (Zen3 より前の AMD CPU)。
+#[cfg(target_arch = "x86_64")]
+fn pext_is_slow() -> bool {
+    use std::arch::x86_64::__cpuid;
+
+    let leaf0 = __cpuid(0);
+    let vendor: Vec<u8> = [leaf0.ebx, leaf0.edx, leaf0.ecx]
+        .into_iter()
+        .flat_map(u32::to_le_bytes)
+        .collect();
+    if vendor != b"AuthenticAMD" {
+        return false;
+    }
+
+    let leaf1 = __cpuid(1);
+    let family_base = (leaf1.eax >> 8) & 0xF;
+    let family_ext = (leaf1.eax >> 20) & 0xFF;
+    let family = if family_base == 0xF {
+        family_base + family_ext
+    } else {
+        family_base
+    };
+
+    // Zen3 は family 19h。
+    family < 0x19
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn pext_is_slow() -> bool {
+    false
 }
 
 #[repr(transparent)]
@@ -46,3 +242,35 @@ impl Iterator for U32OneIndexs {
 impl ExactSizeIterator for U32OneIndexs {}
 
 impl std::iter::FusedIterator for U32OneIndexs {}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use super::*;
+
+    #[test]
+    fn test_u32_pext_impls() {
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for _ in 0..10000 {
+            let x: u32 = rng.gen();
+            let cells: u32 = rng.gen_range(0..1 << 10);
+            let mask = (0..10)
+                .filter(|i| (cells & (1 << i)) != 0)
+                .map(|i| 0b111 << (3 * i))
+                .fold(
+                    if rng.gen() { 0b11 << 30 } else { 0 },
+                    std::ops::BitOr::bitor,
+                );
+
+            let expect = u32_pext_software(x, mask);
+            assert_eq!(u32_pext_cells3(x, mask), expect);
+            assert_eq!(u32_pext(x, mask), expect);
+            #[cfg(target_arch = "x86_64")]
+            if PextImpl::Hardware.is_supported() {
+                assert_eq!(unsafe { u32_pext_hardware(x, mask) }, expect);
+            }
+        }
+    }
+}
@@ -6,18 +6,29 @@ use anyhow::{bail, ensure};
 
 use crate::bitop;
 use crate::hint::assert_unchecked;
-use crate::piece::Piece;
+use crate::piece::{Piece, PIECE_NUM_MAX};
 use crate::square::{Col, ColArray, Row, RowArray, Square};
 
 /// bitboard の列。
 ///
-/// 1 マス 3bit。
+/// 1 マス 3bit で、下位から `H` 行分のマスを持つ (`H <= 10` なので 32bit に収まる)。
 /// メソッドに渡すマスの値は `0b111` 以下でなければならない。
 #[repr(transparent)]
-#[derive(Clone, Copy, Eq, PartialEq)]
-struct BitCol(u32);
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+struct BitCol<const H: usize>(u32);
+
+impl<const H: usize> BitCol<H> {
+    /// 全マスの値が `0b001` であるような `BitCol` の内部値。
+    const LSB: u32 = {
+        let mut res = 0;
+        let mut i = 0;
+        while i < H {
+            res |= 0b001 << (3 * i);
+            i += 1;
+        }
+        res
+    };
 
-impl BitCol {
     const fn zero() -> Self {
         Self(0)
     }
@@ -28,20 +39,9 @@ impl BitCol {
 
     /// 全マスの値が `value` であるような `BitCol` を返す。
     const fn broadcast(value: u8) -> Self {
-        const TABLE: [u32; 8] = [
-            0b000_000_000_000_000_000,
-            0b001_001_001_001_001_001,
-            0b010_010_010_010_010_010,
-            0b011_011_011_011_011_011,
-            0b100_100_100_100_100_100,
-            0b101_101_101_101_101_101,
-            0b110_110_110_110_110_110,
-            0b111_111_111_111_111_111,
-        ];
-
         unsafe { assert_unchecked!(Self::value_is_ok(value)) }
 
-        Self::new(TABLE[value as usize])
+        Self::new(value as u32 * Self::LSB)
     }
 
     const fn inner(self) -> u32 {
@@ -53,11 +53,11 @@ impl BitCol {
     }
 
     /// 指定した行のマスの値を返す。
-    const fn get(self, row: Row) -> u8 {
+    const fn get(self, row: Row<H>) -> u8 {
         ((self.0 >> (3 * row.to_index())) & 0b111) as u8
     }
 
-    fn set(&mut self, row: Row, value: u8) {
+    fn set(&mut self, row: Row<H>, value: u8) {
         unsafe { assert_unchecked!(Self::value_is_ok(value)) }
 
         self.0 &= !(0b111 << (3 * row.to_index()));
@@ -73,8 +73,10 @@ impl BitCol {
 
     fn enumerate(
         self,
-    ) -> impl DoubleEndedIterator<Item = (Row, u8)> + ExactSizeIterator + std::iter::FusedIterator + Clone
-    {
+    ) -> impl DoubleEndedIterator<Item = (Row<H>, u8)>
+           + ExactSizeIterator
+           + std::iter::FusedIterator
+           + Clone {
         Row::all().map(move |row| (row, self.get(row)))
     }
 
@@ -83,7 +85,7 @@ impl BitCol {
     }
 }
 
-impl std::ops::BitAnd for BitCol {
+impl<const H: usize> std::ops::BitAnd for BitCol<H> {
     type Output = Self;
 
     fn bitand(self, rhs: Self) -> Self::Output {
@@ -91,7 +93,7 @@ impl std::ops::BitAnd for BitCol {
     }
 }
 
-impl std::ops::BitOr for BitCol {
+impl<const H: usize> std::ops::BitOr for BitCol<H> {
     type Output = Self;
 
     fn bitor(self, rhs: Self) -> Self::Output {
@@ -99,7 +101,7 @@ impl std::ops::BitOr for BitCol {
     }
 }
 
-impl std::ops::BitXor for BitCol {
+impl<const H: usize> std::ops::BitXor for BitCol<H> {
     type Output = Self;
 
     fn bitxor(self, rhs: Self) -> Self::Output {
@@ -107,29 +109,29 @@ impl std::ops::BitXor for BitCol {
     }
 }
 
-impl std::ops::BitAndAssign for BitCol {
+impl<const H: usize> std::ops::BitAndAssign for BitCol<H> {
     fn bitand_assign(&mut self, rhs: Self) {
         *self = *self & rhs;
     }
 }
 
-impl std::ops::BitOrAssign for BitCol {
+impl<const H: usize> std::ops::BitOrAssign for BitCol<H> {
     fn bitor_assign(&mut self, rhs: Self) {
         *self = *self | rhs;
     }
 }
 
-impl std::ops::BitXorAssign for BitCol {
+impl<const H: usize> std::ops::BitXorAssign for BitCol<H> {
     fn bitxor_assign(&mut self, rhs: Self) {
         *self = *self ^ rhs;
     }
 }
 
-impl std::fmt::Debug for BitCol {
+impl<const H: usize> std::fmt::Debug for BitCol<H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str("0b")?;
 
-        for row in Row::all().rev() {
+        for row in Row::<H>::all().rev() {
             let value = self.get(row);
             write!(f, "_{value:03b}")?;
         }
@@ -140,27 +142,29 @@ impl std::fmt::Debug for BitCol {
 
 /// 盤面。
 ///
-/// `BitCol` を `Col::NUM` 個持っており、常に左詰めされている。
+/// 列数 `W`、行数 `H`、駒種数 `P` を型引数に持つ。デフォルトは「かんたん」モードの 8x6, 5 種。
 ///
-/// `BitCol` のマスの値は 0 が空白、`1..=5` が各駒種を表す。
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Board {
-    bcs: ColArray<BitCol>,
+/// `BitCol` を `W` 個持っており、常に左詰めされている。
+///
+/// `BitCol` のマスの値は 0 が空白、`1..=P` が各駒種を表す。
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Board<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    bcs: ColArray<BitCol<H>, W>,
     width_remain: u32,
 }
 
-impl Board {
+impl<const W: usize, const H: usize, const P: usize> Board<W, H, P> {
     const CHAR_BLANK: char = '.';
 
     /// `Board` を生成する。デバッグモードでは不変条件のチェックも行う。
-    fn new(bcs: ColArray<BitCol>, width_remain: u32) -> Self {
-        unsafe { assert_unchecked!(width_remain as usize <= Col::NUM) }
+    fn new(bcs: ColArray<BitCol<H>, W>, width_remain: u32) -> Self {
+        unsafe { assert_unchecked!(width_remain as usize <= W) }
 
         debug_assert!(
             bcs.as_array()
                 .iter()
                 .copied()
-                .all(|bc| bc.iter().all(|value| value <= Piece::MAX_VALUE)),
+                .all(|bc| bc.iter().all(|value| value <= Piece::<P>::MAX_VALUE)),
             "Board のマスの値が正しくない"
         );
 
@@ -192,7 +196,7 @@ impl Board {
     /// ゲーム内の再生成判定に引っ掛かる場合、`None` を返す。
     ///
     /// ゲーム内乱数から盤面を生成するのに使う。
-    pub fn from_piece_arrays(arrays: &ColArray<RowArray<Piece>>) -> Option<Self> {
+    pub fn from_piece_arrays(arrays: &ColArray<RowArray<Piece<P>, H>, W>) -> Option<Self> {
         let bcs = ColArray::from_fn(|col| {
             let mut bc = BitCol::zero();
             for row in Row::all() {
@@ -202,19 +206,19 @@ impl Board {
             bc
         });
 
-        let width_remain = Col::NUM as u32;
+        let width_remain = W as u32;
 
         let board = Self::new(bcs, width_remain);
 
         Piece::all()
-            .all(|piece| (board.piece_count(piece) as usize) < Square::NUM / 2)
+            .all(|piece| (board.piece_count(piece) as usize) < Square::<W, H>::NUM / 2)
             .then_some(board)
     }
 
     /// 指定したマスの駒を返す。
-    pub fn get(&self, sq: Square) -> Option<Piece> {
+    pub fn get(&self, sq: Square<W, H>) -> Option<Piece<P>> {
         let value = self.bcs[sq.col()].get(sq.row());
-        unsafe { assert_unchecked!(value <= Piece::MAX_VALUE) }
+        unsafe { assert_unchecked!(value <= Piece::<P>::MAX_VALUE) }
 
         Piece::from_inner(value)
     }
@@ -232,12 +236,12 @@ impl Board {
     /// 空でない列を昇順で列挙する。
     pub fn nonempty_cols(
         &self,
-    ) -> impl ExactSizeIterator<Item = Col> + std::iter::FusedIterator + Clone {
+    ) -> impl ExactSizeIterator<Item = Col<W>> + std::iter::FusedIterator + Clone {
         (0..self.width_remain as u8).map(|i| unsafe { Col::from_inner_unchecked(1 + i) })
     }
 
     /// 指定した駒種の数を返す。
-    pub fn piece_count(&self, piece: Piece) -> u32 {
+    pub fn piece_count(&self, piece: Piece<P>) -> u32 {
         self.piece_mask(piece).square_count()
     }
 
@@ -246,14 +250,14 @@ impl Board {
         self.nonempty_cols()
             .map(|col| {
                 let bc = self.bcs[col].0;
-                let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::broadcast(0b001).0;
+                let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
                 bc.count_ones()
             })
             .sum()
     }
 
     /// 指定した駒のみからなる盤面マスクを返す。
-    pub fn piece_mask(&self, piece: Piece) -> MaskBoard {
+    pub fn piece_mask(&self, piece: Piece<P>) -> MaskBoard<W, H> {
         // まず全体を piece の内部値で埋めた盤面との XOR をとる。
         // すると、指定した駒のあるマスのみが 0b000 である盤面が得られる。
         //
@@ -269,8 +273,8 @@ impl Board {
 
         for col in self.nonempty_cols() {
             let bc = (self.bcs[col] ^ filled).0;
-            let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::broadcast(0b001).0;
-            let bc = BitCol::new(bc) ^ BitCol::broadcast(0b001);
+            let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
+            let bc = BitCol::new(bc ^ BitCol::<H>::LSB);
             bcs[col] = bc;
             if !bc.is_zero() {
                 col_mask |= 1 << col.to_index();
@@ -283,7 +287,7 @@ impl Board {
     /// 各駒種について連結成分を列挙する。孤立駒も含むことに注意。
     pub fn piece_components(
         &self,
-    ) -> impl std::iter::FusedIterator<Item = (Piece, MaskBoard)> + Clone + '_ {
+    ) -> impl std::iter::FusedIterator<Item = (Piece<P>, MaskBoard<W, H>)> + Clone + '_ {
         Piece::all().flat_map(|piece| {
             self.piece_mask(piece)
                 .components()
@@ -291,8 +295,38 @@ impl Board {
         })
     }
 
+    /// 駒種を盤面上での初出順 (マスの昇順) に 1, 2, ... と付け替えた盤面を返す。
+    ///
+    /// 駒種の付け替えのみが異なる盤面どうしは同じ結果になる。
+    pub fn canonical(&self) -> Self {
+        // 駒種の内部値ごとの付け替え先 (0 は未出現)。
+        let mut relabel = [0_u32; PIECE_NUM_MAX + 1];
+        let mut piece_num = 0;
+
+        let mut bcs = ColArray::from_elem(BitCol::zero());
+        for col in self.nonempty_cols() {
+            // 各列の駒は下に詰まっているので、空白が現れたら打ち切ってよい。
+            let mut bc = self.bcs[col].0;
+            let mut shift = 0;
+            let mut bc_new = 0;
+            while bc != 0 {
+                let value = (bc & 0b111) as usize;
+                if relabel[value] == 0 {
+                    piece_num += 1;
+                    relabel[value] = piece_num;
+                }
+                bc_new |= relabel[value] << shift;
+                bc >>= 3;
+                shift += 3;
+            }
+            bcs[col] = BitCol::new(bc_new);
+        }
+
+        Self::new(bcs, self.width_remain)
+    }
+
     /// 与えられた盤面マスク内の全ての駒を消し、その結果を返す。
-    pub fn erase(&self, mb: &MaskBoard) -> Self {
+    pub fn erase(&self, mb: &MaskBoard<W, H>) -> Self {
         // mb の各マスの値は 0b000, 0b001 の 2 値だが、0b111 を掛けることで 0b000, 0b111 の 2 値に変換できる。
         // これの NOT をマスクとして PEXT を行えばよい。
         //
@@ -327,8 +361,135 @@ impl Board {
         Self::new(bcs, width_remain)
     }
 
+    /// `erase(erased)` を行ったとき、`mb` 内の各マスの駒の移動先を表す盤面マスクを返す。
+    ///
+    /// `mb` 内のマスには駒がなければならない。`erased` 内のマスは結果に含まれない。
+    pub fn erase_shift_mask(
+        &self,
+        erased: &MaskBoard<W, H>,
+        mb: &MaskBoard<W, H>,
+    ) -> MaskBoard<W, H> {
+        let mut bcs = mb.bcs.clone();
+        let mut erased_col_mask = 0;
+        for col in erased.nonempty_cols() {
+            let mask = !(erased.bcs[col].0 * 0b111);
+            bcs[col] = BitCol::new(bitop::u32_pext(bcs[col].0, mask));
+            if bitop::u32_pext(self.bcs[col].0, mask) == 0 {
+                erased_col_mask |= 1 << col.to_index();
+            }
+        }
+
+        let mut res = ColArray::from_elem(BitCol::zero());
+        let mut col_mask = 0;
+        let mut width_remain = 0;
+        for col in self.nonempty_cols() {
+            if (erased_col_mask & (1 << col.to_index())) != 0 {
+                continue;
+            }
+            if !bcs[col].is_zero() {
+                let col_out = unsafe { Col::from_inner_unchecked(1 + width_remain) };
+                res[col_out] = bcs[col];
+                col_mask |= 1 << width_remain;
+            }
+            width_remain += 1;
+        }
+
+        MaskBoard::new(res, col_mask)
+    }
+
+    /// `erase(mb)` により空になる列があるかどうかを返す。
+    ///
+    /// `mb` 内のマスには駒がなければならない。
+    pub fn erase_empties_col(&self, mb: &MaskBoard<W, H>) -> bool {
+        mb.nonempty_cols()
+            .any(|col| self.erase_empties_col_at(mb, col))
+    }
+
+    /// `erase(mb)` により空になる列たちを表すマスク (bit i が列 i+1 に対応) を返す。
+    ///
+    /// `mb` 内のマスには駒がなければならない。
+    pub fn erase_emptied_col_mask(&self, mb: &MaskBoard<W, H>) -> u32 {
+        mb.nonempty_cols()
+            .filter(|&col| self.erase_empties_col_at(mb, col))
+            .fold(0, |acc, col| acc | (1 << col.to_index()))
+    }
+
+    fn erase_empties_col_at(&self, mb: &MaskBoard<W, H>, col: Col<W>) -> bool {
+        // 列内の駒があるマスが全て mb に含まれるか調べる。
+        let bc = self.bcs[col].0;
+        let occupied = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
+        occupied == mb.bcs[col].0
+    }
+
+    /// 合法手があるかどうか、すなわち同種の駒が上下または左右に隣接している箇所があるかどうかを返す。
+    ///
+    /// 連結成分を求めずに、各列とそれをシフトしたもの、および隣の列との比較のみで判定する。
+    pub fn has_action(&self) -> bool {
+        // 2 つの列の各マスについて、値が等しければ 0b001、さもなくば 0b000 とした値を返す。
+        let eq = |lhs: u32, rhs: u32| {
+            let x = lhs ^ rhs;
+            !(x | (x >> 1) | (x >> 2)) & BitCol::<H>::LSB
+        };
+
+        let mut bc_prev = 0;
+        for col in self.nonempty_cols() {
+            let bc = self.bcs[col].0;
+            let occupied = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
+
+            // 駒のあるマスと値が等しい隣接マスには必ず駒がある (空白どうしの一致は除かれる)。
+            if (eq(bc, bc >> 3) | eq(bc, bc_prev)) & occupied != 0 {
+                return true;
+            }
+
+            bc_prev = bc;
+        }
+
+        false
+    }
+
+    /// 合法手に含まれるマス、すなわち同種の駒が上下または左右に隣接しているマスの集合を返す。
+    ///
+    /// `has_action()` と同様に、連結成分を求めずにシフトと比較のみで求める。
+    pub fn action_squares(&self) -> MaskBoard<W, H> {
+        // 2 つの列の各マスについて、値が等しければ 0b001、さもなくば 0b000 とした値を返す。
+        let eq = |lhs: u32, rhs: u32| {
+            let x = lhs ^ rhs;
+            !(x | (x >> 1) | (x >> 2)) & BitCol::<H>::LSB
+        };
+
+        let mut bcs = ColArray::from_elem(BitCol::zero());
+        let mut col_mask = 0;
+
+        let mut bc_prev = 0;
+        let mut col_prev = None;
+        for col in self.nonempty_cols() {
+            let bc = self.bcs[col].0;
+            let occupied = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
+
+            // 上のマスと値が等しいマス、および左のマスと値が等しいマス。
+            let eq_up = eq(bc, bc >> 3) & occupied;
+            let eq_left = eq(bc, bc_prev) & occupied;
+
+            bcs[col] |= BitCol::new(eq_up | (eq_up << 3) | eq_left);
+            if let Some(col_prev) = col_prev {
+                bcs[col_prev] |= BitCol::new(eq_left);
+            }
+
+            bc_prev = bc;
+            col_prev = Some(col);
+        }
+
+        for col in self.nonempty_cols() {
+            if !bcs[col].is_zero() {
+                col_mask |= 1 << col.to_index();
+            }
+        }
+
+        MaskBoard::new(bcs, col_mask)
+    }
+
     /// `self` と `other` で値が異なるマスの集合を表す盤面マスクを返す。
-    pub fn xor_mask(&self, other: &Self) -> MaskBoard {
+    pub fn xor_mask(&self, other: &Self) -> MaskBoard<W, H> {
         let mut bcs = ColArray::from_elem(BitCol::zero());
         let mut col_mask = 0;
 
@@ -339,7 +500,7 @@ impl Board {
         };
         for col in cols {
             let bc = (self.bcs[col] ^ other.bcs[col]).0;
-            let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::broadcast(0b001).0;
+            let bc = (bc | (bc >> 1) | (bc >> 2)) & BitCol::<H>::LSB;
             bcs[col] = BitCol::new(bc);
             if !bcs[col].is_zero() {
                 col_mask |= 1 << col.to_index();
@@ -348,35 +509,60 @@ impl Board {
 
         MaskBoard::new(bcs, col_mask)
     }
+
+    /// 盤面を 1 マス 3bit で詰めた表現の長さ (u64 単位)。
+    pub const PACKED_LEN: usize = (3 * W * H).div_ceil(64);
+
+    /// 盤面を 1 マス 3bit で詰めた表現を `out` に書き込む。
+    ///
+    /// 各列の `3 * H` bit を左の列から順に下位から連結する。盤面と表現は 1 対 1 に対応する。
+    /// `out` の長さは `Self::PACKED_LEN` でなければならない。
+    pub fn pack_into(&self, out: &mut [u64]) {
+        assert_eq!(out.len(), Self::PACKED_LEN);
+
+        out.fill(0);
+
+        let col_bits = 3 * H;
+        for col in self.nonempty_cols() {
+            let bit = col_bits * col.to_index();
+            let (i, shift) = (bit / 64, bit % 64);
+            let value = u64::from(self.bcs[col].inner());
+            out[i] |= value << shift;
+            if shift + col_bits > 64 {
+                out[i + 1] |= value >> (64 - shift);
+            }
+        }
+    }
 }
 
-impl std::str::FromStr for Board {
+impl<const W: usize, const H: usize, const P: usize> std::str::FromStr for Board<W, H, P> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let lines: Vec<_> = s.lines().collect();
         ensure!(
-            lines.len() == Row::NUM,
-            "盤面文字列はちょうど {} 行でなければならない",
-            Row::NUM
+            lines.len() == H,
+            "盤面文字列はちょうど {H} 行でなければならない"
         );
 
         let mut bcs = ColArray::from_elem(BitCol::zero());
 
-        for (row, line) in itertools::zip_eq(Row::all().rev(), lines) {
+        for (row, line) in itertools::zip_eq(Row::<H>::all().rev(), lines) {
             let chars: Vec<_> = line.chars().collect();
-            ensure!(
-                chars.len() == Col::NUM,
-                "盤面の行 {row} がちょうど {} 文字でない",
-                Col::NUM
-            );
+            ensure!(chars.len() == W, "盤面の行 {row} がちょうど {W} 文字でない");
 
-            for (col, ch) in itertools::zip_eq(Col::all(), chars) {
+            for (col, ch) in itertools::zip_eq(Col::<W>::all(), chars) {
                 let sq = Square::new(col, row);
-                let piece = match ch {
-                    Self::CHAR_BLANK => None,
-                    '1'..='5' => Some(Piece::from_inner(ch.to_digit(10).unwrap() as u8).unwrap()),
-                    _ => bail!("盤面 {sq} の文字が無効: {ch}",),
+                let piece = if ch == Self::CHAR_BLANK {
+                    None
+                } else {
+                    let piece = ch
+                        .to_digit(10)
+                        .and_then(|value| Piece::<P>::from_inner(value as u8));
+                    let Some(piece) = piece else {
+                        bail!("盤面 {sq} の文字が無効: {ch}");
+                    };
+                    Some(piece)
                 };
                 let value = piece.map_or(0, Piece::to_inner);
                 bcs[col].set(row, value);
@@ -388,7 +574,7 @@ impl std::str::FromStr for Board {
             .iter()
             .copied()
             .position(BitCol::is_zero)
-            .unwrap_or(Col::NUM);
+            .unwrap_or(W);
         ensure!(
             bcs.as_array()[width_remain..]
                 .iter()
@@ -402,10 +588,10 @@ impl std::str::FromStr for Board {
     }
 }
 
-impl std::fmt::Display for Board {
+impl<const W: usize, const H: usize, const P: usize> std::fmt::Display for Board<W, H, P> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in Row::all().rev() {
-            for col in Col::all() {
+        for row in Row::<H>::all().rev() {
+            for col in Col::<W>::all() {
                 let sq = Square::new(col, row);
                 let piece = self.get(sq);
                 let ch = piece.map_or(Self::CHAR_BLANK, |piece| {
@@ -422,24 +608,27 @@ impl std::fmt::Display for Board {
 
 /// 盤面のマスの集合を表すマスク。
 ///
-/// `BitCol` を `Col::NUM` 個持っている。
+/// `BitCol` を `W` 個持っている。
 ///
 /// `BitCol` の値は、マスが集合に含まれるなら `0b001`, さもなくば `0b000` となる。
-#[derive(Clone, Eq, PartialEq)]
-pub struct MaskBoard {
-    bcs: ColArray<BitCol>,
+#[derive(Clone, Eq, Hash, PartialEq)]
+pub struct MaskBoard<const W: usize = 8, const H: usize = 6> {
+    bcs: ColArray<BitCol<H>, W>,
 
     /// 空でない列たちを表すマスク。
     col_mask: u32,
 }
 
-impl MaskBoard {
+impl<const W: usize, const H: usize> MaskBoard<W, H> {
     const CHAR_FALSE: char = '.';
     const CHAR_TRUE: char = '*';
 
+    /// 全ての列を表す `col_mask`。
+    const COL_MASK_ALL: u32 = u32::MAX >> (32 - W);
+
     /// `MaskBoard` を生成する。デバッグモードでは不変条件のチェックも行う。
-    fn new(bcs: ColArray<BitCol>, col_mask: u32) -> Self {
-        unsafe { assert_unchecked!((col_mask & !((1 << Col::NUM) - 1)) == 0) }
+    fn new(bcs: ColArray<BitCol<H>, W>, col_mask: u32) -> Self {
+        unsafe { assert_unchecked!((col_mask & !Self::COL_MASK_ALL) == 0) }
 
         debug_assert!(
             bcs.as_array()
@@ -467,7 +656,7 @@ impl MaskBoard {
     }
 
     /// 指定したマスのみを含むマスクを返す。
-    pub fn single(sq: Square) -> Self {
+    pub fn single(sq: Square<W, H>) -> Self {
         let mut bcs = ColArray::from_elem(BitCol::zero());
         bcs[sq.col()].set(sq.row(), 0b001);
 
@@ -475,12 +664,12 @@ impl MaskBoard {
     }
 
     /// 指定したマスが集合に含まれるかどうかを返す。
-    pub fn test(&self, sq: Square) -> bool {
+    pub fn test(&self, sq: Square<W, H>) -> bool {
         self.bcs[sq.col()].get(sq.row()) != 0
     }
 
     /// 指定したマスが集合に含まれるかどうかを設定する。
-    pub fn set(&mut self, sq: Square, value: bool) {
+    pub fn set(&mut self, sq: Square<W, H>, value: bool) {
         let bc = &mut self.bcs[sq.col()];
         let value = if value { 0b001 } else { 0b000 };
 
@@ -508,6 +697,11 @@ impl MaskBoard {
         bc.inner().is_power_of_two()
     }
 
+    /// 空でない列たちを表すマスク (bit i が列 i+1 に対応) を返す。
+    pub fn col_mask(&self) -> u32 {
+        self.col_mask
+    }
+
     /// 空でない列数を返す。
     pub fn nonempty_col_count(&self) -> u32 {
         self.col_mask.count_ones()
@@ -521,7 +715,7 @@ impl MaskBoard {
     }
 
     /// 空でない最小の列を返す。
-    pub fn least_nonempty_col(&self) -> Option<Col> {
+    pub fn least_nonempty_col(&self) -> Option<Col<W>> {
         (!self.is_empty()).then(|| unsafe { self.least_nonempty_col_unchecked() })
     }
 
@@ -530,7 +724,7 @@ impl MaskBoard {
     /// # Safety
     ///
     /// `self` は空であってはならない。
-    pub unsafe fn least_nonempty_col_unchecked(&self) -> Col {
+    pub unsafe fn least_nonempty_col_unchecked(&self) -> Col<W> {
         let col = 1 + self.col_mask.trailing_zeros() as u8;
 
         unsafe { Col::from_inner_unchecked(col) }
@@ -539,7 +733,7 @@ impl MaskBoard {
     /// 空でない列を昇順で列挙する。
     pub fn nonempty_cols(
         &self,
-    ) -> impl ExactSizeIterator<Item = Col> + std::iter::FusedIterator + Clone {
+    ) -> impl ExactSizeIterator<Item = Col<W>> + std::iter::FusedIterator + Clone {
         bitop::u32_one_indexs(self.col_mask).map(|i| {
             let col = 1 + i as u8;
             unsafe { Col::from_inner_unchecked(col) }
@@ -547,7 +741,7 @@ impl MaskBoard {
     }
 
     /// 含まれる最小のマスを返す。
-    pub fn least_square(&self) -> Option<Square> {
+    pub fn least_square(&self) -> Option<Square<W, H>> {
         (!self.is_empty()).then(|| unsafe { self.least_square_unchecked() })
     }
 
@@ -556,7 +750,7 @@ impl MaskBoard {
     /// # Safety
     ///
     /// `self` は空であってはならない。
-    pub unsafe fn least_square_unchecked(&self) -> Square {
+    pub unsafe fn least_square_unchecked(&self) -> Square<W, H> {
         assert_unchecked!(!self.is_empty());
 
         let col = self.least_nonempty_col_unchecked();
@@ -568,7 +762,7 @@ impl MaskBoard {
     }
 
     /// 含まれるマスを昇順で列挙する。
-    pub fn squares(&self) -> impl std::iter::FusedIterator<Item = Square> + Clone + '_ {
+    pub fn squares(&self) -> impl std::iter::FusedIterator<Item = Square<W, H>> + Clone + '_ {
         self.nonempty_cols().flat_map(|col| {
             let bc = self.bcs[col];
             bitop::u32_one_indexs(bc.inner()).map(move |i| {
@@ -579,6 +773,35 @@ impl MaskBoard {
         })
     }
 
+    /// 指定した列たち (bit i が列 i+1 に対応) に含まれるマスのみからなる部分集合を返す。
+    pub fn filter_cols(&self, col_mask: u32) -> Self {
+        let col_mask = self.col_mask & col_mask;
+
+        let mut bcs = ColArray::from_elem(BitCol::zero());
+        for i in bitop::u32_one_indexs(col_mask) {
+            let col = unsafe { Col::from_inner_unchecked(1 + i as u8) };
+            bcs[col] = self.bcs[col];
+        }
+
+        Self::new(bcs, col_mask)
+    }
+
+    /// 列集合 `survived` (bit i が列 i+1 に対応) に含まれない列を取り除き、残りの列を左に詰めた結果を返す。
+    ///
+    /// 取り除く列にマスが含まれていてはならない。
+    pub fn compact_cols(&self, survived: u32) -> Self {
+        unsafe { assert_unchecked!((self.col_mask & !survived) == 0) }
+
+        let mut bcs = ColArray::from_elem(BitCol::zero());
+        for col in self.nonempty_cols() {
+            let i = (survived & ((1 << col.to_index()) - 1)).count_ones() as u8;
+            let col_out = unsafe { Col::from_inner_unchecked(1 + i) };
+            bcs[col_out] = self.bcs[col];
+        }
+
+        Self::new(bcs, bitop::u32_pext_software(self.col_mask, survived))
+    }
+
     /// 差集合 `self` - `rhs` を返す。
     pub fn subtract(&self, rhs: &Self) -> Self {
         let mut res = self.clone();
@@ -617,7 +840,7 @@ impl MaskBoard {
     /// `self` に対して `sq` を始点として flood fill を行った結果を返す。
     ///
     /// `self` は `sq` を含んでいなければならない。
-    pub fn flood_fill(&self, sq: Square) -> Self {
+    pub fn flood_fill(&self, sq: Square<W, H>) -> Self {
         unsafe { assert_unchecked!(self.test(sq)) }
 
         self.flood_fill_impl(Self::single(sq))
@@ -627,10 +850,10 @@ impl MaskBoard {
         unsafe { assert_unchecked!(seed.is_single()) }
         unsafe { assert_unchecked!(self.test(seed.least_square_unchecked())) }
 
-        fn col(col: u8) -> Col {
-            unsafe { assert_unchecked!(matches!(col, Col::MIN_VALUE..=Col::MAX_VALUE)) }
+        let col = |col: u8| -> Col<W> {
+            unsafe { assert_unchecked!(Col::<W>::MIN_VALUE <= col && col <= Col::<W>::MAX_VALUE) }
             unsafe { Col::from_inner_unchecked(col) }
-        }
+        };
 
         macro_rules! update {
             ($lhs:expr, $rhs:expr) => {{
@@ -670,7 +893,7 @@ impl MaskBoard {
                 updated |= update!(bcs[col(c)], bc);
             }
             // 左端を左に伸ばす。
-            if c_min != Col::MIN_VALUE {
+            if c_min != Col::<W>::MIN_VALUE {
                 let bc = bcs[col(c_min)] & self.bcs[col(c_min - 1)];
                 if !bc.is_zero() {
                     bcs[col(c_min - 1)] = bc;
@@ -680,7 +903,7 @@ impl MaskBoard {
                 }
             }
             // 右端を右に伸ばす。
-            if c_max != Col::MAX_VALUE {
+            if c_max != Col::<W>::MAX_VALUE {
                 let bc = bcs[col(c_max)] & self.bcs[col(c_max + 1)];
                 if !bc.is_zero() {
                     bcs[col(c_max + 1)] = bc;
@@ -711,45 +934,40 @@ impl MaskBoard {
     }
 }
 
-impl std::fmt::Debug for MaskBoard {
+impl<const W: usize, const H: usize> std::fmt::Debug for MaskBoard<W, H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("MaskBoard")
             .field("bcs", &self.bcs)
-            .field("col_mask", &ColMaskDebug(self.col_mask))
+            .field("col_mask", &ColMaskDebug::<W>(self.col_mask))
             .finish()
     }
 }
 
-struct ColMaskDebug(u32);
+struct ColMaskDebug<const W: usize>(u32);
 
-impl std::fmt::Debug for ColMaskDebug {
+impl<const W: usize> std::fmt::Debug for ColMaskDebug<W> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "0b{:0width$b}", self.0, width = Col::NUM)
+        write!(f, "0b{:0width$b}", self.0, width = W)
     }
 }
 
-impl std::str::FromStr for MaskBoard {
+impl<const W: usize, const H: usize> std::str::FromStr for MaskBoard<W, H> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let lines: Vec<_> = s.lines().collect();
         ensure!(
-            lines.len() == Row::NUM,
-            "盤面マスク文字列はちょうど {} 行でなければならない",
-            Row::NUM
+            lines.len() == H,
+            "盤面マスク文字列はちょうど {H} 行でなければならない"
         );
 
         let mut this = Self::empty();
 
-        for (row, line) in itertools::zip_eq(Row::all().rev(), lines) {
+        for (row, line) in itertools::zip_eq(Row::<H>::all().rev(), lines) {
             let chars: Vec<_> = line.chars().collect();
-            ensure!(
-                chars.len() == Col::NUM,
-                "盤面の行 {row} がちょうど {} 文字でない",
-                Col::NUM
-            );
+            ensure!(chars.len() == W, "盤面の行 {row} がちょうど {W} 文字でない");
 
-            for (col, ch) in itertools::zip_eq(Col::all(), chars) {
+            for (col, ch) in itertools::zip_eq(Col::<W>::all(), chars) {
                 let sq = Square::new(col, row);
                 let value = match ch {
                     Self::CHAR_FALSE => false,
@@ -764,10 +982,10 @@ impl std::str::FromStr for MaskBoard {
     }
 }
 
-impl std::fmt::Display for MaskBoard {
+impl<const W: usize, const H: usize> std::fmt::Display for MaskBoard<W, H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for row in Row::all().rev() {
-            for col in Col::all() {
+        for row in Row::<H>::all().rev() {
+            for col in Col::<W>::all() {
                 let sq = Square::new(col, row);
                 let ch = if self.test(sq) {
                     Self::CHAR_TRUE
@@ -804,6 +1022,18 @@ mod tests {
         s.as_ref().parse().unwrap()
     }
 
+    fn parse_board_generic<const W: usize, const H: usize, const P: usize>(
+        s: impl AsRef<str>,
+    ) -> Board<W, H, P> {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn parse_mask_board_generic<const W: usize, const H: usize>(
+        s: impl AsRef<str>,
+    ) -> MaskBoard<W, H> {
+        s.as_ref().parse().unwrap()
+    }
+
     #[test]
     fn test_bit_col() {
         let mut bc = BitCol::new(0b111_110_101_100_011_010);
@@ -864,12 +1094,92 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_board_pack() {
+        fn pack<const W: usize, const H: usize, const P: usize>(
+            board: &Board<W, H, P>,
+        ) -> Vec<u64> {
+            let mut out = vec![0; Board::<W, H, P>::PACKED_LEN];
+            board.pack_into(&mut out);
+            out
+        }
+
+        assert_eq!(<Board>::PACKED_LEN, 3);
+        assert_eq!(Board::<12, 10, 7>::PACKED_LEN, 6);
+
+        assert_eq!(pack(&<Board>::empty()), [0, 0, 0]);
+
+        // 列 1 は bit 0-17 に入る。
+        let board = parse_board(indoc! {"
+            1.......
+            1.......
+            1.......
+            1.......
+            1.......
+            1.......
+        "});
+        assert_eq!(pack(&board), [0o111111, 0, 0]);
+
+        // 列 4 は bit 54-71 に入り、u64 の境界をまたぐ。
+        let board = parse_board(indoc! {"
+            1112....
+            1112....
+            1112....
+            1112....
+            1112....
+            1112....
+        "});
+        let col_1 = 0o111111_u64;
+        let col_4 = 0o222222_u64;
+        assert_eq!(
+            pack(&board),
+            [
+                col_1 | (col_1 << 18) | (col_1 << 36) | (col_4 << 54),
+                col_4 >> 10,
+                0
+            ]
+        );
+
+        // 異なる盤面は異なる表現を持つ。
+        let boards = [
+            indoc! {"
+                ........
+                ........
+                .1......
+                121.....
+                1213....
+                1213....
+            "},
+            indoc! {"
+                ........
+                ........
+                ..1.....
+                121.....
+                1213....
+                1213....
+            "},
+            indoc! {"
+                ........
+                ........
+                ........
+                121.....
+                1213....
+                12131...
+            "},
+        ]
+        .map(parse_board);
+        let packs = boards.each_ref().map(pack);
+        assert_ne!(packs[0], packs[1]);
+        assert_ne!(packs[0], packs[2]);
+        assert_ne!(packs[1], packs[2]);
+    }
+
     #[test]
     fn test_board_piece_count() {
-        for piece in Piece::all() {
-            assert_eq!(Board::empty().piece_count(piece), 0);
+        for piece in <Piece>::all() {
+            assert_eq!(<Board>::empty().piece_count(piece), 0);
         }
-        assert_eq!(Board::empty().piece_count_total(), 0);
+        assert_eq!(<Board>::empty().piece_count_total(), 0);
 
         let cases = [
             (
@@ -898,7 +1208,7 @@ mod tests {
 
         for (board, counts) in cases {
             let board = parse_board(board);
-            for piece in Piece::all() {
+            for piece in <Piece>::all() {
                 assert_eq!(board.piece_count(piece), counts[piece.to_index()]);
             }
             assert_eq!(board.piece_count_total(), counts.into_iter().sum());
@@ -1072,12 +1382,233 @@ mod tests {
             let after = parse_board(after);
 
             assert_eq!(before.erase(&mb), after);
+
+            // 各駒の移動先は erase() の結果と一致する。
+            for piece in Piece::all() {
+                assert_eq!(
+                    before.erase_shift_mask(&mb, &before.piece_mask(piece)),
+                    after.piece_mask(piece)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_board_erase_emptied_col_mask() {
+        let board = parse_board(indoc! {"
+            1......2
+            155....2
+            111.4..2
+            12144..1
+            12133.51
+            12135551
+        "});
+        let cases = [
+            (
+                indoc! {"
+                    *.......
+                    *.......
+                    ***.....
+                    *.*.....
+                    *.*.....
+                    *.*.....
+                "},
+                0b1,
+            ),
+            (
+                indoc! {"
+                    ........
+                    .**.....
+                    ........
+                    ........
+                    ........
+                    ........
+                "},
+                0,
+            ),
+        ];
+
+        for (mb, expect) in cases {
+            let mb = parse_mask_board(mb);
+            assert_eq!(board.erase_emptied_col_mask(&mb), expect);
+            assert_eq!(board.erase_empties_col(&mb), expect != 0);
+        }
+    }
+
+    #[test]
+    fn test_board_has_action() {
+        let cases = [
+            (
+                indoc! {"
+                    ........
+                    ........
+                    ........
+                    ........
+                    ........
+                    ........
+                "},
+                false,
+            ),
+            (
+                indoc! {"
+                    12345123
+                    51234512
+                    45123451
+                    34512345
+                    23451234
+                    12345123
+                "},
+                false,
+            ),
+            (
+                indoc! {"
+                    ........
+                    ........
+                    ........
+                    2.......
+                    1.......
+                    23......
+                "},
+                false,
+            ),
+            (
+                indoc! {"
+                    ........
+                    ........
+                    ........
+                    ........
+                    .......5
+                    12341235
+                "},
+                true,
+            ),
+            (
+                indoc! {"
+                    ........
+                    ........
+                    ........
+                    ........
+                    .......3
+                    12341244
+                "},
+                true,
+            ),
+            (
+                indoc! {"
+                    ......1.
+                    ......1.
+                    ......2.
+                    ......3.
+                    ......4.
+                    12345123
+                "},
+                true,
+            ),
+        ];
+
+        for (board, expect) in cases {
+            let board = parse_board(board);
+            assert_eq!(board.has_action(), expect);
+            assert_eq!(
+                board.has_action(),
+                board.piece_components().any(|(_, mb)| !mb.is_single())
+            );
+            assert_eq!(board.action_squares().is_empty(), !expect);
         }
     }
 
+    #[test]
+    fn test_board_action_squares() {
+        let board = parse_board(indoc! {"
+            1......2
+            155....2
+            111.4..2
+            12144..1
+            12133.51
+            12135551
+        "});
+        assert_eq!(
+            board.action_squares(),
+            parse_mask_board(indoc! {"
+                *......*
+                ***....*
+                ***.*..*
+                *****..*
+                *****.**
+                ********
+            "})
+        );
+
+        // 2 マス以上の連結成分の和集合に等しい。
+        for board in [
+            parse_board(indoc! {"
+                12345123
+                51234512
+                45123451
+                34512345
+                23451234
+                12345123
+            "}),
+            parse_board(indoc! {"
+                ........
+                ........
+                ........
+                ...1....
+                2..3....
+                23214...
+            "}),
+        ] {
+            let mut expect: Vec<_> = board
+                .piece_components()
+                .filter(|(_, mb)| !mb.is_single())
+                .flat_map(|(_, mb)| mb.squares().collect::<Vec<_>>())
+                .collect();
+            expect.sort_unstable();
+            assert_equal(board.action_squares().squares(), expect);
+        }
+    }
+
+    #[test]
+    fn test_board_canonical() {
+        assert_eq!(<Board>::empty().canonical(), Board::empty());
+
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            3.......
+            52......
+            25314...
+        "});
+        let expect = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            3.......
+            21......
+            12345...
+        "});
+        assert_eq!(board.canonical(), expect);
+        assert_eq!(expect.canonical(), expect);
+
+        // 駒種を付け替えても結果は変わらない。
+        let relabeled = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            1.......
+            43......
+            34152...
+        "});
+        assert_eq!(relabeled.canonical(), expect);
+    }
+
     #[test]
     fn test_board_xor_mask() {
-        assert_eq!(Board::empty().xor_mask(&Board::empty()), MaskBoard::empty());
+        assert_eq!(
+            <Board>::empty().xor_mask(&Board::empty()),
+            MaskBoard::empty()
+        );
 
         {
             let board = parse_board(indoc! {"
@@ -1183,9 +1714,46 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_mask_board_filter_compact_cols() {
+        let mb = parse_mask_board(indoc! {"
+            ........
+            ........
+            *.......
+            *...*.*.
+            **..*.**
+            **..****
+        "});
+
+        assert_eq!(
+            mb.filter_cols(0b1010_0010),
+            parse_mask_board(indoc! {"
+                ........
+                ........
+                ........
+                ........
+                .*.....*
+                .*...*.*
+            "})
+        );
+
+        // 空の列 3, 4 を取り除く。
+        assert_eq!(
+            mb.compact_cols(0b1111_0011),
+            parse_mask_board(indoc! {"
+                ........
+                ........
+                *.......
+                *.*.*...
+                ***.**..
+                ******..
+            "})
+        );
+    }
+
     #[test]
     fn test_mask_board_squares() {
-        assert_eq!(MaskBoard::empty().squares().next(), None);
+        assert_eq!(<MaskBoard>::empty().squares().next(), None);
 
         let cases = [
             (
@@ -1234,7 +1802,7 @@ mod tests {
 
     #[test]
     fn test_mask_board_components() {
-        assert_eq!(MaskBoard::empty().components().next(), None);
+        assert_eq!(<MaskBoard>::empty().components().next(), None);
 
         let mb = parse_mask_board(indoc! {"
             ****...*
@@ -1298,4 +1866,85 @@ mod tests {
         let expect = expect.map(parse_mask_board);
         assert_equal(mb.components(), expect);
     }
+
+    #[test]
+    fn test_board_large() {
+        type Board12x10 = Board<12, 10, 7>;
+        type MaskBoard12x10 = MaskBoard<12, 10>;
+
+        let before: Board12x10 = parse_board_generic(indoc! {"
+            7.........6.
+            7.........6.
+            71........6.
+            71.......56.
+            612......562
+            6123.....562
+            61234...4562
+            612345.34562
+            612345634562
+            612345634562
+        "});
+        let mb: MaskBoard12x10 = parse_mask_board_generic(indoc! {"
+            **..........
+            **..........
+            **..........
+            **..........
+            **..........
+            **..........
+            **..........
+            **..........
+            **..........
+            **........*.
+        "});
+        let after: Board12x10 = parse_board_generic(indoc! {"
+            ............
+            ........6...
+            ........6...
+            .......56...
+            2......562..
+            23.....562..
+            234...4562..
+            2345.34562..
+            2345634562..
+            2345634562..
+        "});
+
+        assert_eq!(before.erase(&mb), after);
+
+        let piece_7 = Piece::from_inner(7).unwrap();
+        assert_eq!(before.piece_count(piece_7), 4);
+        assert_eq!(before.piece_count_total(), 68);
+
+        let sq = Square::new(Col::from_inner(11).unwrap(), Row::from_inner(10).unwrap());
+        let comp = before
+            .piece_mask(Piece::from_inner(6).unwrap())
+            .flood_fill(sq);
+        assert_eq!(comp.square_count(), 10);
+    }
+
+    /// 使用可能な全ての PEXT 実装で `Board`, `MaskBoard` のテストを行う。
+    ///
+    /// 実装方式の選択はこのテストのスレッドでのみ切り替えるので、並行して動く他のテストには影響しない。
+    #[test]
+    fn test_pext_impls() {
+        for imp in bitop::PextImpl::all().filter(|imp| imp.is_supported()) {
+            imp.with(|| {
+                assert_eq!(bitop::PextImpl::current(), imp);
+
+                test_bit_col();
+                test_board_io();
+                test_board_pack();
+                test_board_piece_count();
+                test_board_erase();
+                test_board_has_action();
+                test_board_action_squares();
+                test_board_canonical();
+                test_board_xor_mask();
+                test_mask_board_io();
+                test_mask_board_squares();
+                test_mask_board_components();
+                test_board_large();
+            });
+        }
+    }
 }
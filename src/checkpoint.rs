@@ -0,0 +1,618 @@
+//! 盤面集合の探索の途中経過 (チェックポイント) の保存と復元。
+
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, ensure, Context as _};
+
+use crate::action::ActionHistory;
+use crate::collision::CollisionCheck;
+use crate::score::Score;
+use crate::score_bound::ScoreBound;
+use crate::solve::{SolveConfig, SolverKind};
+use crate::solver_many::SolutionMany;
+use crate::square::Square;
+use crate::zobrist::zobrist_fingerprint;
+
+/// チェックポイントの設定。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckpointConfig {
+    /// チェックポイントファイルのパス。
+    pub path: PathBuf,
+
+    /// 保存間隔。
+    pub interval: Duration,
+}
+
+/// チェックポイントファイルのマジックナンバー。
+const MAGIC: [u8; 8] = *b"SGSFCCKP";
+
+/// チェックポイントファイルの形式のバージョン。
+const VERSION: u32 = 3;
+
+/// チェックポイントの互換性判定に用いる情報。
+///
+/// 再開時にこれが一致しなければ、中断しなかった場合と結果が変わりうるので再開を拒否する。
+/// (DP テーブルの容量が異なると、ハッシュ衝突の起こり方が変わりうる)
+///
+/// `share_table` を指定した探索では DP テーブルも保存するので、その容量と zobrist テーブルの一致も必須となる。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct CheckpointHeader {
+    solver_kind: SolverKind,
+    collision_check: CollisionCheck,
+    score_bound: ScoreBound,
+    share_table: bool,
+    table_capacity: u64,
+    zobrist_fingerprint: u64,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+}
+
+impl CheckpointHeader {
+    pub(crate) fn new(
+        kind: SolverKind,
+        config: &SolveConfig,
+        states: RangeInclusive<u16>,
+        counters: RangeInclusive<u8>,
+        inc_timings: RangeInclusive<usize>,
+    ) -> Self {
+        Self {
+            solver_kind: kind,
+            collision_check: config.collision_check,
+            score_bound: config.score_bound,
+            share_table: config.share_table,
+            table_capacity: config.table_capacity::<8, 6>() as u64,
+            zobrist_fingerprint: zobrist_fingerprint(),
+            states,
+            counters,
+            inc_timings,
+        }
+    }
+
+    /// `other` から探索を再開できるかどうか確認する。
+    pub(crate) fn ensure_compatible(&self, other: &Self) -> anyhow::Result<()> {
+        ensure!(
+            self.solver_kind == other.solver_kind,
+            "ソルバーが異なる: {} (チェックポイントは {})",
+            self.solver_kind,
+            other.solver_kind
+        );
+        ensure!(
+            self.collision_check == other.collision_check,
+            "ハッシュ衝突の検出方法が異なる: {} (チェックポイントは {})",
+            self.collision_check,
+            other.collision_check
+        );
+        ensure!(
+            self.score_bound == other.score_bound,
+            "スコア上界が異なる: {} (チェックポイントは {})",
+            self.score_bound,
+            other.score_bound
+        );
+        ensure!(
+            self.share_table == other.share_table,
+            "DP テーブルの共有の有無が異なる"
+        );
+        ensure!(
+            self.zobrist_fingerprint == other.zobrist_fingerprint,
+            "zobrist テーブルが異なる"
+        );
+        ensure!(
+            self.table_capacity == other.table_capacity,
+            "DP テーブルの容量が異なる: {} (チェックポイントは {})",
+            self.table_capacity,
+            other.table_capacity
+        );
+        ensure!(
+            self.states == other.states
+                && self.counters == other.counters
+                && self.inc_timings == other.inc_timings,
+            "盤面集合が異なる: {:?} {:?} {:?} (チェックポイントは {:?} {:?} {:?})",
+            self.states,
+            self.counters,
+            self.inc_timings,
+            other.states,
+            other.counters,
+            other.inc_timings
+        );
+
+        Ok(())
+    }
+}
+
+/// 盤面集合の探索の途中経過。
+///
+/// 盤面の探索の切れ目でのみ保存する。DP テーブルの内容は通常は各盤面の探索中のみ有効
+/// (次の盤面では世代の更新により全て無効となる) なので保存しない。
+/// ただし `share_table` を指定した場合はエントリが盤面をまたいで残るので、各ワーカーの DP テーブルを
+/// 途中経過の後ろに続けて保存する。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Checkpoint {
+    pub(crate) header: CheckpointHeader,
+
+    /// 次に探索すべき盤面 `(counter, inc_timing, state)`。これより前の盤面は全て探索済み。
+    /// 全ての盤面を探索済みなら `None`。
+    pub(crate) cursor: Option<(u8, usize, u16)>,
+
+    /// それまでに見つかった最大解。
+    pub(crate) best: Option<SolutionMany>,
+
+    /// 後ろに続けて保存する DP テーブルの数 (`share_table` を指定しなければ 0)。
+    pub(crate) table_count: usize,
+}
+
+impl Checkpoint {
+    /// ファイルから DP テーブル以外の部分を読み込む。
+    ///
+    /// DP テーブルは互換性を確認した上で `read_tables()` により読み込む。
+    pub(crate) fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut r = Self::open(path)?;
+
+        let checkpoint = Self::decode(&mut r);
+        checkpoint
+            .and_then(|checkpoint| {
+                if checkpoint.table_count == 0 {
+                    ensure_eof(&mut r)?;
+                }
+                Ok(checkpoint)
+            })
+            .with_context(|| format!("チェックポイント {} が無効", path.display()))
+    }
+
+    /// ファイルに保存された DP テーブルを先頭から順に `read_table(i, r)` で読み込む。
+    pub(crate) fn read_tables(
+        path: impl AsRef<Path>,
+        mut read_table: impl FnMut(usize, &mut dyn Read) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let mut r = Self::open(path)?;
+
+        (|| {
+            let checkpoint = Self::decode(&mut r)?;
+            for i in 0..checkpoint.table_count {
+                read_table(i, &mut r).with_context(|| format!("DP テーブル {i} が無効"))?;
+            }
+            ensure_eof(&mut r)
+        })()
+        .with_context(|| format!("チェックポイント {} が無効", path.display()))
+    }
+
+    fn open(path: &Path) -> anyhow::Result<BufReader<std::fs::File>> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("チェックポイント {} を読めない", path.display()))?;
+        Ok(BufReader::new(file))
+    }
+
+    /// ファイルに書き込む。`table_count` 個の DP テーブルは `write_table(i, w)` で順に書き出す。
+    ///
+    /// 書き込み中に中断されても既存のファイルが壊れないよう、一時ファイルに書いてから置き換える。
+    pub(crate) fn write(
+        &self,
+        path: impl AsRef<Path>,
+        mut write_table: impl FnMut(usize, &mut dyn Write) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let path_tmp = path.with_extension("tmp");
+
+        let file = std::fs::File::create(&path_tmp)
+            .with_context(|| format!("{} を作成できない", path_tmp.display()))?;
+        let mut w = BufWriter::new(file);
+        w.write_all(&self.encode())?;
+        for i in 0..self.table_count {
+            write_table(i, &mut w)?;
+        }
+        let file = w.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        drop(file);
+
+        std::fs::rename(&path_tmp, path)
+            .with_context(|| format!("チェックポイント {} を保存できない", path.display()))?;
+
+        Ok(())
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::<u8>::new();
+
+        buf.extend(MAGIC);
+        buf.extend(VERSION.to_le_bytes());
+        write_str(&mut buf, &self.header.solver_kind.to_string());
+        write_str(&mut buf, &self.header.collision_check.to_string());
+        write_str(&mut buf, &self.header.score_bound.to_string());
+        buf.push(u8::from(self.header.share_table));
+        buf.extend(self.header.table_capacity.to_le_bytes());
+        buf.extend(self.header.zobrist_fingerprint.to_le_bytes());
+        buf.extend(self.header.states.start().to_le_bytes());
+        buf.extend(self.header.states.end().to_le_bytes());
+        buf.push(*self.header.counters.start());
+        buf.push(*self.header.counters.end());
+        buf.extend((*self.header.inc_timings.start() as u64).to_le_bytes());
+        buf.extend((*self.header.inc_timings.end() as u64).to_le_bytes());
+
+        match self.cursor {
+            Some((counter, inc_timing, state)) => {
+                buf.push(1);
+                buf.push(counter);
+                buf.extend((inc_timing as u64).to_le_bytes());
+                buf.extend(state.to_le_bytes());
+            }
+            None => buf.push(0),
+        }
+
+        match &self.best {
+            Some(best) => {
+                buf.push(1);
+                buf.push(best.rng_counter());
+                buf.extend((best.rng_inc_timing() as u64).to_le_bytes());
+                buf.extend(best.rng_state().to_le_bytes());
+                buf.extend(best.score().to_le_bytes());
+                buf.push(best.solution().len() as u8);
+                buf.extend(best.solution().iter().map(|sq| sq.to_inner()));
+            }
+            None => buf.push(0),
+        }
+
+        buf.extend((self.table_count as u64).to_le_bytes());
+
+        buf
+    }
+
+    /// DP テーブルの手前までを読み込む。
+    fn decode(r: &mut impl Read) -> anyhow::Result<Self> {
+        ensure!(
+            read_array::<8>(r)? == MAGIC,
+            "チェックポイントファイルではない"
+        );
+        let version = u32::from_le_bytes(read_array(r)?);
+        ensure!(
+            version == VERSION,
+            "チェックポイントのバージョンが異なる: {version} (対応バージョンは {VERSION})"
+        );
+
+        let solver_kind = read_str(r)?.parse()?;
+        let collision_check = read_str(r)?.parse()?;
+        let score_bound = read_str(r)?.parse()?;
+        let share_table = match read_u8(r)? {
+            0 => false,
+            1 => true,
+            x => bail!("DP テーブルの共有の有無が無効: {x}"),
+        };
+        let table_capacity = u64::from_le_bytes(read_array(r)?);
+        let zobrist_fingerprint = u64::from_le_bytes(read_array(r)?);
+        let states = u16::from_le_bytes(read_array(r)?)..=u16::from_le_bytes(read_array(r)?);
+        let counters = read_u8(r)?..=read_u8(r)?;
+        let inc_timings = read_usize(r)?..=read_usize(r)?;
+        let header = CheckpointHeader {
+            solver_kind,
+            collision_check,
+            score_bound,
+            share_table,
+            table_capacity,
+            zobrist_fingerprint,
+            states,
+            counters,
+            inc_timings,
+        };
+
+        let cursor = match read_u8(r)? {
+            0 => None,
+            1 => {
+                let counter = read_u8(r)?;
+                let inc_timing = read_usize(r)?;
+                let state = u16::from_le_bytes(read_array(r)?);
+                Some((counter, inc_timing, state))
+            }
+            tag => bail!("カーソルのタグが無効: {tag}"),
+        };
+
+        let best = match read_u8(r)? {
+            0 => None,
+            1 => {
+                let counter = read_u8(r)?;
+                let inc_timing = read_usize(r)?;
+                let state = u16::from_le_bytes(read_array(r)?);
+                let score = Score::from_le_bytes(read_array(r)?);
+                let len = read_u8(r)?;
+                let solution = (0..len)
+                    .map(|_| {
+                        let inner = read_u8(r)?;
+                        Square::from_inner(inner).with_context(|| format!("マスが無効: {inner}"))
+                    })
+                    .collect::<anyhow::Result<ActionHistory>>()?;
+                Some(SolutionMany::new(
+                    state, counter, inc_timing, score, solution,
+                ))
+            }
+            tag => bail!("最大解のタグが無効: {tag}"),
+        };
+
+        let table_count = read_usize(r)?;
+        ensure!(
+            table_count == 0 || header.share_table,
+            "DP テーブルを共有しない探索なのに DP テーブルが保存されている"
+        );
+
+        Ok(Self {
+            header,
+            cursor,
+            best,
+            table_count,
+        })
+    }
+}
+
+fn read_array<const N: usize>(r: &mut (impl Read + ?Sized)) -> anyhow::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)
+        .context("ファイルが途中で終わっている")?;
+    Ok(buf)
+}
+
+fn read_u8(r: &mut (impl Read + ?Sized)) -> anyhow::Result<u8> {
+    read_array::<1>(r).map(|[x]| x)
+}
+
+/// 長さ (1 バイト) を前置した文字列を読み込む。
+fn read_str(r: &mut (impl Read + ?Sized)) -> anyhow::Result<String> {
+    let len = usize::from(read_u8(r)?);
+    let mut s = vec![0; len];
+    r.read_exact(&mut s)
+        .context("ファイルが途中で終わっている")?;
+    String::from_utf8(s).context("文字列が UTF-8 でない")
+}
+
+/// 長さ (1 バイト) を前置した文字列を書き込む。
+fn write_str(buf: &mut Vec<u8>, s: &str) {
+    buf.push(u8::try_from(s.len()).unwrap());
+    buf.extend(s.as_bytes());
+}
+
+fn read_usize(r: &mut (impl Read + ?Sized)) -> anyhow::Result<usize> {
+    let x = u64::from_le_bytes(read_array(r)?);
+    usize::try_from(x).with_context(|| format!("値が大きすぎる: {x}"))
+}
+
+fn ensure_eof(r: &mut impl Read) -> anyhow::Result<()> {
+    ensure!(r.read(&mut [0])? == 0, "末尾に余分なデータがある");
+    Ok(())
+}
+
+/// 一度に変換する `u64` の個数。
+const U64_CHUNK_LEN: usize = 1 << 12;
+
+/// 長さを前置した `u64` の列を書き出す (DP テーブルの保存用)。
+pub(crate) fn write_u64s(
+    w: &mut dyn Write,
+    len: usize,
+    xs: impl IntoIterator<Item = u64>,
+) -> anyhow::Result<()> {
+    w.write_all(&(len as u64).to_le_bytes())?;
+
+    let mut buf = Vec::<u8>::with_capacity(8 * U64_CHUNK_LEN);
+    let mut count = 0;
+    for x in xs {
+        buf.extend(x.to_le_bytes());
+        count += 1;
+        if buf.len() == buf.capacity() {
+            w.write_all(&buf)?;
+            buf.clear();
+        }
+    }
+    w.write_all(&buf)?;
+    assert_eq!(count, len);
+
+    Ok(())
+}
+
+/// `write_u64s()` で書き出した列を読み込み、先頭から順に `f(i, x)` に渡す。長さが `len` と異なれば失敗する。
+pub(crate) fn read_u64s(
+    r: &mut dyn Read,
+    len: usize,
+    mut f: impl FnMut(usize, u64),
+) -> anyhow::Result<()> {
+    let len_file = read_usize(r)?;
+    ensure!(len_file == len, "長さが異なる: {len_file} (期待値は {len})");
+
+    let mut buf = vec![0; 8 * U64_CHUNK_LEN];
+    for start in (0..len).step_by(U64_CHUNK_LEN) {
+        let buf = &mut buf[..8 * U64_CHUNK_LEN.min(len - start)];
+        r.read_exact(buf).context("ファイルが途中で終わっている")?;
+        for (i, bytes) in (start..).zip(buf.chunks_exact(8)) {
+            f(i, u64::from_le_bytes(bytes.try_into().unwrap()));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_new(table_bytes: usize) -> CheckpointHeader {
+        let config = SolveConfig {
+            table_bytes,
+            ..Default::default()
+        };
+        CheckpointHeader::new(SolverKind::Many, &config, 0..=0xFFFF, 0..=0xFF, 39..=40)
+    }
+
+    /// DP テーブルを含まないチェックポイントを読み込む。
+    fn decode_all(mut buf: &[u8]) -> anyhow::Result<Checkpoint> {
+        let checkpoint = Checkpoint::decode(&mut buf)?;
+        ensure_eof(&mut buf)?;
+        Ok(checkpoint)
+    }
+
+    #[test]
+    fn test_checkpoint_io() {
+        let solution: ActionHistory = "1,1 2,3 8,6".parse().unwrap();
+        let checkpoints = [
+            Checkpoint {
+                header: header_new(1 << 20),
+                cursor: Some((0x12, 40, 0x3456)),
+                best: Some(SolutionMany::new(0x1234, 0x00, 39, 101, solution)),
+                table_count: 0,
+            },
+            Checkpoint {
+                header: header_new(1 << 20),
+                cursor: None,
+                best: None,
+                table_count: 0,
+            },
+        ];
+
+        let path = std::env::temp_dir().join(format!(
+            "samegame-sfc-small-test-checkpoint-{}.bin",
+            std::process::id()
+        ));
+        for checkpoint in checkpoints {
+            checkpoint.write(&path, |_, _| unreachable!()).unwrap();
+            assert_eq!(Checkpoint::read(&path).unwrap(), checkpoint);
+        }
+
+        // DP テーブルを共有する探索では、途中経過の後ろに DP テーブルが続く。
+        let config_share = SolveConfig {
+            table_bytes: 1 << 20,
+            share_table: true,
+            ..Default::default()
+        };
+        let checkpoint = Checkpoint {
+            header: CheckpointHeader::new(
+                SolverKind::Many,
+                &config_share,
+                0..=0xFFFF,
+                0..=0xFF,
+                39..=40,
+            ),
+            cursor: Some((0x12, 40, 0x3456)),
+            best: None,
+            table_count: 2,
+        };
+        let tables: [Vec<u64>; 2] = [(0..10000).collect(), vec![u64::MAX, 0, 1]];
+        checkpoint
+            .write(&path, |i, w| {
+                write_u64s(w, tables[i].len(), tables[i].clone())
+            })
+            .unwrap();
+        assert_eq!(Checkpoint::read(&path).unwrap(), checkpoint);
+        let mut tables_read = [vec![0; 10000], vec![0; 3]];
+        Checkpoint::read_tables(&path, |i, r| {
+            let table = &mut tables_read[i];
+            read_u64s(r, table.len(), |j, x| table[j] = x)
+        })
+        .unwrap();
+        assert_eq!(tables_read, tables);
+
+        // DP テーブルの長さが異なれば読み込めない。
+        assert!(Checkpoint::read_tables(&path, |_, r| read_u64s(r, 3, |_, _| ())).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_checkpoint_reject() {
+        let checkpoint = Checkpoint {
+            header: header_new(1 << 20),
+            cursor: None,
+            best: None,
+            table_count: 0,
+        };
+        let buf = checkpoint.encode();
+
+        // 壊れたファイル。
+        assert!(decode_all(&buf[..buf.len() - 1]).is_err());
+        assert!(decode_all(&[buf.as_slice(), &[0]].concat()).is_err());
+        let mut buf_bad = buf.clone();
+        buf_bad[0] ^= 1;
+        assert!(decode_all(&buf_bad).is_err());
+
+        // バージョン違い。
+        let mut buf_bad = buf.clone();
+        buf_bad[MAGIC.len()] ^= 1;
+        assert!(decode_all(&buf_bad).is_err());
+
+        // DP テーブルを共有しない探索に DP テーブルは続かない。
+        let buf_bad = Checkpoint {
+            table_count: 1,
+            ..checkpoint.clone()
+        }
+        .encode();
+        assert!(decode_all(&buf_bad).is_err());
+
+        // DP テーブルの容量や盤面集合が異なれば再開できない。
+        let header = decode_all(&buf).unwrap().header;
+        assert!(header.ensure_compatible(&header_new(1 << 20)).is_ok());
+        assert!(header.ensure_compatible(&header_new(1 << 21)).is_err());
+        let config = SolveConfig {
+            table_bytes: 1 << 20,
+            ..Default::default()
+        };
+        let header_with = |kind, config: &SolveConfig| {
+            CheckpointHeader::new(kind, config, 0..=0xFFFF, 0..=0xFF, 39..=40)
+        };
+        assert!(header
+            .ensure_compatible(&CheckpointHeader::new(
+                SolverKind::Many,
+                &config,
+                0..=0xFFFF,
+                0..=0xFF,
+                39..=39
+            ))
+            .is_err());
+
+        // ソルバーや探索結果に影響する設定が異なれば再開できない。
+        assert!(header
+            .ensure_compatible(&header_with(SolverKind::Beam, &config))
+            .is_err());
+        for config_other in [
+            SolveConfig {
+                collision_check: CollisionCheck::Key128,
+                ..config.clone()
+            },
+            SolveConfig {
+                score_bound: ScoreBound::Separation,
+                ..config.clone()
+            },
+            SolveConfig {
+                share_table: true,
+                ..config.clone()
+            },
+        ] {
+            assert!(header
+                .ensure_compatible(&header_with(SolverKind::Many, &config_other))
+                .is_err());
+        }
+
+        // DP テーブルを共有する探索も、設定が一致すれば再開できる。
+        let config_share = SolveConfig {
+            share_table: true,
+            ..config.clone()
+        };
+        let header_share = header_with(SolverKind::Many, &config_share);
+        let header_share_decoded = decode_all(
+            &Checkpoint {
+                header: header_share.clone(),
+                cursor: None,
+                best: None,
+                table_count: 0,
+            }
+            .encode(),
+        )
+        .unwrap()
+        .header;
+        assert_eq!(header_share_decoded, header_share);
+        assert!(header_share
+            .ensure_compatible(&header_share_decoded)
+            .is_ok());
+
+        // zobrist テーブル違い。
+        let mut header_bad = header.clone();
+        header_bad.zobrist_fingerprint ^= 1;
+        assert!(header.ensure_compatible(&header_bad).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! ハッシュ衝突の検出関連。
+
+use std::io::{Read, Write};
+
+use anyhow::bail;
+
+use crate::board::Board;
+use crate::checkpoint::{read_u64s, write_u64s};
+use crate::square::SQUARE_NUM_MAX;
+
+/// DP テーブルにおけるハッシュ衝突の検出方法。
+///
+/// DP テーブルのエントリはハッシュ値の一部しか照合しないので、異なる局面がエントリを共有してしまう可能性がある。
+/// 検出を有効にすると、各エントリの局面の情報を副テーブルに記録し、照合時に併せて比較する。
+/// 一致しなければ衝突として数えた上で別の局面として扱うので、探索結果は衝突の影響を受けない。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CollisionCheck {
+    /// 検出しない。
+    None,
+    /// 盤面から Zobrist hash とは独立に計算した 64bit ハッシュ値も照合する (実質 128bit のキー)。
+    ///
+    /// 検出漏れの可能性は残るが、副テーブルは 1 エントリあたり 8 バイトで済む。
+    Key128,
+    /// 盤面全体 (1 マス 3bit で詰めたもの) を照合する。衝突を確実に検出できる。
+    Board,
+}
+
+impl CollisionCheck {
+    /// 盤面サイズ `W`x`H` における副テーブルの 1 エントリあたりの長さ (u64 単位) を返す。
+    pub(crate) fn fingerprint_len<const W: usize, const H: usize>(self) -> usize {
+        match self {
+            Self::None => 0,
+            Self::Key128 => 1,
+            Self::Board => Board::<W, H>::PACKED_LEN,
+        }
+    }
+}
+
+impl std::str::FromStr for CollisionCheck {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "key128" => Ok(Self::Key128),
+            "board" => Ok(Self::Board),
+            _ => bail!("ハッシュ衝突の検出方法が無効: '{s}' (none, key128, board のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for CollisionCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::None => "none",
+            Self::Key128 => "key128",
+            Self::Board => "board",
+        };
+        f.write_str(s)
+    }
+}
+
+/// `Board::PACKED_LEN` の上限。
+pub(crate) const PACKED_LEN_MAX: usize = (3 * SQUARE_NUM_MAX).div_ceil(64);
+
+/// `CollisionCheck::Key128` の指紋の計算に用いる初期値。
+const KEY128_SEED: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// DP テーブルの各スロットに対応する局面の情報 (指紋) を保持する副テーブル。
+///
+/// 世代は DP テーブル側で管理するので、ここでは持たない。
+#[derive(Debug)]
+pub(crate) struct CollisionTable<const W: usize, const H: usize> {
+    check: CollisionCheck,
+    fingerprint_len: usize,
+    array: Box<[u64]>,
+    collision_count: u64,
+}
+
+impl<const W: usize, const H: usize> CollisionTable<W, H> {
+    /// 容量 `capacity` の DP テーブルに対応する副テーブルを作る。
+    pub(crate) fn new(check: CollisionCheck, capacity: usize) -> Self {
+        let fingerprint_len = check.fingerprint_len::<W, H>();
+
+        Self {
+            check,
+            fingerprint_len,
+            array: vec![0; fingerprint_len * capacity].into_boxed_slice(),
+            collision_count: 0,
+        }
+    }
+
+    /// ハッシュ衝突を検出するかどうかを返す。
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.check != CollisionCheck::None
+    }
+
+    /// 前回のリセット以降に検出した衝突の数を返す。
+    pub(crate) fn collision_count(&self) -> u64 {
+        self.collision_count
+    }
+
+    /// 衝突の数を 0 に戻す。
+    pub(crate) fn reset_collision_count(&mut self) {
+        self.collision_count = 0;
+    }
+
+    /// スロット `idx` に記録された指紋が `board` のものと一致するかどうかを返す。
+    /// 検出しない場合は常に `true` を返す。
+    pub(crate) fn matches<const P: usize>(&self, idx: usize, board: &Board<W, H, P>) -> bool {
+        if self.check == CollisionCheck::None {
+            return true;
+        }
+
+        let mut buf = [0; PACKED_LEN_MAX];
+        let fingerprint = self.fingerprint(board, &mut buf);
+
+        self.slot(idx) == fingerprint
+    }
+
+    /// `matches()` と同じだが、一致しなければ衝突として数える。
+    pub(crate) fn verify<const P: usize>(&mut self, idx: usize, board: &Board<W, H, P>) -> bool {
+        let ok = self.matches(idx, board);
+        if !ok {
+            self.collision_count += 1;
+        }
+
+        ok
+    }
+
+    /// スロット `idx` に `board` の指紋を記録する。
+    pub(crate) fn record<const P: usize>(&mut self, idx: usize, board: &Board<W, H, P>) {
+        if self.check == CollisionCheck::None {
+            return;
+        }
+
+        let mut buf = [0; PACKED_LEN_MAX];
+        let fingerprint = self.fingerprint(board, &mut buf);
+
+        let len = self.fingerprint_len;
+        self.array[len * idx..][..len].copy_from_slice(fingerprint);
+    }
+
+    /// 記録した指紋を書き出す (DP テーブルの保存用)。
+    pub(crate) fn write(&self, w: &mut dyn Write) -> anyhow::Result<()> {
+        write_u64s(w, self.array.len(), self.array.iter().copied())
+    }
+
+    /// `write()` で書き出した指紋を読み込む。
+    pub(crate) fn read(&mut self, r: &mut dyn Read) -> anyhow::Result<()> {
+        self.collision_count = 0;
+        read_u64s(r, self.array.len(), |i, x| self.array[i] = x)
+    }
+
+    fn slot(&self, idx: usize) -> &[u64] {
+        let len = self.fingerprint_len;
+        &self.array[len * idx..][..len]
+    }
+
+    /// `board` の指紋を計算する。`buf` は作業領域。
+    fn fingerprint<'buf, const P: usize>(
+        &self,
+        board: &Board<W, H, P>,
+        buf: &'buf mut [u64; PACKED_LEN_MAX],
+    ) -> &'buf [u64] {
+        let packed = &mut buf[..Board::<W, H, P>::PACKED_LEN];
+        board.pack_into(packed);
+
+        match self.check {
+            CollisionCheck::None => &[],
+            CollisionCheck::Key128 => {
+                packed[0] = hash_packed(packed, KEY128_SEED);
+                &packed[..1]
+            }
+            CollisionCheck::Board => packed,
+        }
+    }
+}
+
+/// 盤面を詰めた表現から 64bit ハッシュ値を計算する。
+///
+/// 初期値 `seed` に各ワードを XOR するたびに splitmix64 の finalizer をかける。
+/// (乗算とローテートのみで混ぜると、隣接ワードの差分が打ち消し合って衝突しうる)
+pub(crate) fn hash_packed(words: &[u64], seed: u64) -> u64 {
+    words
+        .iter()
+        .fold(seed, |h, &word| splitmix64_finalize(h ^ word))
+}
+
+fn splitmix64_finalize(mut h: u64) -> u64 {
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94D0_49BB_1331_11EB);
+    h ^= h >> 31;
+
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    #[test]
+    fn test_collision_check_parse() {
+        for check in [
+            CollisionCheck::None,
+            CollisionCheck::Key128,
+            CollisionCheck::Board,
+        ] {
+            assert_eq!(check.to_string().parse::<CollisionCheck>().unwrap(), check);
+        }
+        assert!("foo".parse::<CollisionCheck>().is_err());
+    }
+
+    #[test]
+    fn test_collision_table() {
+        let board_a = parse_board(indoc! {"
+            ........
+            ........
+            .1......
+            121.....
+            1213....
+            1213....
+        "});
+        let board_b = parse_board(indoc! {"
+            ........
+            ........
+            ..1.....
+            121.....
+            1213....
+            1213....
+        "});
+
+        for check in [CollisionCheck::Key128, CollisionCheck::Board] {
+            let mut table = CollisionTable::<8, 6>::new(check, 4);
+
+            table.record(2, &board_a);
+            assert!(table.verify(2, &board_a));
+            assert_eq!(table.collision_count(), 0);
+
+            assert!(!table.matches(2, &board_b));
+            assert_eq!(table.collision_count(), 0);
+            assert!(!table.verify(2, &board_b));
+            assert_eq!(table.collision_count(), 1);
+
+            table.record(2, &board_b);
+            assert!(table.verify(2, &board_b));
+            assert_eq!(table.collision_count(), 1);
+
+            table.reset_collision_count();
+            assert_eq!(table.collision_count(), 0);
+        }
+
+        // 検出しない場合は常に一致する。
+        let mut table = CollisionTable::<8, 6>::new(CollisionCheck::None, 4);
+        table.record(2, &board_a);
+        assert!(table.verify(2, &board_b));
+        assert_eq!(table.collision_count(), 0);
+    }
+}
@@ -1,29 +1,66 @@
 //! SFC『鮫亀』: さめがめ「かんたん」モード用ソルバーライブラリ。
+//!
+//! 盤面のサイズと駒種数は型引数で指定できる (デフォルトは「かんたん」モードの 8x6, 5 種)。
+//! ゲーム内乱数による盤面生成は「かんたん」モードのみ対応。
 
 mod action;
 mod asset;
 mod bitop;
 mod board;
+mod checkpoint;
+mod collision;
 mod hint;
+mod movie;
 mod piece;
 mod position;
+mod replay;
+mod results_db;
 mod rng;
+mod rng_search;
 mod score;
+mod score_bound;
+mod score_stats;
+mod sleep_set;
+mod solution_dag;
+mod solve;
 mod solver;
 mod solver2;
+mod solver_aspiration;
+mod solver_beam;
+#[cfg(test)]
+mod solver_diff;
+mod solver_k_best;
 mod solver_many;
+mod solver_mcts;
+mod solver_parallel;
 mod square;
 mod util;
 mod zobrist;
 
 pub use self::action::*;
+pub use self::bitop::PextImpl;
 pub use self::board::*;
+pub use self::checkpoint::*;
+pub use self::collision::*;
+pub use self::movie::*;
 pub use self::piece::*;
 pub use self::position::*;
+pub use self::replay::*;
+pub use self::results_db::*;
 pub use self::rng::*;
+pub use self::rng_search::*;
 pub use self::score::*;
+pub use self::score_bound::*;
+pub use self::score_stats::*;
+pub use self::solution_dag::*;
+pub use self::solve::*;
 pub use self::solver::*;
 pub use self::solver2::*;
+pub use self::solver_aspiration::*;
+pub use self::solver_beam::*;
+pub use self::solver_k_best::*;
 pub use self::solver_many::*;
+pub use self::solver_mcts::*;
+pub use self::solver_parallel::*;
 pub use self::square::*;
 pub use self::zobrist::*;
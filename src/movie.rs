@@ -0,0 +1,424 @@
+//! エミュレータ用の入力ムービー関連。
+//!
+//! 手順をフレームごとの SNES コントローラ入力に変換し、ムービー形式で出力する。
+//!
+//! ゲーム側の挙動について以下を仮定している:
+//!
+//! * カーソルは D-pad を 1 回押すごとに 1 マス動き、盤面の端で折り返さない。
+//! * 着手後もカーソルの座標はそのまま (駒が詰められてもカーソルは動かない)。
+//!
+//! 各種待ちフレーム数は実機での計測に基づいて `MovieConfig` で調整すること。
+//!
+//! 着手ではその駒群のどのマスを選んでもよいので、カーソル移動が最短になるよう手順を最適化できる。
+
+use std::io::Write;
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::replay::{action_at, verify, ReplayError};
+use crate::score::Score;
+use crate::square::Square;
+
+/// 1 フレーム分の SNES コントローラ入力 (押されているボタンの集合)。
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Joypad(u16);
+
+impl Joypad {
+    pub const UP: Self = Self(1 << 0);
+    pub const DOWN: Self = Self(1 << 1);
+    pub const LEFT: Self = Self(1 << 2);
+    pub const RIGHT: Self = Self(1 << 3);
+    pub const SELECT: Self = Self(1 << 4);
+    pub const START: Self = Self(1 << 5);
+    pub const Y: Self = Self(1 << 6);
+    pub const B: Self = Self(1 << 7);
+    pub const X: Self = Self(1 << 8);
+    pub const A: Self = Self(1 << 9);
+    pub const L: Self = Self(1 << 10);
+    pub const R: Self = Self(1 << 11);
+
+    /// BizHawk の SNES コントローラにおけるボタンの並びとニーモニック。
+    const BIZHAWK_BUTTONS: [(Self, char); 12] = [
+        (Self::UP, 'U'),
+        (Self::DOWN, 'D'),
+        (Self::LEFT, 'L'),
+        (Self::RIGHT, 'R'),
+        (Self::SELECT, 's'),
+        (Self::START, 'S'),
+        (Self::Y, 'Y'),
+        (Self::B, 'B'),
+        (Self::X, 'X'),
+        (Self::A, 'A'),
+        (Self::L, 'l'),
+        (Self::R, 'r'),
+    ];
+
+    /// 何も押されていない入力を返す。
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// 何も押されていないかどうかを返す。
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// `other` のボタンが全て押されているかどうかを返す。
+    pub const fn contains(self, other: Self) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl std::ops::BitOr for Joypad {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for Joypad {
+    fn bitor_assign(&mut self, rhs: Self) {
+        *self = *self | rhs;
+    }
+}
+
+/// 入力ムービー生成の設定。
+///
+/// デフォルト値は仮のもの (カーソルは左下から開始) なので、実機に合わせて調整すること。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MovieConfig<const W: usize = 8, const H: usize = 6> {
+    /// 最初の入力を受け付ける時点でのカーソル位置。
+    pub cursor_start: Square<W, H>,
+
+    /// 最初の入力の前に置く空入力のフレーム数。
+    pub lead_frames: usize,
+
+    /// 1 回のボタン入力 (D-pad, A) で押し続けるフレーム数。
+    pub press_frames: usize,
+
+    /// D-pad を離してから次の入力までのフレーム数。
+    pub move_wait_frames: usize,
+
+    /// A を離してから次の入力を受け付けるまでのフレーム数 (駒が消えて詰められるのを待つ)。
+    pub erase_wait_frames: usize,
+}
+
+impl<const W: usize, const H: usize> Default for MovieConfig<W, H> {
+    fn default() -> Self {
+        Self {
+            cursor_start: Square::MIN,
+            lead_frames: 0,
+            press_frames: 1,
+            move_wait_frames: 1,
+            erase_wait_frames: 30,
+        }
+    }
+}
+
+/// 手順をフレームごとのコントローラ入力に変換する。
+///
+/// 各着手について、カーソルを左右、上下の順に目的のマスまで動かしてから A を押す。
+pub fn solution_to_inputs<const W: usize, const H: usize>(
+    solution: &ActionHistory<W, H>,
+    config: &MovieConfig<W, H>,
+) -> Vec<Joypad> {
+    assert!(config.press_frames > 0);
+
+    let mut inputs = vec![Joypad::empty(); config.lead_frames];
+
+    let mut press = |button: Joypad, wait_frames: usize| {
+        inputs.extend(std::iter::repeat_n(button, config.press_frames));
+        inputs.extend(std::iter::repeat_n(Joypad::empty(), wait_frames));
+    };
+
+    let mut cursor = config.cursor_start;
+    for &sq in solution {
+        let dx = i32::from(sq.col().to_inner()) - i32::from(cursor.col().to_inner());
+        let dy = i32::from(sq.row().to_inner()) - i32::from(cursor.row().to_inner());
+
+        // 行番号は下から数えるので、上に動かすと増える。
+        let button_x = if dx > 0 { Joypad::RIGHT } else { Joypad::LEFT };
+        let button_y = if dy > 0 { Joypad::UP } else { Joypad::DOWN };
+        for _ in 0..dx.unsigned_abs() {
+            press(button_x, config.move_wait_frames);
+        }
+        for _ in 0..dy.unsigned_abs() {
+            press(button_y, config.move_wait_frames);
+        }
+
+        press(Joypad::A, config.erase_wait_frames);
+
+        cursor = sq;
+    }
+
+    inputs
+}
+
+/// `solution_to_inputs()` が返す入力列の長さ (フレーム数) を返す。
+pub fn input_frame_count<const W: usize, const H: usize>(
+    solution: &ActionHistory<W, H>,
+    config: &MovieConfig<W, H>,
+) -> usize {
+    let mut res = config.lead_frames;
+
+    let mut cursor = config.cursor_start;
+    for &sq in solution {
+        res += cursor_distance(cursor, sq) * (config.press_frames + config.move_wait_frames);
+        res += config.press_frames + config.erase_wait_frames;
+        cursor = sq;
+    }
+
+    res
+}
+
+/// 各着手で選ぶマスを、`config.cursor_start` からのカーソル移動回数の合計が最小になるよう選び直した手順を返す。
+///
+/// 各着手の駒群 (よってスコア) は変わらない。
+/// 駒が詰められた後の盤面上で次の駒群のマスを選ぶので、座標のずれも考慮される。
+pub fn optimize_cursor_path<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    solution: &ActionHistory<W, H>,
+    config: &MovieConfig<W, H>,
+) -> Result<ActionHistory<W, H>, ReplayError<W, H>> {
+    // layers[i]: i 手目で選べる各マスについて (マス, 最小移動回数, 前の手で選んだマスのインデックス)。
+    let mut layers: Vec<Vec<(Square<W, H>, usize, usize)>> = Vec::with_capacity(solution.len());
+
+    let mut board = board;
+    for (index, &sq) in solution.iter().enumerate() {
+        let action = action_at(&board, index, sq)?;
+
+        let layer = action
+            .mask()
+            .squares()
+            .map(|dst| match layers.last() {
+                None => (dst, cursor_distance(config.cursor_start, dst), 0),
+                Some(prev) => prev
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &(src, cost, _))| (dst, cost + cursor_distance(src, dst), i))
+                    .min_by_key(|&(_, cost, _)| cost)
+                    .unwrap(),
+            })
+            .collect();
+        layers.push(layer);
+
+        board = board.erase(action.mask());
+    }
+
+    let Some(last) = layers.last() else {
+        return Ok(ActionHistory::new());
+    };
+
+    let mut sqs = Vec::with_capacity(layers.len());
+    let mut i = (0..last.len()).min_by_key(|&i| last[i].1).unwrap();
+    for layer in layers.iter().rev() {
+        let (sq, _, parent) = layer[i];
+        sqs.push(sq);
+        i = parent;
+    }
+
+    Ok(sqs.into_iter().rev().collect())
+}
+
+/// 複数の手順からスコアが最大のものを選び、カーソル移動を最適化して返す。
+/// スコアが等しい手順同士では入力フレーム数が最小のものを選ぶ。
+///
+/// 最適化後の手順とそのスコア、入力フレーム数を返す。`solutions` が空ならば `None` を返す。
+pub fn select_fastest_solution<const W: usize, const H: usize, const P: usize>(
+    board: &Board<W, H, P>,
+    solutions: impl IntoIterator<Item = ActionHistory<W, H>>,
+    config: &MovieConfig<W, H>,
+) -> Result<Option<(ActionHistory<W, H>, Score, usize)>, ReplayError<W, H>> {
+    let mut best: Option<(ActionHistory<W, H>, Score, usize)> = None;
+
+    for solution in solutions {
+        let score = verify(board.clone(), &solution)?;
+        let solution = optimize_cursor_path(board.clone(), &solution, config)?;
+        let frame_count = input_frame_count(&solution, config);
+
+        let better = best
+            .as_ref()
+            .is_none_or(|&(_, best_score, best_frame_count)| {
+                (score, std::cmp::Reverse(frame_count))
+                    > (best_score, std::cmp::Reverse(best_frame_count))
+            });
+        if better {
+            best = Some((solution, score, frame_count));
+        }
+    }
+
+    Ok(best)
+}
+
+/// マス `src` から `dst` へカーソルを動かすのに必要な D-pad の入力回数を返す。
+fn cursor_distance<const W: usize, const H: usize>(src: Square<W, H>, dst: Square<W, H>) -> usize {
+    let dx = src.col().to_index().abs_diff(dst.col().to_index());
+    let dy = src.row().to_index().abs_diff(dst.row().to_index());
+    dx + dy
+}
+
+/// 入力列を BizHawk の `Input Log.txt` 形式 (SNES, コントローラ 1 個) で書き出す。
+///
+/// `.bk2` ファイル (zip) 内の `Input Log.txt` を置き換えて用いる。
+pub fn write_bizhawk_input_log(mut wtr: impl Write, inputs: &[Joypad]) -> std::io::Result<()> {
+    writeln!(wtr, "[Input]")?;
+
+    write!(wtr, "LogKey:#Reset|Power|#")?;
+    for (i, name) in [
+        "Up", "Down", "Left", "Right", "Select", "Start", "Y", "B", "X", "A", "L", "R",
+    ]
+    .iter()
+    .enumerate()
+    {
+        if i != 0 {
+            write!(wtr, "|")?;
+        }
+        write!(wtr, "P1 {name}")?;
+    }
+    writeln!(wtr, "|")?;
+
+    for &input in inputs {
+        let mut line = String::from("|..|");
+        for (button, ch) in Joypad::BIZHAWK_BUTTONS {
+            line.push(if input.contains(button) { ch } else { '.' });
+        }
+        line.push('|');
+        writeln!(wtr, "{line}")?;
+    }
+
+    writeln!(wtr, "[/Input]")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::asset::asset_include_str;
+    use crate::score::SCORE_PERFECT;
+    use crate::square::*;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn sq_new(col: Col, row: Row) -> Square {
+        Square::new(col, row)
+    }
+
+    fn solution_new(sqs: impl IntoIterator<Item = Square>) -> ActionHistory {
+        sqs.into_iter().collect()
+    }
+
+    #[test]
+    fn test_solution_to_inputs() {
+        let config = MovieConfig {
+            cursor_start: sq_new(COL_2, ROW_2),
+            lead_frames: 1,
+            press_frames: 2,
+            move_wait_frames: 1,
+            erase_wait_frames: 3,
+        };
+        let solution = solution_new([sq_new(COL_1, ROW_3), sq_new(COL_1, ROW_3)]);
+
+        let e = Joypad::empty();
+        let expect = [
+            vec![e],
+            vec![Joypad::LEFT, Joypad::LEFT, e],
+            vec![Joypad::UP, Joypad::UP, e],
+            vec![Joypad::A, Joypad::A, e, e, e],
+            vec![Joypad::A, Joypad::A, e, e, e],
+        ]
+        .concat();
+
+        assert_eq!(solution_to_inputs(&solution, &config), expect);
+    }
+
+    #[test]
+    fn test_optimize_cursor_path() {
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            1.......
+            22......
+            1133....
+        "});
+        let config = MovieConfig {
+            cursor_start: sq_new(COL_4, ROW_1),
+            ..Default::default()
+        };
+        let solution = solution_new([
+            sq_new(COL_1, ROW_2),
+            sq_new(COL_1, ROW_1),
+            sq_new(COL_1, ROW_1),
+        ]);
+
+        // 2 手目以降は駒が詰められた後の座標で選ぶ。
+        let optimized = optimize_cursor_path(board.clone(), &solution, &config).unwrap();
+        assert_eq!(
+            optimized,
+            solution_new([
+                sq_new(COL_2, ROW_2),
+                sq_new(COL_2, ROW_1),
+                sq_new(COL_2, ROW_1),
+            ])
+        );
+        assert_eq!(
+            verify(board.clone(), &optimized),
+            verify(board.clone(), &solution)
+        );
+
+        for solution in [&solution, &optimized] {
+            assert_eq!(
+                input_frame_count(solution, &config),
+                solution_to_inputs(solution, &config).len()
+            );
+        }
+        assert_eq!(input_frame_count(&optimized, &config), 4 * 2 + 3 * (1 + 30));
+
+        assert_eq!(
+            select_fastest_solution(&board, [solution.clone(), optimized.clone()], &config),
+            Ok(Some((
+                optimized,
+                1 + 1 + 4 + SCORE_PERFECT,
+                4 * 2 + 3 * (1 + 30)
+            )))
+        );
+        assert_eq!(select_fastest_solution(&board, [], &config), Ok(None));
+
+        assert!(
+            optimize_cursor_path(board, &solution_new([sq_new(COL_4, ROW_2)]), &config).is_err()
+        );
+    }
+
+    #[test]
+    fn test_write_bizhawk_input_log() {
+        let config = MovieConfig {
+            cursor_start: sq_new(COL_1, ROW_1),
+            lead_frames: 2,
+            press_frames: 1,
+            move_wait_frames: 1,
+            erase_wait_frames: 2,
+        };
+        let solution = solution_new([
+            sq_new(COL_3, ROW_2),
+            sq_new(COL_2, ROW_1),
+            sq_new(COL_2, ROW_1),
+        ]);
+        let inputs = solution_to_inputs(&solution, &config);
+
+        let mut buf = Vec::new();
+        write_bizhawk_input_log(&mut buf, &inputs).unwrap();
+
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            asset_include_str!("test/bizhawk_input_log.txt")
+        );
+    }
+}
@@ -4,16 +4,23 @@ use std::num::NonZeroU8;
 
 use crate::hint::assert_unchecked;
 
+/// 駒種数の上限。
+pub const PIECE_NUM_MAX: usize = 7;
+
 /// 駒種。
+///
+/// `P` は駒種数で、`1..=PIECE_NUM_MAX` でなければならない。
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub struct Piece(NonZeroU8);
+pub struct Piece<const P: usize = 5>(NonZeroU8);
 
-impl Piece {
-    pub const NUM: usize = 5;
+impl<const P: usize> Piece<P> {
+    pub const NUM: usize = P;
 
     pub const MIN_VALUE: u8 = 1;
-    pub const MAX_VALUE: u8 = 5;
+    pub const MAX_VALUE: u8 = P as u8;
+
+    const DIMS_OK: () = assert!(1 <= P && P <= PIECE_NUM_MAX, "駒種数が無効");
 
     /// 内部値から駒種を作る。
     pub const fn from_inner(inner: u8) -> Option<Self> {
@@ -30,13 +37,14 @@ impl Piece {
     ///
     /// `inner` は有効値でなければならない。
     pub const unsafe fn from_inner_unchecked(inner: u8) -> Self {
+        let () = Self::DIMS_OK;
         assert_unchecked!(Self::is_valid(inner));
 
         Self(NonZeroU8::new_unchecked(inner))
     }
 
     const fn is_valid(inner: u8) -> bool {
-        matches!(inner, Self::MIN_VALUE..=Self::MAX_VALUE)
+        Self::MIN_VALUE <= inner && inner <= Self::MAX_VALUE
     }
 
     /// 内部値を返す。
@@ -61,38 +69,38 @@ impl Piece {
 /// `Piece` でインデックスアクセスできる配列。
 #[repr(transparent)]
 #[derive(Clone, Debug, Eq, PartialEq)]
-pub struct PieceArray<T>([T; Piece::NUM]);
+pub struct PieceArray<T, const P: usize = 5>([T; P]);
 
-impl<T: Default> Default for PieceArray<T> {
+impl<T: Default, const P: usize> Default for PieceArray<T, P> {
     fn default() -> Self {
         Self::from_fn(|_| T::default())
     }
 }
 
-impl<T: Clone> PieceArray<T> {
+impl<T: Clone, const P: usize> PieceArray<T, P> {
     pub fn from_elem(elem: T) -> Self {
         Self::from_fn(|_| elem.clone())
     }
 }
 
-impl<T> PieceArray<T> {
-    pub const fn new(inner: [T; Piece::NUM]) -> Self {
+impl<T, const P: usize> PieceArray<T, P> {
+    pub const fn new(inner: [T; P]) -> Self {
         Self(inner)
     }
 
-    pub fn from_fn(mut f: impl FnMut(Piece) -> T) -> Self {
+    pub fn from_fn(mut f: impl FnMut(Piece<P>) -> T) -> Self {
         Self::new(std::array::from_fn(|i| {
             f(unsafe { Piece::from_inner_unchecked((i + 1) as u8) })
         }))
     }
 
-    pub const fn as_array(&self) -> &[T; Piece::NUM] {
+    pub const fn as_array(&self) -> &[T; P] {
         &self.0
     }
 
     pub fn enumerate(
         &self,
-    ) -> impl DoubleEndedIterator<Item = (Piece, &T)>
+    ) -> impl DoubleEndedIterator<Item = (Piece<P>, &T)>
            + ExactSizeIterator
            + std::iter::FusedIterator
            + Clone {
@@ -100,16 +108,16 @@ impl<T> PieceArray<T> {
     }
 }
 
-impl<T> std::ops::Index<Piece> for PieceArray<T> {
+impl<T, const P: usize> std::ops::Index<Piece<P>> for PieceArray<T, P> {
     type Output = T;
 
-    fn index(&self, piece: Piece) -> &Self::Output {
+    fn index(&self, piece: Piece<P>) -> &Self::Output {
         unsafe { self.0.get_unchecked(piece.to_index()) }
     }
 }
 
-impl<T> std::ops::IndexMut<Piece> for PieceArray<T> {
-    fn index_mut(&mut self, piece: Piece) -> &mut Self::Output {
+impl<T, const P: usize> std::ops::IndexMut<Piece<P>> for PieceArray<T, P> {
+    fn index_mut(&mut self, piece: Piece<P>) -> &mut Self::Output {
         unsafe { self.0.get_unchecked_mut(piece.to_index()) }
     }
 }
@@ -1,43 +1,96 @@
 //! 局面関連。
 
+use std::sync::{Arc, OnceLock};
+
 use crate::action::Action;
-use crate::board::Board;
+use crate::bitop;
+use crate::board::{Board, MaskBoard};
+use crate::collision::hash_packed;
 use crate::piece::{Piece, PieceArray};
 use crate::score::{score_erase, Score, SCORE_PERFECT};
 use crate::square::Square;
-use crate::zobrist::{Key, ZOBRIST_TABLE};
+use crate::zobrist::{Key, ZobristTable};
 
 /// 局面。
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct Position {
-    board: Board,
+///
+/// 盤面の連結成分のラベリングとして、合法手 (2 マス以上の連結成分) の一覧を保持する。
+/// 着手後の局面では、駒が落下する列とその両隣に掛からない連結成分を着手前の局面から引き継ぎ、
+/// それらの列のみ求め直す。
+///
+/// 一覧は初めて要求された際に求める (DP テーブルに載っていた局面などでは求めずに済む)。
+#[derive(Clone, Debug)]
+pub struct Position<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    board: Board<W, H, P>,
     key: Key,
-    piece_counts: PieceArray<u8>,
+    /// 駒種ごとの駒の配置のハッシュ値 (`canonical_key()` に用いる)。
+    piece_keys: PieceArray<Key, P>,
+    piece_counts: PieceArray<u8, P>,
+    /// 合法手の一覧。駒種、最小のマスの順に昇順。
+    actions: OnceLock<Arc<[Action<W, H, P>]>>,
+    /// 着手前の局面の合法手の一覧 (`actions` を差分更新するのに使う)。
+    origin: Option<ActionsOrigin<W, H, P>>,
+}
+
+/// 着手前の局面の合法手の一覧と、着手により更新が必要な列の情報。
+#[derive(Clone, Debug)]
+struct ActionsOrigin<const W: usize, const H: usize, const P: usize> {
+    actions: Arc<[Action<W, H, P>]>,
+    /// 着手により駒が落下する列とその両隣 (着手前の盤面における列集合)。
+    dirty_cols: u32,
+    /// 着手により列が消滅する場合、着手後も残る列 (着手前の盤面における列集合)。
+    survived_cols: Option<u32>,
+}
+
+impl<const W: usize, const H: usize, const P: usize> PartialEq for Position<W, H, P> {
+    fn eq(&self, other: &Self) -> bool {
+        // 合法手の一覧は盤面から定まるので比較しない。
+        self.board == other.board
+            && self.key == other.key
+            && self.piece_keys == other.piece_keys
+            && self.piece_counts == other.piece_counts
+    }
 }
 
-impl Position {
+impl<const W: usize, const H: usize, const P: usize> Eq for Position<W, H, P> {}
+
+impl<const W: usize, const H: usize, const P: usize> Position<W, H, P> {
+    const ZOBRIST_TABLE: ZobristTable<W, H, P> = ZobristTable;
+
+    /// `canonical_key()` の計算に用いる初期値 (`CollisionCheck::Key128` の指紋とは異なる値にする)。
+    const CANONICAL_KEY_SEED: u64 = 0x2545_F491_4F6C_DD1D;
+
     /// 初期盤面を指定して局面を作る。
-    pub fn new(board: Board) -> Self {
+    pub fn new(board: Board<W, H, P>) -> Self {
         let key = Square::all()
             .map(|sq| {
                 board
                     .get(sq)
-                    .map_or(0, |piece| ZOBRIST_TABLE.board(piece, sq))
+                    .map_or(0, |piece| Self::ZOBRIST_TABLE.board(piece, sq))
             })
             .reduce(std::ops::BitXor::bitxor)
             .unwrap();
 
+        let mut piece_keys = PieceArray::<Key, P>::default();
+        for sq in Square::all() {
+            if let Some(piece) = board.get(sq) {
+                piece_keys[piece] ^= Self::ZOBRIST_TABLE.square(sq);
+            }
+        }
+
         let piece_counts = PieceArray::from_fn(|piece| board.piece_count(piece) as u8);
 
         Self {
             board,
             key,
+            piece_keys,
             piece_counts,
+            actions: OnceLock::new(),
+            origin: None,
         }
     }
 
     /// 盤面を返す。
-    pub fn board(&self) -> &Board {
+    pub fn board(&self) -> &Board<W, H, P> {
         &self.board
     }
 
@@ -46,47 +99,142 @@ impl Position {
         self.key
     }
 
+    /// 駒種の付け替えによらないハッシュ値を返す。
+    ///
+    /// 駒種を盤面上での初出順に付け替えた盤面 (`Board::canonical()`) が一致する局面どうしは同じ値になる。
+    /// これは駒種ごとの駒の配置の多重集合が一致することと同値なので、差分計算した駒種ごとの配置のハッシュ値を
+    /// 整列してから混ぜる。スコアは駒種の付け替えによらないので、DP テーブルのキーとして使える。
+    /// 値は `key()` とは無関係。
+    pub fn canonical_key(&self) -> Key {
+        let mut piece_keys = *self.piece_keys.as_array();
+        piece_keys.sort_unstable();
+
+        hash_packed(&piece_keys, Self::CANONICAL_KEY_SEED)
+    }
+
     /// 指定した駒種の数を返す。
-    pub fn piece_count(&self, piece: Piece) -> u8 {
+    pub fn piece_count(&self, piece: Piece<P>) -> u8 {
         self.piece_counts[piece]
     }
 
     /// 合法手があるかどうかを返す。
+    ///
+    /// 合法手の一覧をまだ求めていなければ、連結成分を求めずに判定する (`Board::has_action()`)。
     pub fn has_action(&self) -> bool {
-        // TODO: より高速化できる
-        self.actions().next().is_some()
+        match self.actions.get() {
+            Some(actions) => !actions.is_empty(),
+            None => self.board.has_action(),
+        }
+    }
+
+    /// 合法手を駒種、最小のマスの順に昇順で列挙する。
+    pub fn actions(&self) -> impl std::iter::FusedIterator<Item = Action<W, H, P>> + Clone + '_ {
+        self.action_list().iter().cloned()
+    }
+
+    fn action_list(&self) -> &Arc<[Action<W, H, P>]> {
+        self.actions.get_or_init(|| {
+            let mut actions = Vec::new();
+            match &self.origin {
+                None => Self::push_actions_in_cols(&self.board, u32::MAX, &mut actions),
+                Some(origin) => self.update_actions(origin, &mut actions),
+            }
+            actions.sort_unstable_by_key(Self::action_order);
+            actions.into()
+        })
+    }
+
+    /// 着手前の局面の合法手の一覧から、この局面の合法手の一覧 (未整列) を求める。
+    ///
+    /// 駒が落下する列とその両隣の外では、マスどうしの隣接関係が変わらない。
+    /// よって、それらの列に掛からない連結成分はそのまま (列が消滅した場合は左に詰めて) 引き継げる。
+    fn update_actions(&self, origin: &ActionsOrigin<W, H, P>, actions: &mut Vec<Action<W, H, P>>) {
+        let ActionsOrigin {
+            actions: actions_origin,
+            dirty_cols,
+            survived_cols,
+        } = origin;
+
+        let actions_kept = actions_origin
+            .iter()
+            .filter(|action| action.mask().col_mask() & dirty_cols == 0);
+
+        let dirty_cols = match survived_cols {
+            None => {
+                actions.extend(actions_kept.cloned());
+                *dirty_cols
+            }
+            Some(survived_cols) => {
+                actions.extend(actions_kept.map(|action| {
+                    Action::new(action.piece(), action.mask().compact_cols(*survived_cols))
+                }));
+                bitop::u32_pext_software(*dirty_cols, *survived_cols)
+            }
+        };
+
+        Self::push_actions_in_cols(&self.board, dirty_cols, actions);
+    }
+
+    /// 合法手の一覧の並び順のキー。
+    fn action_order(action: &Action<W, H, P>) -> (usize, Square<W, H>) {
+        (action.piece().to_index(), action.least_square())
     }
 
-    /// 合法手を列挙する。
-    pub fn actions(&self) -> impl std::iter::FusedIterator<Item = Action> + Clone + '_ {
-        self.board
-            .piece_components()
-            .filter(|(_piece, mb)| !mb.is_single())
-            .map(|(piece, mb)| Action::new(piece, mb))
+    /// `board` の合法手のうち、列集合 `cols` (bit i が列 i+1 に対応) に掛かるものを `actions` に追加する。
+    fn push_actions_in_cols(board: &Board<W, H, P>, cols: u32, actions: &mut Vec<Action<W, H, P>>) {
+        // 孤立駒から flood fill せずに済むよう、合法手に含まれるマスのみを始点とする。
+        let mut seeds = board.action_squares().filter_cols(cols);
+        let mut piece_masks = PieceArray::<Option<MaskBoard<W, H>>, P>::default();
+
+        while let Some(sq) = seeds.least_square() {
+            let piece = board.get(sq).unwrap();
+            let mask = piece_masks[piece].get_or_insert_with(|| board.piece_mask(piece));
+            let comp = mask.flood_fill(sq);
+            seeds.subtract_assign(&comp);
+            actions.push(Action::new(piece, comp));
+        }
     }
 
     /// 着手を行い、結果の局面を返す。
-    pub fn do_action(&self, action: &Action) -> Self {
+    pub fn do_action(&self, action: &Action<W, H, P>) -> Self {
         let board = self.board.erase(action.mask());
 
         let mut key = self.key;
+        let mut piece_keys = self.piece_keys.clone();
         for sq in self.board.xor_mask(&board).squares() {
             // 着手前、sq には駒があったとは限らないことに注意(列が詰め直されるケースがあるので)。
             if let Some(piece_before) = self.board.get(sq) {
-                key ^= ZOBRIST_TABLE.board(piece_before, sq);
+                key ^= Self::ZOBRIST_TABLE.board(piece_before, sq);
+                piece_keys[piece_before] ^= Self::ZOBRIST_TABLE.square(sq);
             }
             if let Some(piece_after) = board.get(sq) {
-                key ^= ZOBRIST_TABLE.board(piece_after, sq);
+                key ^= Self::ZOBRIST_TABLE.board(piece_after, sq);
+                piece_keys[piece_after] ^= Self::ZOBRIST_TABLE.square(sq);
             }
         }
 
         let mut piece_counts = self.piece_counts.clone();
         piece_counts[action.piece()] -= action.square_count() as u8;
 
+        // 着手前の局面の合法手の一覧を求めていれば、この局面の一覧はそれを差分更新して求める。
+        let origin = self.actions.get().map(|actions| {
+            let cols_erased = action.mask().col_mask();
+            let cols_emptied = self.board.erase_emptied_col_mask(action.mask());
+            ActionsOrigin {
+                actions: Arc::clone(actions),
+                dirty_cols: cols_erased | (cols_erased << 1) | (cols_erased >> 1),
+                survived_cols: (cols_emptied != 0)
+                    .then(|| (u32::MAX >> (32 - self.board.width_remain())) & !cols_emptied),
+            }
+        });
+
         Self {
             board,
             key,
+            piece_keys,
             piece_counts,
+            actions: OnceLock::new(),
+            origin,
         }
     }
 
@@ -121,7 +269,9 @@ impl Position {
 #[cfg(test)]
 mod tests {
     use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
 
+    use crate::rng::GameRng;
     use crate::square::*;
 
     use super::*;
@@ -141,7 +291,7 @@ mod tests {
 
     #[test]
     fn test_position() {
-        assert_eq!(Position::new(Board::empty()).key(), 0);
+        assert_eq!(Position::new(<Board>::empty()).key(), 0);
 
         let pos_start = Position::new(parse_board(indoc! {"
             1......2
@@ -168,4 +318,100 @@ mod tests {
             assert_eq!(pos.piece_count(piece), pos_expect.piece_count(piece));
         }
     }
+
+    #[test]
+    fn test_position_canonical_key() {
+        let board = parse_board(indoc! {"
+            1......2
+            155....2
+            111.4..2
+            12144..1
+            12133.51
+            12135551
+        "});
+        // 駒種 1..=5 を 3, 5, 1, 4, 2 に付け替えた盤面。
+        let relabeled = parse_board(indoc! {"
+            3......5
+            322....5
+            333.4..5
+            35344..3
+            35311.23
+            35312223
+        "});
+        let pos = Position::new(board);
+        let pos_relabeled = Position::new(relabeled);
+        assert_ne!(pos.key(), pos_relabeled.key());
+        assert_eq!(pos.canonical_key(), pos_relabeled.canonical_key());
+
+        // 着手後の局面どうしも一致する。
+        let pos = pos_do_action(&pos, sq_new(COL_2, ROW_5));
+        let pos_relabeled = pos_do_action(&pos_relabeled, sq_new(COL_2, ROW_5));
+        assert_eq!(pos.canonical_key(), pos_relabeled.canonical_key());
+
+        // 駒種の付け替えでは移り合わない局面とは一致しない。
+        let pos_other = pos_do_action(&pos, sq_new(COL_1, ROW_1));
+        assert_ne!(pos.canonical_key(), pos_other.canonical_key());
+        assert_eq!(
+            Position::new(pos.board().canonical()).canonical_key(),
+            pos.canonical_key()
+        );
+
+        // 差分計算した値は、駒種を付け替えた盤面から直接求めたものと一致する。
+        let mut rng = StdRng::seed_from_u64(2);
+        for state in 0..20 {
+            let Some(board) = GameRng::new(state).gen_board(0x00, 39) else {
+                continue;
+            };
+            let mut pos = Position::new(board);
+            loop {
+                assert_eq!(
+                    pos.canonical_key(),
+                    Position::new(pos.board().canonical()).canonical_key()
+                );
+
+                let actions: Vec<_> = pos.actions().collect();
+                if actions.is_empty() {
+                    break;
+                }
+                pos = pos.do_action(&actions[rng.gen_range(0..actions.len())]);
+            }
+        }
+    }
+
+    /// 差分更新した合法手の一覧は、盤面から直接求めたものと一致する。
+    #[test]
+    fn test_position_actions() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut emptied_count = 0;
+
+        for state in 0..50 {
+            let Some(board) = GameRng::new(state).gen_board(0x00, 39) else {
+                continue;
+            };
+            let mut pos = Position::new(board);
+            loop {
+                let actions: Vec<_> = pos
+                    .board()
+                    .piece_components()
+                    .filter(|(_piece, mb)| !mb.is_single())
+                    .map(|(piece, mb)| Action::new(piece, mb))
+                    .collect();
+                assert_eq!(pos.actions().collect::<Vec<_>>(), actions);
+                assert_eq!(pos.has_action(), !actions.is_empty());
+                assert_eq!(pos.board().has_action(), !actions.is_empty());
+
+                if actions.is_empty() {
+                    break;
+                }
+                let action = &actions[rng.gen_range(0..actions.len())];
+                if pos.board().erase_empties_col(action.mask()) {
+                    emptied_count += 1;
+                }
+                pos = pos.do_action(action);
+            }
+        }
+
+        // 列が消滅する着手も十分に含まれている。
+        assert!(emptied_count >= 10);
+    }
 }
@@ -0,0 +1,215 @@
+//! 手順の再生・検証関連。
+
+use crate::action::{Action, ActionHistory};
+use crate::board::Board;
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::square::Square;
+
+/// 手順の再生に失敗した理由。
+///
+/// `index` は失敗した着手の手順内でのインデックス (0 始まり)。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ReplayError<const W: usize = 8, const H: usize = 6> {
+    /// 指定したマスに駒がない。
+    NoPiece { index: usize, sq: Square<W, H> },
+    /// 指定したマスの駒が孤立している (同種の駒が繋がっていない)。
+    IsolatedPiece { index: usize, sq: Square<W, H> },
+    /// 手順を全て再生した後も合法手が残っている。
+    ActionsRemain,
+}
+
+impl<const W: usize, const H: usize> std::fmt::Display for ReplayError<W, H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoPiece { index, sq } => write!(f, "{} 手目: マス {sq} に駒がない", index + 1),
+            Self::IsolatedPiece { index, sq } => {
+                write!(f, "{} 手目: マス {sq} の駒が孤立している", index + 1)
+            }
+            Self::ActionsRemain => f.write_str("手順の終了後も合法手が残っている"),
+        }
+    }
+}
+
+impl<const W: usize, const H: usize> std::error::Error for ReplayError<W, H> {}
+
+/// 盤面 `board` 上で手順 `solution` を再生し、最終局面まで到達したならそのスコアを返す。
+///
+/// 最終局面で盤面が空ならパーフェクトボーナスも加算する。
+/// 途中で不正な着手があるか、手順の終了後も合法手が残っている場合はエラーを返す。
+pub fn verify<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    solution: &ActionHistory<W, H>,
+) -> Result<Score, ReplayError<W, H>> {
+    let mut pos = Position::new(board);
+    let mut score = 0;
+
+    for (index, &sq) in solution.iter().enumerate() {
+        let action = action_at(pos.board(), index, sq)?;
+        score += score_erase(action.square_count());
+        pos = pos.do_action(&action);
+    }
+
+    if pos.board().is_empty() {
+        score += SCORE_PERFECT;
+    } else if pos.has_action() {
+        return Err(ReplayError::ActionsRemain);
+    }
+
+    Ok(score)
+}
+
+/// 盤面 `board` 上で手順 `solution` を再生し、盤面が空になるかどうかを返す。
+///
+/// 途中で不正な着手がある場合は `false` を返す。
+pub fn is_perfect_clear<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    solution: &ActionHistory<W, H>,
+) -> bool {
+    let mut pos = Position::new(board);
+
+    for (index, &sq) in solution.iter().enumerate() {
+        let Ok(action) = action_at(pos.board(), index, sq) else {
+            return false;
+        };
+        pos = pos.do_action(&action);
+    }
+
+    pos.board().is_empty()
+}
+
+/// 手順の `index` 手目としてマス `sq` を選んだ場合の着手を返す。
+pub(crate) fn action_at<const W: usize, const H: usize, const P: usize>(
+    board: &Board<W, H, P>,
+    index: usize,
+    sq: Square<W, H>,
+) -> Result<Action<W, H, P>, ReplayError<W, H>> {
+    let Some(piece) = board.get(sq) else {
+        return Err(ReplayError::NoPiece { index, sq });
+    };
+    if board.piece_mask(piece).flood_fill(sq).is_single() {
+        return Err(ReplayError::IsolatedPiece { index, sq });
+    }
+
+    Ok(Action::from_board_square(board, sq))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::square::*;
+
+    use super::*;
+
+    fn sq_new(col: Col, row: Row) -> Square {
+        Square::new(col, row)
+    }
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn solution_new(sqs: impl IntoIterator<Item = Square>) -> ActionHistory {
+        sqs.into_iter().collect()
+    }
+
+    #[test]
+    fn test_verify() {
+        assert_eq!(
+            verify(<Board>::empty(), &solution_new([])),
+            Ok(SCORE_PERFECT)
+        );
+
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            1.......
+            22......
+            1133....
+        "});
+
+        // パーフェクト。
+        assert_eq!(
+            verify(
+                board.clone(),
+                &solution_new([
+                    sq_new(COL_1, ROW_2),
+                    sq_new(COL_1, ROW_1),
+                    sq_new(COL_1, ROW_1),
+                ])
+            ),
+            Ok(1 + 1 + 4 + SCORE_PERFECT)
+        );
+
+        // パーフェクトでない終了局面。
+        assert_eq!(
+            verify(
+                parse_board(indoc! {"
+                    ........
+                    ........
+                    ........
+                    1.......
+                    22......
+                    11334...
+                "}),
+                &solution_new([
+                    sq_new(COL_1, ROW_2),
+                    sq_new(COL_1, ROW_1),
+                    sq_new(COL_1, ROW_1),
+                ])
+            ),
+            Ok(1 + 1 + 4)
+        );
+
+        assert_eq!(
+            verify(board.clone(), &solution_new([sq_new(COL_5, ROW_1)])),
+            Err(ReplayError::NoPiece {
+                index: 0,
+                sq: sq_new(COL_5, ROW_1)
+            })
+        );
+        assert_eq!(
+            verify(
+                board.clone(),
+                &solution_new([sq_new(COL_3, ROW_1), sq_new(COL_1, ROW_3)])
+            ),
+            Err(ReplayError::IsolatedPiece {
+                index: 1,
+                sq: sq_new(COL_1, ROW_3)
+            })
+        );
+        assert_eq!(
+            verify(board, &solution_new([sq_new(COL_3, ROW_1)])),
+            Err(ReplayError::ActionsRemain)
+        );
+    }
+    #[test]
+    fn test_is_perfect_clear() {
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            1.......
+            22......
+            1133....
+        "});
+        let solution = solution_new([
+            sq_new(COL_1, ROW_2),
+            sq_new(COL_1, ROW_1),
+            sq_new(COL_1, ROW_1),
+        ]);
+        assert!(is_perfect_clear(board.clone(), &solution));
+
+        // 途中まで、あるいは不正な手順。
+        assert!(!is_perfect_clear(
+            board.clone(),
+            &solution_new([sq_new(COL_1, ROW_2)])
+        ));
+        assert!(!is_perfect_clear(
+            board,
+            &solution_new([sq_new(COL_5, ROW_1)])
+        ));
+    }
+}
@@ -0,0 +1,475 @@
+//! 盤面ごとの探索結果を記録するデータベース。
+//!
+//! 追記専用のバイナリファイルで、ヘッダの後に可変長のレコードが並ぶ。
+//! ヘッダには、記録したソルバーが最大スコアを求めるもの (近似ソルバーでない) かどうかを含める。
+//! 同じ盤面のレコードが複数ある場合 (チェックポイントから再開した場合など) は最後のものを有効とする。
+
+use std::collections::HashMap;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context as _};
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::replay::is_perfect_clear;
+use crate::rng::GameRng;
+use crate::score::Score;
+use crate::square::Square;
+
+/// データベースファイルのマジックナンバー。
+const MAGIC: [u8; 8] = *b"SGSFCRDB";
+
+/// データベースファイルの形式のバージョン。
+const VERSION: u32 = 2;
+
+/// ヘッダのバイト数。
+const HEADER_LEN: usize = MAGIC.len() + std::mem::size_of::<u32>() + 1;
+
+/// 1 つの盤面に対する探索結果。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResultRecord {
+    rng_state: u16,
+    rng_counter: u8,
+    rng_inc_timing: usize,
+    score: Score,
+    solution: ActionHistory,
+    node_count: u64,
+}
+
+impl ResultRecord {
+    /// 初形で手詰まりの盤面に対しては、`score` を 0、`solution` を空とする。
+    pub fn new(
+        rng_state: u16,
+        rng_counter: u8,
+        rng_inc_timing: usize,
+        score: Score,
+        solution: ActionHistory,
+        node_count: u64,
+    ) -> Self {
+        assert!(rng_inc_timing <= <Square>::NUM);
+
+        Self {
+            rng_state,
+            rng_counter,
+            rng_inc_timing,
+            score,
+            solution,
+            node_count,
+        }
+    }
+
+    pub fn rng_state(&self) -> u16 {
+        self.rng_state
+    }
+
+    pub fn rng_counter(&self) -> u8 {
+        self.rng_counter
+    }
+
+    pub fn rng_inc_timing(&self) -> usize {
+        self.rng_inc_timing
+    }
+
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    pub fn solution(&self) -> &ActionHistory {
+        &self.solution
+    }
+
+    pub fn node_count(&self) -> u64 {
+        self.node_count
+    }
+
+    /// 乱数パラメータから盤面を生成して返す。
+    pub fn board(&self) -> Option<Board> {
+        GameRng::new(self.rng_state).gen_board(self.rng_counter, self.rng_inc_timing)
+    }
+
+    /// 手順がパーフェクトクリアかどうかを返す。盤面の生成や手順の再生に失敗した場合は `false` を返す。
+    pub fn is_perfect(&self) -> bool {
+        self.board()
+            .is_some_and(|board| is_perfect_clear(board, &self.solution))
+    }
+
+    fn key(&self) -> (u16, u8, usize) {
+        (self.rng_state, self.rng_counter, self.rng_inc_timing)
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend(self.rng_state.to_le_bytes());
+        buf.push(self.rng_counter);
+        buf.push(self.rng_inc_timing as u8);
+        buf.extend((self.score as u16).to_le_bytes());
+        buf.extend(self.node_count.to_le_bytes());
+        buf.push(self.solution.len() as u8);
+        buf.extend(self.solution.iter().map(|sq| sq.to_inner()));
+    }
+
+    /// レコードを 1 つ読む。入力が尽きていれば `None` を返す。
+    fn decode(r: &mut &[u8]) -> anyhow::Result<Option<Self>> {
+        if r.is_empty() {
+            return Ok(None);
+        }
+
+        let rng_state = u16::from_le_bytes(read_array(r)?);
+        let rng_counter = read_u8(r)?;
+        let rng_inc_timing = usize::from(read_u8(r)?);
+        ensure!(
+            rng_inc_timing <= <Square>::NUM,
+            "inc_timing が無効: {rng_inc_timing}"
+        );
+        let score = Score::from(u16::from_le_bytes(read_array(r)?));
+        let node_count = u64::from_le_bytes(read_array(r)?);
+        let len = read_u8(r)?;
+        let solution = (0..len)
+            .map(|_| {
+                let inner = read_u8(r)?;
+                Square::from_inner(inner).with_context(|| format!("マスが無効: {inner}"))
+            })
+            .collect::<anyhow::Result<ActionHistory>>()?;
+
+        Ok(Some(Self {
+            rng_state,
+            rng_counter,
+            rng_inc_timing,
+            score,
+            solution,
+            node_count,
+        }))
+    }
+}
+
+/// データベースファイルへの追記を行う。
+#[derive(Debug)]
+pub struct ResultsDbWriter {
+    file: BufWriter<std::fs::File>,
+}
+
+impl ResultsDbWriter {
+    /// データベースファイルを追記用に開く。ファイルが存在しなければ作成する。
+    ///
+    /// `exact` は記録するスコアが最大スコアかどうか (`SolverKind::is_exact()`)。
+    /// 既存ファイルの形式が異なるか、`exact` が既存ファイルと異なる場合は失敗する。
+    pub fn open(path: impl AsRef<Path>, exact: bool) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let ctx = || format!("結果データベース {} を開けない", path.display());
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .read(true)
+            .open(path)
+            .with_context(ctx)?;
+
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).with_context(ctx)?;
+        if buf.is_empty() {
+            let mut header = Vec::with_capacity(HEADER_LEN);
+            header.extend(MAGIC);
+            header.extend(VERSION.to_le_bytes());
+            header.push(u8::from(exact));
+            file.write_all(&header).with_context(ctx)?;
+        } else {
+            // 末尾の壊れたレコードは切り捨ててから追記する。
+            let (exact_file, _, valid_len) = decode_records(&buf).with_context(ctx)?;
+            ensure!(
+                exact_file == exact,
+                "{}: 最大スコアと近似ソルバーのスコアを混在させることはできない (既存の記録は{})",
+                ctx(),
+                if exact_file {
+                    "最大スコア"
+                } else {
+                    "近似ソルバーのスコア"
+                }
+            );
+            if valid_len < buf.len() {
+                file.set_len(valid_len as u64).with_context(ctx)?;
+            }
+        }
+
+        Ok(Self {
+            file: BufWriter::new(file),
+        })
+    }
+
+    /// レコードを追記する。実際に書き込まれるのは `flush()` 時とは限らない。
+    pub fn append(&mut self, record: &ResultRecord) -> anyhow::Result<()> {
+        let mut buf = Vec::new();
+        record.encode(&mut buf);
+        self.file.write_all(&buf)?;
+
+        Ok(())
+    }
+
+    /// バッファ内のレコードをファイルに書き込む。
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+
+        Ok(())
+    }
+}
+
+/// データベース全体をメモリ上に読み込んだもの。
+#[derive(Clone, Debug, Default)]
+pub struct ResultsDb {
+    /// 記録されたスコアが最大スコアかどうか。
+    exact: bool,
+    /// 盤面ごとの最新のレコード。
+    records: Vec<ResultRecord>,
+}
+
+impl ResultsDb {
+    /// データベースファイルを読み込む。
+    ///
+    /// 末尾のレコードが途中で終わっている場合 (書き込み中に中断された場合など) は、そのレコードのみ無視する。
+    pub fn read(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let buf = std::fs::read(path)
+            .with_context(|| format!("結果データベース {} を読めない", path.display()))?;
+
+        Self::decode(&buf).with_context(|| format!("結果データベース {} が無効", path.display()))
+    }
+
+    fn decode(buf: &[u8]) -> anyhow::Result<Self> {
+        let (exact, records_all, _) = decode_records(buf)?;
+
+        let mut idxs = HashMap::<(u16, u8, usize), usize>::new();
+        let mut records = Vec::<ResultRecord>::new();
+        for record in records_all {
+            match idxs.get(&record.key()) {
+                Some(&idx) => records[idx] = record,
+                None => {
+                    idxs.insert(record.key(), records.len());
+                    records.push(record);
+                }
+            }
+        }
+
+        Ok(Self { exact, records })
+    }
+
+    /// 記録されたスコアが最大スコアかどうかを返す (近似ソルバーで記録した場合は `false`)。
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    /// 盤面ごとの最新のレコードを、最初に記録された順に返す。
+    pub fn records(&self) -> &[ResultRecord] {
+        &self.records
+    }
+
+    /// スコアの降順で上位 `n` 個のレコードを返す。同点の場合は `(state, counter, inc_timing)` の昇順。
+    pub fn top(&self, n: usize) -> Vec<&ResultRecord> {
+        let mut records: Vec<_> = self.records.iter().collect();
+        records.sort_by_key(|record| (std::cmp::Reverse(record.score), record.key()));
+        records.truncate(n);
+        records
+    }
+
+    /// パーフェクトクリアの手順を持つレコードを全て返す。
+    pub fn perfect_clears(&self) -> Vec<&ResultRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.is_perfect())
+            .collect()
+    }
+
+    /// 指定した乱数パラメータのレコードを返す。
+    pub fn get(
+        &self,
+        rng_state: u16,
+        rng_counter: u8,
+        rng_inc_timing: usize,
+    ) -> Option<&ResultRecord> {
+        self.records
+            .iter()
+            .find(|record| record.key() == (rng_state, rng_counter, rng_inc_timing))
+    }
+
+    /// 指定した盤面を生成するレコードを全て返す (異なる乱数パラメータが同じ盤面を生成することもある)。
+    pub fn find_board(&self, board: &Board) -> Vec<&ResultRecord> {
+        self.records
+            .iter()
+            .filter(|record| record.board().as_ref() == Some(board))
+            .collect()
+    }
+}
+
+/// データベースファイルの内容を全て読み、スコアが最大スコアかどうか、レコード列、有効部分のバイト数を返す。
+///
+/// 末尾のレコードが途中で終わっている場合は、そのレコードを無視する。
+fn decode_records(mut buf: &[u8]) -> anyhow::Result<(bool, Vec<ResultRecord>, usize)> {
+    let len = buf.len();
+    let r = &mut buf;
+    let exact = decode_header(r)?;
+
+    let mut records = Vec::new();
+    let mut valid_len = HEADER_LEN;
+    loop {
+        match ResultRecord::decode(r) {
+            Ok(Some(record)) => records.push(record),
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("末尾の壊れたレコードを無視: {e:#}");
+                break;
+            }
+        }
+        valid_len = len - r.len();
+    }
+
+    Ok((exact, records, valid_len))
+}
+
+/// ヘッダを読み、記録されたスコアが最大スコアかどうかを返す。
+fn decode_header(r: &mut &[u8]) -> anyhow::Result<bool> {
+    ensure!(
+        read_array::<8>(r)? == MAGIC,
+        "結果データベースファイルではない"
+    );
+    let version = u32::from_le_bytes(read_array(r)?);
+    if version != VERSION {
+        bail!("結果データベースのバージョンが異なる: {version} (対応バージョンは {VERSION})");
+    }
+
+    match read_u8(r)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        x => bail!("スコアの種類が無効: {x}"),
+    }
+}
+
+fn read_array<const N: usize>(r: &mut &[u8]) -> anyhow::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)
+        .context("ファイルが途中で終わっている")?;
+    Ok(buf)
+}
+
+fn read_u8(r: &mut &[u8]) -> anyhow::Result<u8> {
+    read_array::<1>(r).map(|[x]| x)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::verify;
+
+    use super::*;
+
+    /// 実際の盤面に対する解のレコードを作る。
+    fn record_new(
+        state: u16,
+        counter: u8,
+        inc_timing: usize,
+        solution: &str,
+        node_count: u64,
+    ) -> ResultRecord {
+        let board = GameRng::new(state).gen_board(counter, inc_timing).unwrap();
+        let solution: ActionHistory = solution.parse().unwrap();
+        let score = verify(board, &solution).unwrap();
+        ResultRecord::new(state, counter, inc_timing, score, solution, node_count)
+    }
+
+    fn record_perfect() -> ResultRecord {
+        record_new(
+            0x28A1,
+            0x00,
+            40,
+            "6,6 8,1 5,3 6,2 2,2 6,1 1,2 1,1 1,1 1,1",
+            1,
+        )
+    }
+
+    fn record_not_perfect() -> ResultRecord {
+        record_new(
+            0x1234,
+            0x00,
+            39,
+            "2,3 6,4 7,2 1,5 2,2 4,6 5,2 7,1 6,2 4,2 1,2 1,2 1,1 1,1",
+            2,
+        )
+    }
+
+    #[test]
+    fn test_results_db() {
+        let path = std::env::temp_dir().join(format!(
+            "samegame-sfc-small-test-results-db-{}.bin",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let record_a = record_not_perfect();
+        let record_b = record_perfect();
+        let record_c = ResultRecord::new(0x0001, 0x00, 40, 1000, "1,1".parse().unwrap(), 42);
+
+        // 2 回に分けて追記する。同じ盤面のレコードは後のものが有効。
+        {
+            let mut writer = ResultsDbWriter::open(&path, true).unwrap();
+            writer.append(&record_a).unwrap();
+            writer.append(&record_c).unwrap();
+            writer.flush().unwrap();
+        }
+        let record_c = ResultRecord::new(0x0001, 0x00, 40, 0, ActionHistory::new(), 43);
+        {
+            let mut writer = ResultsDbWriter::open(&path, true).unwrap();
+            writer.append(&record_b).unwrap();
+            writer.append(&record_c).unwrap();
+            writer.flush().unwrap();
+        }
+
+        let db = ResultsDb::read(&path).unwrap();
+        assert!(db.is_exact());
+        assert_eq!(
+            db.records(),
+            [record_a.clone(), record_c.clone(), record_b.clone()]
+        );
+        assert_eq!(db.top(2), [&record_b, &record_a]);
+        assert_eq!(db.perfect_clears(), [&record_b]);
+        assert_eq!(db.get(0x0001, 0x00, 40), Some(&record_c));
+        assert_eq!(db.get(0x0001, 0x00, 39), None);
+        assert_eq!(db.find_board(&record_a.board().unwrap()), [&record_a]);
+
+        // 末尾が壊れていてもそれ以前のレコードは読める。
+        let mut buf = std::fs::read(&path).unwrap();
+        buf.pop();
+        let db_broken = ResultsDb::decode(&buf).unwrap();
+        assert_eq!(db_broken.records().len(), 3);
+        assert_eq!(db_broken.get(0x0001, 0x00, 40).unwrap().score(), 1000);
+
+        // 壊れたレコードは追記前に切り捨てられる。
+        std::fs::write(&path, &buf).unwrap();
+        {
+            let mut writer = ResultsDbWriter::open(&path, true).unwrap();
+            writer.append(&record_c).unwrap();
+            writer.flush().unwrap();
+        }
+        let db = ResultsDb::read(&path).unwrap();
+        assert_eq!(
+            db.records(),
+            [record_a.clone(), record_c.clone(), record_b.clone()]
+        );
+
+        // 近似ソルバーのスコアは追記できない。
+        assert!(ResultsDbWriter::open(&path, false).is_err());
+
+        // 形式の異なるファイルは開けない。
+        std::fs::write(&path, b"foo").unwrap();
+        assert!(ResultsDbWriter::open(&path, true).is_err());
+        assert!(ResultsDb::read(&path).is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_result_record_is_perfect() {
+        assert!(record_perfect().is_perfect());
+        assert!(!record_not_perfect().is_perfect());
+
+        // 手順が不正なら perfect ではない。
+        let record = ResultRecord::new(0x28A1, 0x00, 40, 0, "1,6".parse().unwrap(), 0);
+        assert!(!record.is_perfect());
+    }
+}
@@ -48,18 +48,18 @@ impl GameRng {
     /// (ゲーム内では盤面生成中に NMI が発生してカウンタがインクリメントされる。
     /// タイミングは CPU サイクルに依存するが、通常は駒が 39 または 40 個生成された直後に起こるようだ)
     pub fn gen_board(&mut self, counter: u8, inc_counter_after: usize) -> Option<Board> {
-        unsafe { assert_unchecked!(inc_counter_after <= Square::NUM) }
+        unsafe { assert_unchecked!(inc_counter_after <= <Square>::NUM) }
 
         // row-major (下から上の順)
-        let mut pieces = ArrayVec::<Piece, { Square::NUM }>::new();
+        let mut pieces = ArrayVec::<Piece, { <Square>::NUM }>::new();
         pieces.extend(std::iter::repeat_with(|| self.gen_piece(counter)).take(inc_counter_after));
         pieces.extend(
             std::iter::repeat_with(|| self.gen_piece(counter.wrapping_add(1)))
-                .take(Square::NUM - inc_counter_after),
+                .take(<Square>::NUM - inc_counter_after),
         );
 
         let arrays = ColArray::from_fn(|col| {
-            RowArray::from_fn(|row| pieces[Col::NUM * row.to_index() + col.to_index()])
+            RowArray::from_fn(|row| pieces[<Col>::NUM * row.to_index() + col.to_index()])
         });
         Board::from_piece_arrays(&arrays)
     }
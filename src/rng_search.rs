@@ -0,0 +1,232 @@
+//! 盤面からゲーム内乱数のパラメータを逆算する。
+
+use anyhow::{bail, ensure};
+
+use crate::board::Board;
+use crate::piece::Piece;
+use crate::rng::GameRng;
+use crate::square::{Col, Row, Square};
+
+/// 初期盤面のパターン。各マスの駒は既知または未知。
+///
+/// 文字列表現は `Board` と同様だが、未知のマスは `?` で表す。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BoardPattern {
+    /// 盤面生成順 (row-major, 下から上の順) に並べた各マスの駒。
+    pieces: [Option<Piece>; <Square>::NUM],
+}
+
+impl BoardPattern {
+    const CHAR_UNKNOWN: char = '?';
+
+    /// 全てのマスが未知のパターンを返す。
+    pub fn unknown() -> Self {
+        Self {
+            pieces: [None; <Square>::NUM],
+        }
+    }
+
+    /// 指定したマスの駒を返す。未知ならば `None` を返す。
+    pub fn get(&self, sq: Square) -> Option<Piece> {
+        self.pieces[Self::gen_index(sq)]
+    }
+
+    /// 指定したマスの駒を設定する。`None` は未知を表す。
+    pub fn set(&mut self, sq: Square, piece: Option<Piece>) {
+        self.pieces[Self::gen_index(sq)] = piece;
+    }
+
+    /// 既知のマスの数を返す。
+    pub fn known_count(&self) -> usize {
+        self.pieces.iter().filter(|piece| piece.is_some()).count()
+    }
+
+    /// 盤面がこのパターンに合致するかどうかを返す。
+    pub fn matches(&self, board: &Board) -> bool {
+        Square::all().all(|sq| {
+            self.get(sq)
+                .is_none_or(|piece| board.get(sq) == Some(piece))
+        })
+    }
+
+    /// 盤面生成順で `idx` 番目のマスに `piece` を置けるかどうかを返す。
+    fn accepts(&self, idx: usize, piece: Piece) -> bool {
+        self.pieces[idx].is_none_or(|p| p == piece)
+    }
+
+    /// マスの盤面生成順でのインデックスを返す。
+    fn gen_index(sq: Square) -> usize {
+        <Col>::NUM * sq.row().to_index() + sq.col().to_index()
+    }
+}
+
+impl From<&Board> for BoardPattern {
+    fn from(board: &Board) -> Self {
+        let mut res = Self::unknown();
+        for sq in Square::all() {
+            res.set(sq, board.get(sq));
+        }
+        res
+    }
+}
+
+impl std::str::FromStr for BoardPattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lines: Vec<_> = s.lines().collect();
+        ensure!(
+            lines.len() == <Row>::NUM,
+            "盤面パターン文字列はちょうど {} 行でなければならない",
+            <Row>::NUM
+        );
+
+        let mut res = Self::unknown();
+
+        for (row, line) in itertools::zip_eq(<Row>::all().rev(), lines) {
+            let chars: Vec<_> = line.chars().collect();
+            ensure!(
+                chars.len() == <Col>::NUM,
+                "盤面パターンの行 {row} がちょうど {} 文字でない",
+                <Col>::NUM
+            );
+
+            for (col, ch) in itertools::zip_eq(<Col>::all(), chars) {
+                let sq = Square::new(col, row);
+                if ch == Self::CHAR_UNKNOWN {
+                    continue;
+                }
+                let piece = ch
+                    .to_digit(10)
+                    .and_then(|value| Piece::from_inner(value as u8));
+                let Some(piece) = piece else {
+                    bail!("盤面パターン {sq} の文字が無効: {ch}");
+                };
+                res.set(sq, Some(piece));
+            }
+        }
+
+        Ok(res)
+    }
+}
+
+impl std::fmt::Display for BoardPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in <Row>::all().rev() {
+            for col in <Col>::all() {
+                let sq = Square::new(col, row);
+                match self.get(sq) {
+                    Some(piece) => write!(f, "{}", piece.to_inner())?,
+                    None => write!(f, "{}", Self::CHAR_UNKNOWN)?,
+                }
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `GameRng::gen_board()` が `pattern` に合致する盤面を生成するような
+/// 全ての `(state, counter, inc_timing)` を昇順で返す。
+///
+/// `inc_timing` は `0..=<Square>::NUM` の全範囲を対象とする。
+///
+/// 各 `(state, counter)` について駒を 1 個ずつ生成し、パターンに合致しなくなった時点で打ち切る。
+/// カウンタのインクリメント前の駒列は `inc_timing` によらず共通なので、
+/// インクリメント前の駒列を辿りつつ、各時点でインクリメントした場合の残りの駒列を調べればよい。
+pub fn find_rng_params(pattern: &BoardPattern) -> Vec<(u16, u8, usize)> {
+    let mut res = vec![];
+
+    for state in 0..=u16::MAX {
+        for counter in 0..=u8::MAX {
+            search_state(pattern, state, counter, &mut res);
+        }
+    }
+
+    res
+}
+
+fn search_state(pattern: &BoardPattern, state: u16, counter: u8, res: &mut Vec<(u16, u8, usize)>) {
+    let mut rng = GameRng::new(state);
+
+    for inc_timing in 0..=<Square>::NUM {
+        // ここでカウンタがインクリメントされる場合、残りの駒列が合致するか調べる。
+        let mut rng_inc = rng;
+        let tail_ok = (inc_timing..<Square>::NUM)
+            .all(|idx| pattern.accepts(idx, rng_inc.gen_piece(counter.wrapping_add(1))));
+        if tail_ok && gen_board_matches(pattern, state, counter, inc_timing) {
+            res.push((state, counter, inc_timing));
+        }
+
+        // まだインクリメントされない場合。
+        if inc_timing == <Square>::NUM || !pattern.accepts(inc_timing, rng.gen_piece(counter)) {
+            break;
+        }
+    }
+}
+
+/// 駒列が合致した後、再生成判定も含めて確認する。
+fn gen_board_matches(pattern: &BoardPattern, state: u16, counter: u8, inc_timing: usize) -> bool {
+    GameRng::new(state)
+        .gen_board(counter, inc_timing)
+        .is_some_and(|board| pattern.matches(&board))
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::square::*;
+
+    use super::*;
+
+    #[test]
+    fn test_board_pattern_io() {
+        let s = indoc! {"
+            ????????
+            ?1234???
+            ????????
+            ????????
+            ????????
+            12345123
+        "};
+        let pattern: BoardPattern = s.parse().unwrap();
+        assert_eq!(pattern.to_string(), s);
+        assert_eq!(pattern.known_count(), 12);
+        assert_eq!(pattern.get(Square::new(COL_1, ROW_1)), Piece::from_inner(1));
+        assert_eq!(pattern.get(Square::new(COL_2, ROW_5)), Piece::from_inner(1));
+        assert_eq!(pattern.get(Square::new(COL_1, ROW_5)), None);
+
+        assert!("????????".parse::<BoardPattern>().is_err());
+        assert!(s.replace('5', "6").parse::<BoardPattern>().is_err());
+    }
+
+    #[test]
+    fn test_find_rng_params() {
+        let params = [(0x1234, 0x00, 39), (0x0001, 0xFF, 0)];
+
+        for (state, counter, inc_timing) in params {
+            let board = GameRng::new(state).gen_board(counter, inc_timing).unwrap();
+
+            // 盤面全体が既知の場合。
+            let found = find_rng_params(&BoardPattern::from(&board));
+            assert!(found.contains(&(state, counter, inc_timing)));
+            for &(s, c, i) in &found {
+                assert_eq!(GameRng::new(s).gen_board(c, i), Some(board.clone()));
+            }
+
+            // 上半分が未知の場合。
+            let mut pattern = BoardPattern::from(&board);
+            for sq in Square::all().filter(|sq| sq.row() > ROW_3) {
+                pattern.set(sq, None);
+            }
+            let found_partial = find_rng_params(&pattern);
+            assert!(found.iter().all(|param| found_partial.contains(param)));
+            for &(s, c, i) in &found_partial {
+                let board = GameRng::new(s).gen_board(c, i).unwrap();
+                assert!(pattern.matches(&board));
+            }
+        }
+    }
+}
@@ -4,12 +4,27 @@ use crate::hint::assert_unchecked;
 
 /// スコアを表す型。
 ///
-/// 実際には `0..=2409` の値をとる (最大値は 48 個全消し時)。
+/// 「かんたん」モードでは実際には `0..=2409` の値をとる (最大値は 48 個全消し時)。
 /// つまり 12bit に収まる。
+/// 盤面サイズによらず、マス数は高々 255 なので 16bit に収まる。
 pub type Score = u32;
 
 pub const SCORE_PERFECT: Score = 200;
 
+/// マス数 `square_num` の盤面で獲得しうる最大スコアを返す。
+pub const fn score_max(square_num: usize) -> Score {
+    if square_num < 2 {
+        return SCORE_PERFECT;
+    }
+
+    score_erase(square_num as u32) + SCORE_PERFECT
+}
+
+/// マス数 `square_num` の盤面で獲得しうるスコアを表すのに必要なビット数を返す。
+pub const fn score_bits(square_num: usize) -> u32 {
+    Score::BITS - score_max(square_num).leading_zeros()
+}
+
 /// n 個の駒を消す着手による獲得スコアを返す。
 ///
 /// `n >= 2` でなければならない。
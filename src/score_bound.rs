@@ -0,0 +1,384 @@
+//! 探索を行わずに見積もるスコア上界。
+
+use anyhow::bail;
+
+use crate::piece::{Piece, PieceArray};
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::square::{ColArray, Row, Square};
+
+/// 局面から追加で獲得しうるスコアの上界の見積もり方。
+///
+/// いずれも許容的 (真の値以上) であり、局面が終了局面でない限り 0 を返さない。
+/// つまり、0 を返すならばその局面はパーフェクトでない終了局面である。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScoreBound {
+    /// `Position::score_upper_bound()` と同じ。
+    ///
+    /// 2 個以上存在する駒種全てが 1 手で全消しできると仮定する。
+    Simple,
+    /// 決して消せない駒の列で盤面を分断し、同種の駒が合流しえない範囲ごとに見積もる。
+    ///
+    /// 詳細は `score_upper_bound_separation()` を参照。`Simple` 以下の値を返す。
+    Separation,
+}
+
+impl ScoreBound {
+    /// 局面 `pos` から追加で獲得しうるスコアの上界を返す。
+    pub fn eval<const W: usize, const H: usize, const P: usize>(
+        self,
+        pos: &Position<W, H, P>,
+    ) -> Score {
+        match self {
+            Self::Simple => pos.score_upper_bound(),
+            Self::Separation => score_upper_bound_separation(pos),
+        }
+    }
+
+    /// 局面 `pos` からパーフェクトクリアできる可能性があるかどうかを返す。
+    ///
+    /// `false` ならば決してパーフェクトクリアできない (`eval()` がパーフェクトボーナスを含まない場合に対応する)。
+    pub fn may_perfect<const W: usize, const H: usize, const P: usize>(
+        self,
+        pos: &Position<W, H, P>,
+    ) -> bool {
+        match self {
+            Self::Simple => Piece::all().all(|piece| pos.piece_count(piece) != 1),
+            Self::Separation => separation_walls(pos).1 == 0,
+        }
+    }
+}
+
+impl std::str::FromStr for ScoreBound {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "simple" => Ok(Self::Simple),
+            "separation" => Ok(Self::Separation),
+            _ => bail!("スコア上界が無効: '{s}' (simple, separation のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for ScoreBound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Simple => "simple",
+            Self::Separation => "separation",
+        };
+        f.write_str(s)
+    }
+}
+
+/// 決して消せない駒を考慮して、局面 `pos` から追加で獲得しうるスコアの上界を返す。
+///
+/// 駒は列をまたいで移動せず、列の左右の順序も変わらない。
+/// よって、決して消せない駒を含む列 (以下「壁」) は消滅せず、その列に駒種 c の駒がなければ、
+/// 壁の左右にある駒種 c の駒同士は決して連結しない。
+///
+/// そこで、各駒種について、その駒種を含まない壁で列を区切った各区間を独立に扱い、
+/// 区間内の駒全てを 1 手で消せると仮定して上界を求める。
+/// 区間内に駒が 1 個しかなければその駒は決して消せないので新たな壁となる。これを不動点まで繰り返す。
+/// 最初の壁は、盤面全体で 1 個しかない駒種の駒を含む列である。
+///
+/// 決して消せない駒がなければパーフェクトボーナスも加算する。
+fn score_upper_bound_separation<const W: usize, const H: usize, const P: usize>(
+    pos: &Position<W, H, P>,
+) -> Score {
+    let (counts, wall_mask) = separation_walls(pos);
+
+    let mut res = 0;
+    for piece in Piece::all() {
+        for_each_segment(&counts[piece], wall_mask, |count, _| {
+            if count >= 2 {
+                res += score_erase(count);
+            }
+        });
+    }
+
+    if wall_mask == 0 {
+        res += SCORE_PERFECT;
+    }
+
+    res
+}
+
+/// `score_upper_bound_separation()` における、各駒種の各列の駒数と壁の列集合 (bit i が列 i+1 に対応) を返す。
+fn separation_walls<const W: usize, const H: usize, const P: usize>(
+    pos: &Position<W, H, P>,
+) -> (PieceArray<ColArray<u8, W>, P>, u32) {
+    let board = pos.board();
+
+    // 各駒種の各列における駒数。
+    let mut counts = PieceArray::<ColArray<u8, W>, P>::default();
+    for col in board.nonempty_cols() {
+        for row in Row::all() {
+            let Some(piece) = board.get(Square::new(col, row)) else {
+                break;
+            };
+            counts[piece][col] += 1;
+        }
+    }
+
+    let mut wall_mask = 0_u32;
+    loop {
+        let mut changed = false;
+        for piece in Piece::all() {
+            for_each_segment(&counts[piece], wall_mask, |count, col_mask| {
+                if count == 1 {
+                    // 区間内唯一の駒を含む列を壁に加える。
+                    let col_idx = (0..W)
+                        .find(|&i| col_mask & (1 << i) != 0 && counts[piece].as_array()[i] != 0)
+                        .unwrap();
+                    if wall_mask & (1 << col_idx) == 0 {
+                        wall_mask |= 1 << col_idx;
+                        changed = true;
+                    }
+                }
+            });
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    (counts, wall_mask)
+}
+
+/// ある駒種の各列の駒数 `counts` について、その駒種を含まない壁で区切った各区間を列挙し、
+/// 区間内の駒数と区間の列集合を `f` に渡す。
+///
+/// 駒のない区間は列挙しない。
+fn for_each_segment<const W: usize>(
+    counts: &ColArray<u8, W>,
+    wall_mask: u32,
+    mut f: impl FnMut(u32, u32),
+) {
+    let mut count = 0;
+    let mut col_mask = 0;
+    for (i, &n) in counts.as_array().iter().enumerate() {
+        let is_separator = n == 0 && wall_mask & (1 << i) != 0;
+        if is_separator {
+            if count != 0 {
+                f(count, col_mask);
+            }
+            count = 0;
+            col_mask = 0;
+        } else {
+            count += u32::from(n);
+            col_mask |= 1 << i;
+        }
+    }
+    if count != 0 {
+        f(count, col_mask);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use std::collections::HashMap;
+
+    use crate::board::Board;
+    use crate::solve::{SolveConfig, SolverKind};
+    use crate::solver::{solve_problem_all, solve_problem_with};
+    use crate::zobrist::Key;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn eval(bound: ScoreBound, s: &str) -> Score {
+        bound.eval(&Position::new(parse_board(s)))
+    }
+
+    #[test]
+    fn test_score_bound_parse() {
+        for bound in [ScoreBound::Simple, ScoreBound::Separation] {
+            assert_eq!(bound.to_string().parse::<ScoreBound>().unwrap(), bound);
+        }
+        assert!("foo".parse::<ScoreBound>().is_err());
+    }
+
+    #[test]
+    fn test_score_bound_separation() {
+        assert_eq!(
+            ScoreBound::Separation.eval(&Position::new(<Board>::empty())),
+            SCORE_PERFECT
+        );
+
+        // 3 が壁となり、1 は左右に分断される。
+        let s = indoc! {"
+            ........
+            ........
+            ........
+            ........
+            ........
+            11311...
+        "};
+        assert_eq!(eval(ScoreBound::Simple, s), score_erase(4));
+        assert_eq!(eval(ScoreBound::Separation, s), 2 * score_erase(2));
+
+        // 2 が壁となり、3 は左右に分断されて共に壁となる。
+        // すると 1 の左端の駒も分断される。
+        let s = indoc! {"
+            ........
+            ........
+            ........
+            ........
+            3.......
+            12113...
+        "};
+        assert_eq!(eval(ScoreBound::Simple, s), score_erase(3) + score_erase(2));
+        assert_eq!(eval(ScoreBound::Separation, s), score_erase(2));
+
+        // 壁がなければパーフェクトボーナスも加算する。
+        let s = indoc! {"
+            ........
+            ........
+            ........
+            ........
+            ........
+            1212....
+        "};
+        assert_eq!(eval(ScoreBound::Separation, s), eval(ScoreBound::Simple, s));
+        assert_eq!(
+            eval(ScoreBound::Separation, s),
+            2 * score_erase(2) + SCORE_PERFECT
+        );
+
+        // 合法手のない局面は 0 になりうる。
+        let s = indoc! {"
+            ........
+            ........
+            ........
+            ........
+            ........
+            121.....
+        "};
+        assert_eq!(eval(ScoreBound::Separation, s), 0);
+    }
+
+    /// 局面 `pos` から追加で獲得しうる最大スコアを素朴に求める。
+    /// 途中の全ての局面について、各上界が最大スコア以上であることも確かめる。
+    fn gain_max_checked(pos: &Position, memo: &mut HashMap<Key, Score>) -> Score {
+        if let Some(&gain) = memo.get(&pos.key()) {
+            return gain;
+        }
+
+        let gain = if pos.board().is_empty() {
+            SCORE_PERFECT
+        } else {
+            pos.actions()
+                .map(|action| {
+                    score_erase(action.square_count())
+                        + gain_max_checked(&pos.do_action(&action), memo)
+                })
+                .max()
+                .unwrap_or(0)
+        };
+
+        let simple = ScoreBound::Simple.eval(pos);
+        let separation = ScoreBound::Separation.eval(pos);
+        assert!(gain <= separation, "{}", pos.board());
+        assert!(separation <= simple, "{}", pos.board());
+        if separation == 0 {
+            assert!(!pos.has_action() && !pos.board().is_empty());
+        }
+
+        memo.insert(pos.key(), gain);
+        gain
+    }
+
+    /// 局面 `pos` からパーフェクトクリアできるかどうかを素朴に求める。
+    /// 途中の全ての局面について、`may_perfect()` と矛盾しないことも確かめる。
+    fn perfect_reachable_checked(pos: &Position, memo: &mut HashMap<Key, bool>) -> bool {
+        if let Some(&reachable) = memo.get(&pos.key()) {
+            return reachable;
+        }
+
+        let reachable = pos.board().is_empty()
+            || pos
+                .actions()
+                .any(|action| perfect_reachable_checked(&pos.do_action(&action), memo));
+
+        for bound in [ScoreBound::Simple, ScoreBound::Separation] {
+            if reachable {
+                assert!(bound.may_perfect(pos), "{bound}\n{}", pos.board());
+            }
+        }
+
+        memo.insert(pos.key(), reachable);
+        reachable
+    }
+
+    /// ランダムな盤面の全ての到達局面について、上界が許容的であることを確かめる。
+    #[test]
+    fn test_score_bound_admissible() {
+        let config = SolveConfig {
+            table_bytes: 1 << 20,
+            ..Default::default()
+        };
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let mut s = "........\n".repeat(3);
+            for _ in 0..3 {
+                let line: String = (0..8)
+                    .map(|_| char::from(b'1' + rng.gen_range(0..4)))
+                    .collect();
+                s.push_str(&line);
+                s.push('\n');
+            }
+            let board = parse_board(s);
+
+            let gain = gain_max_checked(&Position::new(board.clone()), &mut HashMap::new());
+            perfect_reachable_checked(&Position::new(board.clone()), &mut HashMap::new());
+            assert_eq!(solve_problem_all(board, &config).score(), gain);
+        }
+    }
+
+    /// 上界の見積もり方によらず、`Solver`, `ManySolver` は厳密解を返す。
+    #[test]
+    fn test_solvers_with_score_bound() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..20 {
+            let mut s = "........\n".repeat(3);
+            for _ in 0..3 {
+                let line: String = (0..8)
+                    .map(|_| char::from(b'1' + rng.gen_range(0..4)))
+                    .collect();
+                s.push_str(&line);
+                s.push('\n');
+            }
+            let board = parse_board(s);
+
+            let config_new = |score_bound| SolveConfig {
+                table_bytes: 1 << 20,
+                score_bound,
+                ..Default::default()
+            };
+            let (score, _) = solve_problem_with(board.clone(), &config_new(ScoreBound::Simple));
+
+            let mut node_counts = vec![];
+            for score_bound in [ScoreBound::Simple, ScoreBound::Separation] {
+                let config = config_new(score_bound);
+                for kind in [SolverKind::Bound, SolverKind::Many] {
+                    let res = kind.new_solver(&config).solve(board.clone());
+                    assert_eq!(res.as_ref().map_or(0, |res| res.score()), score);
+                    if kind == SolverKind::Bound {
+                        node_counts.push(res.map_or(0, |res| res.node_count()));
+                    }
+                }
+            }
+
+            // より強い上界では探索ノード数は増えない。
+            assert!(node_counts[1] <= node_counts[0]);
+        }
+    }
+}
@@ -0,0 +1,796 @@
+//! 盤面集合に対するスコア分布の統計。
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use anyhow::{bail, ensure};
+
+use crate::board::Board;
+use crate::position::Position;
+use crate::replay::is_perfect_clear;
+use crate::results_db::ResultsDb;
+use crate::rng::GameRng;
+use crate::score::Score;
+use crate::score_bound::ScoreBound;
+use crate::solve::{Solve, SolveConfig, SolverKind};
+use crate::solver_many::{Sweep, SWEEP_CHUNK_LEN};
+
+/// 統計をとる各盤面の値。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatsMetric {
+    /// ソルバーで求めた最大スコア。パーフェクトクリアは実際に最大スコアの手順で達成したもの。
+    Exact,
+    /// 近似ソルバー (beam, mcts) で求めたスコアで、最大スコアの下界となる。
+    /// パーフェクトクリアは実際にその手順で達成したものであり、実際の達成率の下界となる。
+    Approx,
+    /// 探索を行わずに見積もるスコア上界。パーフェクトクリアはその可能性を否定できないもの
+    /// (`ScoreBound::may_perfect()`) であり、実際の達成率の上界となる。
+    Bound(ScoreBound),
+}
+
+impl std::str::FromStr for StatsMetric {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "approx" => Ok(Self::Approx),
+            "simple" => Ok(Self::Bound(ScoreBound::Simple)),
+            "separation" => Ok(Self::Bound(ScoreBound::Separation)),
+            _ => bail!("統計の値が無効: '{s}' (exact, approx, simple, separation のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for StatsMetric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact => f.write_str("exact"),
+            Self::Approx => f.write_str("approx"),
+            Self::Bound(bound) => bound.fmt(f),
+        }
+    }
+}
+
+/// 統計の出力形式。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StatsFormat {
+    /// 人間向けの要約と、全体の度数分布のグラフ。
+    Text,
+    /// 各グループの要約統計量の CSV。
+    SummaryCsv,
+    /// 各グループの度数分布の CSV (縦持ち)。
+    HistogramCsv,
+    /// 要約統計量と度数分布の全て。
+    Json,
+}
+
+impl std::str::FromStr for StatsFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "summary-csv" => Ok(Self::SummaryCsv),
+            "histogram-csv" => Ok(Self::HistogramCsv),
+            "json" => Ok(Self::Json),
+            _ => bail!("出力形式が無効: '{s}' (text, summary-csv, histogram-csv, json のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for StatsFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Text => "text",
+            Self::SummaryCsv => "summary-csv",
+            Self::HistogramCsv => "histogram-csv",
+            Self::Json => "json",
+        };
+        f.write_str(s)
+    }
+}
+
+/// 要約に含める分位点。
+pub const STATS_QUANTILES: [f64; 6] = [0.1, 0.25, 0.5, 0.75, 0.9, 0.99];
+
+/// スコアの度数分布。
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScoreHistogram {
+    counts: BTreeMap<Score, u64>,
+    len: u64,
+    perfect_count: u64,
+}
+
+impl ScoreHistogram {
+    /// 盤面を 1 つ追加する。
+    pub fn add(&mut self, score: Score, perfect: bool) {
+        *self.counts.entry(score).or_default() += 1;
+        self.len += 1;
+        self.perfect_count += u64::from(perfect);
+    }
+
+    /// 別の度数分布を合算する。
+    pub fn merge(&mut self, other: &Self) {
+        for (&score, &count) in &other.counts {
+            *self.counts.entry(score).or_default() += count;
+        }
+        self.len += other.len;
+        self.perfect_count += other.perfect_count;
+    }
+
+    /// 盤面数を返す。
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// パーフェクトクリアの盤面数を返す。
+    pub fn perfect_count(&self) -> u64 {
+        self.perfect_count
+    }
+
+    /// パーフェクトクリアの盤面の割合を返す。空なら `None` を返す。
+    pub fn perfect_rate(&self) -> Option<f64> {
+        (!self.is_empty()).then(|| self.perfect_count as f64 / self.len as f64)
+    }
+
+    pub fn min(&self) -> Option<Score> {
+        self.counts.keys().next().copied()
+    }
+
+    pub fn max(&self) -> Option<Score> {
+        self.counts.keys().next_back().copied()
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        let sum: f64 = self
+            .counts
+            .iter()
+            .map(|(&score, &count)| f64::from(score) * count as f64)
+            .sum();
+        (!self.is_empty()).then(|| sum / self.len as f64)
+    }
+
+    /// `q` 分位点 (`0 <= q <= 1`) を返す。空なら `None` を返す。
+    ///
+    /// 小さい方から数えて累積度数が `ceil(q * 盤面数)` (最低 1) 以上となる最小のスコアとする。
+    pub fn quantile(&self, q: f64) -> Option<Score> {
+        assert!((0.0..=1.0).contains(&q));
+
+        let rank = ((q * self.len as f64).ceil() as u64).max(1);
+        let mut acc = 0;
+        self.counts.iter().find_map(|(&score, &count)| {
+            acc += count;
+            (acc >= rank).then_some(score)
+        })
+    }
+
+    /// スコアごとの度数を昇順に列挙する。
+    pub fn iter(&self) -> impl Iterator<Item = (Score, u64)> + '_ {
+        self.counts.iter().map(|(&score, &count)| (score, count))
+    }
+
+    /// スコアを幅 `bin_width` の区間 `[k * bin_width, (k+1) * bin_width)` にまとめた度数を昇順に返す。
+    /// 度数 0 の区間は含まない。
+    pub fn bins(&self, bin_width: Score) -> Vec<(Score, u64)> {
+        assert!(bin_width > 0);
+
+        let mut bins = BTreeMap::<Score, u64>::new();
+        for (score, count) in self.iter() {
+            *bins.entry(score / bin_width * bin_width).or_default() += count;
+        }
+        bins.into_iter().collect()
+    }
+}
+
+/// 盤面集合全体のスコア統計。
+///
+/// 全体のほか、`inc_timing` ごと、`counter` ごとの度数分布を持つ。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScoreStats {
+    metric: StatsMetric,
+    all: ScoreHistogram,
+    by_inc_timing: BTreeMap<usize, ScoreHistogram>,
+    by_counter: BTreeMap<u8, ScoreHistogram>,
+}
+
+impl ScoreStats {
+    pub fn new(metric: StatsMetric) -> Self {
+        Self {
+            metric,
+            all: ScoreHistogram::default(),
+            by_inc_timing: BTreeMap::new(),
+            by_counter: BTreeMap::new(),
+        }
+    }
+
+    /// 探索結果のデータベースから統計をとる。
+    ///
+    /// 値は近似ソルバーで記録したデータベースなら `StatsMetric::Approx`、そうでなければ `StatsMetric::Exact` とする。
+    pub fn from_results_db(db: &ResultsDb) -> Self {
+        let metric = if db.is_exact() {
+            StatsMetric::Exact
+        } else {
+            StatsMetric::Approx
+        };
+        let mut stats = Self::new(metric);
+        for record in db.records() {
+            stats.add(
+                record.rng_counter(),
+                record.rng_inc_timing(),
+                record.score(),
+                record.is_perfect(),
+            );
+        }
+        stats
+    }
+
+    pub fn metric(&self) -> StatsMetric {
+        self.metric
+    }
+
+    /// 盤面を 1 つ追加する。
+    pub fn add(&mut self, counter: u8, inc_timing: usize, score: Score, perfect: bool) {
+        self.all.add(score, perfect);
+        self.by_inc_timing
+            .entry(inc_timing)
+            .or_default()
+            .add(score, perfect);
+        self.by_counter
+            .entry(counter)
+            .or_default()
+            .add(score, perfect);
+    }
+
+    /// 別の統計を合算する。値の種類は一致していなければならない。
+    pub fn merge(&mut self, other: &Self) {
+        assert_eq!(self.metric, other.metric);
+
+        self.all.merge(&other.all);
+        for (&inc_timing, hist) in &other.by_inc_timing {
+            self.by_inc_timing
+                .entry(inc_timing)
+                .or_default()
+                .merge(hist);
+        }
+        for (&counter, hist) in &other.by_counter {
+            self.by_counter.entry(counter).or_default().merge(hist);
+        }
+    }
+
+    /// 全体の度数分布を返す。
+    pub fn all(&self) -> &ScoreHistogram {
+        &self.all
+    }
+
+    /// `inc_timing` ごとの度数分布を返す。
+    pub fn by_inc_timing(&self) -> &BTreeMap<usize, ScoreHistogram> {
+        &self.by_inc_timing
+    }
+
+    /// `counter` ごとの度数分布を返す。
+    pub fn by_counter(&self) -> &BTreeMap<u8, ScoreHistogram> {
+        &self.by_counter
+    }
+
+    /// `(グループ名, キー, 度数分布)` を全体、`inc_timing` ごと、`counter` ごとの順に列挙する。
+    /// 全体のキーは空文字列とする。
+    fn groups(&self) -> impl Iterator<Item = (&'static str, String, &ScoreHistogram)> {
+        std::iter::once(("all", String::new(), &self.all))
+            .chain(
+                self.by_inc_timing
+                    .iter()
+                    .map(|(inc_timing, hist)| ("inc_timing", inc_timing.to_string(), hist)),
+            )
+            .chain(
+                self.by_counter
+                    .iter()
+                    .map(|(counter, hist)| ("counter", counter.to_string(), hist)),
+            )
+    }
+
+    /// 統計を `format` の形式で書き出す。度数分布はスコアを幅 `bin_width` の区間にまとめる。
+    pub fn write(
+        &self,
+        mut w: impl Write,
+        format: StatsFormat,
+        bin_width: Score,
+    ) -> std::io::Result<()> {
+        match format {
+            StatsFormat::Text => self.write_text(&mut w, bin_width),
+            StatsFormat::SummaryCsv => self.write_summary_csv(&mut w),
+            StatsFormat::HistogramCsv => self.write_histogram_csv(&mut w, bin_width),
+            StatsFormat::Json => self.write_json(&mut w, bin_width),
+        }
+    }
+
+    fn write_text(&self, w: &mut impl Write, bin_width: Score) -> std::io::Result<()> {
+        writeln!(w, "metric: {}", self.metric)?;
+        writeln!(w)?;
+
+        write!(
+            w,
+            "{:<10} {:>5} {:>10} {:>6} {:>8}",
+            "group", "key", "count", "min", "mean"
+        )?;
+        for q in STATS_QUANTILES {
+            write!(w, " {:>6}", format!("p{}", q * 100.0))?;
+        }
+        writeln!(w, " {:>6} {:>8}", "max", "perfect")?;
+        for (group, key, hist) in self.groups() {
+            write!(
+                w,
+                "{group:<10} {key:>5} {:>10} {:>6} {:>8}",
+                hist.len(),
+                fmt_opt(hist.min()),
+                fmt_opt(hist.mean().map(|x| format!("{x:.1}"))),
+            )?;
+            for q in STATS_QUANTILES {
+                write!(w, " {:>6}", fmt_opt(hist.quantile(q)))?;
+            }
+            writeln!(
+                w,
+                " {:>6} {:>7}%",
+                fmt_opt(hist.max()),
+                fmt_opt(hist.perfect_rate().map(|x| format!("{:.3}", 100.0 * x))),
+            )?;
+        }
+        writeln!(w)?;
+
+        let bins = self.all.bins(bin_width);
+        let count_max = bins.iter().map(|&(_, count)| count).max().unwrap_or(0);
+        for (lo, count) in bins {
+            let bar_len = (60 * count).div_ceil(count_max) as usize;
+            writeln!(
+                w,
+                "{lo:>5}-{:<5} {count:>10} {}",
+                lo + bin_width - 1,
+                "#".repeat(bar_len)
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_summary_csv(&self, w: &mut impl Write) -> std::io::Result<()> {
+        write!(w, "group,key,count,min,mean")?;
+        for q in STATS_QUANTILES {
+            write!(w, ",p{}", q * 100.0)?;
+        }
+        writeln!(w, ",max,perfect_count,perfect_rate")?;
+
+        for (group, key, hist) in self.groups() {
+            write!(
+                w,
+                "{group},{key},{},{},{}",
+                hist.len(),
+                fmt_opt(hist.min()),
+                fmt_opt(hist.mean())
+            )?;
+            for q in STATS_QUANTILES {
+                write!(w, ",{}", fmt_opt(hist.quantile(q)))?;
+            }
+            writeln!(
+                w,
+                ",{},{},{}",
+                fmt_opt(hist.max()),
+                hist.perfect_count(),
+                fmt_opt(hist.perfect_rate())
+            )?;
+        }
+
+        Ok(())
+    }
+
+    fn write_histogram_csv(&self, w: &mut impl Write, bin_width: Score) -> std::io::Result<()> {
+        writeln!(w, "group,key,score_min,score_max,count")?;
+
+        for (group, key, hist) in self.groups() {
+            for (lo, count) in hist.bins(bin_width) {
+                writeln!(w, "{group},{key},{lo},{},{count}", lo + bin_width - 1)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, w: &mut impl Write, bin_width: Score) -> std::io::Result<()> {
+        // 値は全て数値か null なので、エスケープは不要。
+        let json_opt = |x: Option<String>| x.unwrap_or_else(|| "null".to_owned());
+
+        writeln!(w, "{{")?;
+        writeln!(w, "  \"metric\": \"{}\",", self.metric)?;
+        writeln!(w, "  \"bin_width\": {bin_width},")?;
+        writeln!(w, "  \"groups\": [")?;
+
+        let groups: Vec<_> = self.groups().collect();
+        for (i, (group, key, hist)) in groups.iter().enumerate() {
+            let key = if key.is_empty() { "null" } else { key };
+            let quantiles = STATS_QUANTILES
+                .iter()
+                .map(|&q| {
+                    format!(
+                        "\"{q}\": {}",
+                        json_opt(hist.quantile(q).map(|x| x.to_string()))
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let histogram = hist
+                .bins(bin_width)
+                .iter()
+                .map(|(lo, count)| format!("[{lo}, {count}]"))
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            writeln!(w, "    {{")?;
+            writeln!(w, "      \"group\": \"{group}\",")?;
+            writeln!(w, "      \"key\": {key},")?;
+            writeln!(w, "      \"count\": {},", hist.len())?;
+            writeln!(
+                w,
+                "      \"min\": {},",
+                json_opt(hist.min().map(|x| x.to_string()))
+            )?;
+            writeln!(
+                w,
+                "      \"mean\": {},",
+                json_opt(hist.mean().map(|x| x.to_string()))
+            )?;
+            writeln!(w, "      \"quantiles\": {{{quantiles}}},")?;
+            writeln!(
+                w,
+                "      \"max\": {},",
+                json_opt(hist.max().map(|x| x.to_string()))
+            )?;
+            writeln!(w, "      \"perfect_count\": {},", hist.perfect_count())?;
+            writeln!(
+                w,
+                "      \"perfect_rate\": {},",
+                json_opt(hist.perfect_rate().map(|x| x.to_string()))
+            )?;
+            writeln!(w, "      \"histogram\": [{histogram}]")?;
+            let sep = if i + 1 < groups.len() { "," } else { "" };
+            writeln!(w, "    }}{sep}")?;
+        }
+
+        writeln!(w, "  ]")?;
+        writeln!(w, "}}")?;
+
+        Ok(())
+    }
+}
+
+fn fmt_opt(x: Option<impl ToString>) -> String {
+    x.map_or_else(String::new, |x| x.to_string())
+}
+
+/// 盤面集合の各盤面について `metric` の値を求め、統計をとる。
+///
+/// `StatsMetric::Exact`, `StatsMetric::Approx` の場合、各盤面を `kind` のソルバーで解く (最大スコアの初期値は無視する)。
+/// `kind` は前者では最大スコアを求めるソルバー、後者では近似ソルバーでなければならない (そうでなければ失敗する)。
+/// 盤面集合を `solve_problems_many_parallel()` と同様に `thread_count` 個のスレッドで分担する
+/// (DP テーブルのメモリ量 `config.table_bytes` も各スレッドに等分される)。
+pub fn collect_score_stats(
+    metric: StatsMetric,
+    kind: SolverKind,
+    config: &SolveConfig,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+    thread_count: usize,
+) -> anyhow::Result<ScoreStats> {
+    assert!(thread_count > 0);
+
+    let use_solver = match metric {
+        StatsMetric::Exact => {
+            ensure!(
+                kind.is_exact(),
+                "{kind} ソルバーは近似ソルバーなので、統計の値 {metric} には使えない (approx を用いること)"
+            );
+            true
+        }
+        StatsMetric::Approx => {
+            ensure!(
+                !kind.is_exact(),
+                "{kind} ソルバーは最大スコアを求めるので、統計の値 {metric} には使えない (exact を用いること)"
+            );
+            true
+        }
+        StatsMetric::Bound(_) => false,
+    };
+    if use_solver {
+        kind.ensure_supported(config)?;
+    }
+
+    let config_worker = SolveConfig {
+        table_bytes: config.table_bytes / thread_count,
+        ..config.clone()
+    };
+    let sweep = Sweep::new(states, counters, inc_timings);
+    let chunk_next = AtomicUsize::new(0);
+    let stats = Mutex::new(ScoreStats::new(metric));
+
+    let worker = || {
+        let mut solver = use_solver.then(|| kind.new_solver(&config_worker));
+        let mut stats_local = ScoreStats::new(metric);
+
+        loop {
+            let idx_chunk = chunk_next.fetch_add(1, Ordering::Relaxed) * SWEEP_CHUNK_LEN;
+            if idx_chunk >= sweep.len() {
+                break;
+            }
+            let idx_end = (idx_chunk + SWEEP_CHUNK_LEN).min(sweep.len());
+
+            for idx in idx_chunk..idx_end {
+                let (counter, inc_timing, state) = sweep.get(idx);
+                let Some(board) = GameRng::new(state).gen_board(counter, inc_timing) else {
+                    continue;
+                };
+
+                let (score, perfect) = match metric {
+                    StatsMetric::Exact | StatsMetric::Approx => {
+                        eval_solver(solver.as_deref_mut().unwrap(), board)
+                    }
+                    StatsMetric::Bound(bound) => {
+                        let pos = Position::new(board);
+                        (bound.eval(&pos), bound.may_perfect(&pos))
+                    }
+                };
+                stats_local.add(counter, inc_timing, score, perfect);
+            }
+        }
+
+        stats.lock().unwrap().merge(&stats_local);
+    };
+
+    std::thread::scope(|scope| {
+        for _ in 0..thread_count {
+            scope.spawn(worker);
+        }
+    });
+
+    Ok(stats.into_inner().unwrap())
+}
+
+/// 盤面 `board` をソルバーで解き、得られたスコアとそれがパーフェクトクリアかどうかを返す。
+fn eval_solver(solver: &mut dyn Solve, board: Board) -> (Score, bool) {
+    solver.set_best_score(0);
+    match solver.solve(board.clone()) {
+        Some(res) => (res.score(), is_perfect_clear(board, res.solution())),
+        None => (0, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::results_db::{ResultRecord, ResultsDbWriter};
+
+    use super::*;
+
+    #[test]
+    fn test_stats_parse() {
+        for metric in [
+            StatsMetric::Exact,
+            StatsMetric::Approx,
+            StatsMetric::Bound(ScoreBound::Simple),
+            StatsMetric::Bound(ScoreBound::Separation),
+        ] {
+            assert_eq!(metric.to_string().parse::<StatsMetric>().unwrap(), metric);
+        }
+        assert!("foo".parse::<StatsMetric>().is_err());
+
+        for format in [
+            StatsFormat::Text,
+            StatsFormat::SummaryCsv,
+            StatsFormat::HistogramCsv,
+            StatsFormat::Json,
+        ] {
+            assert_eq!(format.to_string().parse::<StatsFormat>().unwrap(), format);
+        }
+        assert!("foo".parse::<StatsFormat>().is_err());
+    }
+
+    #[test]
+    fn test_score_histogram() {
+        let empty = ScoreHistogram::default();
+        assert_eq!(empty.min(), None);
+        assert_eq!(empty.mean(), None);
+        assert_eq!(empty.quantile(0.5), None);
+        assert_eq!(empty.perfect_rate(), None);
+
+        let mut hist = ScoreHistogram::default();
+        for score in 1..=10 {
+            hist.add(10 * score, score == 10);
+        }
+        assert_eq!(hist.len(), 10);
+        assert_eq!(hist.min(), Some(10));
+        assert_eq!(hist.max(), Some(100));
+        assert_eq!(hist.mean(), Some(55.0));
+        assert_eq!(hist.quantile(0.0), Some(10));
+        assert_eq!(hist.quantile(0.1), Some(10));
+        assert_eq!(hist.quantile(0.11), Some(20));
+        assert_eq!(hist.quantile(0.5), Some(50));
+        assert_eq!(hist.quantile(1.0), Some(100));
+        assert_eq!(hist.perfect_rate(), Some(0.1));
+        assert_eq!(hist.bins(25), [(0, 2), (25, 2), (50, 3), (75, 2), (100, 1)]);
+
+        let mut merged = ScoreHistogram::default();
+        merged.merge(&hist);
+        merged.merge(&hist);
+        assert_eq!(merged.len(), 20);
+        assert_eq!(merged.perfect_count(), 2);
+        assert_eq!(merged.quantile(0.5), Some(50));
+    }
+
+    #[test]
+    fn test_collect_score_stats_bound() {
+        let bound = ScoreBound::Separation;
+        let states = 0..=0x1FF;
+        let counters = 0..=1;
+        let inc_timings = 39..=40;
+
+        let stats = collect_score_stats(
+            StatsMetric::Bound(bound),
+            SolverKind::Many,
+            &SolveConfig::default(),
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            3,
+        )
+        .unwrap();
+
+        // スレッド数によらず、素朴に求めたものと一致する。
+        let mut expect = ScoreStats::new(StatsMetric::Bound(bound));
+        for (counter, inc_timing, state) in itertools::iproduct!(counters, inc_timings, states) {
+            let Some(board) = GameRng::new(state).gen_board(counter, inc_timing) else {
+                continue;
+            };
+            let pos = Position::new(board);
+            expect.add(
+                counter,
+                inc_timing,
+                bound.eval(&pos),
+                bound.may_perfect(&pos),
+            );
+        }
+        assert_eq!(stats, expect);
+
+        assert_eq!(stats.by_inc_timing().len(), 2);
+        assert_eq!(stats.by_counter().len(), 2);
+        let len_sum: u64 = stats.by_inc_timing().values().map(|hist| hist.len()).sum();
+        assert_eq!(len_sum, stats.all().len());
+    }
+
+    #[test]
+    fn test_score_stats_from_results_db() {
+        let path = std::env::temp_dir().join(format!(
+            "samegame-sfc-small-test-stats-results-{}.bin",
+            std::process::id()
+        ));
+        {
+            let mut writer = ResultsDbWriter::open(&path, true).unwrap();
+            // 0x28A1/0x00/40 はパーフェクトクリアできる。
+            let solution = "6,6 8,1 5,3 6,2 2,2 6,1 1,2 1,1 1,1 1,1".parse().unwrap();
+            writer
+                .append(&ResultRecord::new(0x28A1, 0x00, 40, 720, solution, 0))
+                .unwrap();
+            writer
+                .append(&ResultRecord::new(
+                    0x1234,
+                    0x00,
+                    39,
+                    101,
+                    Default::default(),
+                    0,
+                ))
+                .unwrap();
+            writer.flush().unwrap();
+        }
+        let stats = ScoreStats::from_results_db(&ResultsDb::read(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(stats.metric(), StatsMetric::Exact);
+        assert_eq!(stats.all().len(), 2);
+        assert_eq!(stats.all().perfect_count(), 1);
+        assert_eq!(stats.by_inc_timing()[&40].max(), Some(720));
+        assert_eq!(stats.by_inc_timing()[&39].max(), Some(101));
+        assert_eq!(stats.by_counter()[&0].len(), 2);
+    }
+
+    #[test]
+    fn test_score_stats_from_results_db_approx() {
+        let path = std::env::temp_dir().join(format!(
+            "samegame-sfc-small-test-stats-results-approx-{}.bin",
+            std::process::id()
+        ));
+        {
+            let mut writer = ResultsDbWriter::open(&path, false).unwrap();
+            writer
+                .append(&ResultRecord::new(
+                    0x1234,
+                    0x00,
+                    39,
+                    101,
+                    Default::default(),
+                    0,
+                ))
+                .unwrap();
+            writer.flush().unwrap();
+        }
+        let stats = ScoreStats::from_results_db(&ResultsDb::read(&path).unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        // 近似ソルバーで記録した結果は最大スコアとして扱わない。
+        assert_eq!(stats.metric(), StatsMetric::Approx);
+        assert_eq!(stats.all().len(), 1);
+    }
+
+    #[test]
+    fn test_collect_score_stats_solver_kind() {
+        let config = SolveConfig {
+            table_bytes: 1 << 16,
+            ..Default::default()
+        };
+        let collect =
+            |metric, kind| collect_score_stats(metric, kind, &config, 0..=0, 0..=0, 39..=39, 1);
+
+        // 近似ソルバーの結果を最大スコアとして、最大スコアを求めるソルバーの結果を近似値として集計しない。
+        for kind in [SolverKind::Beam, SolverKind::Mcts] {
+            assert!(collect(StatsMetric::Exact, kind).is_err());
+        }
+        for kind in [SolverKind::Exact, SolverKind::Many] {
+            assert!(collect(StatsMetric::Approx, kind).is_err());
+        }
+    }
+
+    #[test]
+    fn test_score_stats_write() {
+        let mut stats = ScoreStats::new(StatsMetric::Exact);
+        stats.add(0, 39, 100, false);
+        stats.add(1, 40, 300, true);
+
+        let write = |format| {
+            let mut buf = Vec::new();
+            stats.write(&mut buf, format, 100).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        let csv = write(StatsFormat::SummaryCsv);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(
+            lines[0],
+            "group,key,count,min,mean,p10,p25,p50,p75,p90,p99,max,perfect_count,perfect_rate"
+        );
+        assert_eq!(lines[1], "all,,2,100,200,100,100,100,300,300,300,300,1,0.5");
+        assert_eq!(
+            lines[2],
+            "inc_timing,39,1,100,100,100,100,100,100,100,100,100,0,0"
+        );
+        assert_eq!(lines.len(), 1 + 1 + 2 + 2);
+
+        let csv = write(StatsFormat::HistogramCsv);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0], "group,key,score_min,score_max,count");
+        assert_eq!(lines[1], "all,,100,199,1");
+        assert_eq!(lines[2], "all,,300,399,1");
+        assert_eq!(lines.len(), 1 + 2 + 2 + 2);
+
+        let json = write(StatsFormat::Json);
+        assert!(json.contains("\"metric\": \"exact\""));
+        assert!(json.contains("\"key\": null"));
+        assert!(json.contains("\"histogram\": [[100, 1], [300, 1]]"));
+        assert!(json.trim_end().ends_with('}'));
+
+        let text = write(StatsFormat::Text);
+        assert!(text.starts_with("metric: exact"));
+    }
+}
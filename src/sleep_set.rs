@@ -0,0 +1,379 @@
+//! 可換な着手の順序を入れ替えただけの手順を重複して探索しないための sleep set。
+
+use std::ops::Range;
+
+use arrayvec::ArrayVec;
+
+use crate::action::{col_masks_apart, Action};
+use crate::board::Board;
+use crate::score::Score;
+use crate::square::{Square, SQUARE_NUM_MAX};
+
+/// sleep set の容量。1 局面の着手数は高々マス数の半分なので、どの盤面サイズでもこれで足りる。
+const SLEEP_SET_CAP: usize = SQUARE_NUM_MAX / 2;
+
+/// 探索中の局面で「眠っている」着手の集合。
+///
+/// ある局面で着手 a を調べた後、a と可換な着手 b を調べる際には、b の後の局面で a を眠らせて選ばない
+/// (a, b の順の手順を既に調べたので)。可換性は `Action::commutes_with()` で判定する。
+///
+/// 眠っている着手は探索しないが、DP テーブルに記録する値は sleep set によらず局面の正しい上界でなければならない。
+/// そこで、眠っている各着手について「その着手による獲得スコア + その後の追加スコア上界」の上界も保持する。
+/// 着手 a の後の局面で眠る着手 b の値は、a を調べた際に得た b の値から a による獲得スコアを引いたものとする
+/// (b の後の局面の追加スコア上界は、a の獲得スコアと a, b の後の局面の追加スコアの和以上なので)。
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SleepSet<const W: usize, const H: usize> {
+    entries: ArrayVec<SleepEntry<W, H>, SLEEP_SET_CAP>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SleepEntry<const W: usize, const H: usize> {
+    /// 着手の最小のマス。可換な着手を行っても変わらないので、着手の識別に使える。
+    least_square: Square<W, H>,
+    /// 着手の列集合。
+    col_mask: u32,
+    /// 着手による獲得スコア + その後の追加スコア上界、の上界。
+    gain_ub: Score,
+}
+
+/// 可換性の判定に必要な着手の情報。
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ActionFootprint<const W: usize, const H: usize> {
+    least_square: Square<W, H>,
+    col_mask: u32,
+    /// 列を消滅させる着手は何とも可換とみなさない。
+    empties_col: bool,
+}
+
+impl<const W: usize, const H: usize> ActionFootprint<W, H> {
+    pub(crate) fn new<const P: usize>(board: &Board<W, H, P>, action: &Action<W, H, P>) -> Self {
+        Self {
+            least_square: action.least_square(),
+            col_mask: action.mask().col_mask(),
+            empties_col: board.erase_empties_col(action.mask()),
+        }
+    }
+}
+
+impl<const W: usize, const H: usize> SleepSet<W, H> {
+    /// 着手 `action` が眠っているかどうかを返す。
+    pub(crate) fn contains<const P: usize>(&self, action: &Action<W, H, P>) -> bool {
+        let sq = action.least_square();
+        self.entries.iter().any(|entry| entry.least_square == sq)
+    }
+
+    /// 眠っている着手たちの値 (獲得スコア + その後の追加スコア上界) の最大値を返す。空なら 0 を返す。
+    pub(crate) fn gain_ub_max(&self) -> Score {
+        self.entries
+            .iter()
+            .map(|entry| entry.gain_ub)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 調べ終えた着手 `fp` をその値 `gain_ub` と共に追加する。列を消滅させる着手は追加しない。
+    pub(crate) fn insert(&mut self, fp: ActionFootprint<W, H>, gain_ub: Score) {
+        if fp.empties_col {
+            return;
+        }
+
+        self.entries.push(SleepEntry {
+            least_square: fp.least_square,
+            col_mask: fp.col_mask,
+            gain_ub,
+        });
+    }
+
+    /// 獲得スコア `gain` の着手 `fp` を行った後の局面における sleep set を返す。
+    ///
+    /// `self` には、現局面で眠っている着手と、現局面で `fp` より先に調べた着手が含まれていなければならない。
+    pub(crate) fn child(&self, fp: ActionFootprint<W, H>, gain: Score) -> Self {
+        if fp.empties_col {
+            return Self::default();
+        }
+
+        let entries = self
+            .entries
+            .iter()
+            .filter(|entry| col_masks_apart(entry.col_mask, fp.col_mask))
+            .map(|entry| SleepEntry {
+                gain_ub: entry.gain_ub.saturating_sub(gain),
+                ..*entry
+            })
+            .collect();
+
+        Self { entries }
+    }
+}
+
+/// 最大スコアを正確に求めるソルバー (`ExactSolver`, `ParallelSolver`) 用の sleep set において、
+/// ある局面での各着手の正確な値 (獲得スコア + その後の最大スコア) の一覧。
+///
+/// 探索中の局面で眠っている着手とその値の集合としても用いる。
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ActionValues<const W: usize, const H: usize> {
+    entries: ArrayVec<(Square<W, H>, Score), SLEEP_SET_CAP>,
+}
+
+impl<const W: usize, const H: usize> ActionValues<W, H> {
+    /// 着手 `action` が一覧にあるかどうかを返す。
+    pub(crate) fn contains<const P: usize>(&self, action: &Action<W, H, P>) -> bool {
+        let sq = action.least_square();
+        self.entries
+            .iter()
+            .any(|&(least_square, _)| least_square == sq)
+    }
+
+    /// 値の最大値を返す。空なら 0 を返す。
+    pub(crate) fn value_max(&self) -> Score {
+        self.entries
+            .iter()
+            .map(|&(_, value)| value)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// 着手 `action` の値 `value` を追加する。
+    pub(crate) fn insert<const P: usize>(&mut self, action: &Action<W, H, P>, value: Score) {
+        self.entries.push((action.least_square(), value));
+    }
+}
+
+/// 探索中の各局面で調べ終えた着手について、その後の局面における各着手の値の一覧を積んでおくスタック。
+///
+/// 一覧は局面ごとに `ActionValues` 1 個分の大きさがあり、探索の各深さでそれを着手の数だけ保持するので、
+/// 関数呼び出しのスタックには置かずにソルバー (スレッド) ごとにこれを持つ。
+#[derive(Debug, Default)]
+pub(crate) struct ActionValuesStack<const W: usize, const H: usize> {
+    entries: Vec<(Square<W, H>, Score)>,
+}
+
+impl<const W: usize, const H: usize> ActionValuesStack<W, H> {
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// 位置 `base` 以降 (探索を終えた局面の子局面たちの分) を取り除き、探索を終えた局面の各着手の値 `values` を積む。
+    pub(crate) fn replace_top(&mut self, base: usize, values: &ActionValues<W, H>) {
+        self.entries.truncate(base);
+        self.entries.extend_from_slice(&values.entries);
+    }
+}
+
+/// 最大スコアを正確に求めるソルバー用の sleep set を作るため、ある局面で調べ終えた着手たちを保持する。
+///
+/// `SleepSet` は眠っている着手の値として上界しか持たないので、DP テーブルに正確な値を記録するソルバーには使えない。
+/// そこで、着手 a を調べた際に a の後の局面で得た各着手の値 (`ActionValuesStack` に積まれる) を参照し、
+/// 後で a と可換な着手 b を調べる際、b の後の局面で a を眠らせる値を a, b を両方行った局面の値から正確に求める
+/// (a の後の局面における b の値 - b の獲得スコア + a の獲得スコア)。
+///
+/// a の後の局面が既に DP テーブルに載っていた場合は各着手の値がわからないので、a は眠らせない。
+/// また、着手前の局面で眠っていた着手もその後の局面での正確な値がわからないので、引き継がずに探索する。
+#[derive(Debug, Default)]
+pub(crate) struct ExactSleepSet<const W: usize, const H: usize> {
+    done: ArrayVec<ExactSleepEntry<W, H>, SLEEP_SET_CAP>,
+}
+
+#[derive(Debug)]
+struct ExactSleepEntry<const W: usize, const H: usize> {
+    fp: ActionFootprint<W, H>,
+    /// 着手による獲得スコア。
+    gain: Score,
+    /// 着手後の局面における各着手の値の `ActionValuesStack` 上の位置。
+    values: Range<usize>,
+}
+
+impl<const W: usize, const H: usize> ExactSleepSet<W, H> {
+    /// 獲得スコア `gain` の着手 `fp` を調べ終え、その後の局面における各着手の値を `ActionValuesStack` の
+    /// 位置 `values` に得たことを記録する。列を消滅させる着手や、`values` が空の着手は記録しない。
+    pub(crate) fn insert(&mut self, fp: ActionFootprint<W, H>, gain: Score, values: Range<usize>) {
+        if fp.empties_col || values.is_empty() {
+            return;
+        }
+
+        self.done.push(ExactSleepEntry { fp, gain, values });
+    }
+
+    /// 獲得スコア `gain` の着手 `fp` を行った後の局面で眠らせる着手とその値を返す。
+    pub(crate) fn child(
+        &self,
+        stack: &ActionValuesStack<W, H>,
+        fp: ActionFootprint<W, H>,
+        gain: Score,
+    ) -> ActionValues<W, H> {
+        if fp.empties_col {
+            return ActionValues::default();
+        }
+
+        let entries = self
+            .done
+            .iter()
+            .filter(|entry| col_masks_apart(entry.fp.col_mask, fp.col_mask))
+            .filter_map(|entry| {
+                let &(_, value) = stack.entries[entry.values.clone()]
+                    .iter()
+                    .find(|&&(sq, _)| sq == fp.least_square)?;
+                Some((entry.fp.least_square, entry.gain + value - gain))
+            })
+            .collect();
+
+        ActionValues { entries }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use crate::position::Position;
+    use crate::replay::verify;
+    use crate::rng::GameRng;
+    use crate::solve::{SolveConfig, SolverKind};
+    use crate::solver::solve_problem_with;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn random_board(rng: &mut StdRng, height: usize) -> Board {
+        let mut s = "........\n".repeat(6 - height);
+        for _ in 0..height {
+            let line: String = (0..8)
+                .map(|_| char::from(b'1' + rng.gen_range(0..4)))
+                .collect();
+            s.push_str(&line);
+            s.push('\n');
+        }
+        parse_board(s)
+    }
+
+    #[test]
+    fn test_commutes_with() {
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            3.......
+            11225.44
+            23453312
+        "});
+        let pos = Position::new(board.clone());
+        let action_at = |s: &str| Action::from_board_square(&board, s.parse().unwrap());
+
+        // 列が離れていれば可換。
+        assert!(action_at("1,2").commutes_with(&action_at("7,2"), &board));
+        // 列が隣接していれば可換とみなさない。
+        assert!(!action_at("1,2").commutes_with(&action_at("3,2"), &board));
+        // 列を消滅させる着手は可換とみなさない。
+        assert!(!action_at("1,2").commutes_with(&action_at("5,1"), &board));
+        assert!(pos
+            .actions()
+            .all(|action| !action.commutes_with(&action, &board)));
+
+        // 可換と判定された着手の組は、どちらを先に行っても互いの駒群が変わらず、同じ盤面に至る。
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut commute_count = 0;
+        for _ in 0..200 {
+            let board = random_board(&mut rng, 6);
+            let pos = Position::new(board.clone());
+            for lhs in pos.actions() {
+                for rhs in pos.actions() {
+                    if !lhs.commutes_with(&rhs, &board) {
+                        continue;
+                    }
+                    commute_count += 1;
+
+                    let after_lhs = pos.do_action(&lhs);
+                    let after_rhs = pos.do_action(&rhs);
+                    let rhs_moved =
+                        Action::from_board_square(after_lhs.board(), rhs.least_square());
+                    let lhs_moved =
+                        Action::from_board_square(after_rhs.board(), lhs.least_square());
+                    assert_eq!(rhs_moved, rhs);
+                    assert_eq!(lhs_moved, lhs);
+                    assert_eq!(
+                        after_lhs.do_action(&rhs_moved),
+                        after_rhs.do_action(&lhs_moved)
+                    );
+                }
+            }
+        }
+        assert!(commute_count > 0);
+    }
+
+    /// sleep set を用いても各ソルバーは厳密解を返し、探索ノード数は増えない。
+    #[test]
+    fn test_solver_partial_order_reduction() {
+        const KINDS: [SolverKind; 5] = [
+            SolverKind::Exact,
+            SolverKind::Bound,
+            SolverKind::Many,
+            SolverKind::Parallel,
+            SolverKind::Aspiration,
+        ];
+
+        let config_new = |partial_order_reduction| SolveConfig {
+            table_bytes: 1 << 20,
+            partial_order_reduction,
+            ..Default::default()
+        };
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut boards: Vec<_> = (0..40).map(|_| random_board(&mut rng, 3)).collect();
+        boards.push(GameRng::new(0x28A1).gen_board(0x00, 40).unwrap());
+
+        for board in boards {
+            let (score, _) = solve_problem_with(board.clone(), &config_new(false));
+
+            let mut node_counts = [[0; 2]; KINDS.len()];
+            for (i, partial_order_reduction) in [false, true].into_iter().enumerate() {
+                let config = config_new(partial_order_reduction);
+                for (kind, node_counts) in KINDS.into_iter().zip(&mut node_counts) {
+                    let res = kind.new_solver(&config).solve(board.clone());
+                    assert_eq!(
+                        res.as_ref().map_or(0, |res| res.score()),
+                        score,
+                        "{kind}\n{board}"
+                    );
+                    if let Some(res) = &res {
+                        assert_eq!(verify(board.clone(), res.solution()), Ok(score));
+                    }
+                    node_counts[i] = res.map_or(0, |res| res.node_count());
+                }
+            }
+            // AspirationSolver は反復回数が変わりうるので、探索ノード数を比較しない。
+            for (kind, node_counts) in KINDS.into_iter().zip(node_counts) {
+                if kind != SolverKind::Aspiration {
+                    assert!(node_counts[1] <= node_counts[0], "{kind}\n{board}");
+                }
+            }
+        }
+    }
+
+    /// 正確な値を記録するソルバーでは、可換な着手の順序を入れ替えただけの手順を実際に省いている。
+    #[test]
+    fn test_exact_solver_partial_order_reduction() {
+        let board = GameRng::new(0x28A1).gen_board(0x00, 40).unwrap();
+        for kind in [SolverKind::Exact, SolverKind::Parallel] {
+            let node_count = |partial_order_reduction| {
+                let config = SolveConfig {
+                    table_bytes: 1 << 20,
+                    partial_order_reduction,
+                    ..Default::default()
+                };
+                kind.new_solver(&config)
+                    .solve(board.clone())
+                    .unwrap()
+                    .node_count()
+            };
+            assert!(node_count(true) < node_count(false), "{kind}");
+        }
+    }
+}
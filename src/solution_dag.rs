@@ -0,0 +1,466 @@
+//! 最適解全体を表す DAG。
+//!
+//! 独立な着手 (どちらを先に行っても互いの駒群が変わらない 2 手) の順序を入れ替えただけの手順は同一視する。
+//! これは sleep set の手法で実現している: ある局面で着手 a を調べた後、a と独立な着手 b を調べる際には、
+//! b の後の局面で a (の移動先) を「眠らせて」選ばないようにする。
+//! これにより、同一視される手順たちのうちちょうど 1 つのみが DAG 上の経路となる。
+
+use std::collections::HashMap;
+
+use crate::action::{Action, ActionHistory};
+use crate::board::{Board, MaskBoard};
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::square::Square;
+
+/// 最適解全体を表す DAG。
+///
+/// 各ノードは (局面, 眠っている着手の集合) に対応し、各辺は着手に対応する。
+/// 根から終端ノードへの経路が最適解と 1 対 1 に対応する。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SolutionDag<const W: usize = 8, const H: usize = 6> {
+    score: Score,
+    /// `nodes[0]` が根。
+    nodes: Vec<SolutionDagNode<W, H>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SolutionDagNode<const W: usize, const H: usize> {
+    /// (着手で選ぶマス, 子ノードのインデックス)。解を持たない子への辺は含まない。
+    edges: Vec<(Square<W, H>, usize)>,
+    /// このノードから終端ノードへの経路数。
+    solution_count: u128,
+}
+
+impl<const W: usize, const H: usize> SolutionDag<W, H> {
+    /// 最大スコアを返す。
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// 最適解の個数を返す (手順を展開せずに数える)。
+    pub fn solution_count(&self) -> u128 {
+        self.nodes[0].solution_count
+    }
+
+    /// DAG のノード数を返す。
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// 最適解を全て列挙する。各手は駒群の最小のマスで表す。
+    pub fn solutions(&self) -> SolutionDagIter<'_, W, H> {
+        SolutionDagIter {
+            dag: self,
+            stack: vec![(0, 0)],
+            solution: ActionHistory::new(),
+        }
+    }
+}
+
+/// `SolutionDag::solutions()` が返すイテレータ。
+#[derive(Debug)]
+pub struct SolutionDagIter<'a, const W: usize, const H: usize> {
+    dag: &'a SolutionDag<W, H>,
+    /// (ノードのインデックス, 次に辿る辺のインデックス)。
+    stack: Vec<(usize, usize)>,
+    solution: ActionHistory<W, H>,
+}
+
+impl<const W: usize, const H: usize> Iterator for SolutionDagIter<'_, W, H> {
+    type Item = ActionHistory<W, H>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(&mut (node_idx, ref mut edge_idx)) = self.stack.last_mut() {
+            let node = &self.dag.nodes[node_idx];
+
+            // 終端ノード。
+            if node.edges.is_empty() {
+                let res = self.solution.clone();
+                self.stack.pop();
+                if !self.stack.is_empty() {
+                    self.solution.remove_last();
+                }
+                return Some(res);
+            }
+
+            if let Some(&(sq, child)) = node.edges.get(*edge_idx) {
+                *edge_idx += 1;
+                self.solution.push(sq);
+                self.stack.push((child, 0));
+            } else {
+                self.stack.pop();
+                if !self.stack.is_empty() {
+                    self.solution.remove_last();
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl<const W: usize, const H: usize> std::iter::FusedIterator for SolutionDagIter<'_, W, H> {}
+
+/// 最適解全体を表す DAG を構築する。
+///
+/// `gain_max` は空でない盤面を持つ局面に対し、そこから追加で獲得できる最大スコアを返す関数。
+/// (DP テーブルのエントリが置き換えられている可能性があるので、必要なら再探索すること)
+pub(crate) fn reconstruct_solution_dag<const W: usize, const H: usize, const P: usize>(
+    pos_root: &Position<W, H, P>,
+    gain_max: impl FnMut(&Position<W, H, P>) -> Score,
+) -> SolutionDag<W, H> {
+    let mut builder = DagBuilder {
+        gain_max,
+        nodes: vec![],
+        node_map: HashMap::new(),
+    };
+
+    let score = if pos_root.board().is_empty() {
+        SCORE_PERFECT
+    } else {
+        (builder.gain_max)(pos_root)
+    };
+
+    let root = builder.build(pos_root, MaskBoard::empty(), score);
+
+    // 根を先頭に移す。子は常に親より先に作られるので、根は末尾にある。
+    let mut nodes = builder.nodes;
+    debug_assert_eq!(root, nodes.len() - 1);
+    nodes.rotate_right(1);
+    for node in &mut nodes {
+        for (_, child) in &mut node.edges {
+            *child += 1;
+        }
+    }
+
+    SolutionDag { score, nodes }
+}
+
+struct DagBuilder<const W: usize, const H: usize, const P: usize, F> {
+    gain_max: F,
+    nodes: Vec<SolutionDagNode<W, H>>,
+    node_map: HashMap<(Board<W, H, P>, MaskBoard<W, H>), usize>,
+}
+
+impl<const W: usize, const H: usize, const P: usize, F> DagBuilder<W, H, P, F>
+where
+    F: FnMut(&Position<W, H, P>) -> Score,
+{
+    /// 局面 `pos` (追加で獲得できる最大スコアは `gain`)、眠っている着手の集合 `sleep` に対するノードを作り、
+    /// そのインデックスを返す。
+    fn build(&mut self, pos: &Position<W, H, P>, sleep: MaskBoard<W, H>, gain: Score) -> usize {
+        let node_key = (pos.board().clone(), sleep);
+        if let Some(&idx) = self.node_map.get(&node_key) {
+            return idx;
+        }
+        let (_, sleep) = &node_key;
+
+        let actions: Vec<_> = pos.actions().collect();
+
+        let mut edges = vec![];
+        let mut solution_count = if actions.is_empty() { 1 } else { 0 };
+
+        // 既に調べた最適な着手 (眠っているものを含む)。
+        let mut done: Vec<&Action<W, H, P>> = actions
+            .iter()
+            .filter(|action| sleep.test(action.least_square()))
+            .collect();
+
+        for action in &actions {
+            if sleep.test(action.least_square()) {
+                continue;
+            }
+
+            let pos_child = pos.do_action(action);
+            let gain_action = score_erase(action.square_count());
+            let gain_child = if pos_child.board().is_empty() {
+                SCORE_PERFECT
+            } else {
+                (self.gain_max)(&pos_child)
+            };
+            if gain_action + gain_child != gain {
+                continue;
+            }
+
+            let mut sleep_child = MaskBoard::empty();
+            for &other in &done {
+                if actions_independent(pos.board(), action, other) {
+                    let mb = pos.board().erase_shift_mask(action.mask(), other.mask());
+                    for sq in mb.squares() {
+                        sleep_child.set(sq, true);
+                    }
+                }
+            }
+
+            let child = self.build(&pos_child, sleep_child, gain_child);
+            let child_count = self.nodes[child].solution_count;
+            if child_count != 0 {
+                edges.push((action.least_square(), child));
+                solution_count += child_count;
+            }
+
+            done.push(action);
+        }
+
+        let idx = self.nodes.len();
+        self.nodes.push(SolutionDagNode {
+            edges,
+            solution_count,
+        });
+        self.node_map.insert(node_key, idx);
+
+        idx
+    }
+}
+
+/// 盤面 `board` における 2 つの着手が独立か、
+/// すなわちどちらを先に行ってももう一方の駒群が変わらないかどうかを返す。
+fn actions_independent<const W: usize, const H: usize, const P: usize>(
+    board: &Board<W, H, P>,
+    lhs: &Action<W, H, P>,
+    rhs: &Action<W, H, P>,
+) -> bool {
+    let remains_group = |first: &Action<W, H, P>, second: &Action<W, H, P>| {
+        let board_after = board.erase(first.mask());
+        let mb = board.erase_shift_mask(first.mask(), second.mask());
+        let sq = mb.least_square().unwrap();
+        board_after.piece_mask(second.piece()).flood_fill(sq) == mb
+    };
+
+    remains_group(lhs, rhs) && remains_group(rhs, lhs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use crate::replay::verify;
+    use crate::solve::SolveConfig;
+    use crate::solver::solve_problem_all;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn config_small() -> SolveConfig {
+        SolveConfig {
+            table_bytes: 1 << 20,
+            ..Default::default()
+        }
+    }
+
+    /// 手順 `solution` の `i` 手目と `i + 1` 手目が独立ならば、それらを入れ替えた手順を返す。
+    fn swap_independent(
+        board: &Board,
+        solution: &ActionHistory,
+        i: usize,
+    ) -> Option<ActionHistory> {
+        let sqs = solution.as_slice();
+
+        let mut board = board.clone();
+        for &sq in &sqs[..i] {
+            board = board.erase(Action::from_board_square(&board, sq).mask());
+        }
+
+        let first = Action::from_board_square(&board, sqs[i]);
+        let second = Action::from_board_square(&board.erase(first.mask()), sqs[i + 1]);
+
+        // 2 手目の駒群の、1 手目を行う前の位置を求める。
+        let second_before = Position::new(board.clone()).actions().find(|action| {
+            action.piece() == second.piece()
+                && board.erase_shift_mask(first.mask(), action.mask()) == *second.mask()
+        })?;
+        if !actions_independent(&board, &first, &second_before) {
+            return None;
+        }
+
+        let first_after = board.erase_shift_mask(second_before.mask(), first.mask());
+
+        let mut res = sqs.to_vec();
+        res[i] = second_before.least_square();
+        res[i + 1] = first_after.least_square().unwrap();
+        Some(res.into_iter().collect())
+    }
+
+    /// 独立な着手の入れ替えで移り合う手順を同一視し、各手順が属する同値類の代表のインデックスを返す。
+    fn solution_classes(board: &Board, solutions: &[ActionHistory]) -> Vec<usize> {
+        fn root(parent: &mut [usize], i: usize) -> usize {
+            if parent[i] != i {
+                parent[i] = root(parent, parent[i]);
+            }
+            parent[i]
+        }
+
+        let index: HashMap<_, _> = solutions
+            .iter()
+            .enumerate()
+            .map(|(i, solution)| (solution.clone(), i))
+            .collect();
+
+        let mut parent: Vec<_> = (0..solutions.len()).collect();
+        for (i, solution) in solutions.iter().enumerate() {
+            for j in 0..solution.len().saturating_sub(1) {
+                if let Some(swapped) = swap_independent(board, solution, j) {
+                    let (ri, rj) = (root(&mut parent, i), root(&mut parent, index[&swapped]));
+                    parent[ri] = rj;
+                }
+            }
+        }
+
+        (0..solutions.len()).map(|i| root(&mut parent, i)).collect()
+    }
+
+    /// 全ての最適解を素朴に列挙する。
+    fn all_optimal_solutions(board: &Board) -> Vec<ActionHistory> {
+        fn gain_max(pos: &Position, memo: &mut HashMap<Board, Score>) -> Score {
+            if pos.board().is_empty() {
+                return SCORE_PERFECT;
+            }
+            if let Some(&gain) = memo.get(pos.board()) {
+                return gain;
+            }
+            let gain = pos
+                .actions()
+                .map(|action| {
+                    score_erase(action.square_count()) + gain_max(&pos.do_action(&action), memo)
+                })
+                .max()
+                .unwrap_or(0);
+            memo.insert(pos.board().clone(), gain);
+            gain
+        }
+
+        fn dfs(
+            pos: &Position,
+            memo: &mut HashMap<Board, Score>,
+            solution: &mut ActionHistory,
+            res: &mut Vec<ActionHistory>,
+        ) {
+            let gain = gain_max(pos, memo);
+            let mut is_leaf = true;
+            for action in pos.actions() {
+                let pos_child = pos.do_action(&action);
+                if score_erase(action.square_count()) + gain_max(&pos_child, memo) == gain {
+                    is_leaf = false;
+                    solution.push(action.least_square());
+                    dfs(&pos_child, memo, solution, res);
+                    solution.remove_last();
+                }
+            }
+            if is_leaf {
+                res.push(solution.clone());
+            }
+        }
+
+        let mut res = vec![];
+        dfs(
+            &Position::new(board.clone()),
+            &mut HashMap::new(),
+            &mut ActionHistory::new(),
+            &mut res,
+        );
+        res
+    }
+
+    /// 下 3 行のみに 3 種の駒を持つランダムな盤面を返す。
+    fn random_board(rng: &mut StdRng) -> Board {
+        let mut s = "........\n".repeat(3);
+        for _ in 0..3 {
+            let line: String = (0..8)
+                .map(|_| char::from(b'1' + rng.gen_range(0..3)))
+                .collect();
+            s.push_str(&line);
+            s.push('\n');
+        }
+        parse_board(s)
+    }
+
+    fn assert_dag_consistent(board: &Board) {
+        let dag = solve_problem_all(board.clone(), &config_small());
+
+        let solutions: Vec<_> = dag.solutions().collect();
+        assert_eq!(solutions.len() as u128, dag.solution_count());
+        for solution in &solutions {
+            assert_eq!(verify(board.clone(), solution), Ok(dag.score()));
+        }
+
+        // DAG 上の解は互いに同一視されず、素朴に列挙した全ての最適解を過不足なく代表する。
+        let solutions_naive = all_optimal_solutions(board);
+        let classes = solution_classes(board, &solutions_naive);
+        let class_of: HashMap<_, _> = itertools::zip_eq(&solutions_naive, &classes).collect();
+
+        let classes_dag: BTreeSet<_> = solutions
+            .iter()
+            .map(|solution| class_of[solution])
+            .collect();
+        assert_eq!(classes_dag.len(), solutions.len());
+        assert_eq!(classes_dag, classes.iter().collect());
+    }
+
+    #[test]
+    fn test_solution_dag() {
+        {
+            let dag = solve_problem_all(<Board>::empty(), &config_small());
+            assert_eq!(dag.score(), SCORE_PERFECT);
+            assert_eq!(dag.solution_count(), 1);
+            assert_eq!(dag.solutions().collect::<Vec<_>>(), [ActionHistory::new()]);
+        }
+
+        // 3 手は互いに独立なので、順序を入れ替えた 6 通りは同一視される。
+        {
+            let board = parse_board(indoc! {"
+                ........
+                ........
+                ........
+                ........
+                ........
+                112233..
+            "});
+            let dag = solve_problem_all(board.clone(), &config_small());
+            assert_eq!(dag.score(), 3 * score_erase(2) + SCORE_PERFECT);
+            assert_eq!(dag.solution_count(), 1);
+            assert_eq!(all_optimal_solutions(&board).len(), 6);
+        }
+
+        for board in [
+            indoc! {"
+                ........
+                ........
+                ........
+                1.......
+                22......
+                1133....
+            "},
+            indoc! {"
+                ........
+                ........
+                21......
+                11223...
+                22113322
+                11221111
+            "},
+            indoc! {"
+                ........
+                ........
+                32321131
+                23121233
+                31233222
+                12223221
+            "},
+        ] {
+            assert_dag_consistent(&parse_board(board));
+        }
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let board = random_board(&mut rng);
+            assert_dag_consistent(&board);
+        }
+    }
+}
@@ -0,0 +1,479 @@
+//! ソルバー共通のインターフェース。
+
+use std::io::{Read, Write};
+
+use anyhow::bail;
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::collision::CollisionCheck;
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::score_bound::ScoreBound;
+use crate::solver::ExactSolver;
+use crate::solver2::Solver;
+use crate::solver_aspiration::{AspirationSchedule, AspirationSolver};
+use crate::solver_beam::{BeamConfig, BeamSolver};
+use crate::solver_many::ManySolver;
+use crate::solver_mcts::{MctsConfig, MctsSolver};
+use crate::solver_parallel::ParallelSolver;
+
+/// 盤面を解くソルバー。
+///
+/// ソルバーは現時点での最大スコアを保持しており、それより大きいスコアのみを探索する。
+/// 最大スコアの初期値は `SolveConfig::best_score_ini` で与える。
+pub trait Solve<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    /// 与えられた盤面に対して従来より大きいスコアを探索する。
+    /// 見つかった場合、最大スコアの更新も行い、その結果を返す。
+    ///
+    /// 初形で手詰まりの盤面 (スコア 0) に対しては常に `None` を返す。
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>>;
+
+    /// 現時点での最大スコアを返す。
+    fn best_score(&self) -> Score;
+
+    /// 現時点での最大スコアを設定する (下げてもよい)。
+    ///
+    /// 複数のソルバーで最大スコアを共有する場合などに用いる。
+    fn set_best_score(&mut self, best_score: Score);
+
+    /// 盤面をまたいで保持している DP テーブル (`SolveConfig::share_table`) を書き出す。
+    ///
+    /// 保持しないソルバーでは失敗する。
+    fn write_table(&self, _w: &mut dyn Write) -> anyhow::Result<()> {
+        bail!("このソルバーは DP テーブルを盤面をまたいで保持しない")
+    }
+
+    /// `write_table()` で書き出した DP テーブルを読み込む。容量などが異なる場合は失敗する。
+    fn read_table(&mut self, _r: &mut dyn Read) -> anyhow::Result<()> {
+        bail!("このソルバーは DP テーブルを盤面をまたいで保持しない")
+    }
+}
+
+/// ソルバー共通の設定。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SolveConfig {
+    /// 最大スコアの初期値。これより大きいスコアのみを探索する。
+    pub best_score_ini: Score,
+
+    /// 1 つの盤面の探索に用いるスレッド数。並列探索に対応しないソルバーでは無視される。
+    pub thread_count: usize,
+
+    /// DP テーブルに用いるメモリ量の上限 (バイト)。
+    ///
+    /// 実際の容量 (エントリ数) はこれに収まる最大の 2 べきとなる。
+    pub table_bytes: usize,
+
+    /// DP テーブルが満杯になった場合の動作。
+    pub table_full_policy: TableFullPolicy,
+
+    /// DP テーブルにおけるハッシュ衝突の検出方法。並列探索を行うソルバーでは未対応。
+    pub collision_check: CollisionCheck,
+
+    /// 探索を行わずに見積もるスコア上界。`Solver`, `ManySolver`, `AspirationSolver` の枝刈りに用いられ、他のソルバーでは無視される。
+    pub score_bound: ScoreBound,
+
+    /// 可換な着手 (`Action::commutes_with()`) の順序を入れ替えただけの手順の重複探索を省くかどうか。
+    /// 深さ優先探索を行うソルバー (`ExactSolver`, `Solver`, `ManySolver`, `ParallelSolver`, `AspirationSolver`) で有効。
+    /// ビームサーチ、MCTS および k-best 探索では無視される。
+    pub partial_order_reduction: bool,
+
+    /// 盤面をまたいで DP テーブルのエントリを保持するかどうか。`ExactSolver`, `ManySolver` 以外のソルバーでは無視される。
+    ///
+    /// 保持する場合、局面のキーには駒種の付け替えによらない `Position::canonical_key()` を用いる
+    /// (駒種の付け替えのみが異なる局面どうしでエントリを共有できる)。
+    /// エントリは古くならないので、`TableFullPolicy::Replace` と併用すること。
+    ///
+    /// ゲーム内の盤面集合では共有できる局面がほとんどなく、現状では探索全体が遅くなる (README 参照)。
+    pub share_table: bool,
+
+    /// 目標スコアの下げ方。`AspirationSolver` 以外のソルバーでは無視される。
+    pub aspiration: AspirationSchedule,
+
+    /// ビームサーチの設定。ビームサーチ以外のソルバーでは無視される。
+    pub beam: BeamConfig,
+
+    /// モンテカルロ木探索の設定。モンテカルロ木探索以外のソルバーでは無視される。
+    pub mcts: MctsConfig,
+}
+
+impl SolveConfig {
+    /// 盤面サイズ `W`x`H` における DP テーブルの容量 (エントリ数) を返す。
+    ///
+    /// ハッシュ衝突の検出に用いる副テーブルも `table_bytes` に含めて計算する。
+    pub fn table_capacity<const W: usize, const H: usize>(&self) -> usize {
+        self.table_capacity_with::<W, H>(0)
+    }
+
+    /// `table_capacity()` と同じだが、1 エントリあたり `extra_bytes` バイトの副テーブルも併せて確保する場合の容量を返す。
+    pub(crate) fn table_capacity_with<const W: usize, const H: usize>(
+        &self,
+        extra_bytes: usize,
+    ) -> usize {
+        let entry_bytes = std::mem::size_of::<u64>()
+            * (1 + self.collision_check.fingerprint_len::<W, H>())
+            + extra_bytes;
+        assert!(
+            self.table_bytes >= entry_bytes,
+            "DP テーブルのメモリ量が小さすぎる: {} バイト",
+            self.table_bytes
+        );
+
+        let entry_count = self.table_bytes / entry_bytes;
+        1 << entry_count.ilog2()
+    }
+}
+
+impl Default for SolveConfig {
+    fn default() -> Self {
+        Self {
+            best_score_ini: 0,
+            thread_count: 1,
+            table_bytes: 8 << 30,
+            table_full_policy: TableFullPolicy::Replace,
+            collision_check: CollisionCheck::None,
+            score_bound: ScoreBound::Simple,
+            partial_order_reduction: true,
+            share_table: false,
+            aspiration: AspirationSchedule::default(),
+            beam: BeamConfig::default(),
+            mcts: MctsConfig::default(),
+        }
+    }
+}
+
+/// DP テーブルが満杯になった (実際には、probe の上限回数以内に空きが見つからなかった) 場合の動作。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TableFullPolicy {
+    /// panic する。probe 回数は無制限 (テーブルが本当に満杯になるまで探す)。
+    Fail,
+    /// probe 回数を `TABLE_PROBE_LIMIT` に制限し、空きがなければ既存エントリを置き換える。
+    ///
+    /// 置き換えられたエントリの局面は必要になれば再探索されるので、探索結果は変わらない。
+    Replace,
+}
+
+/// `TableFullPolicy::Replace` における probe の上限回数。
+pub const TABLE_PROBE_LIMIT: usize = 16;
+
+impl TableFullPolicy {
+    /// 容量 `capacity` のテーブルにおける probe の上限回数を返す。
+    pub(crate) fn probe_limit(self, capacity: usize) -> usize {
+        match self {
+            Self::Fail => capacity,
+            Self::Replace => TABLE_PROBE_LIMIT.min(capacity),
+        }
+    }
+
+    /// probe の上限回数以内に空きが見つからなかった場合に置き換えるスロットのオフセットを返す。
+    /// `TableFullPolicy::Fail` の場合は panic する。
+    pub(crate) fn victim_offset(self, capacity: usize) -> usize {
+        match self {
+            Self::Fail => panic!("DP テーブルが満杯 (容量 {capacity})"),
+            // probe 範囲の末尾を置き換える。
+            Self::Replace => self.probe_limit(capacity) - 1,
+        }
+    }
+}
+
+impl std::str::FromStr for TableFullPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "replace" => Ok(Self::Replace),
+            _ => bail!("DP テーブル満杯時の動作が無効: '{s}' (fail, replace のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for TableFullPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Fail => "fail",
+            Self::Replace => "replace",
+        };
+        f.write_str(s)
+    }
+}
+
+/// ソルバーの探索結果。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SolveResult<const W: usize = 8, const H: usize = 6> {
+    score: Score,
+    solution: ActionHistory<W, H>,
+    node_count: u64,
+    table_stats: TableStats,
+}
+
+impl<const W: usize, const H: usize> SolveResult<W, H> {
+    pub fn new(
+        score: Score,
+        solution: ActionHistory<W, H>,
+        node_count: u64,
+        table_stats: TableStats,
+    ) -> Self {
+        Self {
+            score,
+            solution,
+            node_count,
+            table_stats,
+        }
+    }
+
+    /// スコアを返す。
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    /// 手順を返す。
+    pub fn solution(&self) -> &ActionHistory<W, H> {
+        &self.solution
+    }
+
+    /// 手順を返す。
+    pub fn into_solution(self) -> ActionHistory<W, H> {
+        self.solution
+    }
+
+    /// 探索したノード数を返す。
+    pub fn node_count(&self) -> u64 {
+        self.node_count
+    }
+
+    /// 探索終了時点での DP テーブルの統計情報を返す。
+    pub fn table_stats(&self) -> &TableStats {
+        &self.table_stats
+    }
+}
+
+/// DP テーブルの統計情報。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TableStats {
+    entry_count: usize,
+    capacity: usize,
+    collision_count: u64,
+}
+
+impl TableStats {
+    pub fn new(entry_count: usize, capacity: usize, collision_count: u64) -> Self {
+        Self {
+            entry_count,
+            capacity,
+            collision_count,
+        }
+    }
+
+    /// エントリ数を返す。
+    pub fn entry_count(&self) -> usize {
+        self.entry_count
+    }
+
+    /// 容量を返す。
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// 使用率を返す。
+    pub fn load_factor(&self) -> f64 {
+        self.entry_count as f64 / self.capacity as f64
+    }
+
+    /// 検出したハッシュ衝突の数を返す。検出が無効な場合は常に 0。
+    pub fn collision_count(&self) -> u64 {
+        self.collision_count
+    }
+}
+
+/// ソルバーの種類。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SolverKind {
+    /// 全探索 + メモ化。
+    Exact,
+    /// スコア上界による枝刈り付き探索 (`solver2::Solver`)。
+    Bound,
+    /// 浅い探索による根での枝刈り + 全探索 (`solve_problems_many`)。
+    Many,
+    /// 全探索 + メモ化の並列版。
+    Parallel,
+    /// ビームサーチ (近似)。
+    Beam,
+    /// モンテカルロ木探索 (近似)。
+    Mcts,
+    /// 目標スコアを下げながらのスコア上界による枝刈り付き探索 (`AspirationSolver`)。
+    Aspiration,
+}
+
+impl SolverKind {
+    /// この種類のソルバーが設定 `config` に対応しているかを確かめる。
+    ///
+    /// 対応していない設定で `new_solver()` を呼ぶと panic するので、事前にこれで確かめること。
+    pub fn ensure_supported(self, config: &SolveConfig) -> anyhow::Result<()> {
+        if self == Self::Parallel && config.collision_check != CollisionCheck::None {
+            bail!(
+                "{self} ソルバーはハッシュ衝突の検出 ({}) に対応していない",
+                config.collision_check
+            );
+        }
+
+        Ok(())
+    }
+
+    /// 最大スコアを求めるソルバーかどうかを返す (近似ソルバーなら `false`)。
+    pub fn is_exact(self) -> bool {
+        !matches!(self, Self::Beam | Self::Mcts)
+    }
+
+    /// `config.share_table` により盤面をまたいで DP テーブルを保持するかどうかを返す。
+    pub(crate) fn shares_table(self, config: &SolveConfig) -> bool {
+        config.share_table && matches!(self, Self::Exact | Self::Many)
+    }
+
+    /// 指定した種類のソルバーを作る。
+    pub fn new_solver<const W: usize, const H: usize, const P: usize>(
+        self,
+        config: &SolveConfig,
+    ) -> Box<dyn Solve<W, H, P> + Send> {
+        match self {
+            Self::Exact => Box::new(ExactSolver::new(config)),
+            Self::Bound => Box::new(Solver::new(config)),
+            Self::Many => Box::new(ManySolver::new(config)),
+            Self::Parallel => Box::new(ParallelSolver::new(config)),
+            Self::Beam => Box::new(BeamSolver::new(config)),
+            Self::Mcts => Box::new(MctsSolver::new(config)),
+            Self::Aspiration => Box::new(AspirationSolver::new(config)),
+        }
+    }
+}
+
+impl std::str::FromStr for SolverKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "bound" => Ok(Self::Bound),
+            "many" => Ok(Self::Many),
+            "parallel" => Ok(Self::Parallel),
+            "beam" => Ok(Self::Beam),
+            "mcts" => Ok(Self::Mcts),
+            "aspiration" => Ok(Self::Aspiration),
+            _ => bail!(
+                "ソルバーの種類が無効: '{s}' (exact, bound, many, parallel, beam, mcts, aspiration のいずれか)"
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for SolverKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Exact => "exact",
+            Self::Bound => "bound",
+            Self::Many => "many",
+            Self::Parallel => "parallel",
+            Self::Beam => "beam",
+            Self::Mcts => "mcts",
+            Self::Aspiration => "aspiration",
+        };
+        f.write_str(s)
+    }
+}
+
+/// 経路復元を行う。
+///
+/// `gain_max` は空でない盤面を持つ局面に対し、そこから追加で獲得できる最大スコアを返す関数。
+/// (DP テーブルのエントリが置き換えられている可能性があるので、必要なら再探索すること)
+pub(crate) fn reconstruct_solution<const W: usize, const H: usize, const P: usize>(
+    pos_root: &Position<W, H, P>,
+    mut gain_max: impl FnMut(&Position<W, H, P>) -> Score,
+) -> ActionHistory<W, H> {
+    let mut solution = ActionHistory::new();
+    let mut pos = pos_root.clone();
+    loop {
+        let best_action = pos.actions().max_by_key(|action| {
+            let pos_child = pos.do_action(action);
+            let gain_action = score_erase(action.square_count());
+            // 空の盤面は DP テーブルに載らないので例外処理が必要。
+            let gain_child = if pos_child.board().is_empty() {
+                SCORE_PERFECT
+            } else {
+                gain_max(&pos_child)
+            };
+            gain_action + gain_child
+        });
+        let Some(best_action) = best_action else {
+            break;
+        };
+        solution.push(best_action.least_square());
+        pos = pos.do_action(&best_action);
+    }
+
+    solution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solver_kind_parse() {
+        for kind in [
+            SolverKind::Exact,
+            SolverKind::Bound,
+            SolverKind::Many,
+            SolverKind::Parallel,
+            SolverKind::Beam,
+            SolverKind::Mcts,
+            SolverKind::Aspiration,
+        ] {
+            assert_eq!(kind.to_string().parse::<SolverKind>().unwrap(), kind);
+        }
+        assert!("foo".parse::<SolverKind>().is_err());
+    }
+
+    #[test]
+    fn test_solver_kind_ensure_supported() {
+        for collision_check in [CollisionCheck::Key128, CollisionCheck::Board] {
+            let config = SolveConfig {
+                collision_check,
+                ..Default::default()
+            };
+            assert!(SolverKind::Parallel.ensure_supported(&config).is_err());
+            assert!(SolverKind::Exact.ensure_supported(&config).is_ok());
+        }
+        assert!(SolverKind::Parallel
+            .ensure_supported(&SolveConfig::default())
+            .is_ok());
+    }
+
+    #[test]
+    fn test_table_capacity() {
+        let config_new = |table_bytes| SolveConfig {
+            table_bytes,
+            ..Default::default()
+        };
+
+        assert_eq!(SolveConfig::default().table_capacity::<8, 6>(), 1 << 30);
+        assert_eq!(config_new(8).table_capacity::<8, 6>(), 1);
+        assert_eq!(config_new(15).table_capacity::<8, 6>(), 1);
+        assert_eq!(config_new(16).table_capacity::<8, 6>(), 2);
+        assert_eq!(config_new(3 << 20).table_capacity::<8, 6>(), 1 << 18);
+
+        // 副テーブルの分だけ容量が減る。
+        let config_check_new = |collision_check| SolveConfig {
+            table_bytes: 1 << 20,
+            collision_check,
+            ..Default::default()
+        };
+        assert_eq!(
+            config_check_new(CollisionCheck::Key128).table_capacity::<8, 6>(),
+            1 << 16
+        );
+        assert_eq!(
+            config_check_new(CollisionCheck::Board).table_capacity::<8, 6>(),
+            1 << 15
+        );
+    }
+}
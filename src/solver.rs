@@ -1,89 +1,200 @@
+use std::borrow::Cow;
+use std::io::{Read, Write};
 use std::num::NonZeroU64;
 
+use anyhow::ensure;
+
 use crate::action::ActionHistory;
 use crate::board::Board;
+use crate::checkpoint::{read_u64s, write_u64s};
+use crate::collision::{CollisionCheck, CollisionTable};
 use crate::position::Position;
-use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::score::{score_bits, score_erase, Score, SCORE_PERFECT};
+use crate::sleep_set::{ActionFootprint, ActionValues, ActionValuesStack, ExactSleepSet};
+use crate::solution_dag::{reconstruct_solution_dag, SolutionDag};
+use crate::solve::{
+    reconstruct_solution, Solve, SolveConfig, SolveResult, TableFullPolicy, TableStats,
+};
+use crate::solver_k_best::KBestTable;
+use crate::solver_parallel::ParallelSolver;
 use crate::util::chmax;
+use crate::zobrist::Key;
 
 /// 与えられた盤面に対する最大スコアとその手順を返す。
-pub fn solve_problem(board: Board) -> (Score, ActionHistory) {
-    // 初期盤面が空の場合について考えたくないので、先に処理してしまう。
-    if board.is_empty() {
-        return (SCORE_PERFECT, ActionHistory::new());
-    }
+pub fn solve_problem<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+) -> (Score, ActionHistory<W, H>) {
+    solve_problem_with(board, &SolveConfig::default())
+}
 
-    let pos = Position::new(board);
+/// 設定を指定して `solve_problem()` を行う。
+///
+/// `config.thread_count` が 2 以上なら `ParallelSolver` で並列に探索する。
+pub fn solve_problem_with<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    config: &SolveConfig,
+) -> (Score, ActionHistory<W, H>) {
+    let config = SolveConfig {
+        best_score_ini: 0,
+        ..config.clone()
+    };
+    let mut solver: Box<dyn Solve<W, H, P>> = if config.thread_count > 1 {
+        Box::new(ParallelSolver::new(&config))
+    } else {
+        Box::new(ExactSolver::new(&config))
+    };
+
+    // スコア 0 の場合は None が返るが、そのとき手順は空となる (そもそも合法手がない)。
+    solver.solve(board).map_or_else(
+        || (0, ActionHistory::new()),
+        |res| (res.score(), res.into_solution()),
+    )
+}
 
-    Solver::new().solve(&pos)
+/// 与えられた盤面に対する最適解全体を表す DAG を返す。
+///
+/// 独立な着手の順序を入れ替えただけの手順は同一視する。
+pub fn solve_problem_all<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    config: &SolveConfig,
+) -> SolutionDag<W, H> {
+    ExactSolver::new(config).solve_all(board)
 }
 
+/// 全探索 + メモ化により最大スコアを求めるソルバー。
 #[derive(Debug)]
-struct Solver {
-    dp: HashTable,
+pub struct ExactSolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    best_score: Score,
+    node_count: u64,
+    share_table: bool,
+    partial_order_reduction: bool,
+    values_stack: ActionValuesStack<W, H>,
+    dp: DpTable<W, H>,
 }
 
-impl Solver {
-    fn new() -> Self {
+impl<const W: usize, const H: usize, const P: usize> ExactSolver<W, H, P> {
+    pub fn new(config: &SolveConfig) -> Self {
         Self {
-            dp: HashTable::new(),
+            best_score: config.best_score_ini,
+            node_count: 0,
+            share_table: config.share_table,
+            partial_order_reduction: config.partial_order_reduction,
+            values_stack: ActionValuesStack::default(),
+            dp: DpTable::new(
+                config.table_capacity::<W, H>(),
+                config.table_full_policy,
+                config.collision_check,
+                config.share_table,
+            ),
         }
     }
 
-    fn solve(mut self, pos_root: &Position) -> (Score, ActionHistory) {
-        let score = self.dfs(pos_root);
-        eprintln!("TT entry count: {}", self.dp.entry_count());
-
-        // 経路復元。
-        let mut solution = ActionHistory::new();
-        let mut pos = pos_root.clone();
-        loop {
-            let best_action = pos.actions().max_by_key(|action| {
-                let pos_child = pos.do_action(action);
-                let gain_action = score_erase(action.square_count());
-                // 空の盤面は DP テーブルに載らないので例外処理が必要。
-                // それ以外の盤面は DP テーブルに載っているはず。
-                let gain_child = if pos_child.board().is_empty() {
-                    SCORE_PERFECT
-                } else {
-                    let HashTableProbe::Found(score) = self.dp.probe(pos_child.key()) else {
-                        eprintln!("この盤面の DP エントリが見つからない!?");
-                        eprint!("{}", pos_child.board());
-                        unreachable!();
-                    };
-                    score
-                };
-                gain_action + gain_child
+    /// 与えられた盤面に対する最適解全体を表す DAG を返す。最大スコアは参照も更新もしない。
+    pub fn solve_all(&mut self, board: Board<W, H, P>) -> SolutionDag<W, H> {
+        self.node_count = 0;
+
+        let dag = reconstruct_solution_dag(&Position::new(board), |pos| {
+            // エントリが置き換えられていたら再探索する。
+            self.dp.get(pos).unwrap_or_else(|| self.dfs_root(pos))
+        });
+
+        if !self.share_table {
+            self.dp.increment_time();
+        }
+
+        dag
+    }
+
+    /// 与えられた局面に対して従来より大きいスコアを探索する。
+    /// 見つかった場合、最大スコアの更新も行う。
+    pub(crate) fn solve_position(
+        &mut self,
+        pos_root: &Position<W, H, P>,
+    ) -> Option<SolveResult<W, H>> {
+        self.node_count = 0;
+
+        // 初期盤面が空の場合について考えたくないので、先に処理してしまう。
+        let score = if pos_root.board().is_empty() {
+            SCORE_PERFECT
+        } else {
+            let score = self.dfs_root(pos_root);
+            eprintln!("TT entry count: {}", self.dp.entry_count());
+            if self.dp.collision_count() != 0 {
+                eprintln!("TT collision count: {}", self.dp.collision_count());
+            }
+            score
+        };
+
+        let res = chmax!(self.best_score, score).then(|| {
+            let solution = reconstruct_solution(pos_root, |pos| {
+                // エントリが置き換えられていたら再探索する。
+                self.dp.get(pos).unwrap_or_else(|| self.dfs_root(pos))
             });
-            let Some(best_action) = best_action else {
-                break;
-            };
-            solution.push(best_action.least_square());
-            pos = pos.do_action(&best_action);
+            let table_stats = TableStats::new(
+                self.dp.entry_count(),
+                self.dp.capacity(),
+                self.dp.collision_count(),
+            );
+            SolveResult::new(score, solution, self.node_count, table_stats)
+        });
+
+        if !self.share_table {
+            self.dp.increment_time();
         }
 
-        (score, solution)
+        res
     }
 
     /// `pos` から追加で獲得できる最大スコアを返す。
-    fn dfs(&mut self, pos: &Position) -> Score {
+    fn dfs_root(&mut self, pos: &Position<W, H, P>) -> Score {
+        let gain_max = self.dfs(pos, &ActionValues::default());
+        self.values_stack.clear();
+        gain_max
+    }
+
+    /// `pos` から追加で獲得できる最大スコアを返す。
+    ///
+    /// `sleep` 内の着手は探索せず、その値を用いる (`ExactSleepSet` を参照)。
+    /// `pos` の DP エントリを新規作成した場合、各着手の値を `values_stack` に積む。
+    fn dfs(&mut self, pos: &Position<W, H, P>, sleep: &ActionValues<W, H>) -> Score {
+        self.node_count += 1;
+
         // 空の盤面に対する DP エントリが作られないよう、先にパーフェクト判定する。
         // 他の終了局面については仮作成するエントリの gain_max が 0 なのでそのままでよい。
         if pos.board().is_empty() {
             return SCORE_PERFECT;
         }
 
-        let key = pos.key();
-
-        match self.dp.probe(key) {
-            HashTableProbe::Found(gain_max) => gain_max,
-            HashTableProbe::Created(dp_idx) => {
-                let mut gain_max = 0;
+        match self.dp.probe(pos) {
+            DpTableProbe::Found(gain_max) => gain_max,
+            DpTableProbe::Created(dp_idx) => {
+                // 眠っている着手は探索せず、その値を用いる。
+                let base = self.values_stack.len();
+                let mut gain_max = sleep.value_max();
+                let mut values = sleep.clone();
+                let mut done = ExactSleepSet::default();
                 for action in pos.actions() {
+                    if sleep.contains(&action) {
+                        continue;
+                    }
+
                     let pos_child = pos.do_action(&action);
                     let gain_action = score_erase(action.square_count());
-                    let gain_child = self.dfs(&pos_child);
+                    let gain_child = if self.partial_order_reduction {
+                        let fp = ActionFootprint::new(pos.board(), &action);
+                        let sleep_child = done.child(&self.values_stack, fp, gain_action);
+                        let start = self.values_stack.len();
+                        let gain_child = self.dfs(&pos_child, &sleep_child);
+                        done.insert(fp, gain_action, start..self.values_stack.len());
+                        gain_child
+                    } else {
+                        self.dfs(&pos_child, &ActionValues::default())
+                    };
                     chmax!(gain_max, gain_action + gain_child);
+                    values.insert(&action, gain_action + gain_child);
+                }
+                if self.partial_order_reduction {
+                    self.values_stack.replace_top(base, &values);
                 }
 
                 // 終了局面ならば単に 0 を返す。
@@ -93,92 +204,320 @@ impl Solver {
                     return 0;
                 }
 
-                self.dp.set_gain_max(dp_idx, gain_max);
+                self.dp.set_gain_max(dp_idx, pos, gain_max);
                 gain_max
             }
         }
     }
 }
 
-const HASH_TABLE_CAP_BITS: u32 = 30;
-const HASH_TABLE_CAP: usize = 1 << HASH_TABLE_CAP_BITS;
-const _: () = assert!(HASH_TABLE_CAP.is_power_of_two());
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for ExactSolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        self.solve_position(&Position::new(board))
+    }
+
+    fn best_score(&self) -> Score {
+        self.best_score
+    }
+
+    fn set_best_score(&mut self, best_score: Score) {
+        self.best_score = best_score;
+    }
 
-const KEY_HI_BITS: u32 = 64 - HASH_TABLE_CAP_BITS;
-const KEY_HI_SHIFT: u32 = 64 - KEY_HI_BITS;
+    fn write_table(&self, w: &mut dyn Write) -> anyhow::Result<()> {
+        ensure!(
+            self.share_table,
+            "DP テーブルを盤面をまたいで保持していない"
+        );
+        self.dp.write(w)
+    }
 
-fn calc_key_hi(key: u64) -> u64 {
-    key >> KEY_HI_SHIFT
+    fn read_table(&mut self, r: &mut dyn Read) -> anyhow::Result<()> {
+        ensure!(
+            self.share_table,
+            "DP テーブルを盤面をまたいで保持していない"
+        );
+        self.dp.read(r)
+    }
 }
 
-/// メモ化再帰のためのハッシュテーブルのエントリ。
+/// DP テーブルのエントリ。
+///
+/// * bit 0-15: 世代 (DP テーブルを毎回再初期化せずに済ませるための機構)。
+/// * bit16- : 1 + (この局面から追加で獲得できる最大スコア) (`GAIN_MAX_BITS` bit)。
+/// * 上位 (64 - KEY_HI_SHIFT) bit: この局面のハッシュ値の上位部分。
 ///
-/// * 下位 12 bit: 1 + (この局面から追加で獲得できる最大スコア)。
-/// * 上位 (64 - HASH_TABLE_CAP_BITS) bit: この局面のハッシュ値の上位部分。
+/// ハッシュ値の上位部分はテーブルの容量によらず一定とする。
+/// (容量が大きい場合、インデックスと重複するビットが生じるが害はない)
+///
+/// `solver_parallel` の並列 DP テーブルでも同じ形式を用いる (値 0 は空きを表す)。
+#[repr(transparent)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct HashTableEntry(NonZeroU64);
+pub(crate) struct DpEntry<const W: usize, const H: usize>(NonZeroU64);
+
+const _: () = assert!(std::mem::size_of::<Option<DpEntry<8, 6>>>() == 8);
 
-const _: () = assert!(std::mem::size_of::<Option<HashTableEntry>>() == 8);
+impl<const W: usize, const H: usize> DpEntry<W, H> {
+    const TIME_BITS: u32 = 16;
+    const TIME_MASK: u64 = (1 << Self::TIME_BITS) - 1;
 
-impl HashTableEntry {
-    const GAIN_MAX_BITS: u32 = 12;
-    const GAIN_MAX_MASK: u64 = (1 << Self::GAIN_MAX_BITS) - 1;
+    // 1 を足して格納するので 1bit 余分に確保する。
+    const GAIN_MAX_BITS: u32 = score_bits(W * H) + 1;
+    const GAIN_MAX_SHIFT: u32 = 16;
+    const GAIN_MAX_MASK: u64 = ((1 << Self::GAIN_MAX_BITS) - 1) << Self::GAIN_MAX_SHIFT;
 
-    const KEY_HI_MASK: u64 = ((1 << KEY_HI_BITS) - 1) << KEY_HI_SHIFT;
+    const KEY_HI_SHIFT: u32 = Self::GAIN_MAX_SHIFT + Self::GAIN_MAX_BITS;
+    const KEY_HI_MASK: u64 = u64::MAX << Self::KEY_HI_SHIFT;
 
-    fn new(key: u64, gain_max: Score) -> Self {
-        let value_gain_max = u64::from(1 + gain_max);
+    pub(crate) fn calc_key_hi(key: u64) -> u64 {
+        key >> Self::KEY_HI_SHIFT
+    }
+
+    pub(crate) fn new(time: u16, key: u64, gain_max: Score) -> Self {
+        let value_time = u64::from(time);
+        let value_gain_max = u64::from(1 + gain_max) << Self::GAIN_MAX_SHIFT;
         let value_key = key & Self::KEY_HI_MASK;
-        let value = value_gain_max | value_key;
+        let value = value_time | value_gain_max | value_key;
 
         Self(unsafe { NonZeroU64::new_unchecked(value) })
     }
 
-    fn gain_max(self) -> Score {
-        ((self.0.get() & Self::GAIN_MAX_MASK) - 1) as Score
+    /// 生の値から復元する。値 0 に対しては `None` を返す。
+    pub(crate) fn from_raw(value: u64) -> Option<Self> {
+        NonZeroU64::new(value).map(Self)
+    }
+
+    /// 生の値を返す。
+    pub(crate) fn to_raw(self) -> u64 {
+        self.0.get()
+    }
+
+    pub(crate) fn time(self) -> u16 {
+        (self.0.get() & Self::TIME_MASK) as u16
+    }
+
+    pub(crate) fn gain_max(self) -> Score {
+        (((self.0.get() & Self::GAIN_MAX_MASK) >> Self::GAIN_MAX_SHIFT) - 1) as Score
     }
 
     fn set_gain_max(&mut self, gain_max: Score) {
-        let value_gain_max = u64::from(1 + gain_max);
+        let value_gain_max = u64::from(1 + gain_max) << Self::GAIN_MAX_SHIFT;
         let value = (self.0.get() & !Self::GAIN_MAX_MASK) | value_gain_max;
 
         self.0 = unsafe { NonZeroU64::new_unchecked(value) };
     }
 
-    fn key_hi(self) -> u64 {
-        self.0.get() >> KEY_HI_SHIFT
+    pub(crate) fn key_hi(self) -> u64 {
+        self.0.get() >> Self::KEY_HI_SHIFT
     }
 }
 
-/// メモ化再帰のためのハッシュテーブル。
+/// メモ化再帰のための DP テーブル。
 ///
 /// インデックス衝突については linear probing で対処する。
-/// ハッシュ値自体の衝突については特に対策していない。
+/// ハッシュ値自体の衝突は `CollisionCheck` を指定すれば検出できる (デフォルトでは検出しない)。
+///
+/// 世代情報を用いることで、配列を再初期化することなく 0x10000 個の問題を続けて解ける。
+///
+/// 盤面をまたいでエントリを共有する場合、世代は更新せず、キーには `Position::canonical_key()` を用いる。
+///
+/// 上位 k 個のスコアを求める場合 (`solver_k_best`)、各エントリのスコアの一覧を副テーブルに保持する。
 #[derive(Debug)]
-struct HashTable {
+pub(crate) struct DpTable<const W: usize, const H: usize> {
+    time: u16,
+    canonical: bool,
     entry_count: usize,
-    array: Box<[Option<HashTableEntry>; HASH_TABLE_CAP]>,
+    policy: TableFullPolicy,
+    probe_limit: usize,
+    array: Box<[Option<DpEntry<W, H>>]>,
+    collision: CollisionTable<W, H>,
+    k_best: KBestTable,
 }
 
-impl HashTable {
-    const INDEX_MASK: usize = HASH_TABLE_CAP - 1;
+impl<const W: usize, const H: usize> DpTable<W, H> {
+    fn new(
+        capacity: usize,
+        policy: TableFullPolicy,
+        check: CollisionCheck,
+        canonical: bool,
+    ) -> Self {
+        assert!(capacity.is_power_of_two());
 
-    fn new() -> Self {
         Self {
+            time: 0,
+            canonical,
             entry_count: 0,
-            array: vec![None; HASH_TABLE_CAP].try_into().unwrap(),
+            policy,
+            probe_limit: policy.probe_limit(capacity),
+            array: vec![None; capacity].into_boxed_slice(),
+            collision: CollisionTable::new(check, capacity),
+            k_best: KBestTable::new(0, capacity),
         }
     }
 
-    fn entry_count(&self) -> usize {
+    /// 各エントリに上位 `k` 個のスコアを保持する DP テーブルを作る。
+    pub(crate) fn new_k_best(
+        capacity: usize,
+        policy: TableFullPolicy,
+        check: CollisionCheck,
+        k: usize,
+    ) -> Self {
+        Self {
+            k_best: KBestTable::new(k, capacity),
+            ..Self::new(capacity, policy, check, false)
+        }
+    }
+
+    /// 容量 (エントリ数) を返す。
+    pub(crate) fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    fn index_mask(&self) -> usize {
+        self.capacity() - 1
+    }
+
+    /// 現在の世代におけるエントリ数を返す。
+    pub(crate) fn entry_count(&self) -> usize {
         self.entry_count
     }
 
-    /// ハッシュ値 `key` に対応するエントリを探し、結果を返す。
+    /// 現在の世代において検出したハッシュ衝突の数を返す。
+    pub(crate) fn collision_count(&self) -> u64 {
+        self.collision.collision_count()
+    }
+
+    /// 局面 `pos` のキーと、ハッシュ衝突の検出に用いる盤面を返す。
+    fn key_board<'a, const P: usize>(
+        &self,
+        pos: &'a Position<W, H, P>,
+    ) -> (Key, Cow<'a, Board<W, H, P>>) {
+        if !self.canonical {
+            return (pos.key(), Cow::Borrowed(pos.board()));
+        }
+
+        // 衝突を検出しないなら盤面は使われないので、付け替えを省く。
+        let board = if self.collision.is_enabled() {
+            Cow::Owned(pos.board().canonical())
+        } else {
+            Cow::Borrowed(pos.board())
+        };
+
+        (pos.canonical_key(), board)
+    }
+
+    /// 現在の世代のエントリとハッシュ衝突検出用の副テーブルを書き出す (チェックポイント用)。
+    ///
+    /// 上位 k 個のスコアの副テーブルは書き出さない。
+    pub(crate) fn write(&self, w: &mut dyn Write) -> anyhow::Result<()> {
+        w.write_all(&self.time.to_le_bytes())?;
+        w.write_all(&(self.entry_count as u64).to_le_bytes())?;
+        write_u64s(
+            w,
+            self.capacity(),
+            self.array
+                .iter()
+                .map(|entry| entry.map_or(0, DpEntry::to_raw)),
+        )?;
+        self.collision.write(w)
+    }
+
+    /// `write()` で書き出したものを読み込む。容量や衝突の検出方法が異なれば失敗する。
+    pub(crate) fn read(&mut self, r: &mut dyn Read) -> anyhow::Result<()> {
+        let mut buf_time = [0; 2];
+        let mut buf_entry_count = [0; 8];
+        r.read_exact(&mut buf_time)?;
+        r.read_exact(&mut buf_entry_count)?;
+        let entry_count = u64::from_le_bytes(buf_entry_count);
+        ensure!(
+            entry_count <= self.capacity() as u64,
+            "エントリ数が容量を超えている: {entry_count}"
+        );
+
+        self.time = u16::from_le_bytes(buf_time);
+        self.entry_count = entry_count as usize;
+        read_u64s(r, self.capacity(), |i, x| {
+            self.array[i] = DpEntry::from_raw(x);
+        })?;
+        self.collision.read(r)
+    }
+
+    /// 世代を更新する。
+    ///
+    /// 世代がオーバーフローする場合のみテーブル全体が再初期化される。
+    fn increment_time(&mut self) {
+        let overflow;
+        (self.time, overflow) = self.time.overflowing_add(1);
+
+        self.entry_count = 0;
+        self.collision.reset_collision_count();
+
+        if overflow {
+            self.array.fill(None);
+        }
+    }
+
+    /// 現在の世代において局面 `pos` に対応するエントリを探し、その値 (gain_max) を返す。
+    /// エントリの作成は行わない。
+    fn get<const P: usize>(&self, pos: &Position<W, H, P>) -> Option<Score> {
+        self.find(pos)
+            .map(|idx| unsafe { self.array.get_unchecked(idx).unwrap_unchecked() }.gain_max())
+    }
+
+    /// `get()` と同じだが、エントリが保持する上位 k 個のスコアを降順で返す。
+    pub(crate) fn get_k_best<const P: usize>(&self, pos: &Position<W, H, P>) -> Option<Vec<Score>> {
+        self.find(pos).map(|idx| self.k_best_at(idx).collect())
+    }
+
+    /// スロット `idx` のエントリが保持する上位 k 個のスコアを降順で列挙する。
+    pub(crate) fn k_best_at(&self, idx: usize) -> impl Iterator<Item = Score> + '_ {
+        self.k_best.gains(idx)
+    }
+
+    /// 現在の世代において局面 `pos` に対応するエントリを探し、そのインデックスを返す。
+    fn find<const P: usize>(&self, pos: &Position<W, H, P>) -> Option<usize> {
+        let (key, board) = self.key_board(pos);
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+
+        let idx_home = key as usize;
+        for i in 0..self.probe_limit {
+            let idx = idx_home.wrapping_add(i) & self.index_mask();
+            match unsafe { *self.array.get_unchecked(idx) } {
+                Some(entry) if entry.time() == self.time => {
+                    if entry.key_hi() == key_hi && self.collision.matches(idx, &board) {
+                        return Some(idx);
+                    }
+                }
+                _ => return None,
+            }
+        }
+
+        None
+    }
+
+    /// 現在の世代において局面 `pos` に対応するエントリを探し、結果を返す。
     ///
     /// エントリが既に存在する場合、その値 (gain_max) を返す。
-    /// エントリ自体がまだ存在しない場合、仮の値でエントリを作成し、そのインデックスを返す。
-    fn probe(&mut self, key: u64) -> HashTableProbe {
+    /// エントリがまだ存在しない場合、仮の値でエントリを作成し、そのインデックスを返す。
+    /// probe の上限回数以内に空きが見つからなければ `TableFullPolicy` に従う。
+    ///
+    /// ハッシュ衝突を検出した場合、そのエントリは別の局面のものとして扱う。
+    fn probe<const P: usize>(&mut self, pos: &Position<W, H, P>) -> DpTableProbe {
+        match self.probe_index(pos) {
+            Ok(idx) => DpTableProbe::Found(
+                unsafe { self.array.get_unchecked(idx).unwrap_unchecked() }.gain_max(),
+            ),
+            Err(idx) => DpTableProbe::Created(idx),
+        }
+    }
+
+    /// `probe()` と同じだが、エントリが既に存在すれば `Ok`、仮作成したなら `Err` でそのインデックスを返す。
+    ///
+    /// 仮作成したエントリの上位 k 個のスコアは `[0]` となる。
+    pub(crate) fn probe_index<const P: usize>(
+        &mut self,
+        pos: &Position<W, H, P>,
+    ) -> Result<usize, usize> {
         // linear probe
 
         // key に対応する局面が終了局面の場合、盤面が空でないなら仮作成したエントリはそのままにできる。
@@ -191,37 +530,104 @@ impl HashTable {
         // というわけで、一応 Solver 側で空の盤面に対する例外処理を行い、
         // 空の盤面は DP テーブルに載らないようにしておく。
 
-        let mut idx = key as usize & Self::INDEX_MASK;
-        loop {
+        let (key, board) = self.key_board(pos);
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+
+        let idx_home = key as usize;
+        for i in 0..self.probe_limit {
+            let idx = idx_home.wrapping_add(i) & self.index_mask();
             let entry = unsafe { self.array.get_unchecked_mut(idx) };
+
             match entry {
-                None => {
+                Some(entry) if entry.time() == self.time => {
+                    if entry.key_hi() == key_hi && self.collision.verify(idx, &board) {
+                        return Ok(idx);
+                    }
+                }
+                _ => {
                     self.entry_count += 1;
+                    /*
                     if self.entry_count.is_power_of_two() {
                         eprintln!("TT entry count: {}", self.entry_count);
                     }
-                    entry.replace(HashTableEntry::new(key, 0));
-                    return HashTableProbe::Created(idx);
+                    */
+                    entry.replace(DpEntry::new(self.time, key, 0));
+                    self.collision.record(idx, &board);
+                    self.k_best.reset(idx);
+                    return Err(idx);
                 }
-                Some(entry) if entry.key_hi() == calc_key_hi(key) => {
-                    return HashTableProbe::Found(entry.gain_max());
-                }
-                _ => idx = idx.wrapping_add(1) & Self::INDEX_MASK,
             }
         }
+
+        // 空きが見つからなかった。既存エントリを置き換える (エントリ数は変わらない)。
+        let idx =
+            idx_home.wrapping_add(self.policy.victim_offset(self.capacity())) & self.index_mask();
+        self.array[idx].replace(DpEntry::new(self.time, key, 0));
+        self.collision.record(idx, &board);
+        self.k_best.reset(idx);
+        Err(idx)
     }
 
     /// `probe()` で仮作成したエントリの値を `gain_max` に設定する。
-    fn set_gain_max(&mut self, idx: usize, gain_max: Score) {
-        let entry = unsafe { self.array.get_unchecked_mut(idx) };
-        let entry = unsafe { entry.as_mut().unwrap_unchecked() };
+    ///
+    /// 仮作成したエントリが既に置き換えられていた場合、エントリを作り直す。
+    fn set_gain_max<const P: usize>(
+        &mut self,
+        idx: usize,
+        pos: &Position<W, H, P>,
+        gain_max: Score,
+    ) {
+        let idx = self.recreate(idx, pos);
+        unsafe {
+            self.array
+                .get_unchecked_mut(idx)
+                .as_mut()
+                .unwrap_unchecked()
+        }
+        .set_gain_max(gain_max);
+    }
 
-        entry.set_gain_max(gain_max);
+    /// `probe_index()` で仮作成したエントリの上位 k 個のスコアを `gains` (降順) に設定する。
+    ///
+    /// 仮作成したエントリが既に置き換えられていた場合、エントリを作り直す。
+    pub(crate) fn set_k_best<const P: usize>(
+        &mut self,
+        idx: usize,
+        pos: &Position<W, H, P>,
+        gains: &[Score],
+    ) {
+        let idx = self.recreate(idx, pos);
+        unsafe {
+            self.array
+                .get_unchecked_mut(idx)
+                .as_mut()
+                .unwrap_unchecked()
+        }
+        .set_gain_max(gains[0]);
+        self.k_best.set(idx, gains);
+    }
+
+    /// スロット `idx` に局面 `pos` のエントリがあればそのまま、なければエントリを作り直してそのインデックスを返す。
+    fn recreate<const P: usize>(&mut self, idx: usize, pos: &Position<W, H, P>) -> usize {
+        let (key, board) = self.key_board(pos);
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+
+        match unsafe { *self.array.get_unchecked(idx) } {
+            Some(entry)
+                if entry.time() == self.time
+                    && entry.key_hi() == key_hi
+                    && self.collision.matches(idx, &board) =>
+            {
+                idx
+            }
+            // 仮作成したエントリは探索中の局面の子孫からは参照されないので、Found にはならない。
+            _ => self.probe_index(pos).unwrap_or_else(|idx| idx),
+        }
     }
 }
 
 #[derive(Debug)]
-enum HashTableProbe {
+enum DpTableProbe {
     Found(Score),
     Created(usize),
 }
@@ -230,6 +636,8 @@ enum HashTableProbe {
 mod tests {
     use indoc::indoc;
 
+    use crate::replay::verify;
+    use crate::rng::GameRng;
     use crate::square::*;
 
     use super::*;
@@ -246,11 +654,33 @@ mod tests {
         sqs.into_iter().collect()
     }
 
+    fn config_small(table_bytes: usize, table_full_policy: TableFullPolicy) -> SolveConfig {
+        SolveConfig {
+            table_bytes,
+            table_full_policy,
+            ..Default::default()
+        }
+    }
+
+    /// 探索空間がそこそこ大きい盤面 (DP エントリ数 1731)。
+    fn board_medium() -> Board {
+        parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "})
+    }
+
     #[test]
-    #[ignore]
     fn test_solve_problem() {
+        let config = config_small(1 << 20, TableFullPolicy::Fail);
+        let solve_problem = |board| solve_problem_with(board, &config);
+
         assert_eq!(
-            solve_problem(Board::empty()),
+            solve_problem(<Board>::empty()),
             (SCORE_PERFECT, solution_new([]))
         );
 
@@ -283,4 +713,137 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_exact_solver_table_full() {
+        let solve = |config: &SolveConfig| {
+            let mut solver = ExactSolver::new(config);
+            let res = solver.solve(board_medium()).unwrap();
+            (res.score(), res.table_stats().entry_count())
+        };
+
+        let (score, entry_count) = solve(&config_small(1 << 20, TableFullPolicy::Fail));
+        assert_eq!(entry_count, 1731);
+
+        // テーブルが小さくても置き換えにより同じスコアが得られる。
+        let (score_replace, entry_count_replace) =
+            solve(&config_small(1 << 13, TableFullPolicy::Replace));
+        assert_eq!(score_replace, score);
+        assert!(entry_count_replace <= 1 << 10);
+    }
+
+    #[test]
+    fn test_exact_solver_collision_check() {
+        let (score, _) = solve_problem_with(
+            board_medium(),
+            &config_small(1 << 20, TableFullPolicy::Fail),
+        );
+
+        for collision_check in [CollisionCheck::Key128, CollisionCheck::Board] {
+            let config = SolveConfig {
+                collision_check,
+                ..config_small(1 << 20, TableFullPolicy::Replace)
+            };
+            let mut solver = ExactSolver::new(&config);
+            let res = solver.solve(board_medium()).unwrap();
+            assert_eq!(res.score(), score);
+            assert_eq!(res.table_stats().collision_count(), 0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "DP テーブルが満杯")]
+    fn test_exact_solver_table_full_fail() {
+        let mut solver = ExactSolver::new(&config_small(1 << 10, TableFullPolicy::Fail));
+        solver.solve(board_medium());
+    }
+
+    /// 盤面をまたいで DP テーブルを共有しても各盤面のスコアは変わらない。
+    #[test]
+    fn test_exact_solver_share_table() {
+        // board_medium() とその駒種を付け替えた盤面、および下 3 行のみを残したゲーム内の盤面たち。
+        let relabel = |perm: &[u8]| {
+            let s: String = board_medium()
+                .to_string()
+                .chars()
+                .map(|c| match c {
+                    '1'..='5' => char::from(b'0' + perm[usize::from(c as u8 - b'1')]),
+                    _ => c,
+                })
+                .collect();
+            parse_board(s)
+        };
+        let mut boards = vec![
+            board_medium(),
+            relabel(&[2, 3, 1, 4, 5]),
+            relabel(&[3, 1, 5, 2, 4]),
+        ];
+        boards.extend((0..8).filter_map(|state| {
+            let board = GameRng::new(state).gen_board(0x00, 40)?;
+            let lines: Vec<_> = board.to_string().lines().map(str::to_owned).collect();
+            Some(parse_board(format!(
+                "{}{}\n",
+                "........\n".repeat(3),
+                lines[3..].join("\n")
+            )))
+        }));
+
+        let scores: Vec<_> = boards
+            .iter()
+            .map(|board| {
+                let mut solver = ExactSolver::new(&config_small(1 << 20, TableFullPolicy::Replace));
+                solver.solve(board.clone()).map_or(0, |res| res.score())
+            })
+            .collect();
+
+        for collision_check in [CollisionCheck::None, CollisionCheck::Board] {
+            let config = SolveConfig {
+                share_table: true,
+                collision_check,
+                ..config_small(1 << 20, TableFullPolicy::Replace)
+            };
+            let mut solver = ExactSolver::new(&config);
+            let mut node_counts = vec![];
+            for (board, &score) in boards.iter().zip(&scores) {
+                solver.set_best_score(0);
+                let res = solver.solve(board.clone()).unwrap();
+                assert_eq!(res.score(), score);
+                assert_eq!(verify(board.clone(), res.solution()), Ok(score));
+                assert_eq!(res.table_stats().collision_count(), 0);
+                node_counts.push(res.node_count());
+            }
+            // 駒種を付け替えただけの盤面は探索せずに済む。
+            assert_eq!(node_counts[1], 1);
+            assert_eq!(node_counts[2], 1);
+
+            // 書き出した DP テーブルを読み込めば、探索済みの盤面は探索せずに済む。
+            let mut buf = Vec::<u8>::new();
+            solver.write_table(&mut buf).unwrap();
+            let mut solver_resumed = ExactSolver::new(&config);
+            solver_resumed.read_table(&mut buf.as_slice()).unwrap();
+            let res = solver_resumed.solve(boards[0].clone()).unwrap();
+            assert_eq!(res.score(), scores[0]);
+            assert_eq!(res.node_count(), 1);
+
+            // 容量が異なる場合や、盤面をまたいで DP テーブルを保持しない場合は読み込めない。
+            for config_other in [
+                SolveConfig {
+                    table_bytes: 1 << 21,
+                    ..config.clone()
+                },
+                SolveConfig {
+                    share_table: false,
+                    ..config.clone()
+                },
+            ] {
+                let mut solver_other = ExactSolver::<8, 6, 5>::new(&config_other);
+                assert!(solver_other.read_table(&mut buf.as_slice()).is_err());
+            }
+            assert!(
+                ExactSolver::<8, 6, 5>::new(&config_small(1 << 20, TableFullPolicy::Replace))
+                    .write_table(&mut Vec::new())
+                    .is_err()
+            );
+        }
+    }
 }
@@ -2,75 +2,153 @@ use std::num::{NonZeroU32, NonZeroU64};
 
 use crate::action::ActionHistory;
 use crate::board::Board;
+use crate::collision::{CollisionCheck, CollisionTable};
 use crate::hint::assert_unchecked;
 use crate::position::Position;
-use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::score::{score_bits, score_erase, Score, SCORE_PERFECT};
+use crate::score_bound::ScoreBound;
+use crate::sleep_set::{ActionFootprint, SleepSet};
+use crate::solve::{Solve, SolveConfig, SolveResult, TableFullPolicy, TableStats};
 use crate::util::chmax;
 
+/// スコア上界による枝刈り付き探索を行うソルバー。
 #[derive(Debug)]
-pub struct Solver {
+pub struct Solver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
     best_score: Score,
-    dp: DpTable,
+    score_bound: ScoreBound,
+    partial_order_reduction: bool,
+    dp: DpTable<W, H>,
 }
 
-impl Solver {
-    /// `best_score_ini` より大きいスコアを探索するソルバーを作る。
-    pub fn new(best_score_ini: Score) -> Self {
+impl<const W: usize, const H: usize, const P: usize> Solver<W, H, P> {
+    /// `config.best_score_ini` より大きいスコアを探索するソルバーを作る。
+    pub fn new(config: &SolveConfig) -> Self {
         Self {
-            best_score: best_score_ini,
-            dp: DpTable::new(),
+            best_score: config.best_score_ini,
+            score_bound: config.score_bound,
+            partial_order_reduction: config.partial_order_reduction,
+            dp: DpTable::new(
+                config.table_capacity::<W, H>(),
+                config.table_full_policy,
+                config.collision_check,
+            ),
         }
     }
+}
 
-    /// 現時点での最大スコアを返す。
-    pub fn best_score(&self) -> Score {
-        self.best_score
+impl<const W: usize, const H: usize, const P: usize> Solver<W, H, P> {
+    /// `pos` において `best_score` より大きいスコアを探索する。DP テーブルの世代は更新しない。
+    ///
+    /// DP テーブルに記録される値は `best_score` によらず常に正しい上界なので、
+    /// 同じ盤面に対して続けて呼び出すと前回までに得た上界がそのまま枝刈りに使われる。
+    ///
+    /// 改善解があればそのスコアと手順を返す。探索ノード数と、`pos` から追加で獲得しうるスコアの上界も併せて返す。
+    pub(crate) fn search_keep_table(
+        &mut self,
+        pos: &Position<W, H, P>,
+        best_score: Score,
+    ) -> (Option<(Score, ActionHistory<W, H>)>, u64, Score) {
+        let sub_solver = SubSolver::new(
+            best_score,
+            self.score_bound,
+            self.partial_order_reduction,
+            &mut self.dp,
+        );
+        sub_solver.solve(pos)
     }
 
-    /// 与えられた盤面に対して従来より大きいスコアを探索する。
-    /// 見つかった場合、最大スコアの更新も行う。
-    pub fn solve(&mut self, board: Board) -> Option<(Score, ActionHistory)> {
-        let sub_solver = SubSolver::new(self.best_score, &mut self.dp);
-        let res = sub_solver.solve(board);
-
+    /// 1 つの盤面の探索を終え、DP テーブルの統計情報を返す。DP テーブルの世代も更新する。
+    pub(crate) fn finish_board(&mut self) -> TableStats {
         eprintln!("DP entry count: {}", self.dp.entry_count());
+        if self.dp.collision_count() != 0 {
+            eprintln!("DP collision count: {}", self.dp.collision_count());
+        }
+        let table_stats = TableStats::new(
+            self.dp.entry_count(),
+            self.dp.capacity(),
+            self.dp.collision_count(),
+        );
         self.dp.increment_time();
 
+        table_stats
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for Solver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let pos = Position::new(board);
+        let (best, node_count, _) = self.search_keep_table(&pos, self.best_score);
+        let table_stats = self.finish_board();
+
         let solution;
-        (self.best_score, solution) = res?;
+        (self.best_score, solution) = best?;
+
+        Some(SolveResult::new(
+            self.best_score,
+            solution,
+            node_count,
+            table_stats,
+        ))
+    }
+
+    fn best_score(&self) -> Score {
+        self.best_score
+    }
 
-        Some((self.best_score, solution))
+    fn set_best_score(&mut self, best_score: Score) {
+        self.best_score = best_score;
     }
 }
 
 #[derive(Debug)]
-struct SubSolver<'solver> {
+struct SubSolver<'solver, const W: usize, const H: usize, const P: usize> {
     best_score: Score,
-    best_solution: Option<ActionHistory>,
-    history: ActionHistory,
-    dp: &'solver mut DpTable,
+    best_solution: Option<ActionHistory<W, H>>,
+    history: ActionHistory<W, H>,
+    node_count: u64,
+    score_bound: ScoreBound,
+    partial_order_reduction: bool,
+    dp: &'solver mut DpTable<W, H>,
 }
 
-impl<'solver> SubSolver<'solver> {
-    fn new(best_score: Score, dp: &'solver mut DpTable) -> Self {
+impl<'solver, const W: usize, const H: usize, const P: usize> SubSolver<'solver, W, H, P> {
+    fn new(
+        best_score: Score,
+        score_bound: ScoreBound,
+        partial_order_reduction: bool,
+        dp: &'solver mut DpTable<W, H>,
+    ) -> Self {
         Self {
             best_score,
             best_solution: None,
             history: ActionHistory::new(),
+            node_count: 0,
+            score_bound,
+            partial_order_reduction,
             dp,
         }
     }
 
-    fn solve(mut self, board: Board) -> Option<(Score, ActionHistory)> {
-        let pos = Position::new(board);
-        self.dfs(&pos, 0);
+    /// 改善解が見つかればそのスコアと手順を返す。探索ノード数と根の追加スコア上界も併せて返す。
+    fn solve(
+        mut self,
+        pos: &Position<W, H, P>,
+    ) -> (Option<(Score, ActionHistory<W, H>)>, u64, Score) {
+        let gain_ub = self.dfs(pos, 0, &SleepSet::default());
+
+        let best = self
+            .best_solution
+            .map(|solution| (self.best_score, solution));
 
-        self.best_solution
-            .map(|solution| (self.best_score, solution))
+        (best, self.node_count, gain_ub)
     }
 
     /// 戻り値は `pos` から追加で獲得しうるスコアの上界。
-    fn dfs(&mut self, pos: &Position, score: Score) -> Score {
+    ///
+    /// `sleep` 内の着手は探索しない (`SleepSet` を参照)。ただし戻り値はそれらも含めた上界とする。
+    fn dfs(&mut self, pos: &Position<W, H, P>, score: Score, sleep: &SleepSet<W, H>) -> Score {
+        self.node_count += 1;
+
         macro_rules! try_improve {
             ($score:expr) => {{
                 if chmax!(self.best_score, $score) {
@@ -86,10 +164,8 @@ impl<'solver> SubSolver<'solver> {
             return SCORE_PERFECT;
         }
 
-        let key = pos.key();
-
         // DP テーブルから pos に対応するエントリを探す。
-        let dp_probe = self.dp.probe(key);
+        let dp_probe = self.dp.probe(pos);
 
         // DP エントリのインデックスとその値 (gain_ub) を得る。
         let (dp_idx, gain_ub) = if let Some(gain_ub) = dp_probe.gain_ub() {
@@ -104,13 +180,13 @@ impl<'solver> SubSolver<'solver> {
             // (先ほどパーフェクトクリア判定も行ったので、終了局面は決して DP テーブルに載らない)
             //
             // pos が終了局面でないなら、DP テーブルにエントリを新規作成する。
-            let gain_ub = pos.score_upper_bound();
+            let gain_ub = self.score_bound.eval(pos);
             let finished = gain_ub == 0 || !pos.has_action();
             if finished {
                 try_improve!(score);
                 return 0;
             }
-            (dp_probe.make_entry(key, gain_ub), gain_ub)
+            (dp_probe.make_entry(pos, gain_ub), gain_ub)
         };
         // この時点で pos は終了局面でないことが確定する。
 
@@ -120,61 +196,79 @@ impl<'solver> SubSolver<'solver> {
         }
 
         // 現時点での最大スコアを超えうるなら、全ての子ノードを探索して pos の追加スコア上界を更新。
-        let mut gain_ub_new = 0;
+        // 眠っている着手は探索せず、sleep set が持つ上界を用いる。
+        let mut gain_ub_new = sleep.gain_ub_max();
+        let mut done = sleep.clone();
         for action in pos.actions() {
+            if sleep.contains(&action) {
+                continue;
+            }
+
             unsafe { self.history.push_unchecked(action.least_square()) }
 
             let pos_child = pos.do_action(&action);
             let gain_action = score_erase(action.square_count());
-            let gain_ub_child = self.dfs(&pos_child, score + gain_action);
+            let (gain_ub_child, fp) = if self.partial_order_reduction {
+                let fp = ActionFootprint::new(pos.board(), &action);
+                let sleep_child = done.child(fp, gain_action);
+                (
+                    self.dfs(&pos_child, score + gain_action, &sleep_child),
+                    Some(fp),
+                )
+            } else {
+                (
+                    self.dfs(&pos_child, score + gain_action, &SleepSet::default()),
+                    None,
+                )
+            };
             chmax!(gain_ub_new, gain_action + gain_ub_child);
+            if let Some(fp) = fp {
+                done.insert(fp, gain_action + gain_ub_child);
+            }
 
             unsafe { self.history.remove_last_unchecked() }
         }
 
         // 新たな追加スコア上界を DP テーブルに記録してから返す。
-        self.dp.set_gain_ub(dp_idx, gain_ub_new);
+        self.dp.set_gain_ub(dp_idx, pos, gain_ub_new);
         gain_ub_new
     }
 }
 
-const DP_TABLE_CAP_BITS: u32 = 30;
-const DP_TABLE_CAP: usize = 1 << DP_TABLE_CAP_BITS;
-const _: () = assert!(DP_TABLE_CAP.is_power_of_two());
-
-const KEY_HI_BITS: u32 = 64 - DP_TABLE_CAP_BITS;
-const KEY_HI_SHIFT: u32 = 64 - KEY_HI_BITS;
-const KEY_HI_MASK: u64 = ((1 << KEY_HI_BITS) - 1) << KEY_HI_SHIFT;
-
-fn calc_key_hi(key: u64) -> u64 {
-    key >> KEY_HI_SHIFT
-}
-
 /// DP テーブルのエントリ。
 ///
 /// * bit 0-15: 世代 (DP テーブルを毎回再初期化せずに済ませるための機構)。
-/// * bit16-28: この局面から追加で獲得しうるスコアの上界。探索を進めるにつれ広義単調減少する。
-///             この値が 0 のエントリが作られることはない。
-/// * bit29   : (未使用)
-/// * bit30-63: この局面のハッシュ値の上位部分。
+/// * bit16- : この局面から追加で獲得しうるスコアの上界 (`GAIN_UB_BITS` bit)。
+///   探索を進めるにつれ広義単調減少する。この値が 0 のエントリが作られることはない。
+/// * 上位 (64 - KEY_HI_SHIFT) bit: この局面のハッシュ値の上位部分。
+///
+/// 「かんたん」モードではスコア上界は 12bit, ハッシュ値の上位部分は bit28-63 となる。
+/// ハッシュ値の上位部分はテーブルの容量によらず一定とする (インデックスと重複してもよい)。
 #[repr(transparent)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct DpEntry(NonZeroU64);
+struct DpEntry<const W: usize, const H: usize>(NonZeroU64);
 
-impl DpEntry {
+impl<const W: usize, const H: usize> DpEntry<W, H> {
     const TIME_BITS: u32 = 16;
     const TIME_MASK: u64 = (1 << Self::TIME_BITS) - 1;
 
-    const GAIN_UB_BITS: u32 = 13;
+    const GAIN_UB_BITS: u32 = score_bits(W * H);
     const GAIN_UB_SHIFT: u32 = 16;
     const GAIN_UB_MASK: u64 = ((1 << Self::GAIN_UB_BITS) - 1) << Self::GAIN_UB_SHIFT;
 
+    const KEY_HI_SHIFT: u32 = Self::GAIN_UB_SHIFT + Self::GAIN_UB_BITS;
+    const KEY_HI_MASK: u64 = u64::MAX << Self::KEY_HI_SHIFT;
+
+    fn calc_key_hi(key: u64) -> u64 {
+        key >> Self::KEY_HI_SHIFT
+    }
+
     fn new(time: u16, key: u64, gain_ub: Score) -> Self {
         unsafe { assert_unchecked!(gain_ub != 0) }
 
         let value_time = u64::from(time);
         let value_gain_ub = u64::from(gain_ub) << Self::GAIN_UB_SHIFT;
-        let value_key = key & KEY_HI_MASK;
+        let value_key = key & Self::KEY_HI_MASK;
         let value = value_time | value_gain_ub | value_key;
 
         Self(unsafe { NonZeroU64::new_unchecked(value) })
@@ -198,7 +292,7 @@ impl DpEntry {
     }
 
     fn key_hi(self) -> u64 {
-        self.0.get() >> KEY_HI_SHIFT
+        self.0.get() >> Self::KEY_HI_SHIFT
     }
 }
 
@@ -208,23 +302,38 @@ impl DpEntry {
 ///
 /// 終了局面は決して DP テーブルに載らない。
 #[derive(Debug)]
-struct DpTable {
+struct DpTable<const W: usize, const H: usize> {
     time: u16,
     entry_count: usize,
-    array: Box<[Option<DpEntry>; DP_TABLE_CAP]>,
+    policy: TableFullPolicy,
+    probe_limit: usize,
+    array: Box<[Option<DpEntry<W, H>>]>,
+    collision: CollisionTable<W, H>,
 }
 
-impl DpTable {
-    const INDEX_MASK: usize = DP_TABLE_CAP - 1;
+impl<const W: usize, const H: usize> DpTable<W, H> {
+    fn new(capacity: usize, policy: TableFullPolicy, check: CollisionCheck) -> Self {
+        assert!(capacity.is_power_of_two());
 
-    fn new() -> Self {
         Self {
             time: 0,
             entry_count: 0,
-            array: vec![None; DP_TABLE_CAP].try_into().unwrap(),
+            policy,
+            probe_limit: policy.probe_limit(capacity),
+            array: vec![None; capacity].into_boxed_slice(),
+            collision: CollisionTable::new(check, capacity),
         }
     }
 
+    /// 容量 (エントリ数) を返す。
+    fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    fn index_mask(&self) -> usize {
+        self.capacity() - 1
+    }
+
     /// 現在の世代を返す。
     #[allow(dead_code)]
     fn time(&self) -> u16 {
@@ -236,6 +345,11 @@ impl DpTable {
         self.entry_count
     }
 
+    /// 現在の世代において検出したハッシュ衝突の数を返す。
+    fn collision_count(&self) -> u64 {
+        self.collision.collision_count()
+    }
+
     /// 世代を更新する。
     ///
     /// 世代がオーバーフローする場合のみテーブル全体が再初期化される。
@@ -244,62 +358,98 @@ impl DpTable {
         (self.time, overflow) = self.time.overflowing_add(1);
 
         self.entry_count = 0;
+        self.collision.reset_collision_count();
 
         if overflow {
             self.array.fill(None);
         }
     }
 
-    /// 現在の世代においてハッシュ値 `key` に対応するエントリを探す。
-    fn probe(&mut self, key: u64) -> DpTableProbe {
+    /// 現在の世代において局面 `pos` に対応するエントリを探す。
+    ///
+    /// probe の上限回数以内に空きが見つからなければ `TableFullPolicy` に従う。
+    /// (置き換える場合、置き換え対象のスロットを空きとして返す)
+    ///
+    /// ハッシュ衝突を検出した場合、そのエントリは別の局面のものとして扱う。
+    fn probe<const P: usize>(&mut self, pos: &Position<W, H, P>) -> DpTableProbe<'_, W, H> {
         // linear probing
-        let mut idx = key as usize & Self::INDEX_MASK;
-        loop {
-            let entry = unsafe { *self.array.get_unchecked_mut(idx) };
+        let key = pos.key();
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+
+        let idx_home = key as usize;
+        for i in 0..self.probe_limit {
+            let idx = idx_home.wrapping_add(i) & self.index_mask();
+            let entry = unsafe { *self.array.get_unchecked(idx) };
 
             match entry {
-                None => return DpTableProbe::new_vacant(self, idx),
-                Some(entry) if entry.time() != self.time => {
-                    return DpTableProbe::new_vacant(self, idx);
-                }
-                Some(entry) if entry.key_hi() == calc_key_hi(key) => {
-                    return DpTableProbe::new_occupied(self, idx, entry.gain_ub());
+                Some(entry) if entry.time() == self.time => {
+                    if entry.key_hi() == key_hi && self.collision.verify(idx, pos.board()) {
+                        return DpTableProbe::new_occupied(self, idx, entry.gain_ub());
+                    }
                 }
-                _ => idx = (idx + 1) & Self::INDEX_MASK,
+                _ => return DpTableProbe::new_vacant(self, idx),
             }
         }
+
+        let idx =
+            idx_home.wrapping_add(self.policy.victim_offset(self.capacity())) & self.index_mask();
+        DpTableProbe::new_vacant(self, idx)
     }
 
-    /// `probe()` で仮作成したエントリの値を `gain_ub` に設定する。
-    fn set_gain_ub(&mut self, idx: usize, gain_ub: Score) {
-        let entry = unsafe { self.array.get_unchecked_mut(idx) };
-        let entry = unsafe { entry.as_mut().unwrap_unchecked() };
+    /// エントリの値を `gain_ub` に設定する。
+    ///
+    /// インデックス `idx` のエントリが既に置き換えられていた場合、エントリを作り直す。
+    fn set_gain_ub<const P: usize>(&mut self, idx: usize, pos: &Position<W, H, P>, gain_ub: Score) {
+        let key_hi = DpEntry::<W, H>::calc_key_hi(pos.key());
 
-        entry.set_gain_ub(gain_ub);
+        let entry = unsafe { self.array.get_unchecked_mut(idx) };
+        match entry {
+            Some(entry)
+                if entry.time() == self.time
+                    && entry.key_hi() == key_hi
+                    && self.collision.matches(idx, pos.board()) =>
+            {
+                entry.set_gain_ub(gain_ub);
+            }
+            _ => {
+                let dp_probe = self.probe(pos);
+                if dp_probe.gain_ub().is_some() {
+                    let idx = dp_probe.into_index();
+                    self.set_gain_ub(idx, pos, gain_ub);
+                } else {
+                    dp_probe.make_entry(pos, gain_ub);
+                }
+            }
+        }
     }
 
-    fn make_entry(&mut self, idx: usize, key: u64, gain_ub: Score) {
-        self.entry_count += 1;
-        /*
-        if self.entry_count.is_power_of_two() {
-            eprintln!("DP entry count: {}", self.entry_count);
+    fn make_entry<const P: usize>(&mut self, idx: usize, pos: &Position<W, H, P>, gain_ub: Score) {
+        let entry = unsafe { self.array.get_unchecked_mut(idx) };
+
+        // 既存エントリを置き換える場合、エントリ数は変わらない。
+        if !entry.is_some_and(|entry| entry.time() == self.time) {
+            self.entry_count += 1;
+            /*
+            if self.entry_count.is_power_of_two() {
+                eprintln!("DP entry count: {}", self.entry_count);
+            }
+            */
         }
-        */
 
-        let entry = unsafe { self.array.get_unchecked_mut(idx) };
-        entry.replace(DpEntry::new(self.time, key, gain_ub));
+        entry.replace(DpEntry::new(self.time, pos.key(), gain_ub));
+        self.collision.record(idx, pos.board());
     }
 }
 
 #[derive(Debug)]
-struct DpTableProbe<'dp> {
-    dp: &'dp mut DpTable,
+struct DpTableProbe<'dp, const W: usize, const H: usize> {
+    dp: &'dp mut DpTable<W, H>,
     idx: usize,
     gain_ub: Option<NonZeroU32>,
 }
 
-impl<'dp> DpTableProbe<'dp> {
-    fn new_occupied(dp: &'dp mut DpTable, idx: usize, gain_ub: Score) -> Self {
+impl<'dp, const W: usize, const H: usize> DpTableProbe<'dp, W, H> {
+    fn new_occupied(dp: &'dp mut DpTable<W, H>, idx: usize, gain_ub: Score) -> Self {
         unsafe { assert_unchecked!(gain_ub != 0) }
 
         Self {
@@ -309,7 +459,7 @@ impl<'dp> DpTableProbe<'dp> {
         }
     }
 
-    fn new_vacant(dp: &'dp mut DpTable, idx: usize) -> Self {
+    fn new_vacant(dp: &'dp mut DpTable<W, H>, idx: usize) -> Self {
         Self {
             dp,
             idx,
@@ -326,10 +476,10 @@ impl<'dp> DpTableProbe<'dp> {
         self.idx
     }
 
-    fn make_entry(self, key: u64, gain_ub: Score) -> usize {
+    fn make_entry<const P: usize>(self, pos: &Position<W, H, P>, gain_ub: Score) -> usize {
         unsafe { assert_unchecked!(self.gain_ub.is_none()) }
 
-        self.dp.make_entry(self.idx, key, gain_ub);
+        self.dp.make_entry(self.idx, pos, gain_ub);
 
         self.idx
     }
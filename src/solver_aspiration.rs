@@ -0,0 +1,227 @@
+//! 目標スコアを段階的に下げながら探索するソルバー。
+
+use anyhow::{bail, Context as _};
+
+use crate::board::Board;
+use crate::position::Position;
+use crate::score::Score;
+use crate::score_bound::ScoreBound;
+use crate::solve::{Solve, SolveConfig, SolveResult};
+use crate::solver2::Solver;
+
+/// 目標スコアの下げ方。
+///
+/// 目標スコアは常に「既知の最大スコアより大きく、スコア上界以下」の範囲から選ぶ。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AspirationSchedule {
+    /// スコア上界から幅 `n` の範囲を 1 回ずつ調べる。つまり目標スコアを `スコア上界 - n + 1` とする。
+    Step(Score),
+    /// 範囲の中央を目標スコアとする (二分探索)。
+    Bisect,
+}
+
+impl AspirationSchedule {
+    /// 既知の最大スコア `best_score` とスコア上界 `score_ub` から次の目標スコアを返す。
+    ///
+    /// `best_score < score_ub` でなければならない。
+    fn target(self, best_score: Score, score_ub: Score) -> Score {
+        debug_assert!(best_score < score_ub);
+
+        match self {
+            Self::Step(n) => (score_ub + 1).saturating_sub(n).max(best_score + 1),
+            Self::Bisect => best_score + (score_ub - best_score).div_ceil(2),
+        }
+    }
+}
+
+impl Default for AspirationSchedule {
+    fn default() -> Self {
+        Self::Step(50)
+    }
+}
+
+impl std::str::FromStr for AspirationSchedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "bisect" {
+            return Ok(Self::Bisect);
+        }
+
+        let n: Score = s
+            .parse()
+            .with_context(|| format!("目標スコアの下げ方が無効: '{s}' (正整数または bisect)"))?;
+        if n == 0 {
+            bail!("目標スコアの下げ幅は正でなければならない");
+        }
+
+        Ok(Self::Step(n))
+    }
+}
+
+impl std::fmt::Display for AspirationSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Step(n) => write!(f, "{n}"),
+            Self::Bisect => f.write_str("bisect"),
+        }
+    }
+}
+
+/// 最大スコアの初期値を推測せずに済むよう、`solver2::Solver` を目標スコアを下げながら繰り返し呼び出すソルバー。
+///
+/// スコア上界から始めて、目標スコア以上の解を探索する。
+/// 見つからなければ目標スコア未満であることが証明されたので、スコア上界を下げて繰り返す。
+/// 見つかればそれが最大スコアである (探索中に改善解が見つかるたびに枝刈りの基準も上がるため)。
+///
+/// 1 つの盤面に対する各反復では DP テーブルの世代を更新しないので、前回までに得た上界が再利用される。
+///
+/// `SolveResult::node_count()` は全反復の合計を返す。
+#[derive(Debug)]
+pub struct AspirationSolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    solver: Solver<W, H, P>,
+    score_bound: ScoreBound,
+    schedule: AspirationSchedule,
+}
+
+impl<const W: usize, const H: usize, const P: usize> AspirationSolver<W, H, P> {
+    pub fn new(config: &SolveConfig) -> Self {
+        Self {
+            solver: Solver::new(config),
+            score_bound: config.score_bound,
+            schedule: config.aspiration,
+        }
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for AspirationSolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let pos = Position::new(board);
+        let best_score = self.solver.best_score();
+
+        let mut score_ub = self.score_bound.eval(&pos);
+        let mut node_count = 0;
+        let mut best = None;
+        while best_score < score_ub {
+            let target = self.schedule.target(best_score, score_ub);
+            let (res, node_count_iter, gain_ub) = self.solver.search_keep_table(&pos, target - 1);
+            node_count += node_count_iter;
+            eprintln!("aspiration: target {target}, node count {node_count_iter}");
+
+            if res.is_some() {
+                best = res;
+                break;
+            }
+
+            // 目標スコア未満であることが証明された。
+            score_ub = gain_ub.min(target - 1);
+        }
+
+        let table_stats = self.solver.finish_board();
+
+        let (score, solution) = best?;
+        self.solver.set_best_score(score);
+
+        Some(SolveResult::new(score, solution, node_count, table_stats))
+    }
+
+    fn best_score(&self) -> Score {
+        self.solver.best_score()
+    }
+
+    fn set_best_score(&mut self, best_score: Score) {
+        self.solver.set_best_score(best_score);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use crate::replay::verify;
+    use crate::solver::solve_problem_with;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    #[test]
+    fn test_aspiration_schedule() {
+        for schedule in [AspirationSchedule::Step(10), AspirationSchedule::Bisect] {
+            assert_eq!(
+                schedule.to_string().parse::<AspirationSchedule>().unwrap(),
+                schedule
+            );
+        }
+        assert!("foo".parse::<AspirationSchedule>().is_err());
+        assert!("0".parse::<AspirationSchedule>().is_err());
+
+        assert_eq!(AspirationSchedule::Step(1).target(0, 100), 100);
+        assert_eq!(AspirationSchedule::Step(10).target(0, 100), 91);
+        assert_eq!(AspirationSchedule::Step(10).target(95, 100), 96);
+        assert_eq!(AspirationSchedule::Step(1000).target(0, 100), 1);
+        assert_eq!(AspirationSchedule::Bisect.target(0, 100), 50);
+        assert_eq!(AspirationSchedule::Bisect.target(10, 13), 12);
+        assert_eq!(AspirationSchedule::Bisect.target(12, 13), 13);
+    }
+
+    #[test]
+    fn test_aspiration_solver() {
+        let board = parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "});
+        let config_new = |aspiration, score_bound| SolveConfig {
+            table_bytes: 1 << 20,
+            score_bound,
+            aspiration,
+            ..Default::default()
+        };
+        let (score_exact, _) = solve_problem_with(
+            board.clone(),
+            &config_new(Default::default(), ScoreBound::Simple),
+        );
+
+        for schedule in [
+            AspirationSchedule::Step(1),
+            AspirationSchedule::Step(30),
+            AspirationSchedule::Bisect,
+        ] {
+            for score_bound in [ScoreBound::Simple, ScoreBound::Separation] {
+                let mut solver = AspirationSolver::new(&config_new(schedule, score_bound));
+                let res = solver.solve(board.clone()).unwrap();
+                assert_eq!(res.score(), score_exact);
+                assert_eq!(verify(board.clone(), res.solution()), Ok(res.score()));
+
+                // 既知の最大スコアを超えない場合は None を返す。
+                assert!(solver.solve(board.clone()).is_none());
+            }
+        }
+
+        // 小さい盤面 (合法手のない盤面を含む) でも厳密解と一致する。
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..30 {
+            let mut s = "........\n".repeat(4);
+            for _ in 0..2 {
+                let line: String = (0..8)
+                    .map(|_| char::from(b'1' + rng.gen_range(0..4)))
+                    .collect();
+                s.push_str(&line);
+                s.push('\n');
+            }
+            let board = parse_board(s);
+
+            let config = config_new(AspirationSchedule::Bisect, ScoreBound::Separation);
+            let (score_exact, _) = solve_problem_with(board.clone(), &config);
+            let res = AspirationSolver::new(&config).solve(board);
+            assert_eq!(res.map_or(0, |res| res.score()), score_exact);
+        }
+    }
+}
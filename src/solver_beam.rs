@@ -0,0 +1,390 @@
+//! ビームサーチによる近似ソルバー。
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::solve::{Solve, SolveConfig, SolveResult, TableStats};
+use crate::util::chmax;
+use crate::zobrist::Key;
+
+/// ビームサーチの設定。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BeamConfig {
+    /// ビーム幅 (各深さで残す局面数)。制限時間またはノード数の上限を指定した場合は初期値となる。
+    pub width: usize,
+
+    /// 局面の評価関数。
+    pub eval: BeamEval,
+
+    /// 1 つの盤面の探索にかける時間の上限。
+    pub time_limit: Option<Duration>,
+
+    /// 1 つの盤面の探索で展開するノード数の上限。
+    pub node_limit: Option<u64>,
+}
+
+impl BeamConfig {
+    /// 制限時間またはノード数の上限が指定されているかどうかを返す。
+    fn has_limit(&self) -> bool {
+        self.time_limit.is_some() || self.node_limit.is_some()
+    }
+}
+
+impl Default for BeamConfig {
+    fn default() -> Self {
+        Self {
+            width: 1000,
+            eval: BeamEval::UpperBound,
+            time_limit: None,
+            node_limit: None,
+        }
+    }
+}
+
+/// ビームサーチにおける局面の評価関数。いずれも値が大きいほど良い。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BeamEval {
+    /// 現在のスコア。
+    Score,
+    /// 現在のスコア + `Position::score_upper_bound()`。
+    UpperBound,
+    /// 現在のスコア + 各連結成分をそのまま消した場合のスコアの合計。
+    ///
+    /// 同種の駒がまとまっている局面ほど高く評価される。
+    Components,
+}
+
+impl BeamEval {
+    fn eval<const W: usize, const H: usize, const P: usize>(
+        self,
+        pos: &Position<W, H, P>,
+        score: Score,
+    ) -> Score {
+        match self {
+            Self::Score => score,
+            Self::UpperBound => score + pos.score_upper_bound(),
+            Self::Components => {
+                score
+                    + pos
+                        .actions()
+                        .map(|action| score_erase(action.square_count()))
+                        .sum::<Score>()
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for BeamEval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "score" => Ok(Self::Score),
+            "upper-bound" => Ok(Self::UpperBound),
+            "components" => Ok(Self::Components),
+            _ => bail!("評価関数が無効: '{s}' (score, upper-bound, components のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for BeamEval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Score => "score",
+            Self::UpperBound => "upper-bound",
+            Self::Components => "components",
+        };
+        f.write_str(s)
+    }
+}
+
+/// ビームサーチにより近似解を求めるソルバー。
+///
+/// 同一の局面 (ハッシュ値で判定) は各深さで 1 つにまとめる。
+///
+/// 制限時間またはノード数の上限を指定した場合、上限に達するまでビーム幅を倍々にしながら探索を繰り返し、
+/// それまでに見つかった最良の解を返す。上限を指定しない場合は初期のビーム幅で 1 回だけ探索する。
+/// いずれの場合も、ビームから局面が溢れなかったならその時点で厳密解が得られているので探索を終える。
+///
+/// 近似ソルバーなので、解が見つからなくても従来より大きいスコアが存在しないとは限らない。
+/// `SolveResult::table_stats()` は、エントリ数として各深さの局面数の最大値を、容量として最後のビーム幅を返す。
+#[derive(Debug)]
+pub struct BeamSolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    best_score: Score,
+    config: BeamConfig,
+}
+
+impl<const W: usize, const H: usize, const P: usize> BeamSolver<W, H, P> {
+    pub fn new(config: &SolveConfig) -> Self {
+        assert!(config.beam.width > 0);
+
+        Self {
+            best_score: config.best_score_ini,
+            config: config.beam.clone(),
+        }
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for BeamSolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let mut search = BeamSearch::new(&self.config, self.best_score);
+        let pos_root = Position::new(board);
+
+        let mut width = self.config.width;
+        loop {
+            let exhaustive = search.run(&pos_root, width);
+            if exhaustive || !self.config.has_limit() || search.is_over_budget() {
+                break;
+            }
+            width = width.saturating_mul(2);
+        }
+
+        eprintln!("beam width: {width}");
+
+        let (score, solution) = search.best?;
+        self.best_score = score;
+
+        let table_stats = TableStats::new(search.layer_len_max, width, 0);
+        Some(SolveResult::new(
+            score,
+            solution,
+            search.node_count,
+            table_stats,
+        ))
+    }
+
+    fn best_score(&self) -> Score {
+        self.best_score
+    }
+
+    fn set_best_score(&mut self, best_score: Score) {
+        self.best_score = best_score;
+    }
+}
+
+#[derive(Debug)]
+struct BeamSearch<'a, const W: usize, const H: usize, const P: usize> {
+    config: &'a BeamConfig,
+    start: Instant,
+    node_count: u64,
+    layer_len_max: usize,
+    /// これより大きいスコアのみを解として記録する。
+    best_score: Score,
+    best: Option<(Score, ActionHistory<W, H>)>,
+}
+
+#[derive(Clone, Debug)]
+struct BeamNode<const W: usize, const H: usize, const P: usize> {
+    pos: Position<W, H, P>,
+    score: Score,
+    eval: Score,
+    history: ActionHistory<W, H>,
+}
+
+impl<'a, const W: usize, const H: usize, const P: usize> BeamSearch<'a, W, H, P> {
+    fn new(config: &'a BeamConfig, best_score: Score) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            node_count: 0,
+            layer_len_max: 0,
+            best_score,
+            best: None,
+        }
+    }
+
+    /// 制限時間またはノード数の上限に達したかどうかを返す。
+    fn is_over_budget(&self) -> bool {
+        self.config
+            .node_limit
+            .is_some_and(|limit| self.node_count >= limit)
+            || self
+                .config
+                .time_limit
+                .is_some_and(|limit| self.start.elapsed() >= limit)
+    }
+
+    /// 終了局面に達した手順を解の候補として記録する。
+    fn try_improve(&mut self, score: Score, history: &ActionHistory<W, H>) {
+        if chmax!(self.best_score, score) {
+            eprintln!("beam: found {score}");
+            self.best.replace((score, history.clone()));
+        }
+    }
+
+    /// ビーム幅 `width` で 1 回探索する。
+    /// ビームから局面が溢れなかった (厳密解が得られた) なら `true` を返す。
+    ///
+    /// 上限に達した場合は途中で打ち切る (その場合は `false` を返す)。
+    fn run(&mut self, pos_root: &Position<W, H, P>, width: usize) -> bool {
+        let mut exhaustive = true;
+
+        let mut layer = vec![BeamNode {
+            pos: pos_root.clone(),
+            score: 0,
+            eval: 0,
+            history: ActionHistory::new(),
+        }];
+        if !pos_root.has_action() {
+            let score = if pos_root.board().is_empty() {
+                SCORE_PERFECT
+            } else {
+                0
+            };
+            self.try_improve(score, &ActionHistory::new());
+            return true;
+        }
+
+        while !layer.is_empty() {
+            let mut nexts = HashMap::<Key, BeamNode<W, H, P>>::new();
+
+            for node in &layer {
+                for action in node.pos.actions() {
+                    self.node_count += 1;
+
+                    let pos = node.pos.do_action(&action);
+                    let score = node.score + score_erase(action.square_count());
+                    let mut history = node.history.clone();
+                    history.push(action.least_square());
+
+                    if pos.board().is_empty() {
+                        self.try_improve(score + SCORE_PERFECT, &history);
+                        continue;
+                    }
+                    if !pos.has_action() {
+                        self.try_improve(score, &history);
+                        continue;
+                    }
+
+                    // 同一局面はスコアが大きい方を残す。
+                    let key = pos.key();
+                    if nexts.get(&key).is_some_and(|other| other.score >= score) {
+                        continue;
+                    }
+                    let eval = self.config.eval.eval(&pos, score);
+                    nexts.insert(
+                        key,
+                        BeamNode {
+                            pos,
+                            score,
+                            eval,
+                            history,
+                        },
+                    );
+                }
+
+                if self.is_over_budget() {
+                    return false;
+                }
+            }
+
+            chmax!(self.layer_len_max, nexts.len());
+
+            // 結果が HashMap の列挙順に依存しないよう、ハッシュ値でタイブレークする。
+            layer = nexts.into_values().collect();
+            layer.sort_unstable_by(|lhs, rhs| {
+                (rhs.eval, lhs.pos.key()).cmp(&(lhs.eval, rhs.pos.key()))
+            });
+            if layer.len() > width {
+                exhaustive = false;
+                layer.truncate(width);
+            }
+        }
+
+        exhaustive
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::replay::verify;
+    use crate::solver::solve_problem_with;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn board_medium() -> Board {
+        parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "})
+    }
+
+    #[test]
+    fn test_beam_eval_parse() {
+        for eval in [BeamEval::Score, BeamEval::UpperBound, BeamEval::Components] {
+            assert_eq!(eval.to_string().parse::<BeamEval>().unwrap(), eval);
+        }
+        assert!("foo".parse::<BeamEval>().is_err());
+    }
+
+    #[test]
+    fn test_beam_solver() {
+        let board = board_medium();
+        let config_new = |beam| SolveConfig {
+            table_bytes: 1 << 20,
+            beam,
+            ..Default::default()
+        };
+        let (score_exact, _) = solve_problem_with(board.clone(), &config_new(Default::default()));
+
+        for eval in [BeamEval::Score, BeamEval::UpperBound, BeamEval::Components] {
+            // 幅が小さくても、正しい手順が得られる。
+            let config = config_new(BeamConfig {
+                width: 4,
+                eval,
+                ..Default::default()
+            });
+            let res = BeamSolver::new(&config).solve(board.clone()).unwrap();
+            assert!(res.score() <= score_exact);
+            assert_eq!(verify(board.clone(), res.solution()), Ok(res.score()));
+
+            // 幅が十分大きければ厳密解が得られる。
+            let config = config_new(BeamConfig {
+                width: 1 << 20,
+                eval,
+                ..Default::default()
+            });
+            let res = BeamSolver::new(&config).solve(board.clone()).unwrap();
+            assert_eq!(res.score(), score_exact);
+        }
+
+        // ノード数の上限を指定すると、上限に達するまで幅を広げながら探索する。
+        let config = config_new(BeamConfig {
+            width: 1,
+            node_limit: Some(1 << 40),
+            ..Default::default()
+        });
+        let res = BeamSolver::new(&config).solve(board.clone()).unwrap();
+        assert_eq!(res.score(), score_exact);
+
+        // 上限に達したらそれまでの最良解を返す。
+        let config = config_new(BeamConfig {
+            width: 1,
+            node_limit: Some(100),
+            ..Default::default()
+        });
+        let mut solver = BeamSolver::new(&config);
+        let res = solver.solve(board.clone()).unwrap();
+        assert!(res.node_count() < 200);
+        assert_eq!(verify(board.clone(), res.solution()), Ok(res.score()));
+
+        // 既知の最大スコアを超えない場合は None を返す。
+        assert!(solver.solve(board).is_none());
+    }
+}
@@ -0,0 +1,207 @@
+//! ソルバーの差分テスト。
+//!
+//! 総当たりできる程度に駒が少ないランダムな盤面について、盤面操作を独自に実装した素朴な全探索と
+//! 各ソルバーの結果が一致することを確かめる。
+
+use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::replay::verify;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::solve::{Solve, SolveConfig, TableFullPolicy};
+use crate::solver::solve_problem_with;
+use crate::solver2::Solver;
+use crate::solver_many::ManySolver;
+
+/// 素朴な全探索用の盤面。左の列から順に、各列の駒を下から順に並べたもの (空の列は持たない)。
+type NaiveBoard = Vec<Vec<u8>>;
+
+/// `Board` とは独立に盤面操作を実装した、メモ化なしの全探索で最大スコアを求める。
+fn naive_best_score(board: &NaiveBoard) -> Score {
+    let components = naive_components(board);
+
+    if components.is_empty() {
+        return if board.is_empty() { SCORE_PERFECT } else { 0 };
+    }
+
+    components
+        .iter()
+        .map(|comp| score_erase(comp.len() as u32) + naive_best_score(&naive_erase(board, comp)))
+        .max()
+        .unwrap()
+}
+
+/// 2 個以上の駒からなる連結成分を全て返す。各連結成分はマス (列, 行) の列。
+fn naive_components(board: &NaiveBoard) -> Vec<Vec<(usize, usize)>> {
+    let mut visited: Vec<Vec<bool>> = board.iter().map(|col| vec![false; col.len()]).collect();
+    let mut components = vec![];
+
+    for x in 0..board.len() {
+        for y in 0..board[x].len() {
+            if visited[x][y] {
+                continue;
+            }
+            let piece = board[x][y];
+            let mut comp = vec![];
+            let mut stack = vec![(x, y)];
+            visited[x][y] = true;
+            while let Some((cx, cy)) = stack.pop() {
+                comp.push((cx, cy));
+                let neighbors = [
+                    (cx.wrapping_sub(1), cy),
+                    (cx + 1, cy),
+                    (cx, cy.wrapping_sub(1)),
+                    (cx, cy + 1),
+                ];
+                for (nx, ny) in neighbors {
+                    if board.get(nx).and_then(|col| col.get(ny)) == Some(&piece) && !visited[nx][ny]
+                    {
+                        visited[nx][ny] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            if comp.len() >= 2 {
+                components.push(comp);
+            }
+        }
+    }
+
+    components
+}
+
+/// 連結成分 `comp` を消し、駒を落下させて空の列を詰めた盤面を返す。
+fn naive_erase(board: &NaiveBoard, comp: &[(usize, usize)]) -> NaiveBoard {
+    board
+        .iter()
+        .enumerate()
+        .map(|(x, col)| {
+            col.iter()
+                .enumerate()
+                .filter(|&(y, _)| !comp.contains(&(x, y)))
+                .map(|(_, &piece)| piece)
+                .collect::<Vec<_>>()
+        })
+        .filter(|col| !col.is_empty())
+        .collect()
+}
+
+/// 駒数が高々 `piece_count_max` のランダムな盤面を生成する。
+fn naive_board_random<const W: usize, const H: usize, const P: usize>(
+    rng: &mut StdRng,
+    piece_count_max: usize,
+) -> NaiveBoard {
+    // 駒種数を絞った盤面も生成し、消せる駒が多い盤面も現れるようにする。
+    let piece_kind_count = rng.gen_range(2..=P) as u8;
+    let width = rng.gen_range(1..=W);
+
+    let mut piece_count_remain = piece_count_max;
+    let mut board = NaiveBoard::new();
+    for _ in 0..width {
+        let height = rng.gen_range(1..=H).min(piece_count_remain);
+        if height == 0 {
+            break;
+        }
+        piece_count_remain -= height;
+        board.push(
+            (0..height)
+                .map(|_| rng.gen_range(1..=piece_kind_count))
+                .collect(),
+        );
+    }
+
+    board
+}
+
+fn naive_board_to_board<const W: usize, const H: usize, const P: usize>(
+    board: &NaiveBoard,
+) -> Board<W, H, P> {
+    let mut s = String::new();
+    for y in (0..H).rev() {
+        for x in 0..W {
+            let ch = board
+                .get(x)
+                .and_then(|col| col.get(y))
+                .map_or('.', |&piece| char::from(b'0' + piece));
+            s.push(ch);
+        }
+        s.push('\n');
+    }
+
+    s.parse().unwrap()
+}
+
+/// ランダムな盤面 `board_count` 個について全てのソルバーの結果を素朴な全探索と照合する。
+fn check_solvers<const W: usize, const H: usize, const P: usize>(
+    seed: u64,
+    board_count: usize,
+    piece_count_max: usize,
+) {
+    let config = SolveConfig {
+        table_bytes: 1 << 16,
+        table_full_policy: TableFullPolicy::Replace,
+        ..Default::default()
+    };
+    // 複数スレッドの場合、`solve_problem_with()` は `ParallelSolver` を用いる。
+    let config_parallel = SolveConfig {
+        thread_count: 2,
+        ..config.clone()
+    };
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut solver2 = Solver::<W, H, P>::new(&config);
+    let mut solver_many = ManySolver::<W, H, P>::new(&config);
+
+    for _ in 0..board_count {
+        let naive_board = naive_board_random::<W, H, P>(&mut rng, piece_count_max);
+        let board = naive_board_to_board::<W, H, P>(&naive_board);
+        let expect = naive_best_score(&naive_board);
+
+        let check_solution = |name: &str, score: Score, solution: &ActionHistory<W, H>| {
+            assert_eq!(score, expect, "{name}: スコアが異なる\n{board}");
+            assert_eq!(
+                verify(board.clone(), solution),
+                Ok(score),
+                "{name}: 手順 {solution} の再生結果が異なる\n{board}"
+            );
+        };
+
+        let (score, solution) = solve_problem_with(board.clone(), &config);
+        check_solution("solve_problem", score, &solution);
+        let (score, solution) = solve_problem_with(board.clone(), &config_parallel);
+        check_solution("solve_problem (parallel)", score, &solution);
+
+        // 最大スコアの初期値 0 から解くと最大スコアが、最大スコアそのものから解くと None が得られる。
+        let solvers: [(&str, &mut dyn Solve<W, H, P>); 2] =
+            [("solver2", &mut solver2), ("solver_many", &mut solver_many)];
+        for (name, solver) in solvers {
+            solver.set_best_score(0);
+            match solver.solve(board.clone()) {
+                Some(res) => check_solution(name, res.score(), res.solution()),
+                None => assert_eq!(expect, 0, "{name}: 解が見つからない\n{board}"),
+            }
+
+            solver.set_best_score(expect);
+            assert!(
+                solver.solve(board.clone()).is_none(),
+                "{name}: 最大スコアより大きい解が見つかった\n{board}"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_solvers_sparse() {
+        check_solvers::<8, 6, 5>(1, 300, 14);
+    }
+
+    #[test]
+    fn test_solvers_small_board() {
+        check_solvers::<4, 4, 3>(2, 300, 16);
+    }
+}
@@ -0,0 +1,365 @@
+//! 上位 k 個のスコアを求めるソルバー。
+
+use crate::action::ActionHistory;
+use crate::board::Board;
+use crate::position::Position;
+use crate::score::{score_erase, score_max, Score, SCORE_PERFECT};
+use crate::solve::SolveConfig;
+use crate::solver::DpTable;
+
+/// 与えられた盤面で獲得しうるスコアのうち、大きい方から相異なる `k` 個とそれぞれの手順を降順で返す。
+///
+/// 獲得しうるスコアが `k` 種類未満ならばその全てを返す。
+/// 合法手がない盤面に対しては `(0, 空の手順)` のみを返す。
+pub fn solve_problem_k_best<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    k: usize,
+) -> Vec<(Score, ActionHistory<W, H>)> {
+    solve_problem_k_best_with(board, k, &SolveConfig::default())
+}
+
+/// 設定を指定して `solve_problem_k_best()` を行う。
+///
+/// DP テーブルは `config` の `table_bytes`, `table_full_policy`, `collision_check` に従う
+/// (各エントリが k 個のスコアを保持する分、容量は `ExactSolver` より小さくなる)。
+pub fn solve_problem_k_best_with<const W: usize, const H: usize, const P: usize>(
+    board: Board<W, H, P>,
+    k: usize,
+    config: &SolveConfig,
+) -> Vec<(Score, ActionHistory<W, H>)> {
+    assert!(k > 0, "k は正でなければならない");
+
+    let mut solver = KBestSolver::new(k, config);
+    let pos_root = Position::new(board);
+
+    let scores = solver.gains(&pos_root);
+    eprintln!("k-best DP entry count: {}", solver.dp.entry_count());
+    if solver.dp.collision_count() != 0 {
+        eprintln!("k-best DP collision count: {}", solver.dp.collision_count());
+    }
+
+    scores
+        .iter()
+        .map(|&score| (score, solver.reconstruct_solution(&pos_root, score)))
+        .collect()
+}
+
+/// 全探索 + メモ化により、各局面から追加で獲得しうるスコアの上位 k 個を求めるソルバー。
+///
+/// ある局面の上位 k 個のスコアは、各子局面の上位 k 個のスコアのみから求まる。
+/// (子局面で上位 k 個に入らないスコアからは、親局面でも上位 k 個に入るスコアは得られない)
+///
+/// DP テーブルは `ExactSolver` と共通で、上位 k 個のスコアは副テーブル (`KBestTable`) に保持する。
+#[derive(Debug)]
+struct KBestSolver<const W: usize, const H: usize, const P: usize> {
+    k: usize,
+    dp: DpTable<W, H>,
+}
+
+impl<const W: usize, const H: usize, const P: usize> KBestSolver<W, H, P> {
+    fn new(k: usize, config: &SolveConfig) -> Self {
+        let capacity = config.table_capacity_with::<W, H>(KBestTable::entry_bytes(k));
+
+        Self {
+            k,
+            dp: DpTable::new_k_best(
+                capacity,
+                config.table_full_policy,
+                config.collision_check,
+                k,
+            ),
+        }
+    }
+
+    /// `pos` から追加で獲得しうるスコアの上位 k 個を降順で返す。
+    ///
+    /// DP テーブルにエントリがあればそれを用い、なければ探索する。
+    fn gains(&mut self, pos: &Position<W, H, P>) -> Vec<Score> {
+        if pos.board().is_empty() {
+            return vec![SCORE_PERFECT];
+        }
+
+        self.dp.get_k_best(pos).unwrap_or_else(|| self.dfs(pos))
+    }
+
+    /// `pos` から追加で獲得しうるスコアの上位 k 個を降順で返す。
+    fn dfs(&mut self, pos: &Position<W, H, P>) -> Vec<Score> {
+        // 空の盤面に対する DP エントリが作られないよう、先にパーフェクト判定する。
+        if pos.board().is_empty() {
+            return vec![SCORE_PERFECT];
+        }
+
+        let dp_idx = match self.dp.probe_index(pos) {
+            Ok(dp_idx) => return self.dp.k_best_at(dp_idx).collect(),
+            Err(dp_idx) => dp_idx,
+        };
+
+        let mut gains = Vec::<Score>::with_capacity(2 * self.k);
+        for action in pos.actions() {
+            let pos_child = pos.do_action(&action);
+            let gain_action = score_erase(action.square_count());
+            let gains_child = self.dfs(&pos_child);
+            gains.extend(gains_child.iter().map(|&gain| gain_action + gain));
+
+            gains.sort_unstable_by(|lhs, rhs| rhs.cmp(lhs));
+            gains.dedup();
+            gains.truncate(self.k);
+        }
+
+        // 終了局面。
+        // DP テーブルに仮作成したエントリのスコアは [0] なのでそのままでよい。
+        if gains.is_empty() {
+            return vec![0];
+        }
+
+        self.dp.set_k_best(dp_idx, pos, &gains);
+        gains
+    }
+
+    /// `pos` から追加でちょうど `gain` を獲得する手順を返す。
+    ///
+    /// `gain` は `gains(pos)` が返した値のいずれかでなければならない。
+    fn reconstruct_solution(
+        &mut self,
+        pos: &Position<W, H, P>,
+        gain: Score,
+    ) -> ActionHistory<W, H> {
+        let mut solution = ActionHistory::new();
+        let mut pos = pos.clone();
+        let mut gain = gain;
+
+        loop {
+            let found = pos.actions().find_map(|action| {
+                let gain_action = score_erase(action.square_count());
+                let gain_child = gain.checked_sub(gain_action)?;
+                let pos_child = pos.do_action(&action);
+                self.gains(&pos_child)
+                    .contains(&gain_child)
+                    .then_some((action, pos_child, gain_child))
+            });
+            let Some((action, pos_child, gain_child)) = found else {
+                break;
+            };
+
+            solution.push(action.least_square());
+            pos = pos_child;
+            gain = gain_child;
+        }
+
+        debug_assert!(gain == 0 || (gain == SCORE_PERFECT && pos.board().is_empty()));
+
+        solution
+    }
+}
+
+/// DP テーブルの各スロットに対応する局面の上位 k 個のスコア (降順) を保持する副テーブル。
+///
+/// スコアは 16bit で格納し、k 個に満たない部分は `NONE` で埋める。
+/// k = 0 の場合は何も保持しない (`ExactSolver` の DP テーブル)。
+#[derive(Debug)]
+pub(crate) struct KBestTable {
+    k: usize,
+    array: Box<[u16]>,
+}
+
+impl KBestTable {
+    const NONE: u16 = u16::MAX;
+
+    /// 1 エントリあたりのバイト数を返す。
+    pub(crate) fn entry_bytes(k: usize) -> usize {
+        std::mem::size_of::<u16>() * k
+    }
+
+    /// 容量 `capacity` の DP テーブルに対応する副テーブルを作る。
+    pub(crate) fn new(k: usize, capacity: usize) -> Self {
+        Self {
+            k,
+            array: vec![Self::NONE; k * capacity].into_boxed_slice(),
+        }
+    }
+
+    /// スロット `idx` のスコアを降順で列挙する。
+    pub(crate) fn gains(&self, idx: usize) -> impl Iterator<Item = Score> + '_ {
+        self.slot(idx)
+            .iter()
+            .take_while(|&&gain| gain != Self::NONE)
+            .map(|&gain| Score::from(gain))
+    }
+
+    /// スロット `idx` のスコアを `gains` (降順、k 個以下) に設定する。
+    pub(crate) fn set(&mut self, idx: usize, gains: &[Score]) {
+        debug_assert!(gains.len() <= self.k);
+
+        let k = self.k;
+        let slot = &mut self.array[k * idx..][..k];
+        for (dst, src) in slot.iter_mut().zip(
+            gains
+                .iter()
+                .map(|&gain| gain as u16)
+                .chain(std::iter::repeat(Self::NONE)),
+        ) {
+            *dst = src;
+        }
+    }
+
+    /// スロット `idx` のスコアを仮作成したエントリの値 `[0]` にする。
+    pub(crate) fn reset(&mut self, idx: usize) {
+        if self.k == 0 {
+            return;
+        }
+
+        self.set(idx, &[0]);
+    }
+
+    fn slot(&self, idx: usize) -> &[u16] {
+        &self.array[self.k * idx..][..self.k]
+    }
+}
+
+// 全てのスコアが NONE 未満の 16bit 値に収まる。
+const _: () = assert!(score_max(crate::square::SQUARE_NUM_MAX) < KBestTable::NONE as Score);
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use indoc::indoc;
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use crate::collision::CollisionCheck;
+    use crate::replay::verify;
+    use crate::solve::TableFullPolicy;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn config_small(table_bytes: usize) -> SolveConfig {
+        SolveConfig {
+            table_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// 獲得しうる全てのスコアを素朴に求める。
+    fn all_scores(board: &Board) -> BTreeSet<Score> {
+        fn dfs(pos: &Position, memo: &mut HashMap<Board, BTreeSet<Score>>) -> BTreeSet<Score> {
+            if pos.board().is_empty() {
+                return BTreeSet::from([SCORE_PERFECT]);
+            }
+            if let Some(gains) = memo.get(pos.board()) {
+                return gains.clone();
+            }
+
+            let mut gains = BTreeSet::new();
+            for action in pos.actions() {
+                let gain_action = score_erase(action.square_count());
+                let gains_child = dfs(&pos.do_action(&action), memo);
+                gains.extend(gains_child.iter().map(|&gain| gain_action + gain));
+            }
+            if gains.is_empty() {
+                gains.insert(0);
+            }
+
+            memo.insert(pos.board().clone(), gains.clone());
+            gains
+        }
+
+        dfs(&Position::new(board.clone()), &mut HashMap::new())
+    }
+
+    fn assert_k_best(board: &Board, k: usize) {
+        let res = solve_problem_k_best_with(board.clone(), k, &config_small(1 << 20));
+
+        let expect: Vec<_> = all_scores(board).into_iter().rev().take(k).collect();
+        let scores: Vec<_> = res.iter().map(|&(score, _)| score).collect();
+        assert_eq!(scores, expect);
+
+        for (score, solution) in &res {
+            assert_eq!(verify(board.clone(), solution), Ok(*score));
+        }
+    }
+
+    #[test]
+    fn test_solve_problem_k_best() {
+        assert_eq!(
+            solve_problem_k_best_with(<Board>::empty(), 3, &config_small(1 << 20)),
+            [(SCORE_PERFECT, ActionHistory::new())]
+        );
+
+        let board = parse_board(indoc! {"
+            ........
+            ........
+            ........
+            1.......
+            22......
+            1133....
+        "});
+        assert_k_best(&board, 1);
+        assert_k_best(&board, 10);
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let mut s = "........\n".repeat(3);
+            for _ in 0..3 {
+                let line: String = (0..8)
+                    .map(|_| char::from(b'1' + rng.gen_range(0..3)))
+                    .collect();
+                s.push_str(&line);
+                s.push('\n');
+            }
+            assert_k_best(&parse_board(s), 5);
+        }
+    }
+
+    /// DP テーブルの設定に従い、小さいテーブルでも置き換えにより同じ結果が得られる。
+    #[test]
+    fn test_solve_problem_k_best_table() {
+        let board = parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "});
+
+        let expect = solve_problem_k_best_with(board.clone(), 4, &config_small(1 << 22));
+        assert_eq!(expect.len(), 4);
+
+        for collision_check in [CollisionCheck::None, CollisionCheck::Board] {
+            let config = SolveConfig {
+                table_full_policy: TableFullPolicy::Replace,
+                collision_check,
+                ..config_small(1 << 12)
+            };
+            let res = solve_problem_k_best_with(board.clone(), 4, &config);
+            let scores: Vec<_> = res.iter().map(|&(score, _)| score).collect();
+            let scores_expect: Vec<_> = expect.iter().map(|&(score, _)| score).collect();
+            assert_eq!(scores, scores_expect);
+            for (score, solution) in &res {
+                assert_eq!(verify(board.clone(), solution), Ok(*score));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "DP テーブルが満杯")]
+    fn test_solve_problem_k_best_table_full_fail() {
+        let board = parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "});
+        let config = SolveConfig {
+            table_full_policy: TableFullPolicy::Fail,
+            ..config_small(1 << 10)
+        };
+        solve_problem_k_best_with(board, 4, &config);
+    }
+}
@@ -1,12 +1,23 @@
-use std::num::NonZeroU64;
+use std::cmp::Reverse;
+use std::io::{Read, Write};
 use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{ensure, Context as _};
 
 use crate::action::ActionHistory;
 use crate::board::Board;
-use crate::piece::Piece;
+use crate::checkpoint::{Checkpoint, CheckpointConfig, CheckpointHeader};
 use crate::position::Position;
+use crate::results_db::{ResultRecord, ResultsDbWriter};
 use crate::rng::GameRng;
 use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::score_bound::ScoreBound;
+use crate::solve::{Solve, SolveConfig, SolveResult, SolverKind};
+use crate::solver::ExactSolver;
 use crate::util::chmax;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -19,6 +30,22 @@ pub struct SolutionMany {
 }
 
 impl SolutionMany {
+    pub(crate) fn new(
+        rng_state: u16,
+        rng_counter: u8,
+        rng_inc_timing: usize,
+        score: Score,
+        solution: ActionHistory,
+    ) -> Self {
+        Self {
+            rng_state,
+            rng_counter,
+            rng_inc_timing,
+            score,
+            solution,
+        }
+    }
+
     pub fn rng_state(&self) -> u16 {
         self.rng_state
     }
@@ -47,362 +74,945 @@ pub fn solve_problems_many(
     inc_timings: RangeInclusive<usize>,
     best_score_ini: Score,
 ) -> SolutionMany {
+    let config = SolveConfig {
+        best_score_ini,
+        ..Default::default()
+    };
+    let mut solver = ManySolver::new(&config);
+
+    solve_problems_many_with(&mut solver, states, counters, inc_timings).unwrap()
+}
+
+/// 与えられたソルバーを用いて、盤面集合内で最大スコアを求める。
+///
+/// ソルバーの最大スコアより大きいスコアが見つからなければ `None` を返す。
+pub fn solve_problems_many_with<S: Solve + ?Sized>(
+    solver: &mut S,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+) -> Option<SolutionMany> {
     assert!(!states.is_empty());
     assert!(!counters.is_empty());
     assert!(!inc_timings.is_empty());
 
-    Solver::new(best_score_ini).solve(states, counters, inc_timings)
+    let mut best_ans = None;
+
+    for (counter, inc_timing) in itertools::iproduct!(counters, inc_timings) {
+        for state in states.clone() {
+            // NOTE: gen_board() で盤面生成するので、初期盤面が空のケースは考えなくてよい。
+            let Some(board) = gen_board(state, counter, inc_timing) else {
+                continue;
+            };
+
+            eprintln!(
+                "Search: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing}"
+            );
+
+            if let Some(res) = solver.solve(board) {
+                let score = res.score();
+                let solution = res.into_solution();
+                eprintln!("Found {score}: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing} solution={solution}");
+                best_ans.replace(SolutionMany {
+                    rng_state: state,
+                    rng_counter: counter,
+                    rng_inc_timing: inc_timing,
+                    score,
+                    solution,
+                });
+            }
+        }
+    }
+
+    best_ans
 }
 
-fn gen_board(state: u16, counter: u8, inc_timing: usize) -> Option<Board> {
-    GameRng::new(state).gen_board(counter, inc_timing)
+/// `solve_problems_many_with()` のマルチスレッド版。
+///
+/// 盤面集合を `solve_problems_many_with()` と同じ順序で一列に並べ、一定数ずつ各スレッドに割り当てる。
+/// 各スレッドは `kind` のソルバーを個別に持つが、最大スコアは全スレッドで共有する。
+/// DP テーブルのメモリ量 `config.table_bytes` は全スレッドの合計で、各スレッドに等分される。
+/// 同点の場合は順序が先の盤面を採るので、結果はシングルスレッドの場合と一致する。
+pub fn solve_problems_many_parallel(
+    kind: SolverKind,
+    config: &SolveConfig,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+    thread_count: usize,
+) -> Option<SolutionMany> {
+    let sweep = Sweep::new(states, counters, inc_timings);
+
+    // チェックポイントを用いなければ失敗しない。
+    solve_sweep_parallel(kind, config, &sweep, thread_count, None, None, None).unwrap()
 }
 
-#[derive(Debug)]
-struct Solver {
-    best_score: Score,
-    best_ans: Option<SolutionMany>,
-    dp: DpTable,
+/// `solve_problems_many_parallel()` に途中経過の保存・復元機能を加えたもの。
+///
+/// `checkpoint.path` が存在すればそこから探索を再開する。
+/// ただし、ソルバーの種類、探索結果に影響する設定 (DP テーブルの容量、ハッシュ衝突の検出方法、スコア上界)、
+/// 盤面集合などが異なる場合は失敗する。
+/// `config.share_table` を指定した場合は各スレッドの DP テーブルも保存するので、スレッド数が異なる場合も失敗する。
+/// (保存時には各スレッドが探索中の盤面を解き終えるまで待つ)
+///
+/// 探索中は `checkpoint.interval` ごとに、また探索終了時にも途中経過を保存する。
+/// 中断・再開しても、結果は中断しなかった場合と一致する。
+pub fn solve_problems_many_resumable(
+    kind: SolverKind,
+    config: &SolveConfig,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+    thread_count: usize,
+    checkpoint: &CheckpointConfig,
+) -> anyhow::Result<Option<SolutionMany>> {
+    let options = SweepOptions {
+        checkpoint: Some(checkpoint.clone()),
+        ..Default::default()
+    };
+
+    solve_problems_many_with_options(
+        kind,
+        config,
+        states,
+        counters,
+        inc_timings,
+        thread_count,
+        &options,
+    )
 }
 
-impl Solver {
-    fn new(best_score_ini: Score) -> Self {
-        Self {
-            best_score: best_score_ini,
-            best_ans: None,
-            dp: DpTable::new(),
+/// 盤面集合の探索の付加機能の設定。
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SweepOptions {
+    /// チェックポイントの設定。`solve_problems_many_resumable()` を参照。
+    pub checkpoint: Option<CheckpointConfig>,
+
+    /// 探索結果を記録するデータベースファイル (`ResultsDb`)。なければ作成し、あれば追記する。
+    ///
+    /// これを指定すると、最大スコアを更新しない盤面も含めて全ての盤面の最大スコアを求めるので、
+    /// 共有の最大スコアによる枝刈りが効かなくなる。
+    pub results_db: Option<PathBuf>,
+}
+
+/// `SweepOptions` で指定した付加機能を用いて `solve_problems_many_parallel()` と同様の探索を行う。
+pub fn solve_problems_many_with_options(
+    kind: SolverKind,
+    config: &SolveConfig,
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+    thread_count: usize,
+    options: &SweepOptions,
+) -> anyhow::Result<Option<SolutionMany>> {
+    let header = CheckpointHeader::new(
+        kind,
+        config,
+        states.clone(),
+        counters.clone(),
+        inc_timings.clone(),
+    );
+    let sweep = Sweep::new(states, counters, inc_timings);
+
+    let resume = match &options.checkpoint {
+        Some(checkpoint) if checkpoint.path.exists() => {
+            let resume = Checkpoint::read(&checkpoint.path)?;
+            header.ensure_compatible(&resume.header).with_context(|| {
+                format!(
+                    "チェックポイント {} から再開できない",
+                    checkpoint.path.display()
+                )
+            })?;
+            Some(resume)
+        }
+        _ => None,
+    };
+
+    let results_db = options
+        .results_db
+        .as_ref()
+        .map(|path| ResultsDbWriter::open(path, kind.is_exact()))
+        .transpose()?;
+
+    solve_sweep_parallel(
+        kind,
+        config,
+        &sweep,
+        thread_count,
+        options
+            .checkpoint
+            .as_ref()
+            .map(|checkpoint| (checkpoint, header)),
+        resume,
+        results_db,
+    )
+}
+
+/// `solve_problems_many_parallel()`, `solve_problems_many_with_options()` の本体。
+fn solve_sweep_parallel(
+    kind: SolverKind,
+    config: &SolveConfig,
+    sweep: &Sweep,
+    thread_count: usize,
+    checkpoint: Option<(&CheckpointConfig, CheckpointHeader)>,
+    resume: Option<Checkpoint>,
+    results_db: Option<ResultsDbWriter>,
+) -> anyhow::Result<Option<SolutionMany>> {
+    assert!(thread_count > 0);
+    kind.ensure_supported(config)?;
+
+    // DP テーブルのメモリ量はスレッド数で等分する。
+    let config_worker = SolveConfig {
+        table_bytes: config.table_bytes / thread_count,
+        ..config.clone()
+    };
+
+    // DP テーブルを保存する場合、チェックポイントの保存時に他のスレッドのソルバーを参照するので共有する。
+    // 各スレッドは盤面を解く間だけ自分のソルバーをロックする。
+    let solvers: Vec<_> = (0..thread_count)
+        .map(|_| Mutex::new(kind.new_solver(&config_worker)))
+        .collect();
+    let table_count = if kind.shares_table(config) {
+        thread_count
+    } else {
+        0
+    };
+
+    let best = SharedBest::new(config.best_score_ini);
+    let mut progress = Progress::new(sweep, 0);
+
+    if let Some(resume) = resume {
+        if resume.table_count != 0 {
+            ensure!(
+                resume.table_count == thread_count,
+                "スレッド数が異なる: {thread_count} (チェックポイントは {})",
+                resume.table_count
+            );
+            let (checkpoint, _) = checkpoint.as_ref().unwrap();
+            Checkpoint::read_tables(&checkpoint.path, |i, r| {
+                solvers[i].lock().unwrap().read_table(r)
+            })?;
+        }
+
+        let idx_start = match resume.cursor {
+            Some((counter, inc_timing, state)) => sweep
+                .index(counter, inc_timing, state)
+                .context("チェックポイントのカーソルが盤面集合外")?,
+            None => sweep.len(),
+        };
+        progress = Progress::new(sweep, idx_start);
+        eprintln!("Resume: {idx_start}/{} boards done", sweep.len());
+
+        if let Some(ans) = resume.best {
+            let idx = sweep
+                .index(ans.rng_counter, ans.rng_inc_timing, ans.rng_state)
+                .context("チェックポイントの最大解が盤面集合外")?;
+            if ans.score > config.best_score_ini {
+                best.update(ans.score, idx);
+                progress.best.replace((idx, ans));
+            }
         }
     }
 
-    fn solve(
-        mut self,
-        states: RangeInclusive<u16>,
-        counters: RangeInclusive<u8>,
-        inc_timings: RangeInclusive<usize>,
-    ) -> SolutionMany {
-        for (counter, inc_timing) in itertools::iproduct!(counters, inc_timings) {
-            self.dp.clear();
+    let idx_start = progress.idx_start;
+    let progress = Mutex::new(progress);
+    let chunk_next = AtomicUsize::new(0);
+    let results_db = results_db.map(Mutex::new);
 
-            for state in states.clone() {
-                // NOTE: gen_board() で盤面生成するので、初期盤面が空のケースは考えなくてよい。
+    let save = |progress: &mut Progress| -> anyhow::Result<()> {
+        // チェックポイントより先に、探索済みの盤面の結果を確実に書き出す。
+        if let Some(results_db) = &results_db {
+            results_db.lock().unwrap().flush()?;
+        }
+        let Some((checkpoint, header)) = &checkpoint else {
+            return Ok(());
+        };
+        progress
+            .to_checkpoint(sweep, header.clone(), table_count)
+            .write(&checkpoint.path, |i, w| {
+                solvers[i].lock().unwrap().write_table(w)
+            })?;
+        progress.saved_at = Instant::now();
+        Ok(())
+    };
+
+    let worker = |solver: &Mutex<Box<dyn Solve + Send>>| {
+        loop {
+            let chunk = chunk_next.fetch_add(1, Ordering::Relaxed);
+            let idx_chunk = idx_start + chunk * SWEEP_CHUNK_LEN;
+            if idx_chunk >= sweep.len() {
+                break;
+            }
+            let idx_end = (idx_chunk + SWEEP_CHUNK_LEN).min(sweep.len());
+
+            for idx in idx_chunk..idx_end {
+                let (counter, inc_timing, state) = sweep.get(idx);
                 let Some(board) = gen_board(state, counter, inc_timing) else {
                     continue;
                 };
-                let pos = Position::new(board);
 
                 eprintln!(
                     "Search: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing}"
                 );
 
-                let (score, solution) = self.solve_one(state, &pos);
-                if chmax!(self.best_score, score) {
+                let threshold = best.threshold(idx);
+                let mut solver = solver.lock().unwrap();
+                let found = match &results_db {
+                    None => {
+                        // 共有の最大解を改善する場合のみ解が返るようにする。
+                        solver.set_best_score(threshold);
+                        solver
+                            .solve(board)
+                            .map(|res| (res.score(), res.into_solution()))
+                    }
+                    Some(results_db) => {
+                        // 全ての盤面の最大スコアを記録するため、共有の最大スコアによる枝刈りは行わない。
+                        solver.set_best_score(0);
+                        let record = match solver.solve(board) {
+                            Some(res) => ResultRecord::new(
+                                state,
+                                counter,
+                                inc_timing,
+                                res.score(),
+                                res.solution().clone(),
+                                res.node_count(),
+                            ),
+                            None => ResultRecord::new(
+                                state,
+                                counter,
+                                inc_timing,
+                                0,
+                                ActionHistory::new(),
+                                0,
+                            ),
+                        };
+                        if let Err(e) = results_db.lock().unwrap().append(&record) {
+                            eprintln!("探索結果の記録に失敗: {e:#}");
+                        }
+                        (record.score() > threshold)
+                            .then(|| (record.score(), record.solution().clone()))
+                    }
+                };
+                drop(solver);
+
+                if let Some((score, solution)) = found {
                     eprintln!("Found {score}: state=0x{state:04X} counter=0x{counter:02X} inc_timing={inc_timing} solution={solution}");
-                    self.best_ans.replace(SolutionMany {
-                        rng_state: state,
-                        rng_counter: counter,
-                        rng_inc_timing: inc_timing,
-                        score,
-                        solution,
-                    });
+                    best.update(score, idx);
+                    progress.lock().unwrap().update_best(
+                        idx,
+                        SolutionMany::new(state, counter, inc_timing, score, solution),
+                    );
                 }
             }
-        }
-
-        self.best_ans.unwrap()
-    }
 
-    fn solve_one(&mut self, time: u16, pos_root: &Position) -> (Score, ActionHistory) {
-        // 浅い探索で見積もったスコア上界が既知の最大スコア以下なら枝刈り。
-        for depth in 0..=3 {
-            if score_upper_bound(pos_root, depth) <= self.best_score {
-                return (0, ActionHistory::new());
+            let mut progress = progress.lock().unwrap();
+            progress.complete_chunk(chunk);
+            if let Some((checkpoint, _)) = &checkpoint {
+                if progress.saved_at.elapsed() >= checkpoint.interval {
+                    // 保存に失敗しても探索は続ける。
+                    if let Err(e) = save(&mut progress) {
+                        eprintln!("チェックポイントの保存に失敗: {e:#}");
+                    }
+                }
             }
         }
+    };
+
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = solvers
+            .iter()
+            .map(|solver| scope.spawn(|| worker(solver)))
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
 
-        self.dp.set_time(time);
+    let mut progress = progress.into_inner().unwrap();
+    save(&mut progress)?;
 
-        let score = self.dfs(pos_root);
-        eprintln!("TT entry count: {}", self.dp.entry_count());
+    Ok(progress.best.map(|(_, ans)| ans))
+}
 
-        // 経路復元。
-        let mut solution = ActionHistory::new();
-        let mut pos = pos_root.clone();
-        loop {
-            let best_action = pos.actions().max_by_key(|action| {
-                let pos_child = pos.do_action(action);
-                let gain_action = score_erase(action.square_count());
-                // 空の盤面は DP テーブルに載らないので例外処理が必要。
-                // それ以外の盤面は DP テーブルに載っているはず。
-                let gain_child = if pos_child.board().is_empty() {
-                    SCORE_PERFECT
-                } else {
-                    let DpTableProbe::Found(score) = self.dp.probe(pos_child.key()) else {
-                        eprintln!("この盤面の DP エントリが見つからない!?");
-                        eprint!("{}", pos_child.board());
-                        unreachable!();
-                    };
-                    score
-                };
-                gain_action + gain_child
-            });
-            let Some(best_action) = best_action else {
-                break;
-            };
-            solution.push(best_action.least_square());
-            pos = pos.do_action(&best_action);
-        }
+/// 並列探索の進捗。
+#[derive(Debug)]
+struct Progress {
+    /// 探索開始位置。
+    idx_start: usize,
+    /// 各チャンクが探索済みかどうか。
+    chunk_dones: Vec<bool>,
+    /// これより前のチャンクは全て探索済み。
+    chunk_frontier: usize,
+    /// 最大解とその盤面の順序。同点の場合は順序が先の盤面を採る。
+    best: Option<(usize, SolutionMany)>,
+    /// 最後にチェックポイントを保存した時刻。
+    saved_at: Instant,
+}
 
-        (score, solution)
-    }
+impl Progress {
+    fn new(sweep: &Sweep, idx_start: usize) -> Self {
+        let chunk_count = (sweep.len() - idx_start).div_ceil(SWEEP_CHUNK_LEN);
 
-    /// `pos` から追加で獲得できる最大スコアを返す。
-    fn dfs(&mut self, pos: &Position) -> Score {
-        // 空の盤面に対する DP エントリが作られないよう、先にパーフェクト判定する。
-        // 他の終了局面については仮作成するエントリの gain_max が 0 なのでそのままでよい。
-        if pos.board().is_empty() {
-            return SCORE_PERFECT;
+        Self {
+            idx_start,
+            chunk_dones: vec![false; chunk_count],
+            chunk_frontier: 0,
+            best: None,
+            saved_at: Instant::now(),
         }
+    }
 
-        let key = pos.key();
-
-        match self.dp.probe(key) {
-            DpTableProbe::Found(gain_max) => gain_max,
-            DpTableProbe::Created(dp_idx) => {
-                let mut gain_max = 0;
-                for action in pos.actions() {
-                    let pos_child = pos.do_action(&action);
-                    let gain_action = score_erase(action.square_count());
-                    let gain_child = self.dfs(&pos_child);
-                    chmax!(gain_max, gain_action + gain_child);
-                }
+    fn complete_chunk(&mut self, chunk: usize) {
+        self.chunk_dones[chunk] = true;
+        while self
+            .chunk_dones
+            .get(self.chunk_frontier)
+            .is_some_and(|&done| done)
+        {
+            self.chunk_frontier += 1;
+        }
+    }
 
-                // 終了局面ならば単に 0 を返す。
-                // DP テーブルに仮作成したエントリの gain_max は 0 なのでそのままでよい。
-                // 終了局面であることと gain_max が 0 であることは同値。
-                if gain_max == 0 {
-                    return 0;
-                }
+    fn update_best(&mut self, idx: usize, ans: SolutionMany) {
+        let improved = self.best.as_ref().is_none_or(|(best_idx, best)| {
+            (ans.score, Reverse(idx)) > (best.score, Reverse(*best_idx))
+        });
+        if improved {
+            self.best.replace((idx, ans));
+        }
+    }
 
-                self.dp.set_gain_max(dp_idx, gain_max);
-                gain_max
-            }
+    fn to_checkpoint(
+        &self,
+        sweep: &Sweep,
+        header: CheckpointHeader,
+        table_count: usize,
+    ) -> Checkpoint {
+        let idx_frontier = self.idx_start + self.chunk_frontier * SWEEP_CHUNK_LEN;
+        let cursor = (idx_frontier < sweep.len()).then(|| sweep.get(idx_frontier));
+
+        Checkpoint {
+            header,
+            cursor,
+            best: self.best.as_ref().map(|(_, ans)| ans.clone()),
+            table_count,
         }
     }
 }
 
-/// 浅い探索を行い、`pos` から追加で獲得しうるスコアの上界を返す。
-fn score_upper_bound(pos: &Position, depth_remain: u32) -> Score {
-    if depth_remain == 0 {
-        return score_upper_bound_leaf(pos);
-    }
+/// 各スレッドに一度に割り当てる盤面数。
+pub(crate) const SWEEP_CHUNK_LEN: usize = 0x100;
 
-    let mut score_ub_max = 0;
-    for action in pos.actions() {
-        let pos_child = pos.do_action(&action);
-        let gain_action = score_erase(action.square_count());
-        let score_ub_child = score_upper_bound(&pos_child, depth_remain - 1);
-        chmax!(score_ub_max, gain_action + score_ub_child);
+/// 盤面集合を `(counter, inc_timing, state)` の辞書順に並べたもの。
+#[derive(Debug)]
+pub(crate) struct Sweep {
+    states: RangeInclusive<u16>,
+    counters: RangeInclusive<u8>,
+    inc_timings: RangeInclusive<usize>,
+}
+
+impl Sweep {
+    pub(crate) fn new(
+        states: RangeInclusive<u16>,
+        counters: RangeInclusive<u8>,
+        inc_timings: RangeInclusive<usize>,
+    ) -> Self {
+        Self {
+            states,
+            counters,
+            inc_timings,
+        }
     }
 
-    // 終了局面ならばパーフェクト判定して値を返す。
-    // 終了局面であることと score_ub_max が 0 であることは同値。
-    if score_ub_max == 0 {
-        return if pos.board().is_empty() {
-            SCORE_PERFECT
-        } else {
-            0
-        };
+    fn state_count(&self) -> usize {
+        usize::from(self.states.end() - self.states.start()) + 1
     }
 
-    score_ub_max
-}
+    fn counter_count(&self) -> usize {
+        usize::from(self.counters.end() - self.counters.start()) + 1
+    }
 
-/// 葉ノードの局面 `pos` から追加で獲得しうるスコアの上界を返す。
-fn score_upper_bound_leaf(pos: &Position) -> Score {
-    // 2 個以上存在する駒種全てが 1 手で全消しできると仮定して上界を求める。
-    // 適宜パーフェクトボーナスも加算する。
-
-    let mut res = 0;
-    let mut perfect = true;
-    for piece in Piece::all() {
-        let count = pos.piece_count(piece);
-        match count {
-            0 => {}
-            1 => perfect = false,
-            _ => res += score_erase(u32::from(count)),
-        }
+    fn inc_timing_count(&self) -> usize {
+        self.inc_timings.end() - self.inc_timings.start() + 1
     }
 
-    if perfect {
-        res += SCORE_PERFECT;
+    pub(crate) fn len(&self) -> usize {
+        self.counter_count() * self.inc_timing_count() * self.state_count()
     }
 
-    res
-}
+    /// `(counter, inc_timing, state)` の順序を返す。盤面集合外なら `None` を返す。
+    fn index(&self, counter: u8, inc_timing: usize, state: u16) -> Option<usize> {
+        if !(self.counters.contains(&counter)
+            && self.inc_timings.contains(&inc_timing)
+            && self.states.contains(&state))
+        {
+            return None;
+        }
 
-const DP_TABLE_CAP_BITS: u32 = 30;
-const DP_TABLE_CAP: usize = 1 << DP_TABLE_CAP_BITS;
-const _: () = assert!(DP_TABLE_CAP.is_power_of_two());
+        let counter = usize::from(counter - self.counters.start());
+        let inc_timing = inc_timing - self.inc_timings.start();
+        let state = usize::from(state - self.states.start());
 
-const KEY_HI_BITS: u32 = 64 - DP_TABLE_CAP_BITS;
-const KEY_HI_SHIFT: u32 = 64 - KEY_HI_BITS;
+        Some((counter * self.inc_timing_count() + inc_timing) * self.state_count() + state)
+    }
 
-fn calc_key_hi(key: u64) -> u64 {
-    key >> KEY_HI_SHIFT
+    /// `idx` 番目の `(counter, inc_timing, state)` を返す。
+    pub(crate) fn get(&self, idx: usize) -> (u8, usize, u16) {
+        let state = idx % self.state_count();
+        let idx = idx / self.state_count();
+        let inc_timing = idx % self.inc_timing_count();
+        let counter = idx / self.inc_timing_count();
+
+        (
+            self.counters.start() + counter as u8,
+            self.inc_timings.start() + inc_timing,
+            self.states.start() + state as u16,
+        )
+    }
 }
 
-/// DP テーブルのエントリ。
+/// スレッド間で共有する最大スコアと、それを達成した盤面の順序。
 ///
-/// * bit 0-15: 世代 (DP テーブルを毎回再初期化せずに済ませるための機構)。
-/// * bit16-27: 1 + (この局面から追加で獲得できる最大スコア)。
-/// * bit28-29: (未使用)
-/// * bit30-63: この局面のハッシュ値の上位部分。
-#[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-struct DpEntry(NonZeroU64);
-
-const _: () = assert!(std::mem::size_of::<Option<DpEntry>>() == 8);
-
-impl DpEntry {
-    const TIME_BITS: u32 = 16;
-    const TIME_MASK: u64 = (1 << Self::TIME_BITS) - 1;
+/// 大小比較がそのまま解の優劣と一致するよう、u64 にパックして `fetch_max` で更新する。
+///
+/// * 上位 16bit: スコア。
+/// * 下位 48bit: `IDX_MASK - (1 + idx)`。初期値は `idx = -1` 相当とする。
+///   (つまり初期スコアと同点の盤面は改善とみなさない)
+#[derive(Debug)]
+struct SharedBest(AtomicU64);
 
-    const GAIN_MAX_BITS: u32 = 12;
-    const GAIN_MAX_SHIFT: u32 = 16;
-    const GAIN_MAX_MASK: u64 = ((1 << Self::GAIN_MAX_BITS) - 1) << Self::GAIN_MAX_SHIFT;
+impl SharedBest {
+    const IDX_BITS: u32 = 48;
+    const IDX_MASK: u64 = (1 << Self::IDX_BITS) - 1;
 
-    const KEY_HI_MASK: u64 = ((1 << KEY_HI_BITS) - 1) << KEY_HI_SHIFT;
+    fn new(best_score_ini: Score) -> Self {
+        Self(AtomicU64::new(Self::pack(best_score_ini, None)))
+    }
 
-    fn new(time: u16, key: u64, gain_max: Score) -> Self {
-        let value_time = u64::from(time);
-        let value_gain_max = u64::from(1 + gain_max) << Self::GAIN_MAX_SHIFT;
-        let value_key = key & Self::KEY_HI_MASK;
-        let value = value_time | value_gain_max | value_key;
+    fn pack(score: Score, idx: Option<usize>) -> u64 {
+        debug_assert!(score < 1 << (64 - Self::IDX_BITS));
 
-        Self(unsafe { NonZeroU64::new_unchecked(value) })
+        let value_idx = Self::IDX_MASK - idx.map_or(0, |idx| 1 + idx as u64);
+        (u64::from(score) << Self::IDX_BITS) | value_idx
     }
 
-    fn time(self) -> u16 {
-        (self.0.get() & Self::TIME_MASK) as u16
-    }
+    fn unpack(value: u64) -> (Score, Option<usize>) {
+        let score = (value >> Self::IDX_BITS) as Score;
+        let idx = (Self::IDX_MASK - (value & Self::IDX_MASK)).checked_sub(1);
 
-    fn gain_max(self) -> Score {
-        (((self.0.get() & Self::GAIN_MAX_MASK) >> Self::GAIN_MAX_SHIFT) - 1) as Score
+        (score, idx.map(|idx| idx as usize))
     }
 
-    fn set_gain_max(&mut self, gain_max: Score) {
-        let value_gain_max = u64::from(1 + gain_max) << Self::GAIN_MAX_SHIFT;
-        let value = (self.0.get() & !Self::GAIN_MAX_MASK) | value_gain_max;
+    /// 順序 `idx` の盤面を解く際、ソルバーに設定すべき最大スコアを返す。
+    /// ソルバーがこれより大きいスコアを見つけたならば、それは共有の最大解の改善となる。
+    fn threshold(&self, idx: usize) -> Score {
+        let (score, best_idx) = Self::unpack(self.0.load(Ordering::Relaxed));
 
-        self.0 = unsafe { NonZeroU64::new_unchecked(value) };
+        match best_idx {
+            // 順序が先の盤面は同点でも改善となる。
+            // (この場合 score は何らかの盤面で達成されているので正である)
+            Some(best_idx) if idx < best_idx => score - 1,
+            _ => score,
+        }
     }
 
-    fn key_hi(self) -> u64 {
-        self.0.get() >> KEY_HI_SHIFT
+    fn update(&self, score: Score, idx: usize) {
+        self.0
+            .fetch_max(Self::pack(score, Some(idx)), Ordering::Relaxed);
     }
 }
 
-/// DP テーブル。
+fn gen_board(state: u16, counter: u8, inc_timing: usize) -> Option<Board> {
+    GameRng::new(state).gen_board(counter, inc_timing)
+}
+
+/// 浅い探索で見積もったスコア上界により根で枝刈りを行い、残った盤面のみ全探索するソルバー。
 ///
-/// 世代情報を用いることで、配列を再初期化することなく 0x10000 個の問題を続けて解ける。
+/// 大量の盤面を解く際、大半の盤面は全探索せずに済む。
 #[derive(Debug)]
-struct DpTable {
-    time: u16,
-    entry_count: usize,
-    array: Box<[Option<DpEntry>; DP_TABLE_CAP]>,
+pub struct ManySolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    score_bound: ScoreBound,
+    exact: ExactSolver<W, H, P>,
 }
 
-impl DpTable {
-    const INDEX_MASK: usize = DP_TABLE_CAP - 1;
-
-    fn new() -> Self {
+impl<const W: usize, const H: usize, const P: usize> ManySolver<W, H, P> {
+    pub fn new(config: &SolveConfig) -> Self {
         Self {
-            time: 0,
-            entry_count: 0,
-            array: vec![None; DP_TABLE_CAP].try_into().unwrap(),
+            score_bound: config.score_bound,
+            exact: ExactSolver::new(config),
         }
     }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for ManySolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let pos_root = Position::new(board);
 
-    /// 現在の世代を返す。
-    #[allow(dead_code)]
-    fn time(&self) -> u16 {
-        self.time
+        // 浅い探索で見積もったスコア上界が既知の最大スコア以下なら枝刈り。
+        for depth in 0..=3 {
+            if score_upper_bound(&pos_root, depth, self.score_bound) <= self.best_score() {
+                return None;
+            }
+        }
+
+        self.exact.solve_position(&pos_root)
     }
 
-    /// 現在の世代におけるエントリ数を返す。
-    fn entry_count(&self) -> usize {
-        self.entry_count
+    fn best_score(&self) -> Score {
+        self.exact.best_score()
     }
 
-    /// テーブル全体をクリアする。世代は 0 に戻る。
-    fn clear(&mut self) {
-        self.time = 0;
-        self.entry_count = 0;
-        self.array.fill(None);
+    fn set_best_score(&mut self, best_score: Score) {
+        self.exact.set_best_score(best_score);
     }
 
-    /// 世代を更新する。
-    fn set_time(&mut self, time: u16) {
-        self.time = time;
-        self.entry_count = 0;
+    fn write_table(&self, w: &mut dyn Write) -> anyhow::Result<()> {
+        self.exact.write_table(w)
     }
 
-    /// 現在の世代においてハッシュ値 `key` に対応するエントリを探す。
-    ///
-    /// エントリが既に存在する場合、その値 (gain_max) を返す。
-    /// エントリがまだ存在しない場合、仮の値でエントリを作成し、そのインデックスを返す。
-    fn probe(&mut self, key: u64) -> DpTableProbe {
-        // linear probe
-
-        // key に対応する局面が終了局面の場合、盤面が空でないなら仮作成したエントリはそのままにできる。
-        // (gain_max を 0 として仮作成するので)
-        // しかし、盤面が空の場合は仮作成してしまうとエントリの値が正しくなくなる。
-        //
-        // これを安直に解決するならハッシュ値 0 に対して SCORE_PERFECT を返すようにすればよいが、
-        // 偶然ハッシュ値が 0 の空でない盤面が生じてしまうとほぼ確実に解がおかしくなる。
-        //
-        // というわけで、一応 Solver 側で空の盤面に対する例外処理を行い、
-        // 空の盤面は DP テーブルに載らないようにしておく。
-
-        let mut idx = key as usize & Self::INDEX_MASK;
-        loop {
-            let entry = unsafe { self.array.get_unchecked_mut(idx) };
-
-            macro_rules! return_created {
-                () => {{
-                    self.entry_count += 1;
-                    /*
-                    if self.entry_count.is_power_of_two() {
-                        eprintln!("TT entry count: {}", self.entry_count);
-                    }
-                    */
-                    entry.replace(DpEntry::new(self.time, key, 0));
-                    return DpTableProbe::Created(idx);
-                }};
-            }
+    fn read_table(&mut self, r: &mut dyn Read) -> anyhow::Result<()> {
+        self.exact.read_table(r)
+    }
+}
 
-            match entry {
-                None => return_created!(),
-                Some(entry) if entry.time() != self.time => return_created!(),
-                Some(entry) if entry.key_hi() == calc_key_hi(key) => {
-                    return DpTableProbe::Found(entry.gain_max());
-                }
-                _ => idx = idx.wrapping_add(1) & Self::INDEX_MASK,
-            }
-        }
+/// 浅い探索を行い、`pos` から追加で獲得しうるスコアの上界を返す。
+///
+/// 葉ノードでは `score_bound` により上界を見積もる。
+fn score_upper_bound<const W: usize, const H: usize, const P: usize>(
+    pos: &Position<W, H, P>,
+    depth_remain: u32,
+    score_bound: ScoreBound,
+) -> Score {
+    if depth_remain == 0 {
+        return score_bound.eval(pos);
     }
 
-    /// `probe()` で仮作成したエントリの値を `gain_max` に設定する。
-    fn set_gain_max(&mut self, idx: usize, gain_max: Score) {
-        let entry = unsafe { self.array.get_unchecked_mut(idx) };
-        let entry = unsafe { entry.as_mut().unwrap_unchecked() };
+    let mut score_ub_max = 0;
+    for action in pos.actions() {
+        let pos_child = pos.do_action(&action);
+        let gain_action = score_erase(action.square_count());
+        let score_ub_child = score_upper_bound(&pos_child, depth_remain - 1, score_bound);
+        chmax!(score_ub_max, gain_action + score_ub_child);
+    }
 
-        entry.set_gain_max(gain_max);
+    // 終了局面ならばパーフェクト判定して値を返す。
+    // 終了局面であることと score_ub_max が 0 であることは同値。
+    if score_ub_max == 0 {
+        return if pos.board().is_empty() {
+            SCORE_PERFECT
+        } else {
+            0
+        };
     }
+
+    score_ub_max
 }
 
-#[derive(Debug)]
-enum DpTableProbe {
-    Found(Score),
-    Created(usize),
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::results_db::ResultsDb;
+    use crate::solver2::Solver;
+    use crate::solver_beam::BeamConfig;
+
+    use super::*;
+
+    #[test]
+    fn test_sweep() {
+        let sweep = Sweep::new(0xFFFE..=0xFFFF, 3..=5, 39..=40);
+        let expect: Vec<_> = itertools::iproduct!(3..=5, 39..=40, 0xFFFE..=0xFFFF).collect();
+
+        assert_eq!(sweep.len(), expect.len());
+        for (idx, &e) in expect.iter().enumerate() {
+            assert_eq!(sweep.get(idx), e);
+            assert_eq!(sweep.index(e.0, e.1, e.2), Some(idx));
+        }
+        assert_eq!(sweep.index(2, 39, 0xFFFE), None);
+        assert_eq!(sweep.index(3, 41, 0xFFFE), None);
+        assert_eq!(sweep.index(3, 39, 0xFFFD), None);
+    }
+
+    #[test]
+    fn test_shared_best() {
+        let best = SharedBest::new(100);
+        assert_eq!(best.threshold(0), 100);
+
+        best.update(150, 10);
+        assert_eq!(best.threshold(9), 149);
+        assert_eq!(best.threshold(10), 150);
+        assert_eq!(best.threshold(11), 150);
+
+        // 同点で順序が後の盤面は無視される。
+        best.update(150, 20);
+        assert_eq!(best.threshold(9), 149);
+        assert_eq!(best.threshold(15), 150);
+
+        // 同点で順序が先の盤面は採用される。
+        best.update(150, 5);
+        assert_eq!(best.threshold(5), 150);
+        assert_eq!(best.threshold(4), 149);
+
+        best.update(200, 30);
+        assert_eq!(best.threshold(29), 199);
+        assert_eq!(best.threshold(30), 200);
+    }
+
+    #[test]
+    fn test_solve_problems_many_parallel() {
+        let states = 0..=0x3F;
+        let counters = 0..=0;
+        let inc_timings = 39..=40;
+        // 最大スコア (555) に近い初期値を与え、枝刈りの強いソルバーで試す。
+        let config = SolveConfig {
+            best_score_ini: 540,
+            table_bytes: 1 << 20,
+            score_bound: ScoreBound::Separation,
+            ..Default::default()
+        };
+
+        let expect = solve_problems_many_with(
+            &mut Solver::new(&config),
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+        );
+        assert_eq!(expect.as_ref().map(SolutionMany::score), Some(555));
+
+        for thread_count in [1, 3] {
+            let actual = solve_problems_many_parallel(
+                SolverKind::Bound,
+                &config,
+                states.clone(),
+                counters.clone(),
+                inc_timings.clone(),
+                thread_count,
+            );
+            assert_eq!(actual, expect);
+        }
+    }
+
+    #[test]
+    fn test_solve_problems_many_resumable() {
+        let states = 0..=0x3F;
+        let counters = 0..=0;
+        let inc_timings = 39..=40;
+        // 高速なソルバーで試す。
+        let config = SolveConfig {
+            table_bytes: 1 << 20,
+            beam: BeamConfig {
+                width: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let solve_resumable = |checkpoint: &CheckpointConfig| {
+            solve_problems_many_resumable(
+                SolverKind::Beam,
+                &config,
+                states.clone(),
+                counters.clone(),
+                inc_timings.clone(),
+                2,
+                checkpoint,
+            )
+        };
+
+        let expect = solve_problems_many_parallel(
+            SolverKind::Beam,
+            &config,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+        );
+        assert!(expect.is_some());
+
+        let checkpoint = CheckpointConfig {
+            path: std::env::temp_dir().join(format!(
+                "samegame-sfc-small-test-resumable-{}.bin",
+                std::process::id()
+            )),
+            interval: Duration::ZERO,
+        };
+
+        // チェックポイントファイルがなければ最初から探索する。
+        assert_eq!(solve_resumable(&checkpoint).unwrap(), expect);
+
+        // 探索済みのチェックポイントから再開すると、保存された最大解をそのまま返す。
+        let ckpt = Checkpoint::read(&checkpoint.path).unwrap();
+        assert_eq!(ckpt.cursor, None);
+        assert_eq!(ckpt.best, expect);
+        assert_eq!(solve_resumable(&checkpoint).unwrap(), expect);
+
+        // 途中のチェックポイントから再開しても結果は変わらない。
+        let best_prefix = solve_problems_many_parallel(
+            SolverKind::Beam,
+            &config,
+            states.clone(),
+            counters.clone(),
+            39..=39,
+            1,
+        );
+        Checkpoint {
+            cursor: Some((0, 40, 0)),
+            best: best_prefix,
+            ..ckpt
+        }
+        .write(&checkpoint.path, |_, _| unreachable!())
+        .unwrap();
+        assert_eq!(solve_resumable(&checkpoint).unwrap(), expect);
+
+        // 設定が異なれば再開できない。
+        let config_other = SolveConfig {
+            table_bytes: 1 << 21,
+            ..config.clone()
+        };
+        assert!(solve_problems_many_resumable(
+            SolverKind::Beam,
+            &config_other,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+            &checkpoint,
+        )
+        .is_err());
+        assert!(solve_problems_many_resumable(
+            SolverKind::Mcts,
+            &config,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+            &checkpoint,
+        )
+        .is_err());
+
+        // DP テーブルの共有の有無が異なれば再開できない。
+        let config_share = SolveConfig {
+            share_table: true,
+            ..config.clone()
+        };
+        assert!(solve_problems_many_resumable(
+            SolverKind::Beam,
+            &config_share,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+            &checkpoint,
+        )
+        .is_err());
+        std::fs::remove_file(&checkpoint.path).unwrap();
+
+        // DP テーブルを共有する探索では、各スレッドの DP テーブルも保存して再開する。
+        // (時間がかからないよう、スコア上界による枝刈りで全ての盤面の探索を省く)
+        let config_share = SolveConfig {
+            best_score_ini: 10000,
+            table_bytes: 1 << 20,
+            share_table: true,
+            ..Default::default()
+        };
+        let solve_share = |thread_count| {
+            solve_problems_many_resumable(
+                SolverKind::Many,
+                &config_share,
+                states.clone(),
+                counters.clone(),
+                inc_timings.clone(),
+                thread_count,
+                &checkpoint,
+            )
+        };
+        assert_eq!(solve_share(2).unwrap(), None);
+        let ckpt = Checkpoint::read(&checkpoint.path).unwrap();
+        assert_eq!(ckpt.table_count, 2);
+        let solver_share = SolverKind::Many.new_solver::<8, 6, 5>(&SolveConfig {
+            table_bytes: config_share.table_bytes / 2,
+            ..config_share.clone()
+        });
+        Checkpoint {
+            cursor: Some((0, 40, 0)),
+            ..ckpt
+        }
+        .write(&checkpoint.path, |_, w| solver_share.write_table(w))
+        .unwrap();
+        assert_eq!(solve_share(2).unwrap(), None);
+
+        // スレッド数が異なると各スレッドの DP テーブルの容量が変わるので再開できない。
+        assert!(solve_share(1).is_err());
+
+        std::fs::remove_file(&checkpoint.path).unwrap();
+    }
+
+    #[test]
+    fn test_solve_problems_many_results_db() {
+        let states = 0..=0x1F;
+        let counters = 0..=0;
+        let inc_timings = 39..=40;
+        let config = SolveConfig {
+            table_bytes: 1 << 20,
+            beam: BeamConfig {
+                width: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let expect = solve_problems_many_parallel(
+            SolverKind::Beam,
+            &config,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+        )
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "samegame-sfc-small-test-sweep-results-{}.bin",
+            std::process::id()
+        ));
+        let options = SweepOptions {
+            results_db: Some(path.clone()),
+            ..Default::default()
+        };
+        let ans = solve_problems_many_with_options(
+            SolverKind::Beam,
+            &config,
+            states.clone(),
+            counters.clone(),
+            inc_timings.clone(),
+            2,
+            &options,
+        )
+        .unwrap();
+        assert_eq!(ans.as_ref(), Some(&expect));
+
+        // 全ての盤面の結果が記録される。
+        let db = ResultsDb::read(&path).unwrap();
+        let sweep = Sweep::new(states, counters, inc_timings);
+        let board_count = (0..sweep.len())
+            .filter(|&idx| {
+                let (counter, inc_timing, state) = sweep.get(idx);
+                gen_board(state, counter, inc_timing).is_some()
+            })
+            .count();
+        assert_eq!(db.records().len(), board_count);
+
+        let top = db.top(1)[0];
+        assert_eq!(top.score(), expect.score());
+        let record = db
+            .get(
+                expect.rng_state(),
+                expect.rng_counter(),
+                expect.rng_inc_timing(),
+            )
+            .unwrap();
+        assert_eq!(record.score(), expect.score());
+        assert_eq!(record.solution(), expect.solution());
+
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,380 @@
+//! モンテカルロ木探索による近似ソルバー。
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use rand::{rngs::StdRng, seq::SliceRandom as _, SeedableRng as _};
+
+use crate::action::{Action, ActionHistory};
+use crate::board::Board;
+use crate::piece::Piece;
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::solve::{Solve, SolveConfig, SolveResult, TableStats};
+use crate::util::chmax;
+use crate::zobrist::Key;
+
+/// モンテカルロ木探索の設定。
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MctsConfig {
+    /// 乱数のシード。制限時間を指定しなければ、同じシードに対して結果は常に同じになる。
+    pub seed: u64,
+
+    /// 1 つの盤面に対して行うプレイアウトの回数。
+    pub playout_count: u64,
+
+    /// 1 つの盤面の探索にかける時間の上限。
+    pub time_limit: Option<Duration>,
+
+    /// プレイアウトの方策。
+    pub policy: PlayoutPolicy,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            playout_count: 10000,
+            time_limit: None,
+            policy: PlayoutPolicy::TabuColor,
+        }
+    }
+}
+
+/// プレイアウトの方策。
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PlayoutPolicy {
+    /// 合法手から一様ランダムに選ぶ。
+    Random,
+    /// プレイアウト開始時点で最も多い駒種を「タブー」とし、それ以外の駒種の合法手から一様ランダムに選ぶ。
+    /// そのような合法手がなければ全ての合法手から選ぶ。
+    ///
+    /// 最も多い駒種を最後にまとめて消す手順を優先することになる。
+    TabuColor,
+}
+
+impl PlayoutPolicy {
+    /// 局面 `pos` から終了局面までプレイアウトし、追加で獲得したスコアを返す。手順は `history` に追加する。
+    fn playout<const W: usize, const H: usize, const P: usize>(
+        self,
+        pos: &Position<W, H, P>,
+        rng: &mut StdRng,
+        history: &mut ActionHistory<W, H>,
+    ) -> Score {
+        let tabu = match self {
+            Self::Random => None,
+            Self::TabuColor => Piece::all().max_by_key(|&piece| pos.piece_count(piece)),
+        };
+
+        let mut pos = pos.clone();
+        let mut gain = 0;
+        let mut actions = Vec::<Action<W, H, P>>::new();
+        loop {
+            actions.clear();
+            actions.extend(pos.actions());
+            if actions.is_empty() {
+                break;
+            }
+
+            let candidates: Vec<_> = actions
+                .iter()
+                .filter(|action| Some(action.piece()) != tabu)
+                .collect();
+            let action = match candidates.choose(rng) {
+                Some(&action) => action,
+                None => actions.choose(rng).unwrap(),
+            };
+
+            gain += score_erase(action.square_count());
+            history.push(action.least_square());
+            pos = pos.do_action(action);
+        }
+
+        if pos.board().is_empty() {
+            gain += SCORE_PERFECT;
+        }
+
+        gain
+    }
+}
+
+impl std::str::FromStr for PlayoutPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "random" => Ok(Self::Random),
+            "tabu-color" => Ok(Self::TabuColor),
+            _ => bail!("プレイアウト方策が無効: '{s}' (random, tabu-color のいずれか)"),
+        }
+    }
+}
+
+impl std::fmt::Display for PlayoutPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Random => "random",
+            Self::TabuColor => "tabu-color",
+        };
+        f.write_str(s)
+    }
+}
+
+/// UCB における探索項の係数 (報酬はその時点での最大スコアで正規化する)。
+const EXPLORATION: f64 = 0.3;
+
+/// モンテカルロ木探索 (UCT) により近似解を求めるソルバー。
+///
+/// 同一の局面 (ハッシュ値で判定) の統計情報は置換表で共有する。
+/// 各局面の統計情報は、その局面から追加で獲得したスコアの合計と訪問回数からなる。
+///
+/// 近似ソルバーなので、解が見つからなくても従来より大きいスコアが存在しないとは限らない。
+/// 得られたスコアは厳密ソルバーの `SolveConfig::best_score_ini` に用いることもできる
+/// (厳密ソルバーは `best_score_ini` より大きいスコアのみを探索するので、1 を引いて与えること)。
+///
+/// `SolveResult::table_stats()` は、エントリ数として置換表のエントリ数を返す (容量は 0)。
+#[derive(Debug)]
+pub struct MctsSolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    best_score: Score,
+    config: MctsConfig,
+}
+
+impl<const W: usize, const H: usize, const P: usize> MctsSolver<W, H, P> {
+    pub fn new(config: &SolveConfig) -> Self {
+        Self {
+            best_score: config.best_score_ini,
+            config: config.mcts.clone(),
+        }
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for MctsSolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let mut search = MctsSearch::new(&self.config, self.best_score);
+        let pos_root = Position::new(board);
+
+        let start = Instant::now();
+        let mut playout_count = 0;
+        while playout_count < self.config.playout_count {
+            if self
+                .config
+                .time_limit
+                .is_some_and(|limit| start.elapsed() >= limit)
+            {
+                break;
+            }
+            search.iterate(&pos_root);
+            playout_count += 1;
+        }
+
+        eprintln!("MCTS playout count: {playout_count}");
+
+        let (score, solution) = search.best?;
+        self.best_score = score;
+
+        let table_stats = TableStats::new(search.tt.len(), 0, 0);
+        Some(SolveResult::new(
+            score,
+            solution,
+            search.node_count,
+            table_stats,
+        ))
+    }
+
+    fn best_score(&self) -> Score {
+        self.best_score
+    }
+
+    fn set_best_score(&mut self, best_score: Score) {
+        self.best_score = best_score;
+    }
+}
+
+#[derive(Debug)]
+struct MctsSearch<'a, const W: usize, const H: usize, const P: usize> {
+    config: &'a MctsConfig,
+    rng: StdRng,
+    node_count: u64,
+    tt: HashMap<Key, NodeStats>,
+    /// 見つかった最大スコア (正規化に用いる)。
+    score_max: Score,
+    /// これより大きいスコアのみを解として記録する。
+    best_score: Score,
+    best: Option<(Score, ActionHistory<W, H>)>,
+}
+
+/// 置換表のエントリ。
+#[derive(Clone, Copy, Debug, Default)]
+struct NodeStats {
+    visit_count: u32,
+    /// この局面から追加で獲得したスコアの合計。
+    gain_sum: u64,
+}
+
+impl NodeStats {
+    fn gain_mean(self) -> f64 {
+        self.gain_sum as f64 / f64::from(self.visit_count)
+    }
+}
+
+impl<'a, const W: usize, const H: usize, const P: usize> MctsSearch<'a, W, H, P> {
+    fn new(config: &'a MctsConfig, best_score: Score) -> Self {
+        Self {
+            config,
+            rng: StdRng::seed_from_u64(config.seed),
+            node_count: 0,
+            tt: HashMap::new(),
+            score_max: 0,
+            best_score,
+            best: None,
+        }
+    }
+
+    /// 選択、展開、プレイアウト、逆伝播を 1 回ずつ行う。
+    fn iterate(&mut self, pos_root: &Position<W, H, P>) {
+        // 経路上の各局面の (ハッシュ値, 根からのスコア)。
+        let mut path = vec![(pos_root.key(), 0)];
+        let mut history = ActionHistory::new();
+        let mut pos = pos_root.clone();
+        let mut score = 0;
+
+        let score_final = loop {
+            let children: Vec<_> = pos
+                .actions()
+                .map(|action| {
+                    let pos_child = pos.do_action(&action);
+                    let stats = self.tt.get(&pos_child.key()).copied().unwrap_or_default();
+                    (action, pos_child, stats)
+                })
+                .collect();
+
+            if children.is_empty() {
+                break score
+                    + if pos.board().is_empty() {
+                        SCORE_PERFECT
+                    } else {
+                        0
+                    };
+            }
+
+            // 未訪問の子があればそれを展開し、プレイアウトする。
+            let unvisited: Vec<_> = children
+                .iter()
+                .filter(|(_, _, stats)| stats.visit_count == 0)
+                .collect();
+            if let Some(&(action, pos_child, _)) = unvisited.choose(&mut self.rng) {
+                score += score_erase(action.square_count());
+                history.push(action.least_square());
+                path.push((pos_child.key(), score));
+                let gain = self
+                    .config
+                    .policy
+                    .playout(pos_child, &mut self.rng, &mut history);
+                break score + gain;
+            }
+
+            // 全ての子が訪問済みなら UCB が最大の子を選ぶ。
+            let visit_count_parent = self.tt[&pos.key()].visit_count;
+            let norm = f64::from(self.score_max.max(1));
+            let ucb = |(action, _, stats): &(Action<W, H, P>, Position<W, H, P>, NodeStats)| {
+                let gain = f64::from(score_erase(action.square_count())) + stats.gain_mean();
+                gain / norm
+                    + EXPLORATION
+                        * (f64::from(visit_count_parent).ln() / f64::from(stats.visit_count)).sqrt()
+            };
+            let (action, pos_child, _) = children
+                .iter()
+                .max_by(|lhs, rhs| ucb(lhs).total_cmp(&ucb(rhs)))
+                .unwrap();
+
+            score += score_erase(action.square_count());
+            history.push(action.least_square());
+            path.push((pos_child.key(), score));
+            pos = pos_child.clone();
+        };
+
+        // プレイアウトを含め、経路上の局面数をノード数とする。
+        self.node_count += 1 + history.len() as u64;
+
+        for (key, score) in path {
+            let stats = self.tt.entry(key).or_default();
+            stats.visit_count += 1;
+            stats.gain_sum += u64::from(score_final - score);
+        }
+
+        chmax!(self.score_max, score_final);
+        if chmax!(self.best_score, score_final) {
+            eprintln!("MCTS: found {score_final}");
+            self.best.replace((score_final, history));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::replay::verify;
+    use crate::solver::solve_problem_with;
+    use crate::solver2::Solver;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    fn board_medium() -> Board {
+        parse_board(indoc! {"
+            ........
+            32321131
+            23121233
+            31233222
+            12223221
+            33232222
+        "})
+    }
+
+    #[test]
+    fn test_playout_policy_parse() {
+        for policy in [PlayoutPolicy::Random, PlayoutPolicy::TabuColor] {
+            assert_eq!(policy.to_string().parse::<PlayoutPolicy>().unwrap(), policy);
+        }
+        assert!("foo".parse::<PlayoutPolicy>().is_err());
+    }
+
+    #[test]
+    fn test_mcts_solver() {
+        let board = board_medium();
+        let config_new = |mcts| SolveConfig {
+            table_bytes: 1 << 20,
+            mcts,
+            ..Default::default()
+        };
+        let (score_exact, _) = solve_problem_with(board.clone(), &config_new(Default::default()));
+
+        for policy in [PlayoutPolicy::Random, PlayoutPolicy::TabuColor] {
+            let config = config_new(MctsConfig {
+                playout_count: 300,
+                policy,
+                ..Default::default()
+            });
+            let res = MctsSolver::new(&config).solve(board.clone()).unwrap();
+            assert!(res.score() <= score_exact);
+            assert_eq!(verify(board.clone(), res.solution()), Ok(res.score()));
+
+            // 同じシードなら同じ結果になる。
+            let res_again = MctsSolver::new(&config).solve(board.clone()).unwrap();
+            assert_eq!(res_again, res);
+
+            // 得られたスコアを厳密ソルバーの最大スコアの初期値に用いる。
+            let config_exact = SolveConfig {
+                best_score_ini: res.score() - 1,
+                ..config.clone()
+            };
+            let res_exact = Solver::new(&config_exact).solve(board.clone()).unwrap();
+            assert_eq!(res_exact.score(), score_exact);
+        }
+    }
+}
@@ -0,0 +1,540 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::action::Action;
+use crate::board::Board;
+use crate::collision::CollisionCheck;
+use crate::position::Position;
+use crate::score::{score_erase, Score, SCORE_PERFECT};
+use crate::sleep_set::{ActionFootprint, ActionValues, ActionValuesStack, ExactSleepSet};
+use crate::solve::{
+    reconstruct_solution, Solve, SolveConfig, SolveResult, TableFullPolicy, TableStats,
+};
+use crate::solver::DpEntry;
+use crate::util::chmax;
+
+/// 全探索 + メモ化を複数スレッドで行うソルバー (lazy SMP)。
+///
+/// 各スレッドは根から独立に全探索を行うが、DP テーブルは全スレッドで共有する。
+/// スレッドごとに着手の順序をずらし、さらに他のスレッドが探索中の子局面は後回しにすることで、
+/// なるべく異なる部分木を担当させる (後回しにした子局面は、その間に DP テーブルに載っていることが多い)。
+/// 各スレッドの探索結果は厳密値なので、どのスレッドが最初に終わっても最大スコアが得られる。
+#[derive(Debug)]
+pub struct ParallelSolver<const W: usize = 8, const H: usize = 6, const P: usize = 5> {
+    best_score: Score,
+    thread_count: usize,
+    partial_order_reduction: bool,
+    dp: ConcurrentDpTable<W, H>,
+    busy: BusyTable,
+}
+
+impl<const W: usize, const H: usize, const P: usize> ParallelSolver<W, H, P> {
+    /// `config.thread_count` 個のスレッドで探索するソルバーを作る。
+    ///
+    /// ハッシュ衝突の検出には対応していないので、`config.collision_check` は `CollisionCheck::None` でなければならない
+    /// (`SolverKind::ensure_supported()` で事前に確かめられる)。
+    pub fn new(config: &SolveConfig) -> Self {
+        assert!(config.thread_count > 0);
+        assert_eq!(
+            config.collision_check,
+            CollisionCheck::None,
+            "ParallelSolver はハッシュ衝突の検出に対応していない"
+        );
+
+        Self {
+            best_score: config.best_score_ini,
+            thread_count: config.thread_count,
+            partial_order_reduction: config.partial_order_reduction,
+            dp: ConcurrentDpTable::new(config.table_capacity::<W, H>(), config.table_full_policy),
+            busy: BusyTable::new(),
+        }
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> Solve<W, H, P> for ParallelSolver<W, H, P> {
+    fn solve(&mut self, board: Board<W, H, P>) -> Option<SolveResult<W, H>> {
+        let pos_root = Position::new(board);
+
+        // 初期盤面が空の場合について考えたくないので、先に処理してしまう。
+        let (score, node_count) = if pos_root.board().is_empty() {
+            (SCORE_PERFECT, 0)
+        } else {
+            let res = self.search(&pos_root);
+            eprintln!("TT entry count: {}", self.dp.entry_count());
+            res
+        };
+
+        let res = chmax!(self.best_score, score).then(|| {
+            // エントリが置き換えられていたらシングルスレッドで再探索する。
+            let finished = AtomicBool::new(false);
+            let mut worker = Worker {
+                thread_idx: 0,
+                node_count: 0,
+                partial_order_reduction: self.partial_order_reduction,
+                values_stack: ActionValuesStack::default(),
+                finished: &finished,
+                dp: &self.dp,
+                busy: &self.busy,
+            };
+            let solution = reconstruct_solution(&pos_root, |pos| {
+                self.dp
+                    .get(pos.key())
+                    .unwrap_or_else(|| worker.dfs_root(pos))
+            });
+            let node_count = node_count + worker.node_count;
+            let table_stats = TableStats::new(self.dp.entry_count(), self.dp.capacity(), 0);
+            SolveResult::new(score, solution, node_count, table_stats)
+        });
+
+        self.dp.increment_time();
+
+        res
+    }
+
+    fn best_score(&self) -> Score {
+        self.best_score
+    }
+
+    fn set_best_score(&mut self, best_score: Score) {
+        self.best_score = best_score;
+    }
+}
+
+impl<const W: usize, const H: usize, const P: usize> ParallelSolver<W, H, P> {
+    /// 空でない盤面を持つ局面 `pos_root` の最大スコアと、全スレッドの探索ノード数の合計を返す。
+    fn search(&self, pos_root: &Position<W, H, P>) -> (Score, u64) {
+        let finished = AtomicBool::new(false);
+
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..self.thread_count)
+                .map(|thread_idx| {
+                    let finished = &finished;
+                    scope.spawn(move || {
+                        let mut worker = Worker {
+                            thread_idx,
+                            node_count: 0,
+                            partial_order_reduction: self.partial_order_reduction,
+                            values_stack: ActionValuesStack::default(),
+                            finished,
+                            dp: &self.dp,
+                            busy: &self.busy,
+                        };
+                        let score = worker.dfs_root(pos_root);
+                        // 最初に探索を完了したスレッドが他のスレッドを止める。
+                        let completed = !finished.swap(true, Ordering::Relaxed);
+                        (completed.then_some(score), worker.node_count)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let node_count = results.iter().map(|&(_, node_count)| node_count).sum();
+        let score = results.into_iter().find_map(|(score, _)| score).unwrap();
+
+        (score, node_count)
+    }
+}
+
+#[derive(Debug)]
+struct Worker<'a, const W: usize, const H: usize> {
+    thread_idx: usize,
+    node_count: u64,
+    partial_order_reduction: bool,
+    values_stack: ActionValuesStack<W, H>,
+    finished: &'a AtomicBool,
+    dp: &'a ConcurrentDpTable<W, H>,
+    busy: &'a BusyTable,
+}
+
+impl<const W: usize, const H: usize> Worker<'_, W, H> {
+    /// `pos` から追加で獲得できる最大スコアを返す。
+    ///
+    /// 他のスレッドが探索を完了した場合は打ち切り、不正確な値を返す。
+    fn dfs_root<const P: usize>(&mut self, pos: &Position<W, H, P>) -> Score {
+        let gain_max = self.dfs(pos, &ActionValues::default());
+        self.values_stack.clear();
+        gain_max
+    }
+
+    /// `pos` から追加で獲得できる最大スコアを返す。
+    ///
+    /// `sleep` 内の着手は探索せず、その値を用いる (`ExactSleepSet` を参照)。
+    /// `pos` を探索した場合、各着手の値を `values_stack` に積む。
+    ///
+    /// 他のスレッドが探索を完了した場合は打ち切り、不正確な値を返す。
+    /// 打ち切った局面の値は DP テーブルに書き込まない。
+    fn dfs<const P: usize>(
+        &mut self,
+        pos: &Position<W, H, P>,
+        sleep: &ActionValues<W, H>,
+    ) -> Score {
+        self.node_count += 1;
+
+        // 空の盤面は DP テーブルに載せない。
+        if pos.board().is_empty() {
+            return SCORE_PERFECT;
+        }
+
+        let key = pos.key();
+
+        if let Some(gain_max) = self.dp.get(key) {
+            return gain_max;
+        }
+
+        let marked = self.busy.enter(key);
+        let gain_max = self.dfs_children(pos, sleep);
+
+        // 子ノードの探索中に打ち切られた場合、gain_max は不正確かもしれない。
+        let aborted = self.finished.load(Ordering::Relaxed);
+        if !aborted {
+            // 終了局面も gain_max = 0 として記録する。
+            // 他のスレッドが再探索しないよう、探索中の記録を消す前に書き込む。
+            self.dp.insert(key, gain_max);
+        }
+        if marked {
+            self.busy.leave(key);
+        }
+
+        if aborted {
+            0
+        } else {
+            gain_max
+        }
+    }
+
+    /// 空でない盤面を持つ局面 `pos` の子局面たちを探索し、`pos` から追加で獲得できる最大スコアを返す。
+    ///
+    /// 他のスレッドが探索中の子局面は後回しにする。
+    fn dfs_children<const P: usize>(
+        &mut self,
+        pos: &Position<W, H, P>,
+        sleep: &ActionValues<W, H>,
+    ) -> Score {
+        // スレッド 0 以外は着手の順序を回転させる。
+        let actions = pos.actions();
+        let rot = if self.thread_idx == 0 {
+            0
+        } else {
+            self.thread_idx % actions.clone().count().max(1)
+        };
+
+        // 眠っている着手は探索せず、その値を用いる。
+        let base = self.values_stack.len();
+        let mut gain_max = sleep.value_max();
+        let mut values = sleep.clone();
+        let mut done = ExactSleepSet::default();
+        let mut deferred = Vec::new();
+        for action in actions.clone().skip(rot).chain(actions.take(rot)) {
+            if self.finished.load(Ordering::Relaxed) {
+                return 0;
+            }
+            if sleep.contains(&action) {
+                continue;
+            }
+
+            let pos_child = pos.do_action(&action);
+            if self.busy.contains(pos_child.key()) {
+                deferred.push((action, pos_child));
+                continue;
+            }
+            let value = self.dfs_child(pos, &action, &pos_child, &mut done);
+            chmax!(gain_max, value);
+            values.insert(&action, value);
+        }
+
+        for (action, pos_child) in deferred {
+            if self.finished.load(Ordering::Relaxed) {
+                return 0;
+            }
+
+            let value = self.dfs_child(pos, &action, &pos_child, &mut done);
+            chmax!(gain_max, value);
+            values.insert(&action, value);
+        }
+
+        if self.partial_order_reduction {
+            self.values_stack.replace_top(base, &values);
+        }
+
+        gain_max
+    }
+
+    /// 局面 `pos` で着手 `action` を行った後の局面 `pos_child` を探索し、着手の値 (獲得スコア + その後の最大スコア) を返す。
+    ///
+    /// `done` には `pos` で調べ終えた着手を記録していく。
+    fn dfs_child<const P: usize>(
+        &mut self,
+        pos: &Position<W, H, P>,
+        action: &Action<W, H, P>,
+        pos_child: &Position<W, H, P>,
+        done: &mut ExactSleepSet<W, H>,
+    ) -> Score {
+        let gain_action = score_erase(action.square_count());
+
+        let gain_child = if self.partial_order_reduction {
+            let fp = ActionFootprint::new(pos.board(), action);
+            let sleep_child = done.child(&self.values_stack, fp, gain_action);
+            let start = self.values_stack.len();
+            let gain_child = self.dfs(pos_child, &sleep_child);
+            done.insert(fp, gain_action, start..self.values_stack.len());
+            gain_child
+        } else {
+            self.dfs(pos_child, &ActionValues::default())
+        };
+
+        gain_action + gain_child
+    }
+}
+
+/// いずれかのスレッドが探索中の局面のハッシュ値を記録するテーブル。
+///
+/// 探索順序の決定にのみ用いるので、スロットの衝突により記録できない局面があってもよい。
+#[derive(Debug)]
+struct BusyTable {
+    array: Box<[AtomicU64]>,
+}
+
+impl BusyTable {
+    /// スロット数。同時に探索中の局面は高々 (スレッド数) x (探索の深さ) 個程度なので、小さくてよい。
+    const LEN: usize = 1 << 16;
+
+    fn new() -> Self {
+        Self {
+            array: (0..Self::LEN).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    fn slot(&self, key: u64) -> &AtomicU64 {
+        unsafe { self.array.get_unchecked(key as usize & (Self::LEN - 1)) }
+    }
+
+    /// ハッシュ値 `key` の局面を探索中として記録し、記録できたかどうかを返す。
+    ///
+    /// スロットが他の局面に使われている場合は記録しない (値 0 は空きを表す)。
+    fn enter(&self, key: u64) -> bool {
+        key != 0
+            && self
+                .slot(key)
+                .compare_exchange(0, key, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+    }
+
+    /// `enter()` で記録した局面の記録を消す。
+    fn leave(&self, key: u64) {
+        self.slot(key).store(0, Ordering::Relaxed);
+    }
+
+    /// ハッシュ値 `key` の局面がいずれかのスレッドで探索中かどうかを返す。
+    fn contains(&self, key: u64) -> bool {
+        key != 0 && self.slot(key).load(Ordering::Relaxed) == key
+    }
+}
+
+/// 複数スレッドから同時にアクセスできる DP テーブル。
+///
+/// エントリの形式は `solver::DpEntry` と同じで、値 0 は空きを表す。
+/// 値が確定したエントリのみを CAS で書き込むので、読み出したエントリは常に正しい値を持つ。
+/// 同一世代内ではスロットが空きに戻ることはないので、linear probing がそのまま使える。
+/// (置き換えにより別の局面のエントリになることはある)
+#[derive(Debug)]
+struct ConcurrentDpTable<const W: usize, const H: usize> {
+    time: u16,
+    entry_count: AtomicU64,
+    policy: TableFullPolicy,
+    probe_limit: usize,
+    array: Box<[AtomicU64]>,
+}
+
+impl<const W: usize, const H: usize> ConcurrentDpTable<W, H> {
+    fn new(capacity: usize, policy: TableFullPolicy) -> Self {
+        assert!(capacity.is_power_of_two());
+
+        // ゼロ初期化された領域を確保する (AtomicU64 は u64 とメモリレイアウトが同じ)。
+        let array = vec![0_u64; capacity].into_boxed_slice();
+        let array = unsafe { Box::from_raw(Box::into_raw(array) as *mut [AtomicU64]) };
+
+        Self {
+            time: 0,
+            entry_count: AtomicU64::new(0),
+            policy,
+            probe_limit: policy.probe_limit(capacity),
+            array,
+        }
+    }
+
+    /// 容量 (エントリ数) を返す。
+    fn capacity(&self) -> usize {
+        self.array.len()
+    }
+
+    fn index_mask(&self) -> usize {
+        self.capacity() - 1
+    }
+
+    /// 現在の世代におけるエントリ数を返す。
+    fn entry_count(&self) -> usize {
+        self.entry_count.load(Ordering::Relaxed) as usize
+    }
+
+    /// 世代を更新する。
+    ///
+    /// 世代がオーバーフローする場合のみテーブル全体が再初期化される。
+    fn increment_time(&mut self) {
+        let overflow;
+        (self.time, overflow) = self.time.overflowing_add(1);
+
+        *self.entry_count.get_mut() = 0;
+
+        if overflow {
+            for entry in self.array.iter_mut() {
+                *entry.get_mut() = 0;
+            }
+        }
+    }
+
+    /// 現在の世代において、slot の値 `value` が空きとみなせるかどうかを返す。
+    fn is_vacant(&self, value: u64) -> bool {
+        DpEntry::<W, H>::from_raw(value).is_none_or(|entry| entry.time() != self.time)
+    }
+
+    /// 現在の世代においてハッシュ値 `key` に対応するエントリを探し、その値 (gain_max) を返す。
+    fn get(&self, key: u64) -> Option<Score> {
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+
+        let idx_home = key as usize;
+        for i in 0..self.probe_limit {
+            let idx = idx_home.wrapping_add(i) & self.index_mask();
+            let value = unsafe { self.array.get_unchecked(idx) }.load(Ordering::Relaxed);
+            if self.is_vacant(value) {
+                return None;
+            }
+
+            let entry = unsafe { DpEntry::<W, H>::from_raw(value).unwrap_unchecked() };
+            if entry.key_hi() == key_hi {
+                return Some(entry.gain_max());
+            }
+        }
+
+        None
+    }
+
+    /// 現在の世代においてハッシュ値 `key` に対応するエントリを書き込む。
+    ///
+    /// 他のスレッドが既に同じ局面のエントリを書き込んでいた場合は何もしない (値は同じはずなので)。
+    /// probe の上限回数以内に空きが見つからなければ `TableFullPolicy` に従う。
+    fn insert(&self, key: u64, gain_max: Score) {
+        let key_hi = DpEntry::<W, H>::calc_key_hi(key);
+        let entry_new = DpEntry::<W, H>::new(self.time, key, gain_max).to_raw();
+
+        let idx_home = key as usize;
+        for i in 0..self.probe_limit {
+            let idx = idx_home.wrapping_add(i) & self.index_mask();
+            let slot = unsafe { self.array.get_unchecked(idx) };
+            let mut value = slot.load(Ordering::Relaxed);
+
+            if self.is_vacant(value) {
+                match slot.compare_exchange(value, entry_new, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {
+                        self.entry_count.fetch_add(1, Ordering::Relaxed);
+                        return;
+                    }
+                    // 他のスレッドに先を越された。書き込まれたエントリを調べる。
+                    Err(value_now) => value = value_now,
+                }
+            }
+
+            let entry = unsafe { DpEntry::<W, H>::from_raw(value).unwrap_unchecked() };
+            if entry.key_hi() == key_hi {
+                return;
+            }
+        }
+
+        // 空きが見つからなかった。既存エントリを置き換える (エントリ数は変わらない)。
+        // エントリは常に確定した値を持つので、単に上書きしてよい。
+        let idx =
+            idx_home.wrapping_add(self.policy.victim_offset(self.capacity())) & self.index_mask();
+        unsafe { self.array.get_unchecked(idx) }.store(entry_new, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use indoc::indoc;
+
+    use crate::action::Action;
+    use crate::solver::ExactSolver;
+
+    use super::*;
+
+    fn parse_board(s: impl AsRef<str>) -> Board {
+        s.as_ref().parse().unwrap()
+    }
+
+    #[test]
+    fn test_parallel_solver() {
+        let boards = [
+            parse_board(indoc! {"
+                ........
+                ........
+                3.......
+                13.2....
+                12321...
+                21321321
+            "}),
+            parse_board(indoc! {"
+                ........
+                32321131
+                23121233
+                31233222
+                12223221
+                33232222
+            "}),
+            parse_board(indoc! {"
+                ........
+                ........
+                ........
+                ........
+                ........
+                11122...
+            "}),
+        ];
+
+        let config_exact = SolveConfig {
+            table_bytes: 1 << 20,
+            ..Default::default()
+        };
+        let mut solver_exact = ExactSolver::new(&config_exact);
+
+        // テーブルが小さい場合 (置き換えが起こる) も試す。
+        let configs = [1 << 20, 1 << 13].map(|table_bytes| SolveConfig {
+            thread_count: 3,
+            table_bytes,
+            ..Default::default()
+        });
+
+        for (board, config) in itertools::iproduct!(boards, configs) {
+            let expect = solver_exact.solve(board.clone()).unwrap();
+            solver_exact.set_best_score(0);
+            let actual = ParallelSolver::new(&config).solve(board.clone()).unwrap();
+
+            assert_eq!(actual.score(), expect.score());
+
+            // 手順が正しいスコアを与えることを確かめる。
+            let mut pos = Position::new(board);
+            let mut score = 0;
+            for &sq in actual.solution().iter() {
+                let action = Action::from_board_square(pos.board(), sq);
+                score += score_erase(action.square_count());
+                pos = pos.do_action(&action);
+            }
+            if pos.board().is_empty() {
+                score += SCORE_PERFECT;
+            }
+            assert_eq!(score, actual.score());
+        }
+    }
+}
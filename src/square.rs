@@ -6,10 +6,21 @@ use anyhow::{anyhow, ensure, Context as _};
 
 use crate::hint::assert_unchecked;
 
+/// 列数の上限。
+pub const COL_NUM_MAX: usize = 32;
+
+/// 行数の上限。
+pub const ROW_NUM_MAX: usize = 10;
+
+/// マス数の上限。
+pub const SQUARE_NUM_MAX: usize = u8::MAX as usize;
+
 /// 盤面の列。左から右の順。
+///
+/// `W` は盤面の列数で、`1..=COL_NUM_MAX` でなければならない。
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Col(NonZeroU8);
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Col<const W: usize = 8>(NonZeroU8);
 
 pub const COL_1: Col = unsafe { Col::from_inner_unchecked(1) };
 pub const COL_2: Col = unsafe { Col::from_inner_unchecked(2) };
@@ -20,11 +31,13 @@ pub const COL_6: Col = unsafe { Col::from_inner_unchecked(6) };
 pub const COL_7: Col = unsafe { Col::from_inner_unchecked(7) };
 pub const COL_8: Col = unsafe { Col::from_inner_unchecked(8) };
 
-impl Col {
-    pub const NUM: usize = 8;
+impl<const W: usize> Col<W> {
+    pub const NUM: usize = W;
 
     pub const MIN_VALUE: u8 = 1;
-    pub const MAX_VALUE: u8 = 8;
+    pub const MAX_VALUE: u8 = W as u8;
+
+    const DIMS_OK: () = assert!(1 <= W && W <= COL_NUM_MAX, "列数が無効");
 
     pub const MIN: Self = unsafe { Self::from_inner_unchecked(Self::MIN_VALUE) };
     pub const MAX: Self = unsafe { Self::from_inner_unchecked(Self::MAX_VALUE) };
@@ -44,13 +57,14 @@ impl Col {
     ///
     /// `inner` は有効値でなければならない。
     pub const unsafe fn from_inner_unchecked(inner: u8) -> Self {
+        let () = Self::DIMS_OK;
         assert_unchecked!(Self::is_valid(inner));
 
         Self(NonZeroU8::new_unchecked(inner))
     }
 
     const fn is_valid(inner: u8) -> bool {
-        matches!(inner, Self::MIN_VALUE..=Self::MAX_VALUE)
+        Self::MIN_VALUE <= inner && inner <= Self::MAX_VALUE
     }
 
     /// 内部値を返す。
@@ -65,7 +79,7 @@ impl Col {
 
     /// 左隣の列を返す。
     pub const fn prev(self) -> Option<Self> {
-        if !matches!(self, Self::MIN) {
+        if self.to_inner() != Self::MIN_VALUE {
             Some(unsafe { self.prev_unchecked() })
         } else {
             None
@@ -78,14 +92,14 @@ impl Col {
     ///
     /// `self != Self::MIN` でなければならない。
     pub const unsafe fn prev_unchecked(self) -> Self {
-        assert_unchecked!(!matches!(self, Self::MIN));
+        assert_unchecked!(self.to_inner() != Self::MIN_VALUE);
 
         Self::from_inner_unchecked(self.to_inner() - 1)
     }
 
     /// 右隣の列を返す。
     pub const fn next(self) -> Option<Self> {
-        if !matches!(self, Self::MAX) {
+        if self.to_inner() != Self::MAX_VALUE {
             Some(unsafe { self.next_unchecked() })
         } else {
             None
@@ -98,7 +112,7 @@ impl Col {
     ///
     /// `self != Self::MAX` でなければならない。
     pub const unsafe fn next_unchecked(self) -> Self {
-        assert_unchecked!(!matches!(self, Self::MAX));
+        assert_unchecked!(self.to_inner() != Self::MAX_VALUE);
 
         Self::from_inner_unchecked(self.to_inner() + 1)
     }
@@ -112,7 +126,7 @@ impl Col {
     }
 }
 
-impl std::str::FromStr for Col {
+impl<const W: usize> std::str::FromStr for Col<W> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -120,20 +134,22 @@ impl std::str::FromStr for Col {
             .parse()
             .with_context(|| format!("Col のパースに失敗: '{s}'"))?;
 
-        Col::from_inner(col).ok_or_else(|| anyhow!("Col の値が無効: {col}"))
+        Self::from_inner(col).ok_or_else(|| anyhow!("Col の値が無効: {col}"))
     }
 }
 
-impl std::fmt::Display for Col {
+impl<const W: usize> std::fmt::Display for Col<W> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
 
 /// 盤面の行。下から上の順。
+///
+/// `H` は盤面の行数で、`1..=ROW_NUM_MAX` でなければならない。
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Row(NonZeroU8);
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Row<const H: usize = 6>(NonZeroU8);
 
 pub const ROW_1: Row = unsafe { Row::from_inner_unchecked(1) };
 pub const ROW_2: Row = unsafe { Row::from_inner_unchecked(2) };
@@ -142,11 +158,13 @@ pub const ROW_4: Row = unsafe { Row::from_inner_unchecked(4) };
 pub const ROW_5: Row = unsafe { Row::from_inner_unchecked(5) };
 pub const ROW_6: Row = unsafe { Row::from_inner_unchecked(6) };
 
-impl Row {
-    pub const NUM: usize = 6;
+impl<const H: usize> Row<H> {
+    pub const NUM: usize = H;
 
     pub const MIN_VALUE: u8 = 1;
-    pub const MAX_VALUE: u8 = 6;
+    pub const MAX_VALUE: u8 = H as u8;
+
+    const DIMS_OK: () = assert!(1 <= H && H <= ROW_NUM_MAX, "行数が無効");
 
     pub const MIN: Self = unsafe { Self::from_inner_unchecked(Self::MIN_VALUE) };
     pub const MAX: Self = unsafe { Self::from_inner_unchecked(Self::MAX_VALUE) };
@@ -166,13 +184,14 @@ impl Row {
     ///
     /// `inner` は有効値でなければならない。
     pub const unsafe fn from_inner_unchecked(inner: u8) -> Self {
+        let () = Self::DIMS_OK;
         assert_unchecked!(Self::is_valid(inner));
 
         Self(NonZeroU8::new_unchecked(inner))
     }
 
     const fn is_valid(inner: u8) -> bool {
-        matches!(inner, Self::MIN_VALUE..=Self::MAX_VALUE)
+        Self::MIN_VALUE <= inner && inner <= Self::MAX_VALUE
     }
 
     /// 内部値を返す。
@@ -194,7 +213,7 @@ impl Row {
     }
 }
 
-impl std::str::FromStr for Row {
+impl<const H: usize> std::str::FromStr for Row<H> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -202,26 +221,34 @@ impl std::str::FromStr for Row {
             .parse()
             .with_context(|| format!("Row のパースに失敗: '{s}'"))?;
 
-        Row::from_inner(row).ok_or_else(|| anyhow!("Row の値が無効: {row}"))
+        Self::from_inner(row).ok_or_else(|| anyhow!("Row の値が無効: {row}"))
     }
 }
 
-impl std::fmt::Display for Row {
+impl<const H: usize> std::fmt::Display for Row<H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
     }
 }
 
 /// 盤面のマス (column-major)。
+///
+/// `W * H` は `SQUARE_NUM_MAX` 以下でなければならない。
 #[repr(transparent)]
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub struct Square(NonZeroU8);
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Square<const W: usize = 8, const H: usize = 6>(NonZeroU8);
 
-impl Square {
-    pub const NUM: usize = 48;
+impl<const W: usize, const H: usize> Square<W, H> {
+    pub const NUM: usize = W * H;
 
     pub const MIN_VALUE: u8 = 1;
-    pub const MAX_VALUE: u8 = 48;
+    pub const MAX_VALUE: u8 = (W * H) as u8;
+
+    const DIMS_OK: () = {
+        let () = Col::<W>::DIMS_OK;
+        let () = Row::<H>::DIMS_OK;
+        assert!(W * H <= SQUARE_NUM_MAX, "マス数が多すぎる");
+    };
 
     pub const MIN: Self = unsafe { Self::from_inner_unchecked(Self::MIN_VALUE) };
     pub const MAX: Self = unsafe { Self::from_inner_unchecked(Self::MAX_VALUE) };
@@ -241,13 +268,14 @@ impl Square {
     ///
     /// `inner` は有効値でなければならない。
     pub const unsafe fn from_inner_unchecked(inner: u8) -> Self {
+        let () = Self::DIMS_OK;
         assert_unchecked!(Self::is_valid(inner));
 
         Self(NonZeroU8::new_unchecked(inner))
     }
 
     const fn is_valid(inner: u8) -> bool {
-        matches!(inner, Self::MIN_VALUE..=Self::MAX_VALUE)
+        Self::MIN_VALUE <= inner && inner <= Self::MAX_VALUE
     }
 
     /// 内部値を返す。
@@ -261,22 +289,22 @@ impl Square {
     }
 
     /// 列と行からマスを作る。
-    pub const fn new(col: Col, row: Row) -> Self {
-        let sq = Row::NUM as u8 * (col.to_inner() - 1) + row.to_inner();
+    pub const fn new(col: Col<W>, row: Row<H>) -> Self {
+        let sq = H as u8 * (col.to_inner() - 1) + row.to_inner();
 
         unsafe { Self::from_inner_unchecked(sq) }
     }
 
     /// マスが属する列を返す。
-    pub const fn col(self) -> Col {
-        let col = 1 + (self.to_inner() - 1) / Row::NUM as u8;
+    pub const fn col(self) -> Col<W> {
+        let col = 1 + (self.to_inner() - 1) / H as u8;
 
         unsafe { Col::from_inner_unchecked(col) }
     }
 
     /// マスが属する行を返す。
-    pub const fn row(self) -> Row {
-        let row = 1 + (self.to_inner() - 1) % Row::NUM as u8;
+    pub const fn row(self) -> Row<H> {
+        let row = 1 + (self.to_inner() - 1) % H as u8;
 
         unsafe { Row::from_inner_unchecked(row) }
     }
@@ -290,17 +318,17 @@ impl Square {
     }
 }
 
-impl std::str::FromStr for Square {
+impl<const W: usize, const H: usize> std::str::FromStr for Square<W, H> {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let fields: Vec<_> = s.split(',').collect();
         ensure!(fields.len() == 2, "Square のパースに失敗: '{s}'");
 
-        let col: Col = fields[0]
+        let col: Col<W> = fields[0]
             .parse()
             .with_context(|| format!("Square の列のパースに失敗: {}", fields[0]))?;
-        let row: Row = fields[1]
+        let row: Row<H> = fields[1]
             .parse()
             .with_context(|| format!("Square の行のパースに失敗: {}", fields[1]))?;
 
@@ -308,7 +336,7 @@ impl std::str::FromStr for Square {
     }
 }
 
-impl std::fmt::Display for Square {
+impl<const W: usize, const H: usize> std::fmt::Display for Square<W, H> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{},{}", self.col(), self.row())
     }
@@ -316,147 +344,155 @@ impl std::fmt::Display for Square {
 
 /// `Col` でインデックスアクセスできる配列。
 #[repr(transparent)]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct ColArray<T>([T; Col::NUM]);
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ColArray<T, const W: usize = 8>([T; W]);
 
-impl<T: Default> Default for ColArray<T> {
+impl<T: Default, const W: usize> Default for ColArray<T, W> {
     fn default() -> Self {
         Self::from_fn(|_| T::default())
     }
 }
 
-impl<T: Clone> ColArray<T> {
+impl<T: Clone, const W: usize> ColArray<T, W> {
     pub fn from_elem(elem: T) -> Self {
         Self::from_fn(|_| elem.clone())
     }
 }
 
-impl<T> ColArray<T> {
-    pub const fn new(inner: [T; Col::NUM]) -> Self {
+impl<T, const W: usize> ColArray<T, W> {
+    pub const fn new(inner: [T; W]) -> Self {
         Self(inner)
     }
 
-    pub fn from_fn(mut f: impl FnMut(Col) -> T) -> Self {
+    pub fn from_fn(mut f: impl FnMut(Col<W>) -> T) -> Self {
         Self::new(std::array::from_fn(|i| {
             f(unsafe { Col::from_inner_unchecked((i + 1) as u8) })
         }))
     }
 
-    pub const fn as_array(&self) -> &[T; Col::NUM] {
+    pub const fn as_array(&self) -> &[T; W] {
         &self.0
     }
 
     pub fn enumerate(
         &self,
-    ) -> impl DoubleEndedIterator<Item = (Col, &T)> + ExactSizeIterator + std::iter::FusedIterator + Clone
-    {
+    ) -> impl DoubleEndedIterator<Item = (Col<W>, &T)>
+           + ExactSizeIterator
+           + std::iter::FusedIterator
+           + Clone {
         Col::all().map(|col| (col, &self[col]))
     }
 }
 
-impl<T> std::ops::Index<Col> for ColArray<T> {
+impl<T, const W: usize> std::ops::Index<Col<W>> for ColArray<T, W> {
     type Output = T;
 
-    fn index(&self, col: Col) -> &Self::Output {
+    fn index(&self, col: Col<W>) -> &Self::Output {
         unsafe { self.0.get_unchecked(col.to_index()) }
     }
 }
 
-impl<T> std::ops::IndexMut<Col> for ColArray<T> {
-    fn index_mut(&mut self, col: Col) -> &mut Self::Output {
+impl<T, const W: usize> std::ops::IndexMut<Col<W>> for ColArray<T, W> {
+    fn index_mut(&mut self, col: Col<W>) -> &mut Self::Output {
         unsafe { self.0.get_unchecked_mut(col.to_index()) }
     }
 }
 
 /// `Row` でインデックスアクセスできる配列。
 #[repr(transparent)]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct RowArray<T>([T; Row::NUM]);
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct RowArray<T, const H: usize = 6>([T; H]);
 
-impl<T: Default> Default for RowArray<T> {
+impl<T: Default, const H: usize> Default for RowArray<T, H> {
     fn default() -> Self {
         Self::from_fn(|_| T::default())
     }
 }
 
-impl<T: Clone> RowArray<T> {
+impl<T: Clone, const H: usize> RowArray<T, H> {
     pub fn from_elem(elem: T) -> Self {
         Self::from_fn(|_| elem.clone())
     }
 }
 
-impl<T> RowArray<T> {
-    pub const fn new(inner: [T; Row::NUM]) -> Self {
+impl<T, const H: usize> RowArray<T, H> {
+    pub const fn new(inner: [T; H]) -> Self {
         Self(inner)
     }
 
-    pub fn from_fn(mut f: impl FnMut(Row) -> T) -> Self {
+    pub fn from_fn(mut f: impl FnMut(Row<H>) -> T) -> Self {
         Self::new(std::array::from_fn(|i| {
             f(unsafe { Row::from_inner_unchecked((i + 1) as u8) })
         }))
     }
 
-    pub const fn as_array(&self) -> &[T; Row::NUM] {
+    pub const fn as_array(&self) -> &[T; H] {
         &self.0
     }
 
     pub fn enumerate(
         &self,
-    ) -> impl DoubleEndedIterator<Item = (Row, &T)> + ExactSizeIterator + std::iter::FusedIterator + Clone
-    {
+    ) -> impl DoubleEndedIterator<Item = (Row<H>, &T)>
+           + ExactSizeIterator
+           + std::iter::FusedIterator
+           + Clone {
         Row::all().map(|row| (row, &self[row]))
     }
 }
 
-impl<T> std::ops::Index<Row> for RowArray<T> {
+impl<T, const H: usize> std::ops::Index<Row<H>> for RowArray<T, H> {
     type Output = T;
 
-    fn index(&self, row: Row) -> &Self::Output {
+    fn index(&self, row: Row<H>) -> &Self::Output {
         unsafe { self.0.get_unchecked(row.to_index()) }
     }
 }
 
-impl<T> std::ops::IndexMut<Row> for RowArray<T> {
-    fn index_mut(&mut self, row: Row) -> &mut Self::Output {
+impl<T, const H: usize> std::ops::IndexMut<Row<H>> for RowArray<T, H> {
+    fn index_mut(&mut self, row: Row<H>) -> &mut Self::Output {
         unsafe { self.0.get_unchecked_mut(row.to_index()) }
     }
 }
 
 /// `Square` でインデックスアクセスできる配列。
+///
+/// 内部的には列ごとの配列の配列 (column-major)。
 #[repr(transparent)]
-#[derive(Clone, Debug, Eq, PartialEq)]
-pub struct SquareArray<T>([T; Square::NUM]);
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SquareArray<T, const W: usize = 8, const H: usize = 6>([[T; H]; W]);
 
-impl<T: Default> Default for SquareArray<T> {
+impl<T: Default, const W: usize, const H: usize> Default for SquareArray<T, W, H> {
     fn default() -> Self {
         Self::from_fn(|_| T::default())
     }
 }
 
-impl<T: Clone> SquareArray<T> {
+impl<T: Clone, const W: usize, const H: usize> SquareArray<T, W, H> {
     pub fn from_elem(elem: T) -> Self {
         Self::from_fn(|_| elem.clone())
     }
 }
 
-impl<T> SquareArray<T> {
-    pub const fn new(inner: [T; Square::NUM]) -> Self {
+impl<T, const W: usize, const H: usize> SquareArray<T, W, H> {
+    pub const fn new(inner: [[T; H]; W]) -> Self {
         Self(inner)
     }
 
-    pub fn from_fn(mut f: impl FnMut(Square) -> T) -> Self {
-        Self::new(std::array::from_fn(|i| {
-            f(unsafe { Square::from_inner_unchecked((i + 1) as u8) })
+    pub fn from_fn(mut f: impl FnMut(Square<W, H>) -> T) -> Self {
+        Self::new(std::array::from_fn(|c| {
+            std::array::from_fn(|r| {
+                f(unsafe { Square::from_inner_unchecked((H * c + r + 1) as u8) })
+            })
         }))
     }
 
-    pub const fn as_array(&self) -> &[T; Square::NUM] {
+    pub const fn as_array(&self) -> &[[T; H]; W] {
         &self.0
     }
 
     pub fn enumerate(
         &self,
-    ) -> impl DoubleEndedIterator<Item = (Square, &T)>
+    ) -> impl DoubleEndedIterator<Item = (Square<W, H>, &T)>
            + ExactSizeIterator
            + std::iter::FusedIterator
            + Clone {
@@ -464,17 +500,25 @@ impl<T> SquareArray<T> {
     }
 }
 
-impl<T> std::ops::Index<Square> for SquareArray<T> {
+impl<T, const W: usize, const H: usize> std::ops::Index<Square<W, H>> for SquareArray<T, W, H> {
     type Output = T;
 
-    fn index(&self, sq: Square) -> &Self::Output {
-        unsafe { self.0.get_unchecked(sq.to_index()) }
+    fn index(&self, sq: Square<W, H>) -> &Self::Output {
+        unsafe {
+            self.0
+                .get_unchecked(sq.col().to_index())
+                .get_unchecked(sq.row().to_index())
+        }
     }
 }
 
-impl<T> std::ops::IndexMut<Square> for SquareArray<T> {
-    fn index_mut(&mut self, sq: Square) -> &mut Self::Output {
-        unsafe { self.0.get_unchecked_mut(sq.to_index()) }
+impl<T, const W: usize, const H: usize> std::ops::IndexMut<Square<W, H>> for SquareArray<T, W, H> {
+    fn index_mut(&mut self, sq: Square<W, H>) -> &mut Self::Output {
+        unsafe {
+            self.0
+                .get_unchecked_mut(sq.col().to_index())
+                .get_unchecked_mut(sq.row().to_index())
+        }
     }
 }
 
@@ -486,18 +530,24 @@ mod tests {
         s.as_ref().parse().unwrap()
     }
 
-    #[test]
-    fn test_square_new() {
-        for (col, row) in itertools::iproduct!(Col::all(), Row::all()) {
+    fn check_square_new<const W: usize, const H: usize>() {
+        for (col, row) in itertools::iproduct!(Col::<W>::all(), Row::<H>::all()) {
             let sq = Square::new(col, row);
             assert_eq!(sq.col(), col);
             assert_eq!(sq.row(), row);
         }
     }
 
+    #[test]
+    fn test_square_new() {
+        check_square_new::<8, 6>();
+        check_square_new::<16, 10>();
+        check_square_new::<32, 7>();
+    }
+
     #[test]
     fn test_square_io() {
-        for sq in Square::all() {
+        for sq in <Square>::all() {
             let s = sq.to_string();
             assert_eq!(parse_sq(s), sq);
         }
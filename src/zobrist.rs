@@ -1,24 +1,80 @@
 //! zobrist hash 関連。
 
 use crate::asset::asset_include;
-use crate::piece::{Piece, PieceArray};
-use crate::square::{Square, SquareArray};
+use crate::piece::{Piece, PIECE_NUM_MAX};
+use crate::square::{Square, COL_NUM_MAX, ROW_NUM_MAX};
 
 /// 局面のハッシュ値。
 pub type Key = u64;
 
-type TableBoard = PieceArray<SquareArray<Key>>;
+/// 駒種、列、行の順にインデックスアクセスするテーブル。
+///
+/// 盤面サイズ・駒種数の上限に合わせて作ってあり、各サイズではその一部のみを使う。
+type TableBoard = [[[Key; ROW_NUM_MAX]; COL_NUM_MAX]; PIECE_NUM_MAX];
 
-#[derive(Debug)]
-pub struct ZobristTable;
+static BOARD: TableBoard = asset_include!("zobrist_board.in");
 
-impl ZobristTable {
-    const BOARD: TableBoard = asset_include!("zobrist_board.in");
+#[derive(Debug)]
+pub struct ZobristTable<const W: usize = 8, const H: usize = 6, const P: usize = 5>;
 
+impl<const W: usize, const H: usize, const P: usize> ZobristTable<W, H, P> {
     /// `piece` が `sq` にあるときのハッシュ値を返す。
-    pub fn board(&self, piece: Piece, sq: Square) -> Key {
-        Self::BOARD[piece][sq]
+    pub fn board(&self, piece: Piece<P>, sq: Square<W, H>) -> Key {
+        // Piece, Square の型引数の制約により、インデックスは常に範囲内。
+        unsafe {
+            *BOARD
+                .get_unchecked(piece.to_index())
+                .get_unchecked(sq.col().to_index())
+                .get_unchecked(sq.row().to_index())
+        }
+    }
+
+    /// 駒種によらない `sq` のハッシュ値を返す。駒種ごとの駒の配置のハッシュ値に用いる。
+    ///
+    /// 値は最初の駒種に対する `board()` と同じだが、用途が異なるので問題ない。
+    pub fn square(&self, sq: Square<W, H>) -> Key {
+        unsafe {
+            *BOARD
+                .get_unchecked(0)
+                .get_unchecked(sq.col().to_index())
+                .get_unchecked(sq.row().to_index())
+        }
     }
 }
 
 pub const ZOBRIST_TABLE: ZobristTable = ZobristTable;
+
+/// zobrist テーブル全体のフィンガープリントを返す。
+///
+/// ハッシュ値を含むデータをファイルに保存する際、読み込み時にテーブルが同一か確認するのに用いる。
+pub fn zobrist_fingerprint() -> u64 {
+    BOARD.iter().flatten().flatten().fold(0, |acc, &key| {
+        (acc.rotate_left(5) ^ key).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::square::*;
+
+    use super::*;
+
+    /// 8x6 盤面のキーは、盤面サイズを一般化する前のテーブルから変わらない。
+    #[test]
+    fn test_zobrist_table_easy_keys() {
+        let piece = |inner| Piece::<5>::from_inner(inner).unwrap();
+
+        assert_eq!(
+            ZOBRIST_TABLE.board(piece(1), Square::new(COL_1, ROW_1)),
+            0xD253497088112D8B
+        );
+        assert_eq!(
+            ZOBRIST_TABLE.board(piece(1), Square::new(COL_2, ROW_1)),
+            0x4A482B8EEDECEA0C
+        );
+        assert_eq!(
+            ZOBRIST_TABLE.board(piece(5), Square::new(COL_8, ROW_6)),
+            0x80641C4876EF58D1
+        );
+    }
+}